@@ -1,12 +1,122 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+const PROTOS: &[&str] = &[
+    "proto/helloworld.proto",
+    "proto/echo_def.proto",
+    "proto/admin.proto",
+    "proto/google/rpc/error_details.proto",
+    // Both versions are served, so both get compiled. They're separate packages
+    // (routeguide.v1 and routeguide.v2), so their generated modules don't clash.
+    "proto/routeguide/v1/route_guide.proto",
+    ROUTE_GUIDE_V2,
+];
+
+const ROUTE_GUIDE_V2: &str = "proto/routeguide/v2/route_guide.proto";
+
+/// Where the serialized `FileDescriptorSet` of all the protos goes, for reflection and tools
+/// like `grpcurl -protoset`.
+const DESCRIPTOR_SET: &str = "descriptor_set.bin";
+
+/// Generated stubs whose calls use `codec::TunedCodec`, sized for each method, instead of
+/// tonic's `ProstCodec`. Only those the library includes, since the codec is its own.
+const TUNED_STUBS: &[&str] = &["routeguide.v2.rs"];
+
+const PROST_CODEC: &str = "tonic::codec::ProstCodec::default()";
+
+/// Gives each call's codec its method path. Client methods name the path right after the
+/// codec, in `PathAndQuery::from_static`; servers match on it just before, as `"/..." =>`.
+///
+/// This patches tonic-build's output as text, so it fails rather than quietly leave a stub
+/// untuned when that output changes: if there's no codec to replace, or a codec without a path
+/// next to it.
+fn tune_codecs(code: &str) -> Result<String, String> {
+    let mut tuned = String::with_capacity(code.len());
+    let mut rest = code;
+    let mut seen = 0;
+    let mut replaced = 0;
+    while let Some(at) = rest.find(PROST_CODEC) {
+        let (before, after) = (&rest[..at], &rest[at + PROST_CODEC.len()..]);
+        let client_path = after
+            .find("PathAndQuery::from_static(\"")
+            .filter(|&start| !after[..start].contains(PROST_CODEC))
+            .and_then(|start| {
+                let path = &after[start + "PathAndQuery::from_static(\"".len()..];
+                path.find('"').map(|end| &path[..end])
+            });
+        let server_path = || {
+            let end = code[..seen + at].rfind("\" =>")?;
+            let start = code[..end].rfind('"')? + 1;
+            Some(&code[start..end]).filter(|path| path.starts_with('/'))
+        };
+
+        let path = client_path
+            .or_else(server_path)
+            .ok_or_else(|| format!("no method path next to the codec at byte {}", seen + at))?;
+        tuned.push_str(before);
+        tuned.push_str(&format!("crate::codec::TunedCodec::new(\"{}\")", path));
+        replaced += 1;
+        seen += at + PROST_CODEC.len();
+        rest = after;
+    }
+    if replaced == 0 {
+        return Err(format!("no `{}` to replace", PROST_CODEC));
+    }
+    tuned.push_str(rest);
+    Ok(tuned)
+}
+
+fn feature_enabled(name: &str) -> bool {
+    env::var_os(format!("CARGO_FEATURE_{}", name.to_uppercase().replace('-', "_"))).is_some()
+}
+
 fn main() {
-    tonic_build::compile_protos("proto/helloworld.proto")
+    for proto in PROTOS {
+        println!("cargo:rerun-if-changed={}", proto);
+    }
+
+    // Stubs are only generated for the sides that are built. Without the `transport` feature
+    // the generated code doesn't use tonic::transport (see tonic-build/transport in Cargo.toml).
+    let (client, server) = (feature_enabled("client"), feature_enabled("server") || feature_enabled("minimal-server"));
+    // routeguide.v2 returns admin messages, but lib.rs doesn't nest the modules by package, so
+    // it's compiled on its own, pointed at rust_server::admin (an absolute path, or tonic-build
+    // puts `super::` in front). That pass also writes an admin.rs without the extern messages,
+    // which the second one replaces.
+    let (route_guide, rest): (Vec<&str>, Vec<&str>) = PROTOS.iter().partition(|&&proto| proto == ROUTE_GUIDE_V2);
+    tonic_build::configure()
+        .build_client(client)
+        .build_server(server)
+        .extern_path(".admin", "::rust_server::admin")
+        .compile(&route_guide, &["proto"])
+        .unwrap_or_else(|e| panic!("Failed to compile protos {:?}", e));
+    tonic_build::configure()
+        .build_client(client)
+        .build_server(server)
+        .compile(&rest, &["proto"])
         .unwrap_or_else(|e| panic!("Failed to compile protos {:?}", e));
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    // Without stubs there are only messages, and no codecs to tune.
+    for stubs in TUNED_STUBS.iter().filter(|_| client || server) {
+        let path = out_dir.join(stubs);
+        let code = fs::read_to_string(&path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+        let tuned = tune_codecs(&code).unwrap_or_else(|e| panic!("Failed to tune the codecs in {}, has tonic-build's output changed? {}", path.display(), e));
+        fs::write(&path, tuned).unwrap_or_else(|e| panic!("Failed to write {}: {}", path.display(), e));
+    }
+
+    let descriptor_set = out_dir.join(DESCRIPTOR_SET);
+    let status = Command::new(prost_build::protoc())
+        .arg("--include_imports")
+        .arg("--include_source_info")
+        .arg(format!("--descriptor_set_out={}", descriptor_set.display()))
+        .arg("-Iproto")
+        .arg(format!("-I{}", prost_build::protoc_include().display()))
+        .args(PROTOS)
+        .status()
+        .unwrap_or_else(|e| panic!("Failed to run protoc {:?}", e));
+    if !status.success() {
+        panic!("Failed to write the descriptor set: protoc exited with {}", status);
+    }
 }
-//
-// fn main() {
-//     tonic_build::configure()
-//         .build_client(false)
-//         .out_dir("data/")
-//         .compile(&["proto/echo_def.proto"], &["."])
-//         .expect("failed to compile protos");
-// }
@@ -0,0 +1,118 @@
+/*
+-- Allocations per message in the streaming paths --
+
+Counts heap allocations (with a counting global allocator) for the echo server's uppercase body
+mapping and for encoding the features streamed by ListFeatures, comparing a fresh `Vec<u8>` per
+message against reusing a single `BytesMut`. There's no buffer pool for the codec: tonic's
+encoder already reuses one buffer per stream, which is what the second encoding case measures.
+
+Run with: cargo run --release --example alloc-bench
+*/
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use bytes::{Bytes, BytesMut};
+use prost::Message;
+
+use rust_server::{data, route_guide};
+
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+
+/// Runs `f` and prints the allocations and time it took per message.
+fn measure<F: FnOnce() -> usize>(name: &str, f: F) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let messages = f();
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    println!(
+        "{:<28} {:>10} allocations  {:>6.2} per message  {:>8.1?}",
+        name, allocations, allocations as f64 / messages as f64, elapsed
+    );
+}
+
+
+// The chunks are dropped right after the mapping, like hyper does once a chunk is written.
+
+fn uppercase_with_vec(chunks: &[Bytes]) -> usize {
+    for chunk in chunks {
+        let mapped: Bytes = chunk.iter()
+            .map(|byte| byte.to_ascii_uppercase())
+            .collect::<Vec<u8>>()
+            .into();
+        drop(mapped);
+    }
+    chunks.len()
+}
+
+fn uppercase_with_bytes_mut(chunks: &[Bytes]) -> usize {
+    let mut buffer = BytesMut::new();
+    for chunk in chunks {
+        buffer.reserve(chunk.len());
+        buffer.extend_from_slice(chunk);
+        buffer.make_ascii_uppercase();
+        let mapped = buffer.split().freeze();
+        drop(mapped);
+    }
+    chunks.len()
+}
+
+
+fn encode_with_vec(features: &[route_guide::Feature]) -> usize {
+    for feature in features {
+        let mut buffer = Vec::new();
+        feature.encode(&mut buffer).unwrap();
+    }
+    features.len()
+}
+
+// This is what tonic's encoder does: one buffer per stream, cleared between messages.
+fn encode_with_bytes_mut(features: &[route_guide::Feature]) -> usize {
+    let mut buffer = BytesMut::with_capacity(8 * 1024);
+    for feature in features {
+        buffer.clear();
+        feature.encode(&mut buffer).unwrap();
+    }
+    features.len()
+}
+
+
+fn main() {
+    let chunks: Vec<Bytes> = (0..10_000)
+        .map(|i| Bytes::from(format!("chunk number {} of the request body ", i).repeat(64)))
+        .collect();
+
+    let features: Vec<_> = data::load()
+        .into_iter()
+        .cycle()
+        .take(100_000)
+        .collect();
+
+    println!("*** ECHO UPPERCASE ({} chunks) ***", chunks.len());
+    measure("Vec<u8> per chunk", || uppercase_with_vec(&chunks));
+    measure("reused BytesMut", || uppercase_with_bytes_mut(&chunks));
+
+    println!("\n*** FEATURE ENCODING ({} messages) ***", features.len());
+    measure("Vec<u8> per message", || encode_with_vec(&features));
+    measure("reused BytesMut", || encode_with_bytes_mut(&features));
+}
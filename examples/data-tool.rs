@@ -0,0 +1,280 @@
+/*
+-- Tools for the feature database --
+
+    cargo run --example data-tool -- validate [--json] [PATH]
+    cargo run --example data-tool -- convert [INPUT] OUTPUT
+    cargo run --example data-tool -- export [INPUT]
+    cargo run --example data-tool -- generate [--count N] [--distribution D] [--seed N] [--binary] OUTPUT
+*/
+use std::collections::HashSet;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::process;
+use std::str::FromStr;
+use std::time::Instant;
+
+use structopt::StructOpt;
+
+use rust_server::geo::EARTH_RADIUS;
+use rust_server::proto_json::ProtoJson;
+use rust_server::route_guide::{Feature, Point};
+use rust_server::{binary_db, data};
+
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Checks the database for out-of-range coordinates, duplicate locations, empty names and
+    /// invalid UTF-8. Exits with status 1 if anything was found.
+    Validate {
+        /// Print the diagnostics as newline-delimited JSON.
+        #[structopt(long)]
+        json: bool,
+
+        #[structopt(default_value = "data/route_guide_db.json")]
+        path: String,
+    },
+
+    /// Converts a JSON database to the binary format, which loads much faster. The input must
+    /// pass validation.
+    Convert {
+        #[structopt(default_value = "data/route_guide_db.json")]
+        input: String,
+
+        output: String,
+    },
+
+    /// Prints a database, JSON or binary, as JSON in the canonical protobuf mapping, which the
+    /// server loads as well. Binary databases can be turned back into JSON this way.
+    Export {
+        #[structopt(default_value = "data/route_guide_db.json")]
+        input: String,
+    },
+
+    /// Writes a synthetic database for benchmarks and load tests. The same seed and options
+    /// always give the same features, which pass validation.
+    Generate {
+        #[structopt(long, default_value = "10000")]
+        count: usize,
+
+        /// Where the features are: uniform over the map, clustered around cities, or along
+        /// roads between them.
+        #[structopt(long, default_value = "cities")]
+        distribution: Distribution,
+
+        /// How far from their city clustered features spread, in kilometres; a standard
+        /// deviation, so most are closer.
+        #[structopt(long, default_value = "20")]
+        spread_km: f64,
+
+        #[structopt(long, default_value = "1")]
+        seed: u64,
+
+        /// Write the binary format instead of JSON.
+        #[structopt(long)]
+        binary: bool,
+
+        output: String,
+    },
+}
+
+
+#[derive(Debug, Copy, Clone)]
+enum Distribution {
+    Uniform,
+    Cities,
+    Roads,
+}
+
+impl FromStr for Distribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(Distribution::Uniform),
+            "cities" => Ok(Distribution::Cities),
+            "roads" => Ok(Distribution::Roads),
+            _ => Err(format!("unknown distribution {:?}, expected uniform, cities or roads", s)),
+        }
+    }
+}
+
+/// Where clustered features gather and roads run between, in degrees.
+const CITIES: &[(&str, f64, f64)] = &[
+    ("New York", 40.7128, -74.0060),
+    ("Los Angeles", 34.0522, -118.2437),
+    ("Chicago", 41.8781, -87.6298),
+    ("Mexico City", 19.4326, -99.1332),
+    ("São Paulo", -23.5505, -46.6333),
+    ("Buenos Aires", -34.6037, -58.3816),
+    ("London", 51.5074, -0.1278),
+    ("Paris", 48.8566, 2.3522),
+    ("Berlin", 52.5200, 13.4050),
+    ("Madrid", 40.4168, -3.7038),
+    ("Cairo", 30.0444, 31.2357),
+    ("Lagos", 6.5244, 3.3792),
+    ("Nairobi", -1.2921, 36.8219),
+    ("Moscow", 55.7558, 37.6173),
+    ("Istanbul", 41.0082, 28.9784),
+    ("Mumbai", 19.0760, 72.8777),
+    ("Delhi", 28.7041, 77.1025),
+    ("Beijing", 39.9042, 116.4074),
+    ("Shanghai", 31.2304, 121.4737),
+    ("Tokyo", 35.6762, 139.6503),
+    ("Seoul", 37.5665, 126.9780),
+    ("Jakarta", -6.2088, 106.8456),
+    ("Sydney", -33.8688, 151.2093),
+    ("Auckland", -36.8485, 174.7633),
+];
+
+/// SplitMix64: small, and unlike `rand`'s generators its output is fixed, so a seed gives the
+/// same database on every version of the tool.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// In [0, 1).
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.unit() * n as f64) as usize
+    }
+
+    /// Normally distributed, by Box-Muller.
+    fn normal(&mut self) -> f64 {
+        let u = 1.0 - self.unit();
+        (-2.0 * u.ln()).sqrt() * (2.0 * PI * self.unit()).cos()
+    }
+}
+
+/// `km` north and east of a place, in degrees, near enough on the scales clusters have.
+fn offset(latitude: f64, longitude: f64, north_km: f64, east_km: f64) -> (f64, f64) {
+    let degrees_per_km = 180.0 / (PI * EARTH_RADIUS / 1000.0);
+    let east = east_km * degrees_per_km / latitude.to_radians().cos().max(0.01);
+    (latitude + north_km * degrees_per_km, longitude + east)
+}
+
+/// Fails when the distribution is too narrow for `count` features at different locations.
+fn generate(count: usize, distribution: Distribution, spread_km: f64, seed: u64) -> Result<Vec<Feature>, String> {
+    let mut rng = SplitMix64(seed);
+    let mut taken = HashSet::new();
+    let mut features = Vec::with_capacity(count);
+    let mut duplicates = 0;
+
+    while features.len() < count {
+        let number = features.len() + 1;
+        let (location, name) = match distribution {
+            Distribution::Uniform => {
+                let location = Point::from_degrees(rng.unit() * 180.0 - 90.0, rng.unit() * 360.0 - 180.0);
+                (location, format!("Feature {}", number))
+            },
+            Distribution::Cities => {
+                let (city, latitude, longitude) = CITIES[rng.below(CITIES.len())];
+                let (latitude, longitude) = offset(latitude, longitude, rng.normal() * spread_km, rng.normal() * spread_km);
+                (Point::from_degrees(latitude, longitude), format!("Feature {} near {}", number, city))
+            },
+            Distribution::Roads => {
+                // A few hundred metres either side of a straight line between two cities.
+                let from = rng.below(CITIES.len());
+                let to = (from + 1 + rng.below(CITIES.len() - 1)) % CITIES.len();
+                let ((a, a_latitude, a_longitude), (b, b_latitude, b_longitude)) = (CITIES[from], CITIES[to]);
+                let along = rng.unit();
+                let (latitude, longitude) = offset(
+                    a_latitude + (b_latitude - a_latitude) * along,
+                    a_longitude + (b_longitude - a_longitude) * along,
+                    rng.normal() * 0.2,
+                    rng.normal() * 0.2,
+                );
+                (Point::from_degrees(latitude, longitude), format!("Feature {} on the road from {} to {}", number, a, b))
+            },
+        };
+        // Validation refuses duplicate locations.
+        if taken.insert((location.latitude, location.longitude)) {
+            features.push(Feature { name, location: Some(location), ..Feature::default() });
+        } else {
+            duplicates += 1;
+            if duplicates > count + 1000 {
+                return Err(format!("only found {} different locations, try a larger --spread-km", features.len()));
+            }
+        }
+    }
+
+    Ok(features)
+}
+
+
+fn load_or_exit(path: &str) -> Vec<Feature> {
+    match data::load_checked(path, data::InvalidDataPolicy::Refuse) {
+        Ok((features, _)) => features,
+        Err(diagnostics) => {
+            for diagnostic in &diagnostics {
+                eprintln!("{}:{}", path, diagnostic);
+            }
+            eprintln!("{} problem(s) found, run validate for details", diagnostics.len());
+            process::exit(1);
+        },
+    }
+}
+
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    match Command::from_args() {
+        Command::Validate { json, path } => {
+            let bytes = std::fs::read(&path)?;
+            let diagnostics = data::validate(&bytes);
+
+            for diagnostic in &diagnostics {
+                if json {
+                    println!("{}", serde_json::to_string(diagnostic)?);
+                } else {
+                    println!("{}:{}", path, diagnostic);
+                }
+            }
+
+            if !diagnostics.is_empty() {
+                if !json {
+                    eprintln!("{} problem(s) found", diagnostics.len());
+                }
+                process::exit(1);
+            }
+        },
+
+        Command::Convert { input, output } => {
+            let features = load_or_exit(&input);
+            binary_db::write(&output, &features)?;
+
+            let started = Instant::now();
+            let db = binary_db::BinaryDb::open(&output)?;
+            println!("Wrote {} features to {} (opened in {:?})", db.len(), output, started.elapsed());
+        },
+
+        Command::Export { input } => {
+            let features: Vec<_> = load_or_exit(&input).iter().map(ProtoJson::to_json).collect();
+            println!("{}", serde_json::to_string_pretty(&features)?);
+        },
+
+        Command::Generate { count, distribution, spread_km, seed, binary, output } => {
+            let features = generate(count, distribution, spread_km, seed)?;
+            if binary {
+                binary_db::write(&output, &features)?;
+            } else {
+                let records: Vec<_> = features.iter().map(ProtoJson::to_json).collect();
+                let mut out = BufWriter::new(File::create(&output)?);
+                serde_json::to_writer_pretty(&mut out, &records)?;
+                out.flush()?;
+            }
+            println!("Wrote {} features to {}", features.len(), output);
+        },
+    }
+
+    Ok(())
+}
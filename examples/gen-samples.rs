@@ -0,0 +1,483 @@
+/*
+-- Client samples for other languages --
+
+    cargo run --example gen-samples -- [--out samples] [--service routeguide.v2.RouteGuide]
+
+Writes, for every method of the service:
+    grpcurl.sh                   a grpcurl command with an example payload
+    python/<method>.py           a client using grpcio and the protoc --python_out modules
+    go/<method>/main.go          a client using grpc-go and the protoc-gen-go package
+plus the descriptor set grpcurl needs. The samples talk to the demo server: its TLS certificate
+and token.
+*/
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use prost::Message;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{DescriptorProto, FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet, MethodDescriptorProto};
+use structopt::StructOpt;
+
+use rust_server::route_guide;
+
+
+#[derive(Debug, StructOpt)]
+struct Options {
+    /// Directory to write the samples to.
+    #[structopt(long, default_value = "samples")]
+    out: PathBuf,
+
+    /// Read the protos from this descriptor set instead of the one built in.
+    #[structopt(long)]
+    descriptor_set: Option<PathBuf>,
+
+    /// Full name of the service.
+    #[structopt(long, default_value = "routeguide.v2.RouteGuide")]
+    service: String,
+
+    #[structopt(long, default_value = "[::1]:50051")]
+    address: String,
+
+    /// The CA the server's certificate is checked against.
+    #[structopt(long, default_value = "data/tls/ca.pem")]
+    ca_file: String,
+
+    /// The name the server's certificate is for.
+    #[structopt(long, default_value = "example.com")]
+    domain: String,
+
+    #[structopt(long, default_value = "1234")]
+    token: String,
+
+    /// Go module the generated package lives in; the package path follows the proto package.
+    #[structopt(long, default_value = "example.com/routeguide")]
+    go_module: String,
+}
+
+
+/// Example values by field path: the longest matching suffix wins, so `lo.latitude` beats
+/// `latitude`. Fields without an example are left out of the payloads.
+const EXAMPLES: &[(&str, Example)] = &[
+    ("lo.latitude", Example::Int(400_000_000)),
+    ("lo.longitude", Example::Int(-750_000_000)),
+    ("hi.latitude", Example::Int(420_000_000)),
+    ("hi.longitude", Example::Int(-730_000_000)),
+    ("latitude", Example::Int(409_146_138)),
+    ("longitude", Example::Int(-746_188_906)),
+    ("name", Example::Str("Berkshire Valley Management Area Trail, Jefferson, NJ, USA")),
+    ("description", Example::Str("A trail through the woods")),
+    ("tags", Example::Str("trail")),
+    ("message", Example::Str("First message")),
+    ("sequence", Example::Int(1)),
+];
+
+/// How deep payloads go into nested messages.
+const MAX_DEPTH: usize = 3;
+
+#[derive(Debug, Copy, Clone)]
+enum Example {
+    Int(i64),
+    Str(&'static str),
+}
+
+
+/// A message type with the file it's declared in.
+struct MessageType<'a> {
+    file: &'a FileDescriptorProto,
+    /// The name inside its package, like `Outer.Inner`.
+    local_name: String,
+    descriptor: &'a DescriptorProto,
+}
+
+struct Protos<'a> {
+    /// By full name without the leading dot, like `routeguide.v2.Point`.
+    messages: HashMap<String, MessageType<'a>>,
+}
+
+impl<'a> Protos<'a> {
+    fn new(set: &'a FileDescriptorSet) -> Self {
+        fn add<'a>(messages: &mut HashMap<String, MessageType<'a>>, file: &'a FileDescriptorProto, prefix: &str, descriptor: &'a DescriptorProto) {
+            let local_name = if prefix.is_empty() {
+                descriptor.name().to_string()
+            } else {
+                format!("{}.{}", prefix, descriptor.name())
+            };
+            for nested in &descriptor.nested_type {
+                add(messages, file, &local_name, nested);
+            }
+            let full_name = match file.package() {
+                "" => local_name.clone(),
+                package => format!("{}.{}", package, local_name),
+            };
+            messages.insert(full_name, MessageType { file, local_name, descriptor });
+        }
+
+        let mut messages = HashMap::new();
+        for file in &set.file {
+            for descriptor in &file.message_type {
+                add(&mut messages, file, "", descriptor);
+            }
+        }
+        Protos { messages }
+    }
+
+    fn message(&self, type_name: &str) -> Option<&MessageType<'a>> {
+        self.messages.get(type_name.trim_start_matches('.'))
+    }
+}
+
+
+/// An example payload, rendered for each language.
+enum Value<'a> {
+    Int { value: i64, quoted: bool },
+    Str(&'static str),
+    Message { message: &'a MessageType<'a>, fields: Vec<(&'a FieldDescriptorProto, Value<'a>)> },
+    List(Vec<Value<'a>>),
+}
+
+fn example(path: &str) -> Option<Example> {
+    EXAMPLES
+        .iter()
+        .filter(|(suffix, _)| path == *suffix || path.ends_with(&format!(".{}", suffix)))
+        .max_by_key(|(suffix, _)| suffix.len())
+        .map(|(_, example)| *example)
+}
+
+fn payload<'a>(protos: &'a Protos<'a>, message: &'a MessageType<'a>, path: &str, depth: usize) -> Value<'a> {
+    let mut fields = vec![];
+
+    for field in &message.descriptor.field {
+        let path = if path.is_empty() { field.name().to_string() } else { format!("{}.{}", path, field.name()) };
+        let single = match field.r#type() {
+            // Well-known types like FieldMask are optional extras; the samples leave them unset.
+            Type::Message if field.type_name().starts_with(".google.protobuf.") => None,
+            Type::Message if depth < MAX_DEPTH => protos
+                .message(field.type_name())
+                .map(|nested| payload(protos, nested, &path, depth + 1))
+                .filter(|value| !matches!(value, Value::Message { fields, .. } if fields.is_empty())),
+            Type::Message => None,
+            kind => match example(&path) {
+                Some(Example::Int(value)) if kind != Type::String => {
+                    let quoted = matches!(kind, Type::Int64 | Type::Uint64 | Type::Sint64 | Type::Fixed64 | Type::Sfixed64);
+                    Some(Value::Int { value, quoted })
+                },
+                Some(Example::Str(value)) if kind == Type::String => Some(Value::Str(value)),
+                _ => None,
+            },
+        };
+
+        if let Some(value) = single {
+            let value = if field.label() == Label::Repeated { Value::List(vec![value]) } else { value };
+            fields.push((field, value));
+        }
+    }
+
+    Value::Message { message, fields }
+}
+
+
+fn json_name(field: &FieldDescriptorProto) -> String {
+    match &field.json_name {
+        Some(name) => name.clone(),
+        None => camel_case(field.name(), false),
+    }
+}
+
+/// `max_features` as `maxFeatures`, or `MaxFeatures` with `upper`.
+fn camel_case(name: &str, upper: bool) -> String {
+    let mut out = String::new();
+    let mut capitalize = upper;
+    for c in name.chars() {
+        if c == '_' {
+            capitalize = true;
+        } else if capitalize {
+            out.extend(c.to_uppercase());
+            capitalize = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            out.push('_');
+        }
+        out.extend(c.to_lowercase());
+    }
+    out
+}
+
+fn quoted(s: &str) -> String {
+    serde_json::to_string(s).unwrap()
+}
+
+fn to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Int { value, quoted: true } => serde_json::Value::String(value.to_string()),
+        Value::Int { value, quoted: false } => serde_json::Value::from(*value),
+        Value::Str(s) => serde_json::Value::from(*s),
+        Value::List(values) => serde_json::Value::Array(values.iter().map(to_json).collect()),
+        Value::Message { fields, .. } => serde_json::Value::Object(
+            fields.iter().map(|(field, value)| (json_name(field), to_json(value))).collect()
+        ),
+    }
+}
+
+
+/// `routeguide/v2/route_guide.proto` as (`routeguide.v2`, `route_guide_pb2`).
+fn python_module(file: &FileDescriptorProto) -> (String, String) {
+    let path = Path::new(file.name());
+    let package = path.parent().map(|parent| parent.to_string_lossy().replace('/', ".")).unwrap_or_default();
+    let module = format!("{}_pb2", path.file_stem().unwrap().to_string_lossy());
+    (package, module)
+}
+
+fn to_python(value: &Value) -> String {
+    match value {
+        Value::Int { value, .. } => value.to_string(),
+        Value::Str(s) => quoted(s),
+        Value::List(values) => format!("[{}]", values.iter().map(to_python).collect::<Vec<_>>().join(", ")),
+        Value::Message { message, fields } => {
+            let arguments: Vec<_> = fields
+                .iter()
+                .map(|(field, value)| format!("{}={}", field.name(), to_python(value)))
+                .collect();
+            format!("{}.{}({})", python_module(message.file).1, message.local_name, arguments.join(", "))
+        },
+    }
+}
+
+fn to_go(value: &Value, field: Option<&FieldDescriptorProto>) -> String {
+    match value {
+        Value::Int { value, .. } => value.to_string(),
+        Value::Str(s) => quoted(s),
+        Value::List(values) => {
+            let element = match (values.first(), field) {
+                (Some(Value::Message { message, .. }), _) => format!("*pb.{}", message.local_name.replace('.', "_")),
+                (_, Some(field)) if field.r#type() == Type::String => "string".to_string(),
+                _ => "int64".to_string(),
+            };
+            format!("[]{}{{{}}}", element, values.iter().map(|value| to_go(value, None)).collect::<Vec<_>>().join(", "))
+        },
+        Value::Message { message, fields } => {
+            let fields: Vec<_> = fields
+                .iter()
+                .map(|(field, value)| format!("{}: {}", camel_case(field.name(), true), to_go(value, Some(field))))
+                .collect();
+            format!("&pb.{}{{{}}}", message.local_name.replace('.', "_"), fields.join(", "))
+        },
+    }
+}
+
+
+fn grpcurl(options: &Options, method: &MethodDescriptorProto, request: &Value) -> String {
+    let json = serde_json::to_string(&to_json(request)).unwrap();
+    // Streams are sent as several messages in a row.
+    let data = if method.client_streaming() { format!("{} {}", json, json) } else { json };
+
+    format!(
+        "# {}\ngrpcurl -cacert {} -authority {} -protoset \"$DIR/routeguide.bin\" \\\n    -H 'authorization: Bearer {}' \\\n    -d '{}' \\\n    {} {}/{}\n",
+        method.name(),
+        options.ca_file,
+        options.domain,
+        options.token,
+        data.replace('\'', "'\\''"),
+        options.address,
+        options.service,
+        method.name(),
+    )
+}
+
+fn python(options: &Options, file: &FileDescriptorProto, service: &str, method: &MethodDescriptorProto, request: &Value) -> String {
+    let (package, module) = python_module(file);
+    let grpc_module = format!("{}_grpc", module);
+    let import = if package.is_empty() {
+        format!("import {}\nimport {}", module, grpc_module)
+    } else {
+        format!("from {} import {}, {}", package, module, grpc_module)
+    };
+    let request = to_python(request);
+
+    let call = match (method.client_streaming(), method.server_streaming()) {
+        (false, false) => format!("response = stub.{}({}, metadata=METADATA)\nprint(response)", method.name(), request),
+        (false, true) => format!("for response in stub.{}({}, metadata=METADATA):\n    print(response)", method.name(), request),
+        (true, false) => format!(
+            "requests = [{}, {}]\nresponse = stub.{}(iter(requests), metadata=METADATA)\nprint(response)",
+            request, request, method.name()
+        ),
+        (true, true) => format!(
+            "requests = [{}]\nfor response in stub.{}(iter(requests), metadata=METADATA):\n    print(response)",
+            request, method.name()
+        ),
+    };
+
+    format!(
+        r#"# {service}.{method} against the route guide demo server.
+#
+# Generate the modules with:
+#   python -m grpc_tools.protoc -Iproto --python_out=. --grpc_python_out=. {proto}
+import grpc
+
+{import}
+
+ADDRESS = {address}
+METADATA = [("authorization", "Bearer {token}")]
+
+
+def main():
+    with open({ca_file}, "rb") as ca:
+        credentials = grpc.ssl_channel_credentials(ca.read())
+    options = (("grpc.ssl_target_name_override", {domain}),)
+    with grpc.secure_channel(ADDRESS, credentials, options=options) as channel:
+        stub = {grpc_module}.{service}Stub(channel)
+{call}
+
+
+if __name__ == "__main__":
+    main()
+"#,
+        service = service,
+        method = method.name(),
+        proto = file.name(),
+        import = import,
+        address = quoted(&options.address),
+        token = options.token,
+        ca_file = quoted(&options.ca_file),
+        domain = quoted(&options.domain),
+        grpc_module = grpc_module,
+        call = prefix_lines(&call, "        "),
+    )
+}
+
+fn go(options: &Options, file: &FileDescriptorProto, service: &str, method: &MethodDescriptorProto, request: &Value) -> String {
+    let request = to_go(request, None);
+    let (call, needs_io) = match (method.client_streaming(), method.server_streaming()) {
+        (false, false) => (format!(
+            "response, err := client.{}(ctx, {})\nif err != nil {{\n\tlog.Fatal(err)\n}}\nfmt.Println(response)",
+            method.name(), request,
+        ), false),
+        (false, true) => (format!(
+            "stream, err := client.{}(ctx, {})\nif err != nil {{\n\tlog.Fatal(err)\n}}\n{}",
+            method.name(), request, GO_RECEIVE_LOOP,
+        ), true),
+        (true, false) => (format!(
+            "stream, err := client.{}(ctx)\nif err != nil {{\n\tlog.Fatal(err)\n}}\n\
+             for i := 0; i < 2; i++ {{\n\tif err := stream.Send({}); err != nil {{\n\t\tlog.Fatal(err)\n\t}}\n}}\n\
+             response, err := stream.CloseAndRecv()\nif err != nil {{\n\tlog.Fatal(err)\n}}\nfmt.Println(response)",
+            method.name(), request,
+        ), false),
+        (true, true) => (format!(
+            "stream, err := client.{}(ctx)\nif err != nil {{\n\tlog.Fatal(err)\n}}\n\
+             if err := stream.Send({}); err != nil {{\n\tlog.Fatal(err)\n}}\nstream.CloseSend()\n{}",
+            method.name(), request, GO_RECEIVE_LOOP,
+        ), true),
+    };
+    let io = if needs_io { "\t\"io\"\n" } else { "" };
+
+    format!(
+        r#"// {service}.{method} against the route guide demo server.
+//
+// Generate the package with:
+//   protoc -Iproto --go_out=. --go-grpc_out=. {proto}
+package main
+
+import (
+	"context"
+	"fmt"
+{io}	"log"
+
+	"google.golang.org/grpc"
+	"google.golang.org/grpc/credentials"
+	"google.golang.org/grpc/metadata"
+
+	pb {package}
+)
+
+func main() {{
+	creds, err := credentials.NewClientTLSFromFile({ca_file}, {domain})
+	if err != nil {{
+		log.Fatal(err)
+	}}
+	conn, err := grpc.Dial({address}, grpc.WithTransportCredentials(creds))
+	if err != nil {{
+		log.Fatal(err)
+	}}
+	defer conn.Close()
+
+	client := pb.New{service}Client(conn)
+	ctx := metadata.AppendToOutgoingContext(context.Background(), "authorization", "Bearer {token}")
+
+{call}}}
+"#,
+        service = service,
+        method = method.name(),
+        proto = file.name(),
+        io = io,
+        package = quoted(&format!("{}/{}", options.go_module, file.package().replace('.', "/"))),
+        ca_file = quoted(&options.ca_file),
+        domain = quoted(&options.domain),
+        address = quoted(&options.address),
+        token = options.token,
+        call = prefix_lines(&call, "\t"),
+    )
+}
+
+const GO_RECEIVE_LOOP: &str = "for {\n\tresponse, err := stream.Recv()\n\tif err == io.EOF {\n\t\tbreak\n\t}\n\
+    \tif err != nil {\n\t\tlog.Fatal(err)\n\t}\n\tfmt.Println(response)\n}";
+
+fn prefix_lines(text: &str, prefix: &str) -> String {
+    text.lines().map(|line| format!("{}{}\n", prefix, line)).collect()
+}
+
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let options = Options::from_args();
+
+    let bytes = match &options.descriptor_set {
+        Some(path) => fs::read(path)?,
+        None => route_guide::FILE_DESCRIPTOR_SET.to_vec(),
+    };
+    let set = FileDescriptorSet::decode(&bytes[..])?;
+    let protos = Protos::new(&set);
+
+    let (package, service_name) = match options.service.rfind('.') {
+        Some(i) => (&options.service[..i], &options.service[i + 1..]),
+        None => ("", options.service.as_str()),
+    };
+    let (file, service) = set
+        .file
+        .iter()
+        .filter(|file| file.package() == package)
+        .find_map(|file| file.service.iter().find(|service| service.name() == service_name).map(|service| (file, service)))
+        .ok_or_else(|| format!("no service {} in the descriptor set", options.service))?;
+
+    fs::create_dir_all(options.out.join("python"))?;
+    fs::write(options.out.join("routeguide.bin"), &bytes)?;
+
+    let mut script = String::from("#!/bin/sh\n# Run from the repository root, with the demo server running.\nDIR=$(dirname \"$0\")\n\n");
+    for method in &service.method {
+        let input = protos
+            .message(method.input_type())
+            .ok_or_else(|| format!("{} takes the unknown type {}", method.name(), method.input_type()))?;
+        let request = payload(&protos, input, "", 0);
+        let name = snake_case(method.name());
+
+        script.push_str(&grpcurl(&options, method, &request));
+        script.push('\n');
+
+        fs::write(options.out.join("python").join(format!("{}.py", name)), python(&options, file, service_name, method, &request))?;
+
+        let go_dir = options.out.join("go").join(&name);
+        fs::create_dir_all(&go_dir)?;
+        fs::write(go_dir.join("main.go"), go(&options, file, service_name, method, &request))?;
+    }
+    fs::write(options.out.join("grpcurl.sh"), script)?;
+
+    println!("Wrote samples for {} method(s) of {} to {}", service.method.len(), options.service, options.out.display());
+    Ok(())
+}
@@ -14,6 +14,7 @@ use std::future::Future;
 use hyper::{Body, Request, Response};  // @CHANGED: Removed Server in favor for tonic::transport::Server.
 use hyper::{Method, StatusCode};
 
+use bytes::BytesMut;
 use futures::TryStreamExt as _;
 
 // @NEW
@@ -22,7 +23,7 @@ use tonic::body::BoxBody;
 use tower::Service;
 
 // @NEW
-#[derive(Debug, Copy, Clone, Send)]
+#[derive(Debug, Copy, Clone)]
 struct CustomService {}
 
 
@@ -34,12 +35,13 @@ impl NamedService for CustomService {
 // Needs to implement the types Response, Error and Future, and the functions poll_ready and call.
 impl Service<Request<Body>> for CustomService
 {
-    type Response = Response<Body>;
+    type Response = Response<BoxBody>;  // tonic's server takes its own body type.
     type Error    = hyper::Error;
     // Pin guarantees the heap allocated pointer (a.k.a. Box type) is fixed to its memory address,
     // i.e. it cannot be moved. So this Future is a heap allocated pointer with a fixed memory
     // address pointing to a future that contains a Result of either a Response or an Error.
-    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+    // It must also be `Send`, as tonic runs it on any thread.
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
     fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         Poll::Ready(Ok(()))
@@ -47,7 +49,7 @@ impl Service<Request<Body>> for CustomService
 
     fn call(&mut self, request: Request<Body>) -> Self::Future {
         let response = service(request);
-        Box::pin(response)
+        Box::pin(async move { Ok(response.await?.map(BoxBody::map_from)) })
     }
 }
 
@@ -75,12 +77,15 @@ async fn reverse_response(request: Request<Body>) -> Result<Body, hyper::Error>
 
 
 fn uppercase_response(request: Request<Body>) -> Body {
+    // The buffer is reused as in hyper_server_05.
+    let mut buffer = BytesMut::new();
     let mapping = request
         .into_body()
-        .map_ok(|chunk| {
-            chunk.iter()
-                .map(|byte| byte.to_ascii_uppercase())
-                .collect::<Vec<u8>>()
+        .map_ok(move |chunk| {
+            buffer.reserve(chunk.len());
+            buffer.extend_from_slice(&chunk);
+            buffer.make_ascii_uppercase();
+            buffer.split().freeze()
         });
 
     // Use `Body::wrap_stream` to convert it to a `Body`...
@@ -122,12 +127,11 @@ async fn main() {
     // @CHANGED: https://docs.rs/tonic/0.3.0/tonic/transport/server/struct.Server.html
     let server = Server::builder().       // Create a new server builder that can configure a Server.
         add_service(CustomService{}).     // Returns a Router that routes to the service.
-        serve(address).await;             // Serves the Server.
+        serve_with_shutdown(address, shutdown_signal());  // Serves it until the signal.
 
 
-    // And now add a graceful shutdown signal and wait.
-    let graceful = server.with_graceful_shutdown(shutdown_signal());
-    if let Err(e) = graceful.await {
+    // And wait.
+    if let Err(e) = server.await {
         eprintln!("server error: {}", e);
     }
 }
\ No newline at end of file
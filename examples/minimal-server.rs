@@ -0,0 +1,22 @@
+//! The RouteGuide service with the embedded features, and nothing else. Built without the
+//! default features it needs none of the optional dependencies, for small static binaries on
+//! edge machines; see the README.
+//!
+//! Listens on the address given as its only argument, `[::]:50051` by default.
+
+use rust_server::minimal::MinimalRouteGuide;
+use rust_server::route_guide::route_guide_server::RouteGuideServer;
+use tonic::transport::Server;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let addr = std::env::args().nth(1).unwrap_or_else(|| "[::]:50051".to_string()).parse()?;
+    let service = MinimalRouteGuide::embedded()?;
+
+    println!("RouteGuide with {} features listening on {}", service.len(), addr);
+    Server::builder()
+        .add_service(RouteGuideServer::new(service))
+        .serve(addr)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,194 @@
+/*
+-- Replays calls recorded with `tonic-server --record <file>` --
+
+The calls are sent at the recorded pacing (scaled by --speed) and the number of responses and
+final status of each call are compared with the recording.
+
+    cargo run --example replay -- recording.jsonl --token 1234
+*/
+use std::collections::BTreeMap;
+use std::process;
+use std::time::Duration;
+
+use bytes::Bytes;
+use structopt::StructOpt;
+use tokio::time::{delay_until, Instant};
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::metadata::{MetadataKey, MetadataValue};
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Code, Request, Status};
+
+use rust_server::client_tls::ClientTlsOptions;
+use rust_server::recording::{self, Event, RawCodec};
+
+
+#[derive(Debug, StructOpt)]
+struct Options {
+    /// The recording to replay.
+    recording: String,
+
+    #[structopt(long, default_value = "http://[::1]:50051")]
+    endpoint: String,
+
+    /// Recorded credentials are redacted, so calls need a token to authenticate with.
+    #[structopt(long)]
+    token: Option<String>,
+
+    /// Pacing factor: 2 replays twice as fast, 0 sends everything immediately.
+    #[structopt(long, default_value = "1")]
+    speed: f64,
+
+    #[structopt(long, default_value = "data/tls/ca.pem")]
+    ca_file: String,
+
+    #[structopt(long, default_value = "example.com")]
+    domain: String,
+}
+
+
+/// Headers that are set by the transport itself.
+const SKIPPED_HEADERS: &[&str] = &["content-type", "te", "user-agent", "grpc-accept-encoding", "grpc-encoding"];
+
+#[derive(Debug, Default)]
+struct RecordedCall {
+    path: String,
+    start_ms: u64,
+    metadata: Vec<(String, String)>,
+    requests: Vec<(u64, Bytes)>,
+    responses: usize,
+    status: Option<i32>,
+}
+
+fn group_calls(events: Vec<Event>) -> Result<BTreeMap<u64, RecordedCall>, Box<dyn std::error::Error>> {
+    let mut calls: BTreeMap<u64, RecordedCall> = BTreeMap::new();
+
+    for event in events {
+        let call = calls.entry(event.call()).or_default();
+        match event {
+            Event::Start { at_ms, path, metadata, .. } => {
+                call.path = path;
+                call.start_ms = at_ms;
+                call.metadata = metadata;
+            },
+            Event::Request { at_ms, message, .. } => call.requests.push((at_ms, base64::decode(&message)?.into())),
+            Event::Response { .. } => call.responses += 1,
+            Event::End { status, .. } => call.status = status,
+        }
+    }
+
+    // Times are relative to when the recording started; replay from the first call instead.
+    let origin = calls.values().map(|call| call.start_ms).min().unwrap_or(0);
+    for call in calls.values_mut() {
+        call.start_ms -= origin;
+        for (at_ms, _) in &mut call.requests {
+            *at_ms = at_ms.saturating_sub(origin);
+        }
+    }
+
+    Ok(calls)
+}
+
+fn scaled(ms: u64, speed: f64) -> Duration {
+    if speed <= 0.0 {
+        Duration::from_millis(0)
+    } else {
+        Duration::from_secs_f64(ms as f64 / 1000.0 / speed)
+    }
+}
+
+
+async fn replay_call(channel: Channel, call: RecordedCall, start: Instant, options: &Options) -> Result<usize, Status> {
+    let mut grpc = tonic::client::Grpc::new(channel);
+    grpc.ready().await.map_err(|e| Status::unavailable(format!("service not ready: {}", e)))?;
+
+    let speed = options.speed;
+    let requests = call.requests;
+    let outbound = async_stream::stream! {
+        for (at_ms, message) in requests {
+            delay_until(start + scaled(at_ms, speed)).await;
+            yield message;
+        }
+    };
+
+    let mut request = Request::new(outbound);
+    for (key, value) in &call.metadata {
+        if key.starts_with(':') || SKIPPED_HEADERS.contains(&key.as_str()) || value == "<redacted>" {
+            continue;
+        }
+        if let (Ok(key), Ok(value)) = (MetadataKey::from_bytes(key.as_bytes()), MetadataValue::from_str(value)) {
+            request.metadata_mut().insert(key, value);
+        }
+    }
+    if let Some(token) = &options.token {
+        let value = MetadataValue::from_str(&format!("Bearer {}", token))
+            .map_err(|_| Status::invalid_argument("token is not valid metadata"))?;
+        request.metadata_mut().insert("authorization", value);
+    }
+
+    let path: PathAndQuery = call.path.parse().map_err(|_| Status::invalid_argument("invalid path in recording"))?;
+    let mut inbound = grpc.streaming(request, path, RawCodec).await?.into_inner();
+
+    let mut responses = 0;
+    while inbound.message().await?.is_some() {
+        responses += 1;
+    }
+
+    Ok(responses)
+}
+
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let options = Options::from_args();
+    let calls = group_calls(recording::read_recording(&options.recording)?)?;
+
+    let tls = ClientTlsOptions {
+        ca_file: Some(options.ca_file.clone()),
+        domain: Some(options.domain.clone()),
+        ..Default::default()
+    }.build().await?;
+    let channel = Endpoint::from_shared(options.endpoint.clone())?
+        .tls_config(tls)?
+        .connect()
+        .await?;
+
+    let start = Instant::now();
+    let mut replays = vec![];
+    for (id, call) in calls {
+        let channel = channel.clone();
+        let call_start = start + scaled(call.start_ms, options.speed);
+        let options = &options;
+
+        replays.push(async move {
+            delay_until(call_start).await;
+            let path = call.path.clone();
+            let expected = (call.responses, call.status.unwrap_or(0));
+            let result = replay_call(channel, call, start, options).await;
+            (id, path, expected, result)
+        });
+    }
+
+    let mut mismatches = 0;
+    for (id, path, (expected_responses, expected_status), result) in futures::future::join_all(replays).await {
+        let (responses, status) = match &result {
+            Ok(responses) => (*responses, Code::Ok as i32),
+            Err(status) => (0, status.code() as i32),
+        };
+        let matches = responses == expected_responses && status == expected_status;
+        if !matches {
+            mismatches += 1;
+        }
+
+        println!(
+            "{} call {} {}: {} response(s), status {} (recorded {} response(s), status {})",
+            if matches { "OK  " } else { "DIFF" }, id, path, responses, status, expected_responses, expected_status
+        );
+    }
+
+    if mismatches > 0 {
+        eprintln!("{} call(s) differ from the recording", mismatches);
+        process::exit(1);
+    }
+
+    Ok(())
+}
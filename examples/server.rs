@@ -47,7 +47,8 @@ impl ThreadPool {
 impl Drop for ThreadPool {
     fn drop(&mut self) {
         for _ in &self.workers {
-            self.sender.send(Terminate);
+            // A worker that already stopped has dropped its receiver; nothing to tell it.
+            let _ = self.sender.send(Terminate);
         }
 
         for worker in &mut self.workers {
@@ -104,11 +105,11 @@ fn main() -> std::io::Result<()> {
 fn handle_connection(mut stream: TcpStream) {
     let mut buffer = [0; 1024];
 
-    stream.read(&mut buffer).unwrap();
+    let read = stream.read(&mut buffer).unwrap();
 
     // The “lossy” part of the name indicates the behavior of this function when it sees an
     // invalid UTF-8 sequence: it will replace the invalid sequence with �.
-    println!("Request: {}", String::from_utf8_lossy(&buffer[..]));
+    println!("Request: {}", String::from_utf8_lossy(&buffer[..read]));
 
     let get = b"GET / HTTP/1.1\r\n";
     let (status, filename) = if buffer.starts_with(get) {
@@ -120,6 +121,6 @@ fn handle_connection(mut stream: TcpStream) {
     let contents = fs::read_to_string(filename).unwrap();
     let response = format!("HTTP/1.1 {}\r\n\r\n{}", status, contents);
 
-    stream.write(response.as_bytes()).unwrap();
+    stream.write_all(response.as_bytes()).unwrap();
     stream.flush().unwrap();
 }
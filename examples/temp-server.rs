@@ -36,7 +36,7 @@ async fn twiddle_service_status(mut reporter: HealthReporter) {
         iter += 1;
         delay_for(Duration::from_secs(1)).await;
 
-        if iter % 2 == 0 {
+        if iter.is_multiple_of(2) {
             reporter.set_serving::<GreeterServer<MyGreeter>>().await;
         } else {
             reporter.set_not_serving::<GreeterServer<MyGreeter>>().await;
@@ -1,10 +1,14 @@
+// For the `select!` in route_chat's stream.
+#![recursion_limit = "1024"]
+// Handlers fail with tonic's `Status`, as in the library.
+#![allow(clippy::result_large_err)]
+
 use std::{
-    collections::HashMap,
-    hash::{Hasher, Hash},
     task::{Context, Poll},
+    future::Future,
+    net::SocketAddr,
     pin::Pin,
     sync::Arc,
-    time::Instant,
 };
 
 use futures_util::StreamExt;
@@ -14,77 +18,398 @@ use hyper::{Body, Request as HyperRequest, Response as HyperResponse};
 
 use tower::Service;
 
-use tokio::sync::mpsc;
+use structopt::StructOpt;
+
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, watch};
 
 use tonic::{Request, Response, Status, metadata::MetadataValue};
 use tonic::body::BoxBody;
-use tonic::transport::{Identity, Server, NamedService, ServerTlsConfig};
-
-
-
-// Generated from .proto file.
-pub mod route_guide {tonic::include_proto!("route_guide"); /* The string must match the proto package name */}
-use route_guide::route_guide_server::{RouteGuide, RouteGuideServer};
-use route_guide::{Feature, Point, Rectangle, RouteNote, RouteSummary};
-
-#[path = "../src/data.rs"] mod data;
-
-
-impl Hash for Point {
-    fn hash<H>(&self, state: &mut H) where H: Hasher {
-        self.latitude.hash(state);
-        self.longitude.hash(state);
-    }
-}
-
-impl Eq for Point {}
-
-fn in_range(point: &Point, rect: &Rectangle) -> bool {
-    use std::cmp;
-
-    let lo = rect.lo.as_ref().unwrap();
-    let hi = rect.hi.as_ref().unwrap();
-
-    let left = cmp::min(lo.longitude, hi.longitude);
-    let right = cmp::max(lo.longitude, hi.longitude);
-    let top = cmp::max(lo.latitude, hi.latitude);
-    let bottom = cmp::min(lo.latitude, hi.latitude);
-
-    point.longitude >= left
-        && point.longitude <= right
-        && point.latitude >= bottom
-        && point.latitude <= top
-}
+use tonic::transport::{Certificate, Endpoint, Identity, Server, NamedService, ServerTlsConfig};
 
-/// Calculates the distance between two points using the "haversine" formula.
-/// This code was taken from http://www.movable-type.co.uk/scripts/latlong.html.
-fn get_distance(p1: &Point, p2: &Point) -> i32 {
-    const CORD_FACTOR: f64 = 1e7;
-    const R: f64 = 6_371_000.0; // meters
 
-    let lat1 = p1.latitude as f64 / CORD_FACTOR;
-    let lat2 = p2.latitude as f64 / CORD_FACTOR;
-    let lng1 = p1.longitude as f64 / CORD_FACTOR;
-    let lng2 = p2.longitude as f64 / CORD_FACTOR;
 
-    let lat_rad1 = lat1.to_radians();
-    let lat_rad2 = lat2.to_radians();
-
-    let delta_lat = (lat2 - lat1).to_radians();
-    let delta_lng = (lng2 - lng1).to_radians();
-
-    let a = (delta_lat / 2f64).sin() * (delta_lat / 2f64).sin()
-        + (lat_rad1).cos() * (lat_rad2).cos() * (delta_lng / 2f64).sin() * (delta_lng / 2f64).sin();
-
-    let c = 2f64 * a.sqrt().atan2((1f64 - a).sqrt());
+// Generated from the .proto files.
+use rust_server::route_guide::route_guide_client::RouteGuideClient;
+use rust_server::route_guide::route_guide_server::{RouteGuide, RouteGuideServer};
+use rust_server::route_guide::{
+    self, ChangeEvent, ClientConfig, ExportRequest, Feature, FeatureChunk, ImportSummary, Point, PointWithTimestamp,
+    Rectangle, ReplicateRequest, ReplicationEvent, Room, RoomMembers, RouteNote, RouteSummary, TimeRange,
+};
+use rust_server::admin::tenant_admin_server::{TenantAdmin, TenantAdminServer};
+use rust_server::admin::{
+    ChatMutes, Connection, ErrorBudgets, GetErrorBudgetsRequest, GetIpFilterRequest, GetLoadSheddingRequest, GetLogFilterRequest, GetQuotaRequest, IpFilter,
+    ListAuditEntriesRequest, ListAuditEntriesResponse, ListChatMutesRequest, ListConnectionsRequest, ListConnectionsResponse,
+    ListTenantsRequest, ListTenantsResponse, LoadShedding, LogFilter, ProvisionTenantRequest, Quota, SetChatMuteRequest, Tenant,
+};
 
-    (R * c) as i32
+use rust_server::{chat, conditional, connections, data, export, gateway, geo, history, i18n, idempotency, import, lifecycle, log_filter, metrics, replica, request_context, route_spool, runtime_metrics, scan_report};
+#[cfg(feature = "sqlite")]
+use rust_server::note_store;
+use rust_server::audit::{AuditFilter, AuditLog, FileAuditLog, MemoryAuditLog};
+use rust_server::chat_hub::{HubConfig, SlowConsumerPolicy};
+use rust_server::client_config::{self, ClientConfigs};
+use rust_server::client_tls::ClientTlsOptions;
+use rust_server::codec::{self, BufferSize, BufferSizes};
+use rust_server::cors::{AllowedOrigins, Cors};
+use rust_server::event_bus::{ConfigReloaded, Event, EventBus, EventMetrics};
+use rust_server::field_mask::FeatureMask;
+use rust_server::grpc_compression::ServerCompression;
+use rust_server::hooks::{NoHooks, RouteGuideHooks};
+use rust_server::idempotency::IdempotencyCache;
+use rust_server::ids::IdScheme;
+use rust_server::introspection::{Introspected, IntrospectionConfig, Introspector, UnreachablePolicy};
+use rust_server::ip_filter::{self, Cidr, IpRules};
+use rust_server::latency_budget::{Budgeted, LatencyBudget, LatencyBudgets};
+use rust_server::lifecycle::{Lifecycle, State};
+use rust_server::multiplex::{self, GrpcRoutes, Multiplexer, PortMode};
+use rust_server::load_shed::{AdaptiveLimiter, LoadShedService, ShedConfig};
+use rust_server::message_metrics::MessageMetrics;
+use rust_server::moderation::{self, BannedWordAction, BannedWords, LengthLimit, Moderation, MuteList};
+use rust_server::note_store::{FileNoteStore, MemoryNoteStore, NoteStore, RetentionPolicy};
+use rust_server::panics::{self, CatchPanic};
+use rust_server::policy::{Authorized, PolicyEngine, Resource};
+use rust_server::quota::{FileQuotaStore, MemoryQuotaStore, QuotaLimits, QuotaStore, QuotaTracker, Quotas};
+use rust_server::recorder::{GuardPolicy, RecorderLimits, RouteRecorder};
+use rust_server::recording::{Recorder, RecordingService};
+use rust_server::reload::{self, Rebind, ServerConfig, TlsFiles};
+use rust_server::request_context::RequestContext;
+use rust_server::scan_report::{FeatureSource, ScanReport};
+use rust_server::service_alias::{AliasName, ServiceAlias};
+use rust_server::shutdown::ShutdownHooks;
+use rust_server::slo::{Slo, SloConfig, SloService, SloTracker};
+use rust_server::storage_metrics::Instrumented;
+use rust_server::tasks::TaskTracker;
+use rust_server::tenant::{TenantData, TenantId, Tenants};
+use rust_server::validation::{self, Validated};
+use rust_server::wal::{self, FeatureWal};
+
+
+#[derive(Debug, StructOpt)]
+struct Options {
+    /// The feature database, or `embedded` for the one compiled in with the embedded-db feature.
+    #[structopt(long, default_value = data::DEFAULT_SOURCE)]
+    data: String,
+
+    /// What to do with invalid records in the database: refuse to start, or skip them.
+    #[structopt(long, default_value = "refuse")]
+    on_invalid_data: data::InvalidDataPolicy,
+
+    /// Make changes to the features durable: log them to `<data>.wal` before acknowledging
+    /// them, replay the log on startup and write the features back into `--data` now and then.
+    #[structopt(long)]
+    wal: bool,
+
+    /// With `--wal`, how often to write the features back into the database and empty the log.
+    #[structopt(long, default_value = "60")]
+    checkpoint_secs: u64,
+
+    /// How features are given ids: `ulid`, or `sequential` for ids that are the same on every
+    /// run.
+    #[structopt(long, default_value = "ulid")]
+    feature_ids: IdScheme,
+
+    /// Where to serve `GET /metrics`.
+    #[structopt(long, default_value = "127.0.0.1:9090")]
+    metrics_address: std::net::SocketAddr,
+
+    /// Where to serve the REST API (`GET /features`).
+    #[structopt(long, default_value = "127.0.0.1:8080")]
+    gateway_address: std::net::SocketAddr,
+
+    /// Also serve gRPC, gRPC-Web and the REST API together on this address. Connections can be
+    /// TLS, with HTTP/2 or HTTP/1.1 picked in ALPN, or plaintext.
+    #[structopt(long)]
+    shared_address: Option<std::net::SocketAddr>,
+
+    /// What the shared address accepts: tls, h2c (plaintext HTTP/2 with prior knowledge and
+    /// HTTP/1.1, for behind a proxy that terminates TLS) or auto, either one.
+    #[structopt(long, default_value = "auto")]
+    shared_port_mode: PortMode,
+
+    /// Browser origins allowed to call the REST API, e.g. https://maps.example.com, or * for any.
+    /// Can be given several times.
+    #[structopt(long = "cors-origin")]
+    cors_origins: Vec<String>,
+
+    /// Encode buffer size for a method's messages, as METHOD=BYTES, like ListFeatures=65536.
+    /// Added to the recommended sizes; 0 lets the buffer grow only as messages need. Can be given
+    /// several times.
+    #[structopt(long = "codec-buffer")]
+    codec_buffers: Vec<BufferSize>,
+
+    /// How long browsers may cache CORS preflight answers.
+    #[structopt(long)]
+    cors_max_age_secs: Option<u64>,
+
+    /// Allow cross-origin requests with credentials.
+    #[structopt(long)]
+    cors_credentials: bool,
+
+    /// Where to serve the `/livez` and `/readyz` probes.
+    #[structopt(long, default_value = "127.0.0.1:8081")]
+    probe_address: std::net::SocketAddr,
+
+    /// After CTRL+C, how long to keep answering while traffic moves elsewhere. Also how long a
+    /// listener replaced by a reload lets its connections finish their calls.
+    #[structopt(long, default_value = "5")]
+    drain_secs: u64,
+
+    /// Directory to persist RouteChat history in. Kept in memory if not given.
+    #[structopt(long)]
+    chat_history: Option<String>,
+
+    /// SQLite database to persist RouteChat history in, instead of `--chat-history`.
+    #[cfg(feature = "sqlite")]
+    #[structopt(long, conflicts_with = "chat-history")]
+    chat_db: Option<String>,
+
+    /// File to keep the audit log of feature changes in. Kept in memory if not given.
+    #[structopt(long)]
+    audit_log: Option<String>,
+
+    /// The most RouteGuide calls each subject may make per UTC day.
+    #[structopt(long)]
+    quota_daily_rpcs: Option<u64>,
+
+    /// The most RouteGuide calls each subject may make per calendar month.
+    #[structopt(long)]
+    quota_monthly_rpcs: Option<u64>,
+
+    /// The most bytes of messages sent to each subject per UTC day.
+    #[structopt(long)]
+    quota_daily_bytes: Option<u64>,
+
+    /// The most bytes of messages sent to each subject per calendar month.
+    #[structopt(long)]
+    quota_monthly_bytes: Option<u64>,
+
+    /// File to keep quota usage in across restarts. Kept in memory if not given.
+    #[structopt(long)]
+    quota_file: Option<String>,
+
+    /// OAuth2 token introspection endpoint (RFC 7662) to check bearer tokens that aren't a
+    /// tenant's own with, like https://auth.example.com/oauth2/introspect.
+    #[structopt(long)]
+    introspection_url: Option<hyper::Uri>,
+
+    /// Client id the server introspects as, with the secret from `--introspection-secret-file`.
+    #[structopt(long, default_value = "")]
+    introspection_client_id: String,
+
+    #[structopt(long)]
+    introspection_secret_file: Option<String>,
+
+    /// The claim of an introspected token that names its tenant.
+    #[structopt(long, default_value = "tenant")]
+    introspection_tenant_claim: String,
+
+    /// How long an introspected token is trusted before asking again, at most until it expires.
+    #[structopt(long, default_value = "60")]
+    introspection_cache_secs: u64,
+
+    /// What to do while the endpoint can't be reached: reject, or local-jwt to accept HS256
+    /// JWTs signed with `--jwt-secret-file`.
+    #[structopt(long, default_value = "reject")]
+    introspection_unreachable: UnreachablePolicy,
+
+    #[structopt(long)]
+    jwt_secret_file: Option<String>,
+
+    /// Drop chat notes older than this many seconds.
+    #[structopt(long)]
+    chat_ttl_secs: Option<u64>,
+
+    /// Keep at most this many notes per location.
+    #[structopt(long)]
+    chat_max_notes: Option<usize>,
+
+    /// How many RouteChat notes from others may wait for a participant that reads slowly.
+    #[structopt(long, default_value = "64")]
+    chat_queue_size: usize,
+
+    /// What to do when a participant's queue is full: drop-oldest, drop-newest or disconnect
+    /// (the call fails with RESOURCE_EXHAUSTED).
+    #[structopt(long, default_value = "drop-oldest")]
+    chat_slow_consumer: SlowConsumerPolicy,
+
+    /// Notes kept per RouteChat room, for those who join it later; 0 keeps none.
+    #[structopt(long, default_value = "100")]
+    chat_room_history: usize,
+
+    /// RouteChat rooms per tenant. Rooms nobody is in are forgotten, the one posted to longest
+    /// ago first, to make more.
+    #[structopt(long, default_value = "1000")]
+    chat_max_rooms: usize,
+
+    /// Send a heartbeat on a RouteChat stream after this many seconds without traffic; 0 turns
+    /// heartbeats off.
+    #[structopt(long, default_value = "15")]
+    chat_heartbeat_secs: u64,
+
+    /// Close a RouteChat stream after this many heartbeat intervals without a word from a
+    /// client that answers heartbeats.
+    #[structopt(long, default_value = "3")]
+    chat_missed_heartbeats: u32,
+
+    /// Reject RouteChat notes longer than this many characters.
+    #[structopt(long)]
+    chat_max_length: Option<usize>,
+
+    /// File of words that mustn't be posted to RouteChat, one per line.
+    #[structopt(long)]
+    chat_banned_words: Option<String>,
+
+    /// What to do with a note that has a banned word: redact or reject.
+    #[structopt(long, default_value = "redact")]
+    chat_banned_action: BannedWordAction,
+
+    /// PEM file with the CA that signs client certificates. Turns on mutual TLS; a client whose
+    /// certificate names a tenant (common name or alternative name) needs no token. Not with
+    /// --shared-address, which doesn't check certificates.
+    #[structopt(long)]
+    client_ca: Option<String>,
+
+    /// Record every RouteGuide call, with its messages, to this file for `replay`.
+    #[structopt(long)]
+    record: Option<String>,
+
+    /// Never reject RouteGuide calls because of load. Can be changed later through the admin
+    /// service.
+    #[structopt(long)]
+    no_load_shedding: bool,
+
+    /// On shutdown, write the final values of all metrics to this file, since nothing scrapes them
+    /// after the server is gone.
+    #[structopt(long)]
+    final_metrics: Option<String>,
+
+    /// Write the descriptors of the served protos to this file, for `grpcurl -protoset`, and exit.
+    #[structopt(long)]
+    write_descriptor_set: Option<String>,
+
+    /// The most RouteGuide calls to run at once; the limit adapts below this.
+    #[structopt(long, default_value = "1000")]
+    max_concurrency: usize,
+
+    /// Shrink the concurrency limit when calls take longer than this.
+    #[structopt(long, default_value = "250")]
+    target_latency_ms: u64,
+
+    /// File with the IP allow and deny lists: lines like `allow 10.0.0.0/8`, `deny
+    /// 10.1.0.0/16` or `trust 192.168.0.1` for a proxy setting x-forwarded-for. Everyone may
+    /// connect without one. Can be changed later through the admin service.
+    #[structopt(long)]
+    ip_rules: Option<String>,
+
+    /// The latency budget of a RouteGuide method, like GetFeature=100 in milliseconds; slower
+    /// calls are logged under the slow_rpc target. Replaces the method's default, 0 turns it
+    /// off. Can be given several times.
+    #[structopt(long = "latency-budget", number_of_values = 1)]
+    latency_budgets: Vec<LatencyBudget>,
+
+    /// The share of a RouteGuide method's calls that should succeed, like GetFeature=99.9, for
+    /// error budgets. Can be given several times.
+    #[structopt(long = "slo", number_of_values = 1)]
+    slos: Vec<Slo>,
+
+    /// How far back the success rates of `--slo` look.
+    #[structopt(long, default_value = "60")]
+    slo_window_mins: u64,
+
+    /// Reject calls to the methods without an `--slo` while an error budget is exhausted.
+    #[structopt(long)]
+    slo_degrade: bool,
+
+    /// The most points a recorded route may have.
+    #[structopt(long, default_value = "100000")]
+    route_max_points: u32,
+
+    /// How many bytes of a recorded route's path, kept for its polyline, stay in memory before
+    /// the rest is spooled to a temporary file.
+    #[structopt(long, default_value = "1048576")]
+    route_spool_bytes: usize,
+
+    /// Where route paths are spooled, instead of the system's temporary directory.
+    #[structopt(long)]
+    route_spool_dir: Option<String>,
+
+    /// The fastest a recorded route may move between two points, in metres per second.
+    #[structopt(long)]
+    route_max_speed: Option<f64>,
+
+    /// The most points a recorded route may have in any one second.
+    #[structopt(long)]
+    route_max_rate: Option<u32>,
+
+    /// The most times in a row a recorded route may send the same point again.
+    #[structopt(long)]
+    route_max_repeats: Option<u32>,
+
+    /// What to do with route points over --route-max-speed, --route-max-rate or
+    /// --route-max-repeats, in RecordRoute and the gateway: reject the upload, or filter them
+    /// out of the route.
+    #[structopt(long, default_value = "reject")]
+    route_guard: GuardPolicy,
+
+    /// File with the settings that can change while serving: the log filter, load shedding,
+    /// tokens, the gRPC listen addresses and their TLS files. Read again on SIGHUP; the
+    /// listeners are bound again, one at a time, if their addresses or TLS files changed.
+    #[structopt(long)]
+    config: Option<String>,
+
+    /// File with the rules deciding who may call which RouteGuide methods, like `deny
+    /// AddFeature subject=token:*` (see `policy::Policy`). Read again on SIGHUP. Everything is
+    /// allowed if not given.
+    #[structopt(long)]
+    policy: Option<String>,
+
+    /// Serve as a read replica of the default tenant of the primary at this URI, like
+    /// `http://primary:50051`, over TLS: its features are copied and kept up to date, and calls that
+    /// would change them fail.
+    #[structopt(long, conflicts_with = "wal")]
+    follow: Option<String>,
+
+    /// The primary's token for --follow.
+    #[structopt(long, default_value = "1234")]
+    follow_token: String,
+
+    /// CA certificate the primary's certificate is checked against, for --follow.
+    #[structopt(long, default_value = "data/tls/ca.pem")]
+    follow_ca: String,
+
+    /// Name to verify the primary's certificate against, for --follow.
+    #[structopt(long, default_value = "example.com")]
+    follow_domain: String,
+
+    /// How long to wait before following the primary again once replication breaks.
+    #[structopt(long, default_value = "5")]
+    follow_retry_secs: u64,
 }
 
 
 #[derive(Debug)]
 pub struct RouteGuideService {
-    features: Arc<Vec<Feature>>,
+    tenants: Arc<Tenants>,
+    limits: RecorderLimits,
+    heartbeats: Option<chat::Heartbeats>,
+    idempotency: Arc<IdempotencyCache<Feature>>,
+    route_idempotency: Arc<IdempotencyCache<RouteSummary>>,
+    tasks: TaskTracker,
+    moderation: Arc<Moderation>,
+    hooks: Arc<dyn RouteGuideHooks>,
+    client_configs: Arc<ClientConfigs>,
+    /// For what `Authorized` can't check before the call, like each imported feature.
+    policy: Arc<PolicyEngine>,
+}
+
+impl RouteGuideService {
+    /// The call's context, once the hooks let it go ahead.
+    fn before<T>(&self, request: &Request<T>, method: &str) -> Result<RequestContext, Status> {
+        let context = RequestContext::of(request)?;
+        self.hooks.before_call(&context, method)?;
+        Ok(context)
+    }
 }
 
 
@@ -92,29 +417,102 @@ pub struct RouteGuideService {
 impl RouteGuide for RouteGuideService {
     type ListFeaturesStream = mpsc::Receiver<Result<Feature, Status>>;
     type RouteChatStream = Pin<Box<dyn Stream<Item = Result<RouteNote, Status>> + Send + Sync + 'static>>;
+    type GetNotesAtStream = Pin<Box<dyn Stream<Item = Result<RouteNote, Status>> + Send + Sync + 'static>>;
+    type ListChangesStream = Pin<Box<dyn Stream<Item = Result<ChangeEvent, Status>> + Send + Sync + 'static>>;
+    type ExportFeaturesStream = Pin<Box<dyn Stream<Item = Result<FeatureChunk, Status>> + Send + Sync + 'static>>;
+    type ReplicateStream = Pin<Box<dyn Stream<Item = Result<ReplicationEvent, Status>> + Send + Sync + 'static>>;
+    type WatchConfigStream = Pin<Box<dyn Stream<Item = Result<ClientConfig, Status>> + Send + Sync + 'static>>;
+
+    async fn get_feature(&self, mut request: Request<Point>) -> Result<Response<Feature>, Status> {
+        let context = self.before(&request, "GetFeature")?;
+        let tenant = self.tenants.for_context(&context)?;
+        // Taken out so the point compares equal to the feature's location.
+        let mask = FeatureMask::parse(request.get_mut().read_mask.take().as_ref()).map_err(Status::invalid_argument)?;
+        let languages = i18n::Languages::of(&request);
+
+        let feature = match tenant.features().get(request.get_ref()) {
+            Some(feature) => mask.apply(languages.localize(feature.clone())),
+            None => Feature::default(),
+        };
+        self.hooks.on_get_feature(&context, request.get_ref(), &feature);
 
-    async fn get_feature(&self, request: Request<Point>) -> Result<Response<Feature>, Status> {
-        for feature in &self.features[..] {
-            if feature.location.as_ref() == Some(request.get_ref()) {
-                return Ok(Response::new(feature.clone()));
-            }
+        // Tile-refreshing clients revalidate what they have instead of downloading it again.
+        let etag = conditional::etag(&feature);
+        if conditional::matches(&request, &etag) {
+            return Err(conditional::not_modified(&etag));
         }
-
-        Ok(Response::new(Feature::default()))
+        let mut response = Response::new(feature);
+        if let Ok(value) = MetadataValue::from_str(&etag) {
+            response.metadata_mut().insert(conditional::ETAG_HEADER, value);
+        }
+        Ok(response)
     }
 
     async fn list_features(&self, request: Request<Rectangle>)
         -> Result<Response<Self::ListFeaturesStream>, Status> {
+        self.before(&request, "ListFeatures")?;
         let (mut tx, rx) = mpsc::channel(4);
-        let features = self.features.clone();
+        let features = self.tenants.scope(&request)?.features();
+        let mask = FeatureMask::parse(request.get_ref().read_mask.as_ref()).map_err(Status::invalid_argument)?;
+        let languages = i18n::Languages::of(&request);
+        let partial = scan_report::allows_partial(&request);
+        let open = (open_streams("ListFeatures").track(), connections::registry().track_stream(request.remote_addr()));
+
+        self.tasks.spawn("list_features", async move {
+            let _open = open;
+            let rect = request.get_ref();
+            if let Some(clustering) = &rect.cluster {
+                if features.in_rectangle(rect).count() > clustering.max_features as usize {
+                    for cluster in features.clusters(rect, clustering.zoom) {
+                        if tx.send(Ok(mask.apply(cluster))).await.is_err() {
+                            return;
+                        }
+                    }
+                    return;
+                }
+            }
 
-        tokio::spawn(async move {
-            for feature in &features[..] {
-                if in_range(feature.location.as_ref().unwrap(), request.get_ref()) {
-                    tx.send(Ok(feature.clone())).await.unwrap();
+            // Features go out in the same order every time, a band at a time and south first
+            // whether or not the call allows partial results, so a resumed stream skips up to and
+            // including the last one the client got.
+            let mut resuming = !rect.resume_after.is_empty();
+            let mut after_resume = |feature: &Feature| {
+                if !resuming {
+                    return true;
+                }
+                resuming = feature.id != rect.resume_after;
+                false
+            };
+
+            // With partial results a band that fails to read costs only its features. The stream
+            // still succeeds, with what was skipped in its trailers.
+            let mut report = ScanReport::default();
+            for band in scan_report::bands(rect, scan_report::MAX_BANDS) {
+                let batch = match features.read(&band) {
+                    Ok(batch) => batch,
+                    Err(status) if partial => {
+                        tracing::warn!(band = ?band, error = %status.message(), "ListFeatures skipped a band that failed to read");
+                        report.skip(band);
+                        continue;
+                    },
+                    Err(status) => {
+                        let _ = tx.send(Err(status)).await;
+                        return;
+                    },
+                };
+                for feature in batch.into_iter().filter(|feature| after_resume(feature)) {
+                    if tx.send(Ok(mask.apply(languages.localize(feature)))).await.is_err() {
+                        return;
+                    }
                 }
             }
-        });
+            if resuming {
+                let status = Status::failed_precondition(format!("can't resume after feature {}, it's gone", rect.resume_after));
+                let _ = tx.send(Err(status)).await;
+            } else if partial {
+                let _ = tx.send(Err(report.into_status())).await;
+            }
+        })?;
 
         Ok(Response::new(rx))
     }
@@ -123,30 +521,46 @@ impl RouteGuide for RouteGuideService {
         &self,
         request: Request<tonic::Streaming<Point>>,
     ) -> Result<Response<RouteSummary>, Status> {
+        let context = self.before(&request, "RecordRoute")?;
+        let tenant = self.tenants.for_context(&context)?;
+        let key = idempotency::key(&request)?.map(|key| format!("{}/{}", tenant.id, key));
+        let polyline = request.metadata().get(geo::POLYLINE_HEADER).and_then(|value| value.to_str().ok()) == Some("true");
         let mut stream = request.into_inner();
+        let limits = self.limits;
+
+        // The points arrive after the call starts, so the key alone names the route: a replayed
+        // route gets the summary of its first upload without its points being read.
+        let summary = self.route_idempotency.run(key, 0, || async move {
+            let mut recorder = RouteRecorder::new(tenant.features(), limits);
+            if polyline {
+                recorder = recorder.keep_path();
+            }
 
-        let mut summary = RouteSummary::default();
-        let mut last_point = None;
-        let now = Instant::now();
-
-        while let Some(point) = stream.next().await {
-            let point = point?;
-            summary.point_count += 1;
-
-            for feature in &self.features[..] {
-                if feature.location.as_ref() == Some(&point) {
-                    summary.feature_count += 1;
+            loop {
+                let next = match limits.idle_timeout {
+                    Some(idle) => tokio::time::timeout(idle, stream.next())
+                        .await
+                        .map_err(|_| Status::deadline_exceeded(format!("no point received for {}s", idle.as_secs())))?,
+                    None => stream.next().await,
+                };
+                match next {
+                    Some(point) => {
+                        let mut point = point?;
+                        validation::validate(&mut point)?;
+                        recorder.push(point)?
+                    },
+                    None => break,
                 }
             }
 
-            if let Some(ref last_point) = last_point {
-                summary.distance += get_distance(last_point, &point);
+            if recorder.filtered() > 0 {
+                tracing::debug!(tenant = %tenant.id, "filtered {} implausible points from a route", recorder.filtered());
             }
-
-            last_point = Some(point);
-        }
-
-        summary.elapsed_time = now.elapsed().as_secs() as i32;
+            let summary = recorder.finish()?;
+            tenant.add_route(summary.clone());
+            Ok(summary)
+        }).await?;
+        self.hooks.on_record_route_complete(&context, &summary);
 
         Ok(Response::new(summary))
     }
@@ -155,39 +569,509 @@ impl RouteGuide for RouteGuideService {
         &self,
         request: Request<tonic::Streaming<RouteNote>>,
     ) -> Result<Response<Self::RouteChatStream>, Status> {
-        let mut notes = HashMap::new();
+        let context = self.before(&request, "RouteChat")?;
+        let tenant = self.tenants.for_context(&context)?;
+        let client = chat::client_id(&request)?;
+        let user = context.subject.clone();
+        let sender = client.clone().unwrap_or_else(|| user.clone());
+        let last_sequence = client.as_ref().map(|client| tenant.chat_sequences().last(client));
+        let heartbeats = self.heartbeats.filter(|_| chat::wants_heartbeats(&request));
+        let open = (open_streams("RouteChat").track(), connections::registry().track_stream(context.peer));
         let mut stream = request.into_inner();
+        let undelivered = metrics::registry()
+            .counter("route_chat_undelivered_direct_notes_total", "Direct RouteChat notes to users not in the chat.", &[]);
+        let subscription = tenant.chat_hub().subscribe(&user);
+        let (moderation, hooks) = (self.moderation.clone(), self.hooks.clone());
+        let mut ticks = heartbeats.map(|heartbeats| tokio::time::interval(heartbeats.interval));
+        let mut liveness = heartbeats.map(chat::Liveness::new);
 
         let output = async_stream::try_stream! {
-            while let Some(note) = stream.next().await {
-                let note = note?;
+            let _open = open;
+            loop {
+                let event = tokio::select! {
+                    note = stream.next() => ChatEvent::Posted(note),
+                    note = subscription.recv() => ChatEvent::Received(note),
+                    _ = tick(&mut ticks) => ChatEvent::Tick,
+                };
+                let mut note = match event {
+                    ChatEvent::Posted(Some(note)) => note?,
+                    ChatEvent::Posted(None) => break,
+                    ChatEvent::Received(note) => {
+                        let note = note?;
+                        if let Some(liveness) = &mut liveness {
+                            liveness.sent();
+                        }
+                        yield note;
+                        continue;
+                    },
+                    ChatEvent::Tick => {
+                        if let Some(liveness) = &mut liveness {
+                            if let Some(missed) = liveness.missed() {
+                                Err(Status::deadline_exceeded(format!("missed {} heartbeats", missed)))?;
+                            }
+                            if liveness.idle() {
+                                liveness.sent();
+                                yield chat::heartbeat();
+                            }
+                        }
+                        continue;
+                    },
+                };
+                if let Some(liveness) = &mut liveness {
+                    liveness.heard(&note);
+                }
+                if note.heartbeat {
+                    continue;
+                }
+                validation::validate(&mut note)?;
+
+                // A presence note only says where the client is, so it's answered like a post
+                // there but neither stored nor passed on. With a room, it joins the room.
+                if note.message.is_empty() && note.to_user.is_empty() {
+                    let notes = if note.room.is_empty() {
+                        tenant.notes_at(note.location.as_ref().unwrap())?
+                    } else {
+                        match tenant.chat_hub().join(&subscription, &note.room) {
+                            Ok(history) => history,
+                            Err(status) => vec![moderation::rejection(&note, &status)],
+                        }
+                    };
+                    for note in notes {
+                        if let Some(liveness) = &mut liveness {
+                            liveness.sent();
+                        }
+                        yield note;
+                    }
+                    continue;
+                }
+
+                // Redelivered after a reconnect; the server already has it.
+                if let Some(client) = &client {
+                    if !tenant.chat_sequences().accept(client, note.sequence) {
+                        continue;
+                    }
+                }
+
+                note.sender = sender.clone();
+                note.from_user = user.clone();
+                note.posted_at_ms = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |since| since.as_millis() as u64);
+
+                // Only the poster hears of a rejection, and the call carries on.
+                if let Err(status) = moderation.check(&mut note).await {
+                    if let Some(liveness) = &mut liveness {
+                        liveness.sent();
+                    }
+                    yield moderation::rejection(&note, &status);
+                    continue;
+                }
+                // Posting to a room joins it, which fails only if it can't be made.
+                if !note.room.is_empty() {
+                    if let Err(status) = tenant.chat_hub().join(&subscription, &note.room) {
+                        if let Some(liveness) = &mut liveness {
+                            liveness.sent();
+                        }
+                        yield moderation::rejection(&note, &status);
+                        continue;
+                    }
+                }
+                hooks.on_note(&context, &note);
+                tenant.note_posted(&note);
+                let delivered = tenant.chat_hub().publish(&subscription, &note);
+
+                // Only for the addressee, so neither stored nor answered.
+                if !note.to_user.is_empty() {
+                    if delivered == 0 {
+                        undelivered.inc();
+                    }
+                    continue;
+                }
+
+                // Kept in the room's history by the hub, and answered with it.
+                let notes = if note.room.is_empty() {
+                    tenant.add_note(note.location.clone().unwrap(), note)?
+                } else {
+                    tenant.chat_hub().history(&note.room)
+                };
+                for note in notes {
+                    if let Some(liveness) = &mut liveness {
+                        liveness.sent();
+                    }
+                    yield note;
+                }
+            }
+        };
+
+        let mut response = Response::new(Box::pin(output) as Self::RouteChatStream);
+        if let Some(last_sequence) = last_sequence {
+            response.metadata_mut().insert(chat::LAST_SEQUENCE_HEADER, MetadataValue::from(last_sequence));
+        }
+        Ok(response)
+    }
+
+    async fn get_notes_at(&self, request: Request<Point>) -> Result<Response<Self::GetNotesAtStream>, Status> {
+        self.before(&request, "GetNotesAt")?;
+        let notes = self.tenants.scope(&request)?.notes_at(request.get_ref())?;
+        let output = futures::stream::iter(notes.into_iter().map(Ok));
+
+        Ok(Response::new(Box::pin(output) as Self::GetNotesAtStream))
+    }
+
+    async fn add_feature(&self, request: Request<Feature>) -> Result<Response<Feature>, Status> {
+        let context = self.before(&request, "AddFeature")?;
+        let tenant = self.tenants.for_context(&context)?;
+        let actor = context.subject.clone();
+        let key = idempotency::key(&request)?.map(|key| format!("{}/{}", tenant.id, key));
+        let fingerprint = idempotency::fingerprint(request.get_ref());
+        let feature = request.into_inner();
+
+        let added = self.idempotency.run(key, fingerprint, || async move {
+            tenant.add_feature(feature, &actor)
+        }).await?;
+        self.hooks.on_feature_added(&context, &added);
+
+        Ok(Response::new(added))
+    }
+
+    async fn delete_feature(&self, request: Request<Point>) -> Result<Response<Feature>, Status> {
+        let context = self.before(&request, "DeleteFeature")?;
+        let tenant = self.tenants.for_context(&context)?;
 
-                let location = note.location.clone().unwrap();
+        let deleted = tenant.delete_feature(request.get_ref(), &context.subject)?;
+        self.hooks.on_feature_deleted(&context, &deleted);
+        Ok(Response::new(deleted))
+    }
+
+    async fn import_features(&self, request: Request<tonic::Streaming<Feature>>) -> Result<Response<ImportSummary>, Status> {
+        let context = self.before(&request, "ImportFeatures")?;
+        let tenant = self.tenants.for_context(&context)?;
+        let actor = context.subject.clone();
+        let policy = import::DuplicatePolicy::of(&request)?;
+
+        let check = |method: &str, resource: &Resource| self.policy.check_context(&context, method, resource);
+        let summary = import::import(&tenant, &actor, policy, request.into_inner(), check).await?;
+        self.hooks.on_import_complete(&context, &summary);
+        Ok(Response::new(summary))
+    }
+
+    async fn get_feature_as_of(&self, request: Request<PointWithTimestamp>) -> Result<Response<Feature>, Status> {
+        self.before(&request, "GetFeatureAsOf")?;
+        let tenant = self.tenants.scope(&request)?;
+        let request = request.into_inner();
+        let point = Point { read_mask: None, ..request.point.unwrap_or_default() };
+
+        // Taken before the log is read: a change made in between is then in the log too.
+        let current = tenant.features().get(&point).cloned();
+        let after = std::time::UNIX_EPOCH + std::time::Duration::from_millis(request.at_ms.saturating_add(1));
+        let later = runtime_metrics::blocking("query_audit_log", move || tenant.audit_entries(Some(after), None)).await?;
+
+        Ok(Response::new(history::feature_as_of(&later, &point, current.as_ref()).unwrap_or_default()))
+    }
+
+    async fn list_changes(&self, request: Request<TimeRange>) -> Result<Response<Self::ListChangesStream>, Status> {
+        self.before(&request, "ListChanges")?;
+        let tenant = self.tenants.scope(&request)?;
+        let range = request.into_inner();
+        let time = |ms: u64| if ms == 0 { None } else { Some(std::time::UNIX_EPOCH + std::time::Duration::from_millis(ms)) };
+        let (since, until) = (time(range.since_ms), time(range.until_ms));
 
-                let location_notes = notes.entry(location).or_insert(vec![]);
-                location_notes.push(note);
+        let entries = runtime_metrics::blocking("query_audit_log", move || tenant.audit_entries(since, until)).await?;
+        let events: Vec<_> = entries.iter().map(history::change_event).map(Ok).collect();
+        Ok(Response::new(Box::pin(futures::stream::iter(events)) as Self::ListChangesStream))
+    }
 
-                for note in location_notes {
-                    yield note.clone();
+    async fn export_features(&self, request: Request<ExportRequest>) -> Result<Response<Self::ExportFeaturesStream>, Status> {
+        self.before(&request, "ExportFeatures")?;
+        let features = self.tenants.scope(&request)?.features();
+        let batch_size = export::batch_size(request.get_ref());
+
+        // Chunks are made as the client reads them, from the snapshot.
+        let output = async_stream::stream! {
+            let mut checksum = export::Checksum::default();
+            let all: Vec<&Feature> = features.iter().collect();
+            for batch in all.chunks(batch_size) {
+                let features: Vec<Feature> = batch.iter().map(|feature| (*feature).clone()).collect();
+                for feature in &features {
+                    checksum.add(feature);
                 }
+                yield Ok(FeatureChunk { features, trailer: None });
             }
+            yield Ok(FeatureChunk { features: vec![], trailer: Some(checksum.trailer()) });
         };
+        Ok(Response::new(Box::pin(output) as Self::ExportFeaturesStream))
+    }
+
+    async fn replicate(&self, request: Request<ReplicateRequest>) -> Result<Response<Self::ReplicateStream>, Status> {
+        self.before(&request, "Replicate")?;
+        let tenant = self.tenants.scope(&request)?;
+        let events = replica::events(tenant, request.get_ref());
+        Ok(Response::new(Box::pin(events) as Self::ReplicateStream))
+    }
+
+    async fn watch_config(&self, request: Request<()>) -> Result<Response<Self::WatchConfigStream>, Status> {
+        self.before(&request, "WatchConfig")?;
+        Ok(Response::new(Box::pin(self.client_configs.watch()) as Self::WatchConfigStream))
+    }
 
-        Ok(Response::new(Box::pin(output) as Self::RouteChatStream))
+    async fn list_room_members(&self, request: Request<Room>) -> Result<Response<RoomMembers>, Status> {
+        self.before(&request, "ListRoomMembers")?;
+        let tenant = self.tenants.scope(&request)?;
+        let name = &request.get_ref().name;
+        let members = tenant.chat_hub().room(name).ok_or_else(|| Status::not_found(format!("no chat room {:?}", name)))?;
+        Ok(Response::new(members))
+    }
+
+    async fn get_quota(&self, _request: Request<()>) -> Result<Response<Quota>, Status> {
+        // `Quotas` answers it before it gets here.
+        Err(Status::unimplemented("quotas aren't tracked"))
+    }
+}
+
+#[derive(Debug)]
+pub struct TenantAdminService {
+    tenants: Arc<Tenants>,
+    limiter: Arc<AdaptiveLimiter>,
+    audit: Arc<dyn AuditLog>,
+    ip_filter: Arc<ip_filter::IpFilter>,
+    mutes: Arc<MuteList>,
+    quotas: Arc<QuotaTracker>,
+    slos: Arc<SloTracker>,
+}
+
+fn load_shedding_message(limiter: &AdaptiveLimiter) -> LoadShedding {
+    let config = limiter.config();
+    LoadShedding {
+        enabled: config.enabled,
+        min_limit: config.min_limit as u32,
+        max_limit: config.max_limit as u32,
+        target_latency_ms: config.target_latency.as_millis() as u32,
+        current_limit: limiter.limit() as u32,
+        shed_count: limiter.shed_count(),
     }
 }
 
-fn check_authentication(request: Request<()>) -> Result<Request<()>, Status> {
-    let token = "1234";
-    let token = MetadataValue::from_str(&format!("Bearer {}", token)).unwrap();
+fn ip_filter_message(filter: &ip_filter::IpFilter) -> IpFilter {
+    let rules = filter.rules();
+    let strings = |ranges: &[Cidr]| ranges.iter().map(Cidr::to_string).collect();
+    IpFilter {
+        allow: strings(&rules.allow),
+        deny: strings(&rules.deny),
+        trusted_proxies: strings(&rules.trusted_proxies),
+        rejected_count: filter.rejected_count(),
+    }
+}
+
+fn tenant_message(id: &TenantId, data: &TenantData) -> Tenant {
+    Tenant {
+        id: id.to_string(),
+        feature_count: data.features().len() as i32,
+        route_count: data.route_count() as i32,
+    }
+}
+
+#[tonic::async_trait]
+impl TenantAdmin for TenantAdminService {
+    async fn provision_tenant(&self, request: Request<ProvisionTenantRequest>) -> Result<Response<Tenant>, Status> {
+        let request = request.into_inner();
+        let id = TenantId::new(&request.id)?;
+
+        if request.token.is_empty() {
+            return Err(Status::invalid_argument("token must not be empty"));
+        }
+
+        let data = self.tenants.provision(id.clone(), &request.token, vec![])?;
+
+        Ok(Response::new(tenant_message(&id, &data)))
+    }
+
+    async fn list_tenants(&self, _request: Request<ListTenantsRequest>) -> Result<Response<ListTenantsResponse>, Status> {
+        let tenants = self.tenants
+            .list()
+            .iter()
+            .map(|(id, data)| tenant_message(id, data))
+            .collect();
+
+        Ok(Response::new(ListTenantsResponse { tenants }))
+    }
+
+    async fn get_load_shedding(&self, _request: Request<GetLoadSheddingRequest>) -> Result<Response<LoadShedding>, Status> {
+        Ok(Response::new(load_shedding_message(&self.limiter)))
+    }
+
+    async fn set_load_shedding(&self, request: Request<LoadShedding>) -> Result<Response<LoadShedding>, Status> {
+        let request = request.into_inner();
+
+        if request.min_limit == 0 || request.min_limit > request.max_limit {
+            return Err(Status::invalid_argument("min_limit must be between 1 and max_limit"));
+        }
+        if request.target_latency_ms == 0 {
+            return Err(Status::invalid_argument("target_latency_ms must not be 0"));
+        }
+
+        self.limiter.set_config(ShedConfig {
+            enabled: request.enabled,
+            min_limit: request.min_limit as usize,
+            max_limit: request.max_limit as usize,
+            target_latency: std::time::Duration::from_millis(request.target_latency_ms as u64),
+        });
+
+        Ok(Response::new(load_shedding_message(&self.limiter)))
+    }
+
+    async fn list_connections(&self, _request: Request<ListConnectionsRequest>) -> Result<Response<ListConnectionsResponse>, Status> {
+        let connections = connections::registry()
+            .list()
+            .into_iter()
+            .map(|connection| Connection {
+                id: connection.id,
+                peer: connection.peer.to_string(),
+                opened_at_ms: connection.opened.duration_since(std::time::UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64),
+                tls_version: connection.tls.version.unwrap_or_default().to_string(),
+                alpn: connection.tls.alpn.unwrap_or_default(),
+                tls_fingerprint: connection.tls.fingerprint.unwrap_or_default(),
+                ja3: connection.tls.ja3.unwrap_or_default(),
+                user_agent: connection.user_agent.unwrap_or_default(),
+                active_streams: connection.active_streams as u32,
+                total_rpcs: connection.total_rpcs,
+                bytes_received: connection.bytes_received,
+                bytes_sent: connection.bytes_sent,
+            })
+            .collect();
+
+        Ok(Response::new(ListConnectionsResponse { connections }))
+    }
+
+    async fn list_audit_entries(&self, request: Request<ListAuditEntriesRequest>) -> Result<Response<ListAuditEntriesResponse>, Status> {
+        let request = request.into_inner();
+        let time = |ms: u64| std::time::UNIX_EPOCH + std::time::Duration::from_millis(ms);
+        let filter = AuditFilter {
+            tenant: if request.tenant.is_empty() { None } else { Some(TenantId::new(&request.tenant)?) },
+            since: if request.since_ms == 0 { None } else { Some(time(request.since_ms)) },
+            until: if request.until_ms == 0 { None } else { Some(time(request.until_ms)) },
+        };
+
+        let audit = self.audit.clone();
+        let entries = runtime_metrics::blocking("query_audit_log", move || audit.query(&filter))
+            .await
+            .map_err(|e| Status::internal(format!("failed to read the audit log: {}", e)))?;
+
+        Ok(Response::new(ListAuditEntriesResponse { entries }))
+    }
+
+    async fn get_log_filter(&self, _request: Request<GetLogFilterRequest>) -> Result<Response<LogFilter>, Status> {
+        Ok(Response::new(LogFilter { directives: log_filter::current().unwrap_or_default() }))
+    }
+
+    async fn set_log_filter(&self, request: Request<LogFilter>) -> Result<Response<LogFilter>, Status> {
+        log_filter::set(&request.get_ref().directives).map_err(Status::invalid_argument)?;
+        Ok(Response::new(LogFilter { directives: log_filter::current().unwrap_or_default() }))
+    }
+
+    async fn get_ip_filter(&self, _request: Request<GetIpFilterRequest>) -> Result<Response<IpFilter>, Status> {
+        Ok(Response::new(ip_filter_message(&self.ip_filter)))
+    }
+
+    async fn set_ip_filter(&self, request: Request<IpFilter>) -> Result<Response<IpFilter>, Status> {
+        let request = request.into_inner();
+        let parse = |ranges: &[String]| ranges
+            .iter()
+            .map(|range| range.parse::<Cidr>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Status::invalid_argument);
+
+        self.ip_filter.set_rules(IpRules {
+            allow: parse(&request.allow)?,
+            deny: parse(&request.deny)?,
+            trusted_proxies: parse(&request.trusted_proxies)?,
+        });
+
+        Ok(Response::new(ip_filter_message(&self.ip_filter)))
+    }
+
+    async fn set_chat_mute(&self, request: Request<SetChatMuteRequest>) -> Result<Response<ChatMutes>, Status> {
+        let request = request.into_inner();
+        if request.user.is_empty() {
+            return Err(Status::invalid_argument("user must not be empty"));
+        }
+        self.mutes.set(&request.user, request.muted);
+        Ok(Response::new(ChatMutes { users: self.mutes.users() }))
+    }
+
+    async fn list_chat_mutes(&self, _request: Request<ListChatMutesRequest>) -> Result<Response<ChatMutes>, Status> {
+        Ok(Response::new(ChatMutes { users: self.mutes.users() }))
+    }
+
+    async fn get_quota(&self, request: Request<GetQuotaRequest>) -> Result<Response<Quota>, Status> {
+        let subject = &request.get_ref().subject;
+        if subject.is_empty() {
+            return Err(Status::invalid_argument("subject must not be empty"));
+        }
+        Ok(Response::new(self.quotas.quota(subject)))
+    }
+
+    async fn get_error_budgets(&self, _request: Request<GetErrorBudgetsRequest>) -> Result<Response<ErrorBudgets>, Status> {
+        Ok(Response::new(self.slos.error_budgets()))
+    }
+}
+
+/// What a RouteChat call waits for: the caller's next note, one from someone else, or the next
+/// heartbeat check.
+enum ChatEvent {
+    Posted(Option<Result<RouteNote, Status>>),
+    Received(Result<RouteNote, Status>),
+    Tick,
+}
+
+/// The next heartbeat check; never, without heartbeats.
+async fn tick(ticks: &mut Option<tokio::time::Interval>) {
+    match ticks {
+        Some(ticks) => { ticks.tick().await; },
+        None => futures::future::pending().await,
+    }
+}
+
+/// Gauge of the streaming calls of a method that are still open.
+fn open_streams(method: &str) -> Arc<metrics::Gauge> {
+    metrics::registry().gauge("grpc_open_streams", "Streaming calls that are still open.", &[("method", method)])
+}
+
+/// The admin service is only reachable with the token in the `ADMIN_TOKEN` environment variable.
+/// Without it, every admin call is rejected.
+fn check_admin_authentication(request: Request<()>) -> Result<Request<()>, Status> {
+    let token = match std::env::var("ADMIN_TOKEN") {
+        Ok(token) if !token.is_empty() => token,
+        _ => return Err(Status::permission_denied("Admin service is disabled")),
+    };
+    let token = MetadataValue::from_str(&format!("Bearer {}", token))
+        .map_err(|_| Status::internal("ADMIN_TOKEN is not valid metadata"))?;
 
     match request.metadata().get("authorization") {
         Some(t) if token == t => Ok(request),
-        _ => Err(Status::unauthenticated("No valid auth token")),
+        _ => Err(Status::unauthenticated("No valid admin token")),
     }
 }
 
 
+/// routeguide.v1 messages are a subset of v2, so v1 calls are answered by the v2 service.
+/// v1 is frozen: its RouteNote has no `heartbeat`, so its RouteChat streams never get one.
+struct RouteGuideV1;
+
+impl AliasName for RouteGuideV1 {
+    const NAME: &'static str = "routeguide.v1.RouteGuide";
+    const DROPPED_METADATA: &'static [&'static str] = &[chat::HEARTBEATS_HEADER];
+}
+
+/// The name from before the API was versioned.
+struct LegacyRouteGuide;
+
+impl AliasName for LegacyRouteGuide {
+    const NAME: &'static str = "route_guide.RouteGuide";
+    // Older than v1, so no heartbeats either.
+    const DROPPED_METADATA: &'static [&'static str] = &[chat::HEARTBEATS_HEADER];
+}
+
+
 #[derive(Debug, Clone)]
 struct InterceptedService<S> {
     inner: S,
@@ -213,12 +1097,13 @@ impl<S> Service<HyperRequest<Body>> for InterceptedService<S>
 
     fn call(&mut self, req: HyperRequest<Body>) -> Self::Future {
         let mut svc = self.inner.clone();
+        let task = req.uri().path().to_string();
 
-        Box::pin(async move {
+        Box::pin(runtime_metrics::instrument(&task, async move {
             // Do async work here....
             println!("Work is being done...");
             svc.call(req).await
-        })
+        }))
     }
 }
 
@@ -227,48 +1112,737 @@ impl<S: NamedService> NamedService for InterceptedService<S> {
 }
 
 
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The TLS settings of the gRPC listeners, and the same for the shared port. The files are
+/// parsed here, so a reload with broken ones fails before any listener is replaced.
+async fn server_tls(files: &TlsFiles) -> Result<(ServerTlsConfig, rustls::ServerConfig), BoxError> {
+    let cert = tokio::fs::read(&files.cert).await.map_err(|e| format!("failed to read {}: {}", files.cert, e))?;
+    let key  = tokio::fs::read(&files.key).await.map_err(|e| format!("failed to read {}: {}", files.key, e))?;
+    let shared = multiplex::tls_config(&cert, &key).map_err(|e| format!("{} and {}: {}", files.cert, files.key, e))?;
+    let mut tls_config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+    if let Some(path) = &files.client_ca {
+        // Mutual TLS: clients must present a certificate signed by this CA.
+        let ca = tokio::fs::read(path).await.map_err(|e| format!("failed to read {}: {}", path, e))?;
+        tls_config = tls_config.client_ca_root(Certificate::from_pem(ca));
+    }
+    Ok((tls_config, shared))
+}
+
+/// The shared port doesn't ask for client certificates; tonic can't be handed them from there,
+/// and its plaintext modes have none. So mutual TLS would silently not apply to it.
+fn check_shared_port(files: &TlsFiles, shared_port: bool) -> Result<(), BoxError> {
+    if shared_port && files.client_ca.is_some() {
+        return Err("a client CA can't be used with --shared-address, which doesn't check client certificates".into());
+    }
+    Ok(())
+}
+
+/// Binds the address, retrying for a second while a listener being replaced lets go of it.
+async fn bind_retrying(address: SocketAddr) -> std::io::Result<TcpListener> {
+    let mut attempts = 0;
+    loop {
+        match TcpListener::bind(address).await {
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse && attempts < 20 => {
+                attempts += 1;
+                tokio::time::delay_for(std::time::Duration::from_millis(50)).await;
+            },
+            result => return result,
+        }
+    }
+}
+
+/// Resolves once the value is true. Never resolves if the sender is dropped first.
+async fn until_true(receiver: &mut watch::Receiver<bool>) {
+    while let Some(value) = receiver.recv().await {
+        if value {
+            return;
+        }
+    }
+    futures::future::pending::<()>().await
+}
+
+/// A gRPC listener that is serving. Retiring it makes it stop accepting; its connections are
+/// told to go away and dropped if they're still open `drain_secs` later.
+struct Listener {
+    address: SocketAddr,
+    retire: watch::Sender<bool>,
+}
+
+impl Listener {
+    fn retire(self) {
+        // Fails only once the listener has stopped on its own.
+        let _ = self.retire.broadcast(true);
+    }
+}
+
+/// Moves the listeners to the addresses and TLS settings of `config`, one address at a time so
+/// the others keep serving meanwhile.
+async fn rebind<F, Fut>(listeners: &mut Vec<Listener>, config: &ServerConfig, tls: ServerTlsConfig, start: &F)
+    where
+        F: Fn(SocketAddr, ServerTlsConfig) -> Fut,
+        Fut: Future<Output = Result<Listener, BoxError>>,
+{
+    let old: Vec<SocketAddr> = listeners.iter().map(|listener| listener.address).collect();
+    for step in reload::rebind_order(&old, &config.listen) {
+        let address = match step {
+            Rebind::Replace(address) | Rebind::Start(address) | Rebind::Stop(address) => address,
+        };
+        if let Some(i) = listeners.iter().position(|listener| listener.address == address) {
+            if let Rebind::Replace(_) | Rebind::Stop(_) = step {
+                listeners.remove(i).retire();
+            }
+        }
+        if let Rebind::Stop(_) = step {
+            tracing::info!(%address, "listener stopped");
+            continue;
+        }
+        match start(address, tls.clone()).await {
+            Ok(listener) => {
+                tracing::info!(%address, "listener bound");
+                listeners.push(listener);
+            },
+            Err(e) => tracing::error!(%address, "failed to bind the listener: {}", e),
+        }
+    }
+}
+
+/// What a reload changes in place.
+struct Reloadable {
+    tenants: Arc<Tenants>,
+    limiter: Arc<AdaptiveLimiter>,
+    client_configs: Arc<ClientConfigs>,
+    shared_port: bool,
+}
+
+/// Reads the configuration file again and applies what changed. A file that doesn't parse, or
+/// TLS files that don't load, change nothing.
+async fn reload_config<F, Fut>(
+    path: &str,
+    base: &ServerConfig,
+    current: &mut ServerConfig,
+    listeners: &mut Vec<Listener>,
+    start: &F,
+    live: &Reloadable,
+) -> Result<(), BoxError>
+    where
+        F: Fn(SocketAddr, ServerTlsConfig) -> Fut,
+        Fut: Future<Output = Result<Listener, BoxError>>,
+{
+    let text = tokio::fs::read_to_string(path).await.map_err(|e| format!("failed to read {}: {}", path, e))?;
+    let new = ServerConfig::parse(&text, base).map_err(|e| format!("{}: {}", path, e))?;
+    check_shared_port(&new.tls, live.shared_port)?;
+    let changes = current.changes(&new);
+    let tls = if changes.listeners { Some(server_tls(&new.tls).await?.0) } else { None };
+
+    if changes.log_filter {
+        let directives = new.log_filter.clone().or_else(|| base.log_filter.clone()).unwrap_or_else(|| log_filter::DEFAULT_DIRECTIVES.to_string());
+        log_filter::set(&directives)?;
+    }
+    if changes.shedding {
+        live.limiter.set_config(new.shedding);
+    }
+    if changes.tokens {
+        for (tenant, token) in current.dropped_tokens(&new) {
+            if live.tenants.revoke(tenant, token) {
+                tracing::info!(%tenant, "revoked the token {} no longer lists", path);
+            }
+        }
+        for (tenant, token) in &new.tokens {
+            live.tenants.provision(tenant.clone(), token, vec![])?;
+        }
+    }
+    if changes.client_config {
+        live.client_configs.set(new.client.clone());
+    }
+    if let Some(tls) = tls {
+        rebind(listeners, &new, tls, start).await;
+    }
+
+    tracing::info!(changed = ?changes.names(), "reloaded {}", path);
+    live.tenants.events().publish(Event::ConfigReloaded(ConfigReloaded { changed: changes.names() }));
+    *current = new;
+    Ok(())
+}
+
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let options = Options::from_args();
+
+    if let Some(path) = &options.write_descriptor_set {
+        std::fs::write(path, route_guide::FILE_DESCRIPTOR_SET)?;
+        return Ok(());
+    }
+
+    // Logging. RUST_LOG sets the filter; the admin service, PUT /admin/log-filter and the
+    // configuration file change it.
+    log_filter::init()?;
+    // Panics are logged with an incident ID, which a call that panicked is answered with.
+    panics::install_hook();
+    // Before any call, so every stream gets its method's encode buffer.
+    codec::configure(options.codec_buffers.iter().fold(BufferSizes::recommended(), |sizes, size| sizes.with(&size.method, size.bytes)));
+
+    // Settings that can change while serving, from the command line and `--config`.
+    let base = ServerConfig {
+        log_filter: log_filter::current(),
+        shedding: ShedConfig {
+            enabled: !options.no_load_shedding,
+            max_limit: options.max_concurrency,
+            target_latency: std::time::Duration::from_millis(options.target_latency_ms),
+            ..ShedConfig::default()
+        },
+        tokens: vec![(TenantId::new("default")?, "1234".to_string())],
+        listen: reload::DEFAULT_LISTEN.iter().map(|address| address.parse().unwrap()).collect(),
+        tls: TlsFiles { client_ca: options.client_ca.clone(), ..TlsFiles::default() },
+        client: client_config::defaults(),
+    };
+    let config = match &options.config {
+        Some(path) => ServerConfig::parse(&tokio::fs::read_to_string(path).await?, &base).map_err(|e| format!("{}: {}", path, e))?,
+        None => base.clone(),
+    };
+    if config.log_filter != base.log_filter {
+        if let Some(directives) = &config.log_filter {
+            log_filter::set(directives)?;
+        }
+    }
+    // Pushed to WatchConfig calls, again whenever a reload changes them.
+    let client_configs = Arc::new(ClientConfigs::new(config.client.clone()));
+
+    // Run once the servers have stopped, in the order they're registered.
+    let hooks = ShutdownHooks::default();
+
+    // Background work spawned by handlers gets the drain time to finish once the servers have
+    // stopped, and is aborted after that.
+    let tasks = TaskTracker::default();
+    let (stopping, grace) = (tasks.clone(), std::time::Duration::from_secs(options.drain_secs));
+    hooks.register("background tasks", grace + std::time::Duration::from_secs(1), move || async move {
+        let aborted = stopping.shutdown(grace).await;
+        if aborted > 0 {
+            eprintln!("Aborted {} background tasks still running after {:?}", aborted, grace);
+        }
+        Ok::<(), std::convert::Infallible>(())
+    });
+
+    // Metrics.
+    runtime_metrics::spawn_scheduler_probe(std::time::Duration::from_millis(100));
+    let metrics_address = options.metrics_address;
+    tokio::spawn(async move {
+        if let Err(e) = metrics::serve(metrics_address).await {
+            eprintln!("Metrics server error = {:?}", e);
+        }
+    });
+    if let Some(path) = options.final_metrics.clone() {
+        hooks.register("final metrics", std::time::Duration::from_secs(5), move || {
+            tokio::fs::write(path, metrics::registry().render())
+        });
+    }
+
+    // Lifecycle. Not ready until the database is loaded.
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    let services = vec![
+        <RouteGuideServer<RouteGuideService> as NamedService>::NAME,
+        RouteGuideV1::NAME,
+        LegacyRouteGuide::NAME,
+        <TenantAdminServer<TenantAdminService> as NamedService>::NAME,
+    ];
+    let lifecycle = Arc::new(Lifecycle::new(health_reporter, services).await);
+    let (probe_address, probe_lifecycle) = (options.probe_address, lifecycle.clone());
+    tokio::spawn(async move {
+        if let Err(e) = lifecycle::serve(probe_address, probe_lifecycle).await {
+            eprintln!("Probe server error = {:?}", e);
+        }
+    });
+
     // TLS.
-    let cert = tokio::fs::read("data/tls/server.pem").await?;
-    let key  = tokio::fs::read("data/tls/server.key").await?;
-    let identity = Identity::from_pem(cert, key);
-    let tls_config = ServerTlsConfig::new().identity(identity);
+    check_shared_port(&config.tls, options.shared_address.is_some()).map_err(|e| e as Box<dyn std::error::Error>)?;
+    let (tls_config, shared_tls) = server_tls(&config.tls).await.map_err(|e| e as Box<dyn std::error::Error>)?;
+    let shared_tls = Arc::new(shared_tls);
+
+    // Load database. The original token keeps working and sees the whole database.
+    let (path, policy) = (options.data.clone(), options.on_invalid_data);
+    let features = match runtime_metrics::blocking("load_database", move || data::load_checked(&path, policy)).await {
+        Ok((features, skipped)) => {
+            for diagnostic in &skipped {
+                eprintln!("Skipping record in {}: {}", options.data, diagnostic);
+            }
+            features
+        },
+        Err(diagnostics) => {
+            for diagnostic in &diagnostics {
+                eprintln!("{}:{}", options.data, diagnostic);
+            }
+            return Err(format!("refusing to start with an invalid database ({} problem(s))", diagnostics.len()).into());
+        },
+    };
+
+    // Write-ahead log of the changes since the database was last written.
+    let (features, wal) = if options.wal {
+        if options.data == data::EMBEDDED_PATH {
+            return Err("--wal needs a database file to write the features back into, not the embedded one".into());
+        }
+        let (wal, records) = FeatureWal::open(&options.data)?;
+        if !records.is_empty() {
+            tracing::info!(changes = records.len(), "replaying {}", wal.path().display());
+        }
+        (wal::replay(features, records), Some(Arc::new(wal)))
+    } else {
+        (features, None)
+    };
+
+    // Chat history.
+    let policy = RetentionPolicy {
+        ttl: options.chat_ttl_secs.map(std::time::Duration::from_secs),
+        max_notes_per_location: options.chat_max_notes,
+    };
+    let notes: Arc<dyn NoteStore> = match &options.chat_history {
+        Some(directory) => Arc::new(Instrumented::new(FileNoteStore::open(directory, policy)?, "file")),
+        None => Arc::new(MemoryNoteStore::new(policy)),
+    };
+    #[cfg(feature = "sqlite")]
+    let notes: Arc<dyn NoteStore> = match &options.chat_db {
+        Some(path) => Arc::new(Instrumented::new(note_store::SqliteNoteStore::open(path, policy)?, "sqlite")),
+        None => notes,
+    };
+    let compacted = notes.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(300));
+        loop {
+            interval.tick().await;
+            let notes = compacted.clone();
+            if let Err(e) = runtime_metrics::blocking("compact_notes", move || notes.compact()).await {
+                eprintln!("Failed to compact chat history: {}", e);
+            }
+        }
+    });
+    let flushed = notes.clone();
+    hooks.register("chat history", std::time::Duration::from_secs(30), move || {
+        runtime_metrics::blocking("compact_notes", move || flushed.compact())
+    });
+
+    // Audit log.
+    let audit: Arc<dyn AuditLog> = match &options.audit_log {
+        Some(path) => Arc::new(Instrumented::new(FileAuditLog::open(path)?, "file")),
+        None => Arc::new(MemoryAuditLog::default()),
+    };
+
+    let chat = HubConfig {
+        queue_size: options.chat_queue_size,
+        policy: options.chat_slow_consumer,
+        room_history: options.chat_room_history,
+        max_rooms: options.chat_max_rooms,
+    };
+    let heartbeats = Some(options.chat_heartbeat_secs).filter(|&secs| secs > 0).map(|secs| chat::Heartbeats {
+        interval: std::time::Duration::from_secs(secs),
+        max_missed: options.chat_missed_heartbeats.max(1),
+    });
+    // What reacts to changes, rather than each handler doing it.
+    let events = EventBus::default().with(Arc::new(EventMetrics));
+    // Tokens from an identity provider, on top of the tenants' own.
+    let introspector = match &options.introspection_url {
+        Some(endpoint) => {
+            let read = |path: &Option<String>| -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+                match path {
+                    Some(path) => Ok(Some(std::fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))?)),
+                    None => Ok(None),
+                }
+            };
+            let client_secret = read(&options.introspection_secret_file)?.map(|secret| String::from_utf8_lossy(&secret).trim().to_string());
+            Some(Arc::new(Introspector::new(IntrospectionConfig {
+                endpoint: endpoint.clone(),
+                client_id: options.introspection_client_id.clone(),
+                client_secret: client_secret.unwrap_or_default(),
+                tenant_claim: options.introspection_tenant_claim.clone(),
+                cache_ttl: std::time::Duration::from_secs(options.introspection_cache_secs),
+                negative_ttl: std::time::Duration::from_secs(5),
+                timeout: std::time::Duration::from_secs(2),
+                on_unreachable: options.introspection_unreachable,
+                jwt_secret: read(&options.jwt_secret_file)?,
+            })?))
+        },
+        None => None,
+    };
+    let mut registry = Tenants::new(notes, audit.clone(), chat).with_ids(options.feature_ids.generator()).with_events(events);
+    if let Some(introspector) = &introspector {
+        registry = registry.with_authority(introspector.clone());
+    }
+    let tenants = Arc::new(registry);
+    let default_tenant = TenantId::new("default")?;
+    match wal {
+        Some(wal) => {
+            let durable = tenants.provision_durable(default_tenant, "1234", features, wal)?;
+            // Right away, so the replayed changes and the ids given to features without one are
+            // in the database.
+            let checkpointed = durable.clone();
+            runtime_metrics::blocking("checkpoint_features", move || checkpointed.checkpoint()).await?;
+
+            let (checkpointed, every) = (durable.clone(), std::time::Duration::from_secs(options.checkpoint_secs.max(1)));
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(every);
+                loop {
+                    interval.tick().await;
+                    let tenant = checkpointed.clone();
+                    if let Err(e) = runtime_metrics::blocking("checkpoint_features", move || tenant.checkpoint()).await {
+                        tracing::error!("failed to checkpoint the features: {}", e);
+                    }
+                }
+            });
+            hooks.register("feature checkpoint", std::time::Duration::from_secs(30), move || {
+                runtime_metrics::blocking("checkpoint_features", move || durable.checkpoint().map(|_| ()))
+            });
+        },
+        None => {
+            let tenant = tenants.provision(default_tenant, "1234", features)?;
+            if let Some(primary) = &options.follow {
+                // The local database is served until the first copy arrives.
+                tenant.set_replica(true);
+                let tls = ClientTlsOptions {
+                    ca_file: Some(options.follow_ca.clone()),
+                    domain: Some(options.follow_domain.clone()),
+                    ..ClientTlsOptions::default()
+                }.build().await?;
+                let endpoint = Endpoint::from_shared(primary.clone())?.tls_config(tls)?;
+                let (token, retry) = (options.follow_token.clone(), std::time::Duration::from_secs(options.follow_retry_secs.max(1)));
+                tokio::spawn(async move {
+                    let channel = loop {
+                        match endpoint.connect().await {
+                            Ok(channel) => break channel,
+                            Err(e) => tracing::warn!("failed to connect to the primary: {}", e),
+                        }
+                        tokio::time::delay_for(retry).await;
+                    };
+                    replica::follow(RouteGuideClient::new(channel), tenant, token, retry).await;
+                });
+            }
+        },
+    }
+    for (tenant, token) in &config.tokens {
+        tenants.provision(tenant.clone(), token, vec![])?;
+    }
+
+    // IP allow and deny lists, shared by all servers.
+    let ip_rules = match &options.ip_rules {
+        Some(path) => IpRules::parse(&tokio::fs::read_to_string(path).await?).map_err(|e| format!("{}: {}", path, e))?,
+        None => IpRules::default(),
+    };
+    let ip_filter = Arc::new(ip_filter::IpFilter::new(ip_rules));
+
+    // REST gateway.
+    let cors = Cors {
+        origins: if options.cors_origins.iter().any(|origin| origin == "*") {
+            AllowedOrigins::Any
+        } else {
+            AllowedOrigins::List(options.cors_origins.clone())
+        },
+        max_age: options.cors_max_age_secs.map(std::time::Duration::from_secs),
+        credentials: options.cors_credentials,
+        ..Cors::default()
+    };
+
+    let policy = Arc::new(match &options.policy {
+        Some(path) => PolicyEngine::load(path)?,
+        None => PolicyEngine::default(),
+    });
+    let (gateway_address, gateway_tenants, gateway_cors) = (options.gateway_address, tenants.clone(), cors.clone());
+    let (gateway_ip_filter, gateway_policy) = (ip_filter.clone(), policy.clone());
+    if let Some(directory) = &options.route_spool_dir {
+        route_spool::configure(directory.into());
+    }
+    let route_limits = RecorderLimits {
+        max_points: Some(options.route_max_points),
+        max_speed: options.route_max_speed,
+        max_rate: options.route_max_rate,
+        max_repeats: options.route_max_repeats,
+        policy: options.route_guard,
+        spool_after: Some(options.route_spool_bytes),
+        ..RecorderLimits::default()
+    };
+    tokio::spawn(async move {
+        if let Err(e) = gateway::serve(gateway_address, gateway_tenants, gateway_policy, gateway_cors, gateway_ip_filter, route_limits).await {
+            eprintln!("Gateway error = {:?}", e);
+        }
+    });
+
+    // Retried AddFeature calls are recognized for ten minutes, and replayed routes for a day, since
+    // clients may stay offline that long.
+    let idempotency = Arc::new(IdempotencyCache::new(std::time::Duration::from_secs(600), 10_000));
+    let route_idempotency = Arc::new(IdempotencyCache::new(std::time::Duration::from_secs(24 * 3600), 10_000));
+
+    let recorder = match &options.record {
+        Some(path) => Some(Arc::new(Recorder::create(path)?)),
+        None => None,
+    };
+
+    // Shared by all addresses, since they share the same machine.
+    let limiter = Arc::new(AdaptiveLimiter::new(config.shedding));
+
+    let budgets = Arc::new(LatencyBudgets::with(&options.latency_budgets));
+    let slos = Arc::new(SloTracker::new(SloConfig {
+        slos: options.slos.clone(),
+        window: std::time::Duration::from_secs(options.slo_window_mins * 60),
+        degrade: options.slo_degrade,
+        ..SloConfig::default()
+    }));
+
+    // RouteChat moderation. Muted users first, so their notes are rejected whatever they say.
+    // Quotas, flushed to their store now and then and at shutdown.
+    let quota_store: Arc<dyn QuotaStore> = match &options.quota_file {
+        Some(path) => Arc::new(Instrumented::new(FileQuotaStore::new(path), "file")),
+        None => Arc::new(MemoryQuotaStore),
+    };
+    let quota_limits = QuotaLimits {
+        daily_rpcs: options.quota_daily_rpcs,
+        monthly_rpcs: options.quota_monthly_rpcs,
+        daily_bytes: options.quota_daily_bytes,
+        monthly_bytes: options.quota_monthly_bytes,
+    };
+    let quotas = Arc::new(QuotaTracker::open(quota_limits, quota_store)?);
+    let flushed = quotas.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(10));
+        loop {
+            interval.tick().await;
+            let quotas = flushed.clone();
+            if let Err(e) = runtime_metrics::blocking("flush_quotas", move || quotas.flush()).await {
+                eprintln!("Failed to save quota usage: {}", e);
+            }
+        }
+    });
+    let flushed = quotas.clone();
+    hooks.register("quota usage", std::time::Duration::from_secs(10), move || {
+        runtime_metrics::blocking("flush_quotas", move || flushed.flush())
+    });
+
+    let mutes = Arc::new(MuteList::default());
+    let mut moderation = Moderation::default().with(mutes.clone());
+    if let Some(max_chars) = options.chat_max_length {
+        moderation = moderation.with(Arc::new(LengthLimit { max_chars }));
+    }
+    if let Some(path) = &options.chat_banned_words {
+        let words = BannedWords::parse(&tokio::fs::read_to_string(path).await?, options.chat_banned_action)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        moderation = moderation.with(Arc::new(words));
+    }
+    let moderation = Arc::new(moderation);
+    // A server built on this one puts its own here.
+    let route_guide_hooks: Arc<dyn RouteGuideHooks> = Arc::new(NoHooks);
+
+    // Create servers.
+    let route_guide_service = {
+        let (tenants, ip_filter, limiter, recorder) = (tenants.clone(), ip_filter.clone(), limiter.clone(), recorder.clone());
+        let (idempotency, route_idempotency, budgets) = (idempotency.clone(), route_idempotency.clone(), budgets.clone());
+        let (policy, moderation, route_guide_hooks, quotas) = (policy.clone(), moderation.clone(), route_guide_hooks.clone(), quotas.clone());
+        let (client_configs, slos) = (client_configs.clone(), slos.clone());
+        move || {
+            let authenticate = tenants.clone().interceptor();
+            let ip_filter = ip_filter.clone();
+            ServerCompression {
+                inner: MessageMetrics {
+                    inner: RecordingService {
+                        inner: SloService { inner: LoadShedService {
+                            inner: CatchPanic { inner: InterceptedService { inner: Introspected {
+                                inner: RouteGuideServer::with_interceptor(
+                                    Budgeted {
+                                        inner: Validated(Authorized {
+                                            inner: Quotas { inner: RouteGuideService {
+                                                tenants: tenants.clone(),
+                                                limits: route_limits,
+                                                heartbeats,
+                                                idempotency: idempotency.clone(),
+                                                route_idempotency: route_idempotency.clone(),
+                                                tasks: tasks.clone(),
+                                                moderation: moderation.clone(),
+                                                hooks: route_guide_hooks.clone(),
+                                                client_configs: client_configs.clone(),
+                                                policy: policy.clone(),
+                                            }, tracker: quotas.clone() },
+                                            policy: policy.clone(),
+                                        }),
+                                        budgets: budgets.clone(),
+                                    },
+                                    move |request: Request<()>| {
+                                        connections::registry().record_call(&request);
+                                        ip_filter.check(&request)?;
+                                        let mut request = authenticate(request)?;
+                                        request_context::stamp(&mut request);
+                                        Ok(request)
+                                    }
+                                ),
+                                introspector: introspector.clone(),
+                                tenants: tenants.clone(),
+                            } } },
+                            limiter: limiter.clone(),
+                        }, tracker: slos.clone() },
+                        recorder: recorder.clone(),
+                    },
+                },
+            }
+        }
+    };
+    let admin_service = {
+        let (tenants, ip_filter, limiter, audit) = (tenants.clone(), ip_filter.clone(), limiter.clone(), audit.clone());
+        let (mutes, quotas) = (mutes.clone(), quotas.clone());
+        move || {
+            let checked = ip_filter.clone();
+            CatchPanic { inner: TenantAdminServer::with_interceptor(
+                TenantAdminService {
+                    tenants: tenants.clone(),
+                    limiter: limiter.clone(),
+                    audit: audit.clone(),
+                    ip_filter: ip_filter.clone(),
+                    mutes: mutes.clone(),
+                    quotas: quotas.clone(),
+                    slos: slos.clone(),
+                },
+                move |request: Request<()>| {
+                    connections::registry().record_call(&request);
+                    checked.check(&request)?;
+                    check_admin_authentication(request)
+                }
+            ) }
+        }
+    };
 
-    // Load-balancing.
+    // The gRPC listeners. Each serves until the lifecycle stops, or until a reload retires it.
+    // Only the end of a listener that wasn't retired stops the server.
     let (tx, mut rx) = mpsc::unbounded_channel();
-    let addresses = ["[::1]:50051", "[::1]:50052"]
-        .iter()
-        .map(|endpoint| endpoint.parse().unwrap());
+    let drain = std::time::Duration::from_secs(options.drain_secs);
+    let start_listener = {
+        let (route_guide_service, admin_service) = (route_guide_service.clone(), admin_service.clone());
+        let (health_service, ip_filter, lifecycle) = (health_service.clone(), ip_filter.clone(), lifecycle.clone());
+        move |address: SocketAddr, tls: ServerTlsConfig| {
+            let service = route_guide_service();
+            let admin = admin_service();
+            let (health_service, ip_filter, lifecycle, tx) = (health_service.clone(), ip_filter.clone(), lifecycle.clone(), tx.clone());
+
+            async move {
+                // Accepted here rather than by the server so every connection is in the registry.
+                let incoming = connections::incoming(bind_retrying(address).await?, ip_filter);
+                let (retire, retired) = watch::channel(false);
+                let mut retiring = retired.clone();
+                let stopped = async move {
+                    tokio::select! {
+                        _ = lifecycle.reached(State::Stopped) => {},
+                        _ = until_true(&mut retiring) => {},
+                    }
+                };
+                let serve = Server::builder().
+                    tls_config(tls)?.                 // Returns a Server with TLS configuration.
+                    add_service(service.clone()).     // Returns a Router that routes to the service.
+                    add_service(ServiceAlias::<_, RouteGuideV1>::new(service.clone())).
+                    add_service(ServiceAlias::<_, LegacyRouteGuide>::new(service)).
+                    add_service(admin).
+                    add_service(health_service).
+                    serve_with_incoming_shutdown(incoming, stopped);  // Serves until the lifecycle stops or the listener is retired (it's async so it's not called until await).
+
+                tokio::spawn(async move {
+                    let mut retired = retired;
+                    tokio::select! {
+                        result = serve => {
+                            if let Err(e) = result {
+                                eprintln!("Error = {:?}", e);
+                            }
+                        },
+                        _ = async { until_true(&mut retired).await; tokio::time::delay_for(drain).await } => {
+                            tracing::info!(%address, "dropped the connections still open after draining");
+                        },
+                    }
+
+                    if !*retired.borrow() {
+                        let _ = tx.send(());
+                    }
+                });
+                Ok::<_, BoxError>(Listener { address, retire })
+            }
+        }
+    };
 
-    // Load database.
-    let database = data::load();
+    let mut listeners = vec![];
+    for &address in &config.listen {
+        listeners.push(start_listener(address, tls_config.clone()).await.map_err(|e| e as Box<dyn std::error::Error>)?);
+    }
 
-    // Create servers.
-    for address in addresses {
-        let service = InterceptedService {
-            inner: RouteGuideServer::with_interceptor(
-                RouteGuideService { features: Arc::new(database.clone()) },
-                check_authentication
-            )
+    // SIGHUP reloads `--config` and `--policy`.
+    #[cfg(unix)]
+    {
+        let mut hangups = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+        let path = options.config.clone();
+        let live = Reloadable {
+            tenants: tenants.clone(),
+            limiter: limiter.clone(),
+            client_configs: client_configs.clone(),
+            shared_port: options.shared_address.is_some(),
         };
+        let (policy, has_policy) = (policy.clone(), options.policy.is_some());
+        tokio::spawn(async move {
+            let mut config = config;
+            while hangups.recv().await.is_some() {
+                if has_policy {
+                    match policy.reload() {
+                        Ok(()) => tracing::info!("reloaded the authorization policy"),
+                        Err(e) => tracing::error!("failed to reload the authorization policy: {}", e),
+                    }
+                }
+                let path = match &path {
+                    Some(path) => path,
+                    None if has_policy => continue,
+                    None => {
+                        tracing::warn!("SIGHUP received, but there's no --config or --policy to reload");
+                        continue;
+                    },
+                };
+                if let Err(e) = reload_config(path, &base, &mut config, &mut listeners, &start_listener, &live).await {
+                    tracing::error!("failed to reload the configuration: {}", e);
+                }
+            }
+        });
+    }
 
-        let serve = Server::builder().
-            tls_config(tls_config.clone())?.  // Returns a Server with TLS configuration.
-            add_service(service).             // Returns a Router that routes to the service.
-            serve(address);                   // Serves the Server (it's async so it's not called until await).
+    if let Some(address) = options.shared_address {
+        let service = route_guide_service();
+        let grpc = GrpcRoutes::new()
+            .add_service(service.clone())
+            .add_service(ServiceAlias::<_, RouteGuideV1>::new(service.clone()))
+            .add_service(ServiceAlias::<_, LegacyRouteGuide>::new(service))
+            .add_service(admin_service())
+            .add_service(health_service.clone());
+        // Browsers send and read these on gRPC-Web calls.
+        let mut cors = cors;
+        cors.headers.extend(["x-grpc-web", "x-user-agent", "grpc-timeout"].iter().map(|header| header.to_string()));
+        cors.expose_headers.extend(["grpc-status", "grpc-message"].iter().map(|header| header.to_string()));
+        let multiplexer = Multiplexer {
+            grpc,
+            http: gateway::handler(tenants.clone(), policy.clone(), cors, route_limits),
+            tls: if options.shared_port_mode == PortMode::H2c { None } else { Some(shared_tls) },
+            mode: options.shared_port_mode,
+            ip_filter: ip_filter.clone(),
+        };
 
-        let tx = tx.clone();
+        let lifecycle = lifecycle.clone();
+        let stopped = async move { lifecycle.reached(State::Stopped).await };
         tokio::spawn(async move {
-            if let Err(e) = serve.await {
-                eprintln!("Error = {:?}", e);
+            if let Err(e) = multiplexer.serve(address, stopped).await {
+                eprintln!("Shared port error = {:?}", e);
             }
-
-            tx.send(()).unwrap();
         });
     }
 
+    lifecycle.advance(State::Serving).await;
+
+    // Shutdown.
+    let draining = lifecycle.clone();
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install CTRL+C signal handler");
+
+        draining.advance(State::Draining).await;
+        tokio::time::delay_for(drain).await;
+        draining.advance(State::Stopped).await;
+    });
+
     rx.recv().await;
+    hooks.run().await;
 
     Ok(())
 }
@@ -0,0 +1,54 @@
+//! Decodes the input as each message the server reads from clients, then runs what the server
+//! runs on a decoded message first: validation and read mask parsing. None of it may panic.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use prost::Message;
+
+use rust_server::admin;
+use rust_server::field_mask::FeatureMask;
+use rust_server::route_guide::{Feature, Point, Rectangle, RouteNote};
+use rust_server::validation::{self, Validate};
+
+
+fn decode<M: Message + Default>(data: &[u8]) -> Option<M> {
+    M::decode(data).ok()
+}
+
+/// Re-encoding what was decoded must give a message that decodes to the same thing.
+fn round_trip<M: Message + Default + PartialEq + std::fmt::Debug>(message: &M) {
+    let mut encoded = Vec::with_capacity(message.encoded_len());
+    message.encode(&mut encoded).unwrap();
+    assert_eq!(&M::decode(&encoded[..]).unwrap(), message);
+}
+
+fn check<M: Message + Default + PartialEq + std::fmt::Debug + Validate>(data: &[u8]) {
+    if let Some(mut message) = decode::<M>(data) {
+        round_trip(&message);
+        let _ = validation::validate(&mut message);
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    check::<Point>(data);
+    check::<Rectangle>(data);
+    check::<Feature>(data);
+    check::<RouteNote>(data);
+
+    if let Some(point) = decode::<Point>(data) {
+        let _ = FeatureMask::parse(point.read_mask.as_ref()).map(|mask| mask.apply(Feature::default()));
+    }
+
+    if let Some(request) = decode::<admin::ProvisionTenantRequest>(data) {
+        round_trip(&request);
+    }
+    if let Some(request) = decode::<admin::LoadShedding>(data) {
+        round_trip(&request);
+    }
+    if let Some(request) = decode::<admin::ListAuditEntriesRequest>(data) {
+        round_trip(&request);
+    }
+    if let Some(request) = decode::<admin::LogFilter>(data) {
+        round_trip(&request);
+    }
+});
@@ -0,0 +1,30 @@
+//! Loads the input as a JSON feature database, with both policies for invalid records. Malformed
+//! databases must come back as diagnostics, never as a panic.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use rust_server::data::{self, InvalidDataPolicy};
+use rust_server::index::FeatureIndex;
+
+
+fuzz_target!(|data: &[u8]| {
+    let diagnostics = data::validate(data);
+
+    match data::load_bytes(data, InvalidDataPolicy::Refuse) {
+        Ok((features, skipped)) => {
+            assert!(diagnostics.is_empty() && skipped.is_empty());
+            FeatureIndex::new(features);
+        },
+        Err(refused) => assert!(!refused.is_empty()),
+    }
+
+    if let Ok((features, skipped)) = data::load_bytes(data, InvalidDataPolicy::Skip) {
+        assert_eq!(skipped.len(), diagnostics.len());
+        FeatureIndex::new(features);
+    }
+
+    for diagnostic in diagnostics {
+        let _ = diagnostic.to_string();
+    }
+});
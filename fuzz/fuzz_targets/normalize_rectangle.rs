@@ -0,0 +1,77 @@
+//! Validates a Rectangle built from the input's first 20 bytes (the corners and a clustering
+//! zoom, little-endian) and checks that normalization keeps the area it describes: the same
+//! bounds, a south-west `lo`, and parts that together contain exactly its points.
+
+#![no_main]
+use std::convert::TryInto;
+
+use libfuzzer_sys::fuzz_target;
+
+use rust_server::geo::{self, Bounds};
+use rust_server::index::FeatureIndex;
+use rust_server::route_guide::{Clustering, Feature, Point, Rectangle};
+use rust_server::validation;
+
+
+fn point(latitude: i32, longitude: i32) -> Point {
+    Point { latitude, longitude, read_mask: None }
+}
+
+fn word(data: &[u8], i: usize) -> [u8; 4] {
+    data[i * 4..i * 4 + 4].try_into().unwrap()
+}
+
+fn int(data: &[u8], i: usize) -> i32 {
+    i32::from_le_bytes(word(data, i))
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 20 {
+        return;
+    }
+    let zoom = u32::from_le_bytes(word(data, 4));
+    let original = Rectangle {
+        lo: Some(point(int(data, 0), int(data, 1))),
+        hi: Some(point(int(data, 2), int(data, 3))),
+        cluster: Some(Clustering { zoom, max_features: 0 }),
+        read_mask: None,
+    };
+    let mut rect = original.clone();
+    if validation::validate(&mut rect).is_err() {
+        return;
+    }
+
+    let bounds = Bounds::of(&rect).unwrap();
+    assert_eq!(Some(bounds), Bounds::of(&original));
+    let (lo, hi) = (rect.lo.as_ref().unwrap(), rect.hi.as_ref().unwrap());
+    assert!(lo.latitude <= hi.latitude);
+
+    let parts = bounds.split();
+    assert!(parts.iter().all(|part| !part.crosses_antimeridian()));
+
+    // The corners, the middle and the points just outside, which differ between a rectangle
+    // across the antimeridian and one the long way around.
+    let latitudes = [
+        bounds.south, bounds.north, bounds.south / 2 + bounds.north / 2,
+        bounds.south.saturating_sub(1), bounds.north.saturating_add(1),
+    ];
+    let longitudes = [
+        bounds.west, bounds.east, bounds.west / 2 + bounds.east / 2,
+        bounds.west.saturating_sub(1), bounds.east.saturating_add(1), geo::MAX_LONGITUDE, -geo::MAX_LONGITUDE,
+    ];
+    let mut features = vec![];
+    for &latitude in &latitudes {
+        for &longitude in &longitudes {
+            let p = point(latitude, longitude);
+            assert_eq!(bounds.contains(&p), parts.iter().any(|part| part.contains(&p)));
+            assert_eq!(geo::in_range(&p, &rect), geo::in_range(&p, &original));
+            features.push(Feature { name: "probe".to_string(), location: Some(p), ..Feature::default() });
+        }
+    }
+
+    let index = FeatureIndex::new(features);
+    let inside = index.iter().filter(|feature| geo::in_range(feature.location.as_ref().unwrap(), &rect)).count();
+    assert_eq!(index.in_rectangle(&rect).count(), inside);
+    let clustered: u32 = index.clusters(&rect, zoom).iter().map(|cluster| cluster.cluster_size.max(1)).sum();
+    assert_eq!(clustered as usize, inside);
+});
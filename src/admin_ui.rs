@@ -0,0 +1,133 @@
+use std::convert::Infallible;
+use std::time::Duration;
+
+use futures::StreamExt as _;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::json;
+
+use crate::connections;
+use crate::gateway::query_param;
+use crate::log_filter;
+use crate::metrics;
+
+
+/// The page, embedded so the server has no files to find at runtime.
+pub const PAGE: &str = include_str!("../static/ui.html");
+
+/// How often the stats stream sends the metric values.
+pub const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+
+/// Browsers can't set headers on an `EventSource`, so the stats stream takes the admin token in
+/// the query string instead.
+fn is_admin(request: &Request<Body>) -> bool {
+    match std::env::var("ADMIN_TOKEN") {
+        Ok(token) if !token.is_empty() => query_param(request.uri().query(), "token") == Some(token.as_str()),
+        _ => false,
+    }
+}
+
+/// The admin token in an `authorization: Bearer <token>` header, for tools that can set one.
+fn is_admin_header(request: &Request<Body>) -> bool {
+    let header = request
+        .headers()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match std::env::var("ADMIN_TOKEN") {
+        Ok(token) if !token.is_empty() => header == Some(token.as_str()),
+        _ => false,
+    }
+}
+
+fn text_response(status: StatusCode, text: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(text));
+    *response.status_mut() = status;
+    response.headers_mut().insert("content-type", "text/plain; charset=utf-8".parse().unwrap());
+    response
+}
+
+/// `GET /admin/log-filter` returns the log filter and `PUT /admin/log-filter` replaces it with the
+/// directives in the body, e.g. `curl -X PUT -H "authorization: Bearer $ADMIN_TOKEN" -d
+/// info,rust_server::chat_hub=debug`. Both need the admin token.
+pub async fn log_filter(request: Request<Body>) -> Response<Body> {
+    if !is_admin_header(&request) {
+        return text_response(StatusCode::UNAUTHORIZED, "No valid admin token\n".to_string());
+    }
+
+    match *request.method() {
+        Method::GET => {},
+        Method::PUT => {
+            let body = match hyper::body::to_bytes(request.into_body()).await {
+                Ok(body) => body,
+                Err(e) => return text_response(StatusCode::BAD_REQUEST, format!("{}\n", e)),
+            };
+            let directives = String::from_utf8_lossy(&body);
+            if let Err(e) = log_filter::set(directives.trim()) {
+                return text_response(StatusCode::BAD_REQUEST, format!("{}\n", e));
+            }
+        },
+        _ => return text_response(StatusCode::METHOD_NOT_ALLOWED, "Use GET or PUT\n".to_string()),
+    }
+
+    match log_filter::current() {
+        Some(directives) => text_response(StatusCode::OK, format!("{}\n", directives)),
+        None => text_response(StatusCode::SERVICE_UNAVAILABLE, "No log filter is installed\n".to_string()),
+    }
+}
+
+fn connections_json() -> serde_json::Value {
+    let connections: Vec<_> = connections::registry()
+        .list()
+        .iter()
+        .map(|connection| json!({
+            "id": connection.id,
+            "peer": connection.peer.to_string(),
+            "tls_version": connection.tls.version,
+            "alpn": connection.tls.alpn,
+            "tls_fingerprint": connection.tls.fingerprint,
+            "user_agent": connection.user_agent,
+            "active_streams": connection.active_streams,
+            "total_rpcs": connection.total_rpcs,
+            "bytes_received": connection.bytes_received,
+            "bytes_sent": connection.bytes_sent,
+        }))
+        .collect();
+    serde_json::Value::from(connections)
+}
+
+fn stats_stream() -> Body {
+    let events = tokio::time::interval(STATS_INTERVAL).map(|_| {
+        let values = serde_json::to_string(&metrics::registry().values()).unwrap_or_default();
+        Ok::<_, Infallible>(format!(
+            "event: stats\ndata: {}\n\nevent: connections\ndata: {}\n\n",
+            values, connections_json()
+        ))
+    });
+    Body::wrap_stream(events)
+}
+
+/// Answers `GET /ui` (the page) and `GET /ui/stats` (server-sent events with the metric values
+/// and the open gRPC connections, for the admin). Returns `None` for other requests.
+pub fn handle(request: &Request<Body>) -> Option<Response<Body>> {
+    if request.method() != Method::GET {
+        return None;
+    }
+
+    let mut response = Response::new(Body::empty());
+    match request.uri().path() {
+        "/ui" => {
+            response.headers_mut().insert("content-type", "text/html; charset=utf-8".parse().unwrap());
+            *response.body_mut() = Body::from(PAGE);
+        },
+        "/ui/stats" if is_admin(request) => {
+            response.headers_mut().insert("content-type", "text/event-stream".parse().unwrap());
+            response.headers_mut().insert("cache-control", "no-cache".parse().unwrap());
+            *response.body_mut() = stats_stream();
+        },
+        "/ui/stats" => *response.status_mut() = StatusCode::UNAUTHORIZED,
+        _ => return None,
+    }
+
+    Some(response)
+}
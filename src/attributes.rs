@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use once_cell::sync::Lazy;
+use prost::Message;
+use prost_types::Any;
+use serde_json::{Map, Value};
+
+use crate::proto_json::ProtoJson;
+use crate::route_guide::{Feature, OpeningHours, Photo, Rating};
+
+
+/// What type URLs start with, before the full name of the message.
+pub const TYPE_URL_PREFIX: &str = "type.googleapis.com/";
+
+/// A message that can be attached to a Feature as one of its `attributes`.
+pub trait Attribute: Message + ProtoJson + Default {
+    /// The full name of the message, like `routeguide.v2.Rating`.
+    const TYPE_NAME: &'static str;
+    /// The key `get` and `set` use.
+    const KEY: &'static str;
+
+    fn type_url() -> String {
+        format!("{}{}", TYPE_URL_PREFIX, Self::TYPE_NAME)
+    }
+}
+
+impl Attribute for OpeningHours {
+    const TYPE_NAME: &'static str = "routeguide.v2.OpeningHours";
+    const KEY: &'static str = "opening_hours";
+}
+
+impl Attribute for Photo {
+    const TYPE_NAME: &'static str = "routeguide.v2.Photo";
+    const KEY: &'static str = "photo";
+}
+
+impl Attribute for Rating {
+    const TYPE_NAME: &'static str = "routeguide.v2.Rating";
+    const KEY: &'static str = "rating";
+}
+
+
+pub fn pack<A: Attribute>(value: &A) -> Any {
+    let mut bytes = Vec::with_capacity(value.encoded_len());
+    value.encode(&mut bytes).expect("a Vec has room for any message");
+    Any { type_url: A::type_url(), value: bytes }
+}
+
+/// `None` if `any` holds another type.
+pub fn unpack<A: Attribute>(any: &Any) -> Result<Option<A>, String> {
+    if any.type_url != A::type_url() {
+        return Ok(None);
+    }
+    A::decode(any.value.as_slice()).map(Some).map_err(|e| format!("{} doesn't decode: {}", A::TYPE_NAME, e))
+}
+
+/// The attribute at `A::KEY`, if the feature has one of that type there.
+pub fn get<A: Attribute>(feature: &Feature) -> Result<Option<A>, String> {
+    Ok(feature.attributes.get(A::KEY).map(unpack).transpose()?.flatten())
+}
+
+/// Attaches `value` at `A::KEY`, replacing what was there.
+pub fn set<A: Attribute>(feature: &mut Feature, value: &A) {
+    feature.attributes.insert(A::KEY.to_string(), pack(value));
+}
+
+/// Whether there was an attribute at `A::KEY`.
+pub fn remove<A: Attribute>(feature: &mut Feature) -> bool {
+    feature.attributes.remove(A::KEY).is_some()
+}
+
+
+impl OpeningHours {
+    /// Whether one of the periods covers `minute` after midnight on `weekday`, counting those of
+    /// the day before that run past midnight.
+    pub fn is_open(&self, weekday: u32, minute: u32) -> bool {
+        let yesterday = if weekday == 1 { 7 } else { weekday - 1 };
+        self.periods.iter().any(|period| {
+            (period.weekday == weekday && period.opens_minute <= minute && minute < period.closes_minute)
+                || (period.weekday == yesterday && minute + 24 * 60 < period.closes_minute)
+        })
+    }
+}
+
+
+/// How the JSON mapping converts one attribute type.
+struct Codec {
+    to_json: fn(&[u8]) -> Result<Value, String>,
+    from_json: fn(&Value) -> Result<Vec<u8>, String>,
+}
+
+fn to_json<A: Attribute>(bytes: &[u8]) -> Result<Value, String> {
+    A::decode(bytes).map(|value| value.to_json()).map_err(|e| format!("{} doesn't decode: {}", A::TYPE_NAME, e))
+}
+
+fn from_json<A: Attribute>(value: &Value) -> Result<Vec<u8>, String> {
+    let value = A::from_json(value)?;
+    let mut bytes = Vec::with_capacity(value.encoded_len());
+    value.encode(&mut bytes).expect("a Vec has room for any message");
+    Ok(bytes)
+}
+
+/// The attribute types the server knows, by type URL. Known attributes are checked when a
+/// feature is added and are written in JSON with their fields next to `@type`; unknown ones
+/// are kept as they are and written with their bytes in base64 as `value`.
+#[derive(Default)]
+pub struct AttributeTypes {
+    codecs: RwLock<HashMap<String, Codec>>,
+}
+
+impl AttributeTypes {
+    pub fn register<A: Attribute>(&self) {
+        self.codecs.write().unwrap().insert(A::type_url(), Codec { to_json: to_json::<A>, from_json: from_json::<A> });
+    }
+
+    pub fn is_known(&self, type_url: &str) -> bool {
+        self.codecs.read().unwrap().contains_key(type_url)
+    }
+
+    /// Fails if `any` is of a known type and doesn't decode as one.
+    pub fn check(&self, any: &Any) -> Result<(), String> {
+        match self.codecs.read().unwrap().get(&any.type_url) {
+            Some(codec) => (codec.to_json)(&any.value).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn to_json(&self, any: &Any) -> Value {
+        let known = self.codecs.read().unwrap().get(&any.type_url).map(|codec| (codec.to_json)(&any.value));
+        let mut fields = match known {
+            Some(Ok(Value::Object(fields))) => fields,
+            _ => {
+                let mut fields = Map::new();
+                fields.insert("value".to_string(), Value::from(base64::encode(&any.value)));
+                fields
+            },
+        };
+        fields.insert("@type".to_string(), Value::from(any.type_url.as_str()));
+        Value::Object(fields)
+    }
+
+    pub fn from_json(&self, value: &Value) -> Result<Any, String> {
+        let type_url = value
+            .get("@type")
+            .and_then(Value::as_str)
+            .ok_or_else(|| "an attribute must be an object with an @type".to_string())?
+            .to_string();
+        let from_json = self.codecs.read().unwrap().get(&type_url).map(|codec| codec.from_json);
+        let value = match from_json {
+            Some(from_json) => from_json(value)?,
+            None => {
+                let encoded = value.get("value").and_then(Value::as_str).unwrap_or("");
+                base64::decode(encoded).map_err(|_| format!("an attribute of unknown type {} needs its bytes in base64 as value", type_url))?
+            },
+        };
+        Ok(Any { type_url, value })
+    }
+}
+
+/// The attribute types known to this process: `OpeningHours`, `Photo` and `Rating`, and any
+/// registered since.
+pub fn registry() -> &'static AttributeTypes {
+    static TYPES: Lazy<AttributeTypes> = Lazy::new(|| {
+        let types = AttributeTypes::default();
+        types.register::<OpeningHours>();
+        types.register::<Photo>();
+        types.register::<Rating>();
+        types
+    });
+    &TYPES
+}
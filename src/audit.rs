@@ -0,0 +1,177 @@
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use prost::Message;
+
+use crate::admin::{audit_entry::Action, AuditEntry, AuditedFeature};
+use crate::route_guide::Feature;
+use crate::tenant::TenantId;
+
+
+/// Which entries a query returns. `None` doesn't filter.
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub tenant: Option<TenantId>,
+    /// Entries at or after this time.
+    pub since: Option<SystemTime>,
+    /// Entries before this time.
+    pub until: Option<SystemTime>,
+}
+
+impl AuditFilter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.tenant.as_ref().is_none_or(|tenant| entry.tenant == tenant.as_str())
+            && self.since.is_none_or(|since| entry.at_ms >= millis(since))
+            && self.until.is_none_or(|until| entry.at_ms < millis(until))
+    }
+}
+
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+fn audited(feature: &Feature) -> AuditedFeature {
+    let location = feature.location.clone().unwrap_or_default();
+    AuditedFeature {
+        name: feature.name.clone(),
+        latitude: location.latitude,
+        longitude: location.longitude,
+        description: feature.description.clone(),
+        tags: feature.tags.clone(),
+        id: feature.id.clone(),
+        names_by_locale: feature.names_by_locale.clone(),
+        attributes: feature.attributes.clone(),
+    }
+}
+
+/// An entry for a change made now. The log assigns the id when it's appended.
+pub fn entry(tenant: &TenantId, actor: &str, action: Action, before: Option<&Feature>, after: Option<&Feature>) -> AuditEntry {
+    let mut entry = AuditEntry {
+        id: 0,
+        tenant: tenant.to_string(),
+        actor: actor.to_string(),
+        at_ms: millis(SystemTime::now()),
+        action: 0,
+        before: before.map(audited),
+        after: after.map(audited),
+    };
+    entry.set_action(action);
+    entry
+}
+
+
+/// Where feature changes are recorded. Entries are only ever appended.
+///
+/// Tenants append the entry before they apply the change, so a change that couldn't be recorded
+/// is never made.
+pub trait AuditLog: Debug + Send + Sync {
+    /// Stores the entry and returns it with its id.
+    fn append(&self, entry: AuditEntry) -> io::Result<AuditEntry>;
+
+    /// The matching entries, oldest first.
+    fn query(&self, filter: &AuditFilter) -> io::Result<Vec<AuditEntry>>;
+}
+
+
+/// Keeps the log in memory only; it's gone after a restart.
+#[derive(Debug, Default)]
+pub struct MemoryAuditLog {
+    entries: Mutex<Vec<AuditEntry>>,
+}
+
+impl AuditLog for MemoryAuditLog {
+    fn append(&self, mut entry: AuditEntry) -> io::Result<AuditEntry> {
+        let mut entries = self.entries.lock().unwrap();
+        entry.id = entries.len() as u64 + 1;
+        entries.push(entry.clone());
+        Ok(entry)
+    }
+
+    fn query(&self, filter: &AuditFilter) -> io::Result<Vec<AuditEntry>> {
+        Ok(self.entries.lock().unwrap().iter().filter(|entry| filter.matches(entry)).cloned().collect())
+    }
+}
+
+
+/// An append-only file of records, each the length of the encoded entry (u32, little endian)
+/// and the protobuf encoded `AuditEntry`. Every append is synced before it returns.
+#[derive(Debug)]
+pub struct FileAuditLog {
+    path: PathBuf,
+    // The last id and the file, opened for appending.
+    state: Mutex<(u64, File)>,
+}
+
+impl FileAuditLog {
+    /// Opens the log, creating it if needed.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let last_id = read_log(&path)?.last().map_or(0, |entry| entry.id);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        Ok(FileAuditLog { path, state: Mutex::new((last_id, file)) })
+    }
+}
+
+/// Reads all records. A truncated record at the end (from a crash during an append) is ignored.
+fn read_log(path: &Path) -> io::Result<Vec<AuditEntry>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let mut reader = BufReader::new(file);
+    let mut entries = vec![];
+
+    loop {
+        let mut length = [0u8; 4];
+        match reader.read_exact(&mut length) {
+            Ok(()) => {},
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+
+        let mut encoded = vec![0u8; u32::from_le_bytes(length) as usize];
+        match reader.read_exact(&mut encoded) {
+            Ok(()) => {},
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+
+        entries.push(AuditEntry::decode(&encoded[..]).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?);
+    }
+
+    Ok(entries)
+}
+
+impl AuditLog for FileAuditLog {
+    fn append(&self, mut entry: AuditEntry) -> io::Result<AuditEntry> {
+        let mut state = self.state.lock().unwrap();
+        let (last_id, file) = &mut *state;
+        entry.id = *last_id + 1;
+
+        let mut encoded = Vec::with_capacity(entry.encoded_len());
+        entry.encode(&mut encoded).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        // One write per record, so a record is never split between two appends.
+        let mut record = Vec::with_capacity(4 + encoded.len());
+        record.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+        record.extend_from_slice(&encoded);
+        file.write_all(&record)?;
+        file.sync_data()?;
+
+        *last_id = entry.id;
+        Ok(entry)
+    }
+
+    fn query(&self, filter: &AuditFilter) -> io::Result<Vec<AuditEntry>> {
+        // Held so an append doesn't leave a half-written record at the end.
+        let _state = self.state.lock().unwrap();
+        Ok(read_log(&self.path)?.into_iter().filter(|entry| filter.matches(entry)).collect())
+    }
+}
@@ -0,0 +1,98 @@
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use tonic::body::BoxBody;
+use tonic::client::GrpcService;
+use tonic::codegen::{Body, HttpBody, StdError};
+use tonic::{Code, Request};
+
+use crate::metrics;
+use crate::route_guide::route_guide_client::RouteGuideClient;
+use crate::route_guide::ClientConfig;
+use crate::startup::RetrySchedule;
+
+
+/// The settings the server recommends through WatchConfig, kept up to date by `follow`. Until
+/// the first ones arrive, and for servers without WatchConfig, every recommendation is `None`
+/// and callers keep their own settings.
+#[derive(Debug, Clone, Default)]
+pub struct AutoTune {
+    config: Arc<RwLock<Option<ClientConfig>>>,
+}
+
+impl AutoTune {
+    pub fn config(&self) -> Option<ClientConfig> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// The server's deadline for `method`, like `GetFeature`, or its default one.
+    pub fn deadline(&self, method: &str) -> Option<Duration> {
+        let config = self.config.read().unwrap();
+        let config = config.as_ref()?;
+        let ms = config.method_deadlines_ms.get(method).copied().unwrap_or(config.default_deadline_ms);
+        if ms == 0 { None } else { Some(Duration::from_millis(ms.into())) }
+    }
+
+    /// `deadline`, or `fallback` without one.
+    pub fn deadline_or(&self, method: &str, fallback: Duration) -> Duration {
+        self.deadline(method).unwrap_or(fallback)
+    }
+
+    /// The largest message the server takes.
+    pub fn max_request_bytes(&self) -> Option<usize> {
+        self.config().map(|config| config.max_request_message_bytes as usize).filter(|&bytes| bytes > 0)
+    }
+
+    /// The largest message the server sends.
+    pub fn max_response_bytes(&self) -> Option<usize> {
+        self.config().map(|config| config.max_response_message_bytes as usize).filter(|&bytes| bytes > 0)
+    }
+
+    /// Calls a second, and the burst allowed after being idle.
+    pub fn rate_limit(&self) -> Option<(f64, u32)> {
+        self.config().filter(|config| config.max_calls_per_second > 0.0).map(|config| (config.max_calls_per_second, config.burst))
+    }
+
+    fn update(&self, config: ClientConfig) {
+        metrics::registry()
+            .counter("client_config_updates_total", "Client settings received from the server's WatchConfig.", &[])
+            .inc();
+        *self.config.write().unwrap() = Some(config);
+    }
+
+    /// Watches the server's settings for as long as the returned future runs, usually on a task
+    /// of its own. A broken stream is called again, waiting as `schedule` says and starting over
+    /// once the server answers; the last settings stay in force meanwhile. Ends if the server
+    /// doesn't have WatchConfig.
+    pub async fn follow<T>(self, mut client: RouteGuideClient<T>, schedule: RetrySchedule)
+        where
+            T: GrpcService<BoxBody>,
+            T::ResponseBody: Body + HttpBody + Send + 'static,
+            T::Error: Into<StdError>,
+            <T::ResponseBody as HttpBody>::Error: Into<StdError> + Send,
+    {
+        let mut attempt = 0;
+        loop {
+            match client.watch_config(Request::new(())).await {
+                Ok(response) => {
+                    let mut stream = response.into_inner();
+                    loop {
+                        match stream.message().await {
+                            Ok(Some(config)) => {
+                                attempt = 0;
+                                self.update(config);
+                            },
+                            Ok(None) => break,
+                            Err(status) if status.code() == Code::Unimplemented => return,
+                            Err(_) => break,
+                        }
+                    }
+                },
+                Err(status) if status.code() == Code::Unimplemented => return,
+                Err(_) => {},
+            }
+            attempt += 1;
+            tokio::time::delay_for(schedule.delay(attempt)).await;
+        }
+    }
+}
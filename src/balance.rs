@@ -0,0 +1,301 @@
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use rand::Rng;
+use tonic::body::BoxBody;
+use tonic::client::GrpcService;
+use tonic::codegen::http::{Request, Response};
+use tower::discover::{Change, Discover};
+use tower::Service;
+
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Weight of the newest sample in the moving averages.
+const DECAY: f64 = 0.2;
+
+/// Endpoints failing more often than this are only used when nothing else is left.
+pub const UNHEALTHY_ERROR_RATE: f64 = 0.5;
+
+
+/// Exponentially weighted latency and error rate of one endpoint.
+#[derive(Debug, Default)]
+pub struct EndpointStats {
+    inner: Mutex<StatsInner>,
+    in_flight: AtomicUsize,
+}
+
+#[derive(Debug, Default)]
+struct StatsInner {
+    latency: Option<f64>,
+    error_rate: f64,
+}
+
+impl EndpointStats {
+    fn record(&self, latency: Duration, failed: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        let sample = latency.as_secs_f64();
+        inner.latency = Some(match inner.latency {
+            Some(average) => average + DECAY * (sample - average),
+            None => sample,
+        });
+        let failure = if failed { 1.0 } else { 0.0 };
+        inner.error_rate += DECAY * (failure - inner.error_rate);
+    }
+
+    /// Endpoints that haven't answered yet count as instant, so they get tried.
+    pub fn latency(&self) -> Duration {
+        Duration::from_secs_f64(self.inner.lock().unwrap().latency.unwrap_or(0.0))
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        self.inner.lock().unwrap().error_rate
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.error_rate() < UNHEALTHY_ERROR_RATE
+    }
+
+    /// Expected wait for a new request: the average latency for every request ahead of it.
+    pub fn cost(&self) -> f64 {
+        self.latency().as_secs_f64() * (self.in_flight() + 1) as f64
+    }
+}
+
+
+/// Decides which endpoint gets the next request. `endpoints` is never empty.
+pub trait Policy: fmt::Debug + Send + Sync {
+    fn pick(&self, endpoints: &[&EndpointStats]) -> usize;
+}
+
+/// Every endpoint in turn, ignoring how they perform.
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl Policy for RoundRobin {
+    fn pick(&self, endpoints: &[&EndpointStats]) -> usize {
+        self.next.fetch_add(1, Ordering::Relaxed) % endpoints.len()
+    }
+}
+
+/// Power of two choices: the cheaper of two random endpoints, healthy ones first.
+#[derive(Debug, Default)]
+pub struct PowerOfTwoChoices;
+
+impl Policy for PowerOfTwoChoices {
+    fn pick(&self, endpoints: &[&EndpointStats]) -> usize {
+        if endpoints.len() == 1 {
+            return 0;
+        }
+
+        let mut rng = rand::thread_rng();
+        let a = rng.gen_range(0, endpoints.len());
+        let b = (a + rng.gen_range(1, endpoints.len())) % endpoints.len();
+
+        let key = |i: usize| (!endpoints[i].is_healthy(), endpoints[i].cost());
+        if key(b) < key(a) { b } else { a }
+    }
+}
+
+/// The fastest healthy endpoint. When all are unhealthy, the one failing least.
+#[derive(Debug, Default)]
+pub struct LatencyWeighted;
+
+impl Policy for LatencyWeighted {
+    fn pick(&self, endpoints: &[&EndpointStats]) -> usize {
+        let fastest_healthy = endpoints
+            .iter()
+            .enumerate()
+            .filter(|(_, stats)| stats.is_healthy())
+            .min_by(|(_, a), (_, b)| a.cost().partial_cmp(&b.cost()).unwrap())
+            .map(|(i, _)| i);
+
+        fastest_healthy.unwrap_or_else(|| {
+            endpoints
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.error_rate().partial_cmp(&b.error_rate()).unwrap())
+                .map_or(0, |(i, _)| i)
+        })
+    }
+}
+
+
+/// Names of the built in policies, for command line options.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PolicyKind {
+    RoundRobin,
+    PowerOfTwoChoices,
+    LatencyWeighted,
+}
+
+impl PolicyKind {
+    pub fn policy(self) -> Arc<dyn Policy> {
+        match self {
+            PolicyKind::RoundRobin        => Arc::new(RoundRobin::default()),
+            PolicyKind::PowerOfTwoChoices => Arc::new(PowerOfTwoChoices),
+            PolicyKind::LatencyWeighted   => Arc::new(LatencyWeighted),
+        }
+    }
+}
+
+impl FromStr for PolicyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round-robin" => Ok(PolicyKind::RoundRobin),
+            "p2c"         => Ok(PolicyKind::PowerOfTwoChoices),
+            "latency"     => Ok(PolicyKind::LatencyWeighted),
+            _ => Err(format!("unknown balancing policy '{}', expected round-robin, p2c or latency", s)),
+        }
+    }
+}
+
+
+struct Endpoint<K, S> {
+    key: K,
+    service: S,
+    stats: Arc<EndpointStats>,
+}
+
+/// Spreads requests over the services from `discover`, picking each one with `policy` and
+/// measuring how it answers.
+pub struct Balancer<D: Discover> {
+    discover: D,
+    policy: Arc<dyn Policy>,
+    endpoints: Vec<Endpoint<D::Key, D::Service>>,
+    ready: Option<usize>,
+}
+
+impl<D: Discover> Balancer<D> {
+    pub fn new(discover: D, policy: Arc<dyn Policy>) -> Self {
+        Balancer { discover, policy, endpoints: vec![], ready: None }
+    }
+
+    /// The stats of every endpoint, in discovery order.
+    pub fn stats(&self) -> impl Iterator<Item = (&D::Key, &EndpointStats)> {
+        self.endpoints.iter().map(|endpoint| (&endpoint.key, &*endpoint.stats))
+    }
+}
+
+impl<D> Balancer<D>
+    where
+        D: Discover + Unpin,
+        D::Key: Hash + Eq,
+        D::Error: Into<BoxError>,
+{
+    fn update_endpoints(&mut self, cx: &mut Context<'_>) -> Result<(), BoxError> {
+        while let Poll::Ready(change) = Pin::new(&mut self.discover).poll_discover(cx) {
+            match change.map_err(Into::into)? {
+                Change::Insert(key, service) => {
+                    self.endpoints.retain(|endpoint| endpoint.key != key);
+                    self.endpoints.push(Endpoint { key, service, stats: Arc::default() });
+                },
+                Change::Remove(key) => self.endpoints.retain(|endpoint| endpoint.key != key),
+            }
+            self.ready = None;
+        }
+        Ok(())
+    }
+}
+
+impl<D, B, RB> Service<Request<B>> for Balancer<D>
+    where
+        D: Discover + Unpin,
+        D::Key: Hash + Eq,
+        D::Error: Into<BoxError>,
+        D::Service: Service<Request<B>, Response = Response<RB>>,
+        <D::Service as Service<Request<B>>>::Error: Into<BoxError>,
+        <D::Service as Service<Request<B>>>::Future: Send + 'static,
+{
+    type Response = Response<RB>;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.update_endpoints(cx)?;
+
+        if self.ready.is_none() {
+            if self.endpoints.is_empty() {
+                return Poll::Pending;
+            }
+            let stats: Vec<_> = self.endpoints.iter().map(|endpoint| &*endpoint.stats).collect();
+            self.ready = Some(self.policy.pick(&stats));
+        }
+
+        let index = self.ready.unwrap();
+        let endpoint = &mut self.endpoints[index];
+        match endpoint.service.poll_ready(cx) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(())),
+            Poll::Ready(Err(e)) => {
+                // Let the next attempt pick again, with this failure counted.
+                endpoint.stats.record(Duration::from_secs(0), true);
+                self.ready = None;
+                Poll::Ready(Err(e.into()))
+            },
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let index = self.ready.take().expect("poll_ready must be called before call");
+        let endpoint = &mut self.endpoints[index];
+        let stats = endpoint.stats.clone();
+
+        stats.in_flight.fetch_add(1, Ordering::Relaxed);
+        let started = Instant::now();
+        let response = endpoint.service.call(request);
+
+        Box::pin(async move {
+            let result = response.await.map_err(Into::into);
+            stats.in_flight.fetch_sub(1, Ordering::Relaxed);
+
+            // Errors answered "trailers-only" carry the status in the headers; statuses in the
+            // trailers of a streamed response are not seen here.
+            let failed = match &result {
+                Ok(response) => {
+                    !response.status().is_success()
+                        || response.headers().get("grpc-status").is_some_and(|status| status != "0")
+                },
+                Err(_) => true,
+            };
+            stats.record(started.elapsed(), failed);
+            result
+        })
+    }
+}
+
+
+/// A tower `Service` over a tonic `GrpcService`, for endpoints like tonic's `Channel` that only
+/// implement the latter.
+#[derive(Debug, Clone)]
+pub struct GrpcEndpoint<S>(pub S);
+
+impl<S: GrpcService<BoxBody>> Service<Request<BoxBody>> for GrpcEndpoint<S> {
+    type Response = Response<S::ResponseBody>;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        GrpcService::poll_ready(&mut self.0, cx)
+    }
+
+    fn call(&mut self, request: Request<BoxBody>) -> Self::Future {
+        GrpcService::call(&mut self.0, request)
+    }
+}
@@ -0,0 +1,103 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+use memmap::Mmap;
+use prost::Message;
+
+use crate::route_guide::Feature;
+
+
+/// First bytes of a binary feature database.
+pub const MAGIC: &[u8; 8] = b"RGFEAT01";
+
+
+/// A binary feature database: `MAGIC` followed by length-delimited protobuf `Feature`s.
+///
+/// The file is memory-mapped and opening it only walks the length prefixes, so features are
+/// decoded when they're asked for.
+pub struct BinaryDb {
+    map: Mmap,
+    offsets: Vec<usize>,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Whether the file starts with `MAGIC`. Missing or short files are not binary databases.
+pub fn is_binary(path: impl AsRef<Path>) -> bool {
+    let mut magic = [0u8; 8];
+    File::open(path).and_then(|mut file| file.read_exact(&mut magic)).is_ok() && &magic == MAGIC
+}
+
+impl BinaryDb {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // Safe as long as nobody truncates the file while it's mapped; the database is only
+        // ever replaced, never written in place.
+        let map = unsafe { Mmap::map(&file)? };
+
+        if map.len() < MAGIC.len() || &map[..MAGIC.len()] != MAGIC {
+            return Err(invalid_data("not a binary feature database".to_string()));
+        }
+
+        let mut offsets = vec![];
+        let mut position = MAGIC.len();
+        while position < map.len() {
+            let length = prost::decode_length_delimiter(&map[position..])
+                .map_err(|e| invalid_data(format!("record {} at byte {}: {}", offsets.len(), position, e)))?;
+            let end = position + prost::length_delimiter_len(length) + length;
+            if end > map.len() {
+                return Err(invalid_data(format!("record {} at byte {} is truncated", offsets.len(), position)));
+            }
+            offsets.push(position);
+            position = end;
+        }
+
+        Ok(BinaryDb { map, offsets })
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Decodes the feature at `index`.
+    pub fn get(&self, index: usize) -> Option<Result<Feature, prost::DecodeError>> {
+        let offset = *self.offsets.get(index)?;
+        Some(Feature::decode_length_delimited(&self.map[offset..]))
+    }
+
+    /// Decodes the features one at a time, in file order.
+    pub fn iter(&self) -> impl Iterator<Item = Result<Feature, prost::DecodeError>> + '_ {
+        (0..self.len()).map(move |index| self.get(index).unwrap())
+    }
+
+    /// Decodes every feature.
+    pub fn features(&self) -> io::Result<Vec<Feature>> {
+        self.iter()
+            .enumerate()
+            .map(|(record, feature)| feature.map_err(|e| invalid_data(format!("record {}: {}", record, e))))
+            .collect()
+    }
+}
+
+
+/// Writes the features as a binary database.
+pub fn write(path: impl AsRef<Path>, features: &[Feature]) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(MAGIC)?;
+
+    let mut buffer = Vec::new();
+    for feature in features {
+        buffer.clear();
+        feature.encode_length_delimited(&mut buffer).map_err(|e| invalid_data(e.to_string()))?;
+        out.write_all(&buffer)?;
+    }
+
+    out.into_inner()?.sync_all()
+}
@@ -0,0 +1,188 @@
+use std::convert::TryInto;
+
+use tokio::runtime::{Builder, Runtime};
+use tonic::codegen::StdError;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Request, Status, Streaming};
+
+use crate::admin::Quota;
+use crate::client_error::ClientError;
+use crate::route_guide::route_guide_client::RouteGuideClient;
+use crate::route_guide::{
+    ChangeEvent, ExportRequest, Feature, FeatureChunk, ImportSummary, Point, PointWithTimestamp, Rectangle, ReplicateRequest,
+    ReplicationEvent, Room, RoomMembers, RouteNote, RouteSummary, TimeRange,
+};
+
+
+/// A RouteGuide client for code that isn't async. It owns a runtime of its own, on the calling
+/// thread, which only runs while one of its methods does; so there are no background tasks to
+/// keep a connection warm between calls.
+///
+/// Streamed responses come back as a `BlockingStream`, an iterator that waits for each message.
+/// Requests are plain messages, and client streams any iterator, which is read as the call
+/// needs it.
+#[derive(Debug)]
+pub struct RouteGuideBlockingClient {
+    client: RouteGuideClient<Channel>,
+    runtime: Runtime,
+}
+
+impl RouteGuideBlockingClient {
+    /// Connects to `destination`, like `http://[::1]:50051`.
+    pub fn connect<D>(destination: D) -> Result<Self, ClientError>
+        where
+            D: TryInto<Endpoint>,
+            D::Error: Into<StdError>,
+    {
+        let mut runtime = runtime()?;
+        let client = runtime.block_on(RouteGuideClient::connect(destination))?;
+        Ok(RouteGuideBlockingClient { client, runtime })
+    }
+
+    /// Wraps a client made some other way, e.g. with TLS or metadata set up. The channel is
+    /// driven by this client's runtime from then on, so it should be a lazy one, or one whose
+    /// runtime is still running.
+    pub fn new(client: RouteGuideClient<Channel>) -> Result<Self, ClientError> {
+        Ok(RouteGuideBlockingClient { client, runtime: runtime()? })
+    }
+
+    pub fn get_feature(&mut self, point: impl Into<Request<Point>>) -> Result<Feature, ClientError> {
+        let client = &mut self.client;
+        Ok(self.runtime.block_on(client.get_feature(point.into()))?.into_inner())
+    }
+
+    pub fn list_features(&mut self, rectangle: impl Into<Request<Rectangle>>) -> Result<BlockingStream<'_, Feature>, ClientError> {
+        let client = &mut self.client;
+        let stream = self.runtime.block_on(client.list_features(rectangle.into()))?.into_inner();
+        Ok(BlockingStream { runtime: &mut self.runtime, stream })
+    }
+
+    pub fn record_route<I>(&mut self, points: I) -> Result<RouteSummary, ClientError>
+        where
+            I: IntoIterator<Item = Point>,
+            I::IntoIter: Send + Sync + 'static,
+    {
+        let client = &mut self.client;
+        Ok(self.runtime.block_on(client.record_route(futures::stream::iter(points)))?.into_inner())
+    }
+
+    /// Sends `notes` while the server's notes arrive. The call ends once the iterator does and
+    /// the server has answered the last one.
+    pub fn route_chat<I>(&mut self, notes: I) -> Result<BlockingStream<'_, RouteNote>, ClientError>
+        where
+            I: IntoIterator<Item = RouteNote>,
+            I::IntoIter: Send + Sync + 'static,
+    {
+        let client = &mut self.client;
+        let stream = self.runtime.block_on(client.route_chat(futures::stream::iter(notes)))?.into_inner();
+        Ok(BlockingStream { runtime: &mut self.runtime, stream })
+    }
+
+    pub fn add_feature(&mut self, feature: impl Into<Request<Feature>>) -> Result<Feature, ClientError> {
+        let client = &mut self.client;
+        Ok(self.runtime.block_on(client.add_feature(feature.into()))?.into_inner())
+    }
+
+    pub fn delete_feature(&mut self, point: impl Into<Request<Point>>) -> Result<Feature, ClientError> {
+        let client = &mut self.client;
+        Ok(self.runtime.block_on(client.delete_feature(point.into()))?.into_inner())
+    }
+
+    pub fn get_notes_at(&mut self, point: impl Into<Request<Point>>) -> Result<BlockingStream<'_, RouteNote>, ClientError> {
+        let client = &mut self.client;
+        let stream = self.runtime.block_on(client.get_notes_at(point.into()))?.into_inner();
+        Ok(BlockingStream { runtime: &mut self.runtime, stream })
+    }
+
+    pub fn import_features<I>(&mut self, features: I) -> Result<ImportSummary, ClientError>
+        where
+            I: IntoIterator<Item = Feature>,
+            I::IntoIter: Send + Sync + 'static,
+    {
+        let client = &mut self.client;
+        Ok(self.runtime.block_on(client.import_features(futures::stream::iter(features)))?.into_inner())
+    }
+
+    pub fn get_feature_as_of(&mut self, request: impl Into<Request<PointWithTimestamp>>) -> Result<Feature, ClientError> {
+        let client = &mut self.client;
+        Ok(self.runtime.block_on(client.get_feature_as_of(request.into()))?.into_inner())
+    }
+
+    pub fn list_changes(&mut self, range: impl Into<Request<TimeRange>>) -> Result<BlockingStream<'_, ChangeEvent>, ClientError> {
+        let client = &mut self.client;
+        let stream = self.runtime.block_on(client.list_changes(range.into()))?.into_inner();
+        Ok(BlockingStream { runtime: &mut self.runtime, stream })
+    }
+
+    pub fn export_features(&mut self, request: impl Into<Request<ExportRequest>>) -> Result<BlockingStream<'_, FeatureChunk>, ClientError> {
+        let client = &mut self.client;
+        let stream = self.runtime.block_on(client.export_features(request.into()))?.into_inner();
+        Ok(BlockingStream { runtime: &mut self.runtime, stream })
+    }
+
+    pub fn replicate(&mut self, request: impl Into<Request<ReplicateRequest>>) -> Result<BlockingStream<'_, ReplicationEvent>, ClientError> {
+        let client = &mut self.client;
+        let stream = self.runtime.block_on(client.replicate(request.into()))?.into_inner();
+        Ok(BlockingStream { runtime: &mut self.runtime, stream })
+    }
+
+    pub fn list_room_members(&mut self, room: impl Into<Request<Room>>) -> Result<RoomMembers, ClientError> {
+        let client = &mut self.client;
+        Ok(self.runtime.block_on(client.list_room_members(room.into()))?.into_inner())
+    }
+
+    /// The caller's own usage and limits.
+    pub fn get_quota(&mut self) -> Result<Quota, ClientError> {
+        let client = &mut self.client;
+        Ok(self.runtime.block_on(client.get_quota(Request::new(())))?.into_inner())
+    }
+
+    /// The async client underneath, for what this one doesn't cover. Its calls have to be run
+    /// on `block_on`.
+    pub fn inner(&mut self) -> &mut RouteGuideClient<Channel> {
+        &mut self.client
+    }
+
+    /// Runs `future` on this client's runtime until it's done.
+    pub fn block_on<F: std::future::Future>(&mut self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+}
+
+/// One thread, driven by whichever call is waiting.
+fn runtime() -> Result<Runtime, ClientError> {
+    Builder::new().basic_scheduler().enable_all().build().map_err(|e| ClientError::Runtime(e.to_string()))
+}
+
+
+/// The messages of a streamed response, each waited for as it's asked for. It borrows the
+/// client, since the client's runtime is what runs the call; dropping it cancels the call.
+#[derive(Debug)]
+pub struct BlockingStream<'a, T> {
+    runtime: &'a mut Runtime,
+    stream: Streaming<T>,
+}
+
+impl<'a, T> BlockingStream<'a, T> {
+    /// The rest of the messages, failing with the first error.
+    pub fn collect_all(self) -> Result<Vec<T>, ClientError> {
+        self.collect()
+    }
+
+    /// The response's trailers, once every message has been read.
+    pub fn trailers(&mut self) -> Result<Option<tonic::metadata::MetadataMap>, ClientError> {
+        let stream = &mut self.stream;
+        Ok(self.runtime.block_on(stream.trailers())?)
+    }
+}
+
+impl<'a, T> Iterator for BlockingStream<'a, T> {
+    type Item = Result<T, ClientError>;
+
+    /// `None` once the server has ended the stream.
+    fn next(&mut self) -> Option<Self::Item> {
+        let stream = &mut self.stream;
+        let message: Result<Option<T>, Status> = self.runtime.block_on(stream.message());
+        message.map_err(ClientError::from).transpose()
+    }
+}
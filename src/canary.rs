@@ -0,0 +1,186 @@
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+
+use futures::future::BoxFuture;
+use rand::Rng;
+use tonic::codegen::http::{HeaderMap, Request};
+use tower::Service;
+
+use crate::metrics;
+
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Request metadata that picks the pool of a single call: `true` for the canary, `false` for the
+/// primary endpoints.
+pub const CANARY_HEADER: &str = "x-canary";
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Pool {
+    Primary,
+    Canary,
+}
+
+impl Pool {
+    pub fn name(self) -> &'static str {
+        match self {
+            Pool::Primary => "primary",
+            Pool::Canary => "canary",
+        }
+    }
+}
+
+
+/// Sends calls whose metadata `key` equals `value` to `pool`, whatever the weight says.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataOverride {
+    pub key: String,
+    pub value: String,
+    pub pool: Pool,
+}
+
+impl FromStr for MetadataOverride {
+    type Err = String;
+
+    /// `<key>=<value>:<pool>`, like `x-user=qa:canary`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid override '{}', expected <key>=<value>:<primary|canary>", s);
+        let (rule, pool) = match s.rfind(':') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => return Err(invalid()),
+        };
+        let (key, value) = match rule.find('=') {
+            Some(i) => (&rule[..i], &rule[i + 1..]),
+            None => return Err(invalid()),
+        };
+        let pool = match pool {
+            "primary" => Pool::Primary,
+            "canary" => Pool::Canary,
+            _ => return Err(invalid()),
+        };
+        if key.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(MetadataOverride { key: key.to_ascii_lowercase(), value: value.to_string(), pool })
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CanaryConfig {
+    /// The share of calls, from 0 to 1, sent to the canary when no override matches.
+    pub weight: f64,
+    /// Tried in order; the first match wins.
+    pub overrides: Vec<MetadataOverride>,
+}
+
+impl Default for CanaryConfig {
+    /// No calls go to the canary unless they ask for it with `CANARY_HEADER`.
+    fn default() -> Self {
+        let header = |value: &str, pool| MetadataOverride { key: CANARY_HEADER.to_string(), value: value.to_string(), pool };
+        CanaryConfig { weight: 0.0, overrides: vec![header("true", Pool::Canary), header("false", Pool::Primary)] }
+    }
+}
+
+impl CanaryConfig {
+    pub fn route(&self, headers: &HeaderMap) -> Pool {
+        let matched = self.overrides.iter().find(|rule| {
+            headers.get(rule.key.as_str()).and_then(|value| value.to_str().ok()) == Some(rule.value.as_str())
+        });
+        match matched {
+            Some(rule) => rule.pool,
+            None if rand::thread_rng().gen::<f64>() < self.weight => Pool::Canary,
+            None => Pool::Primary,
+        }
+    }
+}
+
+
+/// Changes the routing of a running `CanaryRouter`, e.g. to raise the canary's weight step by
+/// step during a rollout.
+#[derive(Debug, Clone, Default)]
+pub struct CanaryControl(Arc<RwLock<CanaryConfig>>);
+
+impl CanaryControl {
+    pub fn new(config: CanaryConfig) -> Self {
+        CanaryControl(Arc::new(RwLock::new(config)))
+    }
+
+    pub fn config(&self) -> CanaryConfig {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn set(&self, config: CanaryConfig) {
+        *self.0.write().unwrap() = config;
+    }
+
+    /// Clamped to 0 to 1, and NaN is 0, which `clamp` would keep.
+    #[allow(clippy::manual_clamp)]
+    pub fn set_weight(&self, weight: f64) {
+        self.0.write().unwrap().weight = weight.max(0.0).min(1.0);
+    }
+}
+
+
+/// Routes each call to the primary or the canary service, normally a `Balancer` over each list
+/// of endpoints. While the canary isn't ready, its calls go to the primary service.
+pub struct CanaryRouter<S> {
+    primary: S,
+    canary: Option<S>,
+    canary_ready: bool,
+    control: CanaryControl,
+}
+
+impl<S> CanaryRouter<S> {
+    /// Without a `canary` every call goes to `primary`.
+    pub fn new(primary: S, canary: Option<S>, control: CanaryControl) -> Self {
+        CanaryRouter { primary, canary, canary_ready: false, control }
+    }
+
+    pub fn control(&self) -> &CanaryControl {
+        &self.control
+    }
+}
+
+impl<S, B> Service<Request<B>> for CanaryRouter<S>
+    where
+        S: Service<Request<B>>,
+        S::Error: Into<BoxError>,
+        S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // A canary that's down or still connecting must not hold up the primary calls.
+        self.canary_ready = match &mut self.canary {
+            Some(canary) => matches!(canary.poll_ready(cx), Poll::Ready(Ok(()))),
+            None => false,
+        };
+        self.primary.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let pool = match self.control.0.read().unwrap().route(request.headers()) {
+            Pool::Canary if self.canary_ready => Pool::Canary,
+            _ => Pool::Primary,
+        };
+        metrics::registry()
+            .counter("client_canary_requests_total", "Client calls by the pool they were routed to.", &[("pool", pool.name())])
+            .inc();
+
+        let response = match (pool, &mut self.canary) {
+            (Pool::Canary, Some(canary)) => {
+                self.canary_ready = false;
+                canary.call(request)
+            },
+            _ => self.primary.call(request),
+        };
+        Box::pin(async move { response.await.map_err(Into::into) })
+    }
+}
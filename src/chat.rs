@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tonic::{Request, Status};
+
+use crate::route_guide::RouteNote;
+
+
+/// Request metadata naming the chat client. Sequence numbers are tracked per client, so a client
+/// must keep its id across reconnects.
+pub const CLIENT_ID_HEADER: &str = "x-chat-client-id";
+
+/// Response metadata with the last sequence number the server has from the client, so a
+/// reconnecting client knows where to resume.
+pub const LAST_SEQUENCE_HEADER: &str = "x-chat-last-sequence";
+
+/// Request metadata a RouteChat client sets to `true` to get heartbeats. Streams without it get
+/// none, as do streams under names whose RouteNote has no `heartbeat` field.
+pub const HEARTBEATS_HEADER: &str = "x-chat-heartbeats";
+
+const MAX_CLIENT_ID_LENGTH: usize = 64;
+
+
+/// The client id of a RouteChat call, if it sent one.
+pub fn client_id<T>(request: &Request<T>) -> Result<Option<String>, Status> {
+    let value = match request.metadata().get(CLIENT_ID_HEADER) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+
+    let id = value
+        .to_str()
+        .ok()
+        .filter(|id| !id.is_empty() && id.len() <= MAX_CLIENT_ID_LENGTH)
+        .ok_or_else(|| Status::invalid_argument(format!(
+            "{} must be 1 to {} visible ASCII characters", CLIENT_ID_HEADER, MAX_CLIENT_ID_LENGTH
+        )))?;
+
+    Ok(Some(id.to_string()))
+}
+
+
+/// Whether a RouteChat call asked for heartbeats.
+pub fn wants_heartbeats<T>(request: &Request<T>) -> bool {
+    request.metadata().get(HEARTBEATS_HEADER).and_then(|value| value.to_str().ok()) == Some("true")
+}
+
+
+/// The highest sequence number seen from each chat client.
+#[derive(Debug, Default)]
+pub struct ChatSequences {
+    last: Mutex<HashMap<String, u64>>,
+}
+
+impl ChatSequences {
+    /// 0 if nothing has been received from the client.
+    pub fn last(&self, client: &str) -> u64 {
+        self.last.lock().unwrap().get(client).copied().unwrap_or(0)
+    }
+
+    /// Whether a note is new. Notes without a sequence number (0) are always new; others only if
+    /// their number is higher than any seen before, which then becomes the last one.
+    pub fn accept(&self, client: &str, sequence: u64) -> bool {
+        if sequence == 0 {
+            return true;
+        }
+
+        let mut last = self.last.lock().unwrap();
+        let last = last.entry(client.to_string()).or_insert(0);
+        if sequence <= *last {
+            return false;
+        }
+        *last = sequence;
+        true
+    }
+}
+
+
+/// Heartbeats on RouteChat streams that asked for them. The server sends one after `interval`
+/// without sending or hearing anything, and the client answers it. A stream whose client stops answering is closed
+/// after `max_missed` intervals of silence, so a dead client doesn't stay subscribed to the chat.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Heartbeats {
+    pub interval: Duration,
+    pub max_missed: u32,
+}
+
+/// A heartbeat note.
+pub fn heartbeat() -> RouteNote {
+    RouteNote { heartbeat: true, ..RouteNote::default() }
+}
+
+/// When one RouteChat stream last sent and heard something.
+///
+/// Only clients that have answered a heartbeat are held to them, so older clients that ignore
+/// them aren't cut off.
+#[derive(Debug)]
+pub struct Liveness {
+    heartbeats: Heartbeats,
+    last_sent: Instant,
+    last_heard: Instant,
+    answers: bool,
+}
+
+impl Liveness {
+    pub fn new(heartbeats: Heartbeats) -> Self {
+        let now = Instant::now();
+        Liveness { heartbeats, last_sent: now, last_heard: now, answers: false }
+    }
+
+    pub fn sent(&mut self) {
+        self.last_sent = Instant::now();
+    }
+
+    pub fn heard(&mut self, note: &RouteNote) {
+        self.last_heard = Instant::now();
+        self.answers |= note.heartbeat;
+    }
+
+    /// Whether the stream has been quiet long enough for a heartbeat.
+    pub fn idle(&self) -> bool {
+        let interval = self.heartbeats.interval;
+        self.last_sent.elapsed() >= interval || self.last_heard.elapsed() >= interval
+    }
+
+    /// The heartbeats the client has missed, if it's missed too many.
+    pub fn missed(&self) -> Option<u32> {
+        let missed = (self.last_heard.elapsed().as_millis() / self.heartbeats.interval.as_millis().max(1)) as u32;
+        Some(missed).filter(|&missed| self.answers && missed >= self.heartbeats.max_missed)
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+use tonic::Status;
+
+use crate::metrics;
+use crate::route_guide::{RoomMembers, RouteNote};
+
+
+/// What happens when a subscriber's queue is full and another note arrives.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SlowConsumerPolicy {
+    /// The oldest queued note is dropped to make room.
+    DropOldest,
+    /// The new note is dropped.
+    DropNewest,
+    /// The subscriber's call fails with RESOURCE_EXHAUSTED.
+    Disconnect,
+}
+
+impl SlowConsumerPolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            SlowConsumerPolicy::DropOldest => "drop-oldest",
+            SlowConsumerPolicy::DropNewest => "drop-newest",
+            SlowConsumerPolicy::Disconnect => "disconnect",
+        }
+    }
+}
+
+impl FromStr for SlowConsumerPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(SlowConsumerPolicy::DropOldest),
+            "drop-newest" => Ok(SlowConsumerPolicy::DropNewest),
+            "disconnect"  => Ok(SlowConsumerPolicy::Disconnect),
+            other         => Err(format!("unknown policy '{}' (expected drop-oldest, drop-newest or disconnect)", other)),
+        }
+    }
+}
+
+
+#[derive(Debug, Copy, Clone)]
+pub struct HubConfig {
+    /// Notes queued per subscriber before the policy applies.
+    pub queue_size: usize,
+    pub policy: SlowConsumerPolicy,
+    /// Notes kept per room, for those who join it later.
+    pub room_history: usize,
+    /// Rooms per tenant. Past it, the room posted to longest ago that nobody is in is
+    /// forgotten for a new one, or, if every room has someone in it, joining a new one fails.
+    pub max_rooms: usize,
+}
+
+impl Default for HubConfig {
+    fn default() -> Self {
+        HubConfig { queue_size: 64, policy: SlowConsumerPolicy::DropOldest, room_history: 100, max_rooms: 1000 }
+    }
+}
+
+
+#[derive(Debug, Default)]
+struct QueueState {
+    notes: VecDeque<RouteNote>,
+    disconnected: bool,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    state: Mutex<QueueState>,
+    // Holds a permit if notes arrived while nobody waited, so none is missed.
+    ready: Notify,
+}
+
+#[derive(Debug, Default)]
+struct Members {
+    queues: HashMap<u64, Arc<Queue>>,
+    /// The subscriptions of each user, for direct notes. A user may be in the chat more than
+    /// once, e.g. from two devices.
+    by_user: HashMap<String, HashSet<u64>>,
+    rooms: HashMap<String, Room>,
+}
+
+#[derive(Debug, Default)]
+struct Room {
+    members: HashSet<u64>,
+    /// The newest notes, oldest first.
+    history: VecDeque<RouteNote>,
+}
+
+impl Room {
+    fn last_posted_at_ms(&self) -> u64 {
+        self.history.back().map_or(0, |note| note.posted_at_ms)
+    }
+}
+
+type Subscribers = Arc<Mutex<Members>>;
+
+
+/// Fans the notes posted to RouteChat out to the tenant's other participants, or for room notes
+/// to the others in the room. Every subscriber has a queue of its own, so memory stays bounded
+/// however slow a subscriber reads.
+///
+/// Rooms are made by joining them, and kept while someone is in them or their history has
+/// notes. Their history is in memory only, unlike the notes at locations.
+#[derive(Debug)]
+pub struct ChatHub {
+    config: HubConfig,
+    next_id: AtomicU64,
+    subscribers: Subscribers,
+}
+
+impl ChatHub {
+    pub fn new(config: HubConfig) -> Self {
+        ChatHub { config, next_id: AtomicU64::new(1), subscribers: Subscribers::default() }
+    }
+
+    pub fn config(&self) -> HubConfig {
+        self.config
+    }
+
+    /// Joins the chat as `user`, the authenticated identity of the call. Leaves when the
+    /// subscription is dropped.
+    pub fn subscribe(&self, user: &str) -> Subscription {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let queue = Arc::new(Queue::default());
+        let mut members = self.subscribers.lock().unwrap();
+        members.queues.insert(id, queue.clone());
+        members.by_user.entry(user.to_string()).or_default().insert(id);
+        drop(members);
+
+        Subscription { id, user: user.to_string(), queue, subscribers: self.subscribers.clone() }
+    }
+
+    /// Puts the subscription in the room, if it isn't already, and returns the room's history.
+    /// Fails with RESOURCE_EXHAUSTED for a new room when there are `max_rooms` that can't be
+    /// forgotten.
+    pub fn join(&self, subscription: &Subscription, name: &str) -> Result<Vec<RouteNote>, Status> {
+        let mut members = self.subscribers.lock().unwrap();
+        if !members.rooms.contains_key(name) && members.rooms.len() >= self.config.max_rooms {
+            let idle = members.rooms
+                .iter()
+                .filter(|(_, room)| room.members.is_empty())
+                .min_by_key(|(_, room)| room.last_posted_at_ms())
+                .map(|(name, _)| name.clone())
+                .ok_or_else(|| Status::resource_exhausted(format!("there are already {} chat rooms", self.config.max_rooms)))?;
+            members.rooms.remove(&idle);
+        }
+
+        let room = members.rooms.entry(name.to_string()).or_default();
+        room.members.insert(subscription.id);
+        Ok(room.history.iter().cloned().collect())
+    }
+
+    /// Who is in the room, if it's kept.
+    pub fn room(&self, name: &str) -> Option<RoomMembers> {
+        let members = self.subscribers.lock().unwrap();
+        let room = members.rooms.get(name)?;
+        let mut users: Vec<_> = members.by_user
+            .iter()
+            .filter(|(_, ids)| !ids.is_disjoint(&room.members))
+            .map(|(user, _)| user.clone())
+            .collect();
+        users.sort();
+
+        Some(RoomMembers { room: name.to_string(), members: users, history_size: room.history.len() as u32 })
+    }
+
+    /// The notes kept for the room, oldest first.
+    pub fn history(&self, name: &str) -> Vec<RouteNote> {
+        let members = self.subscribers.lock().unwrap();
+        members.rooms.get(name).map_or_else(Vec::new, |room| room.history.iter().cloned().collect())
+    }
+
+    /// Queues the note for every subscriber except its sender, with `room` set only for the
+    /// others in the room, which must have been joined and keeps the note in its history, or
+    /// with `to_user` set only for that user's subscriptions. Returns how many subscribers it
+    /// was queued for.
+    pub fn publish(&self, from: &Subscription, note: &RouteNote) -> usize {
+        let mut guard = self.subscribers.lock().unwrap();
+        let members = &mut *guard;
+        let queues: Vec<_> = if !note.to_user.is_empty() {
+            members.by_user
+                .get(&note.to_user)
+                .into_iter()
+                .flatten()
+                .filter(|id| **id != from.id)
+                .filter_map(|id| members.queues.get(id).cloned())
+                .collect()
+        } else if !note.room.is_empty() {
+            let room = match members.rooms.get_mut(&note.room) {
+                Some(room) => room,
+                None => return 0,
+            };
+            if self.config.room_history > 0 {
+                while room.history.len() >= self.config.room_history {
+                    room.history.pop_front();
+                }
+                room.history.push_back(note.clone());
+            }
+            let queues = &members.queues;
+            room.members
+                .iter()
+                .filter(|id| **id != from.id)
+                .filter_map(|id| queues.get(id).cloned())
+                .collect()
+        } else {
+            members.queues
+                .iter()
+                .filter(|(id, _)| **id != from.id)
+                .map(|(_, queue)| queue.clone())
+                .collect()
+        };
+        drop(guard);
+
+        tracing::debug!(sender = %note.sender, to = %note.to_user, room = %note.room, subscribers = queues.len(), "fanning out a RouteChat note");
+        for queue in &queues {
+            self.push(queue, note);
+        }
+        queues.len()
+    }
+
+    fn push(&self, queue: &Queue, note: &RouteNote) {
+        let mut state = queue.state.lock().unwrap();
+        if state.disconnected {
+            return;
+        }
+
+        if state.notes.len() >= self.config.queue_size {
+            dropped_notes(self.config.policy).inc();
+            match self.config.policy {
+                SlowConsumerPolicy::DropOldest => { state.notes.pop_front(); },
+                SlowConsumerPolicy::DropNewest => return,
+                SlowConsumerPolicy::Disconnect => {
+                    tracing::warn!(queue_size = self.config.queue_size, "disconnecting a slow RouteChat subscriber");
+                    metrics::registry()
+                        .counter("route_chat_slow_disconnects_total", "RouteChat calls ended for reading too slowly.", &[])
+                        .inc();
+                    state.notes.clear();
+                    state.disconnected = true;
+                    drop(state);
+                    queue.ready.notify();
+                    return;
+                },
+            }
+        }
+
+        state.notes.push_back(note.clone());
+        drop(state);
+        queue.ready.notify();
+    }
+}
+
+fn dropped_notes(policy: SlowConsumerPolicy) -> Arc<metrics::Counter> {
+    metrics::registry().counter(
+        "route_chat_dropped_notes_total",
+        "RouteChat notes not delivered because a subscriber's queue was full.",
+        &[("policy", policy.name())],
+    )
+}
+
+
+/// A participant of the chat.
+#[derive(Debug)]
+pub struct Subscription {
+    id: u64,
+    user: String,
+    queue: Arc<Queue>,
+    subscribers: Subscribers,
+}
+
+impl Subscription {
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    /// The next note from someone else. Fails with RESOURCE_EXHAUSTED if the subscriber fell so
+    /// far behind that the `Disconnect` policy dropped it.
+    pub async fn recv(&self) -> Result<RouteNote, Status> {
+        loop {
+            {
+                let mut state = self.queue.state.lock().unwrap();
+                if let Some(note) = state.notes.pop_front() {
+                    return Ok(note);
+                }
+                if state.disconnected {
+                    return Err(Status::resource_exhausted("too slow to keep up with the chat"));
+                }
+            }
+            self.queue.ready.notified().await;
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut members = self.subscribers.lock().unwrap();
+        members.queues.remove(&self.id);
+        if let Some(ids) = members.by_user.get_mut(&self.user) {
+            ids.remove(&self.id);
+            if ids.is_empty() {
+                members.by_user.remove(&self.user);
+            }
+        }
+        let id = self.id;
+        members.rooms.retain(|_, room| {
+            room.members.remove(&id);
+            !room.members.is_empty() || !room.history.is_empty()
+        });
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::time::Duration;
+
+use tokio::sync::{mpsc, watch};
+use tonic::body::BoxBody;
+use tonic::client::GrpcService;
+use tonic::codegen::{Body, HttpBody, StdError};
+use tonic::metadata::MetadataValue;
+use tonic::{Code, Request, Status};
+
+use crate::chat;
+use crate::route_guide::route_guide_client::RouteGuideClient;
+use crate::route_guide::{Point, RouteNote};
+
+
+/// Where a `ChatSession` is, for the application to show.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    /// The stream broke with `error`; this is the `attempt`th try to get it back, from 1.
+    Reconnecting { attempt: u32, error: String },
+    /// The application closed the session and the server has answered everything.
+    Closed,
+    /// Given up, after an error reconnecting won't fix or too many attempts.
+    Failed(String),
+}
+
+/// How a `ChatSession` reconnects. The wait before each attempt doubles from `backoff` up to
+/// `max_backoff`, and starts over once connected.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    pub max_attempts: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// The most notes kept for resending; older ones are dropped first.
+    pub max_queued: usize,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy { max_attempts: 5, backoff: Duration::from_secs(1), max_backoff: Duration::from_secs(30), max_queued: 1000 }
+    }
+}
+
+/// Errors from a broken connection rather than from the call, including the server closing a
+/// stream whose heartbeats went unanswered.
+fn is_transport_error(status: &Status) -> bool {
+    matches!(status.code(), Code::Unavailable | Code::Unknown | Code::DeadlineExceeded)
+}
+
+
+#[derive(Debug)]
+enum Outgoing {
+    Note(RouteNote),
+    Goto(Point),
+    Join(String),
+}
+
+/// Sends into a `ChatSession`; there can be several. The session's stream is closed once all of
+/// them are dropped.
+#[derive(Debug, Clone)]
+pub struct ChatSender {
+    outgoing: mpsc::UnboundedSender<Outgoing>,
+}
+
+impl ChatSender {
+    /// Queues a note. One without a location is sent from the last `goto`. Returns false once the
+    /// session is over.
+    pub fn send(&self, note: RouteNote) -> bool {
+        self.outgoing.send(Outgoing::Note(note)).is_ok()
+    }
+
+    /// Moves to `location`, telling the server, which answers with the notes there.
+    pub fn goto(&self, location: Point) -> bool {
+        self.outgoing.send(Outgoing::Goto(location)).is_ok()
+    }
+
+    /// Joins a chat room, which the server answers with the room's history. Notes with `room`
+    /// set join it too.
+    pub fn join(&self, room: &str) -> bool {
+        self.outgoing.send(Outgoing::Join(room.to_string())).is_ok()
+    }
+}
+
+/// The application's side of a `ChatSession`.
+#[derive(Debug)]
+pub struct ChatHandle {
+    pub sender: ChatSender,
+    /// The notes from the server. Heartbeats are answered by the session and left out.
+    pub received: mpsc::UnboundedReceiver<RouteNote>,
+    pub state: watch::Receiver<ConnectionState>,
+}
+
+
+/// A RouteChat call that outlives its streams: when one breaks, the session opens another,
+/// tells the server the current location and rooms again and resends the notes the server
+/// didn't get.
+///
+/// Notes are numbered for the server's `x-chat-last-sequence` (see `chat::ChatSequences`), so
+/// the client id has to stay the same for as long as the server should recognize them.
+#[derive(Debug)]
+pub struct ChatSession {
+    client_id: String,
+    policy: ReconnectPolicy,
+    outgoing: mpsc::UnboundedReceiver<Outgoing>,
+    received: mpsc::UnboundedSender<RouteNote>,
+    state: watch::Sender<ConnectionState>,
+    location: Option<Point>,
+    rooms: BTreeSet<String>,
+    next_sequence: u64,
+    /// Numbered notes not yet known to have reached the server, oldest first.
+    unacked: VecDeque<RouteNote>,
+    closing: bool,
+}
+
+/// A note that only says where the client is.
+fn presence(location: &Point) -> RouteNote {
+    RouteNote { location: Some(location.clone()), ..RouteNote::default() }
+}
+
+/// A note that only joins a room.
+fn joining(room: &str) -> RouteNote {
+    RouteNote { room: room.to_string(), ..RouteNote::default() }
+}
+
+impl ChatSession {
+    pub fn new(client_id: &str, policy: ReconnectPolicy) -> (ChatSession, ChatHandle) {
+        let (sender, outgoing) = mpsc::unbounded_channel();
+        let (received, receiver) = mpsc::unbounded_channel();
+        let (state, states) = watch::channel(ConnectionState::Connecting);
+
+        let session = ChatSession {
+            client_id: client_id.to_string(),
+            policy,
+            outgoing,
+            received,
+            state,
+            location: None,
+            rooms: BTreeSet::new(),
+            next_sequence: 1,
+            unacked: VecDeque::new(),
+            closing: false,
+        };
+        (session, ChatHandle { sender: ChatSender { outgoing: sender }, received: receiver, state: states })
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        let _ = self.state.broadcast(state);
+    }
+
+    /// Chats until every `ChatSender` is dropped and the server has ended the call, reconnecting
+    /// as the policy allows.
+    pub async fn run<T>(mut self, client: &mut RouteGuideClient<T>) -> Result<(), Status>
+        where
+            T: GrpcService<BoxBody>,
+            T::ResponseBody: Body + HttpBody + Send + 'static,
+            T::Error: Into<StdError>,
+            <T::ResponseBody as HttpBody>::Error: Into<StdError> + Send,
+    {
+        let mut attempt = 0;
+        loop {
+            let error = match self.connect(client, &mut attempt).await {
+                Ok(()) => {
+                    self.set_state(ConnectionState::Closed);
+                    return Ok(());
+                },
+                Err(error) => error,
+            };
+            if !is_transport_error(&error) || attempt >= self.policy.max_attempts {
+                self.set_state(ConnectionState::Failed(error.message().to_string()));
+                return Err(error);
+            }
+
+            attempt += 1;
+            self.set_state(ConnectionState::Reconnecting { attempt, error: error.message().to_string() });
+            let backoff = self.policy.backoff * 2u32.saturating_pow(attempt - 1);
+            tokio::time::delay_for(backoff.min(self.policy.max_backoff)).await;
+        }
+    }
+
+    /// One stream: from connecting until it ends or breaks.
+    async fn connect<T>(&mut self, client: &mut RouteGuideClient<T>, attempt: &mut u32) -> Result<(), Status>
+        where
+            T: GrpcService<BoxBody>,
+            T::ResponseBody: Body + HttpBody + Send + 'static,
+            T::Error: Into<StdError>,
+            <T::ResponseBody as HttpBody>::Error: Into<StdError> + Send,
+    {
+        let (outbound, stream) = mpsc::unbounded_channel();
+        let mut request = Request::new(stream);
+        let client_id = MetadataValue::from_str(&self.client_id)
+            .map_err(|_| Status::invalid_argument(format!("{} isn't valid metadata", chat::CLIENT_ID_HEADER)))?;
+        request.metadata_mut().insert(chat::CLIENT_ID_HEADER, client_id);
+        request.metadata_mut().insert(chat::HEARTBEATS_HEADER, MetadataValue::from_static("true"));
+
+        let response = client.route_chat(request).await?;
+        *attempt = 0;
+        self.set_state(ConnectionState::Connected);
+
+        // Resend what the server doesn't have, from where it was.
+        let last_sequence = response
+            .metadata()
+            .get(chat::LAST_SEQUENCE_HEADER)
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+            .unwrap_or(0);
+        self.unacked.retain(|note| note.sequence > last_sequence);
+        self.next_sequence = self.next_sequence.max(last_sequence + 1);
+        if let Some(location) = &self.location {
+            let _ = outbound.send(presence(location));
+        }
+        for room in &self.rooms {
+            let _ = outbound.send(joining(room));
+        }
+        for note in &self.unacked {
+            let _ = outbound.send(note.clone());
+        }
+
+        let mut outbound = if self.closing { None } else { Some(outbound) };
+        let mut inbound = response.into_inner();
+        loop {
+            tokio::select! {
+                note = inbound.message() => match note? {
+                    Some(note) if note.heartbeat => {
+                        if let Some(outbound) = &outbound {
+                            let _ = outbound.send(chat::heartbeat());
+                        }
+                    },
+                    Some(note) => { let _ = self.received.send(note); },
+                    None => return Ok(()),
+                },
+                outgoing = self.outgoing.recv(), if outbound.is_some() => {
+                    let note = match outgoing {
+                        Some(Outgoing::Goto(location)) => {
+                            self.location = Some(location.clone());
+                            presence(&location)
+                        },
+                        Some(Outgoing::Join(room)) => {
+                            let note = joining(&room);
+                            self.rooms.insert(room);
+                            note
+                        },
+                        Some(Outgoing::Note(note)) => self.number(note),
+                        // Closing the stream; the server ends the call once it's answered.
+                        None => {
+                            self.closing = true;
+                            outbound = None;
+                            continue;
+                        },
+                    };
+                    if let Some(outbound) = &outbound {
+                        let _ = outbound.send(note);
+                    }
+                },
+            }
+        }
+    }
+
+    /// Gives the note the current location if it has none and the next sequence number, and
+    /// keeps it for resending. A room note joins its room.
+    fn number(&mut self, mut note: RouteNote) -> RouteNote {
+        if !note.room.is_empty() {
+            self.rooms.insert(note.room.clone());
+        }
+        match &note.location {
+            Some(location) => self.location = Some(location.clone()),
+            None => note.location = self.location.clone(),
+        }
+        note.sequence = self.next_sequence;
+        self.next_sequence += 1;
+
+        while self.unacked.len() >= self.policy.max_queued.max(1) {
+            self.unacked.pop_front();
+        }
+        self.unacked.push_back(note.clone());
+        note
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::Mutex;
+
+use futures::Stream;
+use tokio::sync::watch;
+use tonic::Status;
+
+use crate::metrics;
+use crate::route_guide::ClientConfig;
+
+
+/// The largest message tonic takes by default, which the server hasn't changed.
+pub const DEFAULT_MAX_MESSAGE_BYTES: u32 = 4 << 20;
+
+/// What the server recommends when the configuration doesn't say: tonic's message size limit
+/// and no deadlines or rate limits.
+pub fn defaults() -> ClientConfig {
+    ClientConfig {
+        max_request_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+        max_response_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+        ..ClientConfig::default()
+    }
+}
+
+
+/// The client settings the server recommends, pushed to every WatchConfig call as they change.
+#[derive(Debug)]
+pub struct ClientConfigs {
+    sender: Mutex<watch::Sender<ClientConfig>>,
+    receiver: watch::Receiver<ClientConfig>,
+}
+
+impl ClientConfigs {
+    /// Starts at version 1 of `config`.
+    pub fn new(config: ClientConfig) -> Self {
+        let (sender, receiver) = watch::channel(ClientConfig { version: 1, ..config });
+        ClientConfigs { sender: Mutex::new(sender), receiver }
+    }
+
+    pub fn current(&self) -> ClientConfig {
+        self.receiver.borrow().clone()
+    }
+
+    /// Publishes `config` as the next version, unless it's what's published already. Returns
+    /// whether it was new.
+    pub fn set(&self, config: ClientConfig) -> bool {
+        let sender = self.sender.lock().unwrap();
+        let version = {
+            let current = self.receiver.borrow();
+            if (ClientConfig { version: current.version, ..config.clone() }) == *current {
+                return false;
+            }
+            current.version + 1
+        };
+        // Only fails when there are no receivers, and `self` holds one.
+        let _ = sender.broadcast(ClientConfig { version, ..config });
+        metrics::registry()
+            .gauge("client_config_version", "The version of the client settings WatchConfig pushes.", &[])
+            .set(version as i64);
+        true
+    }
+
+    /// The current settings, then each new version. Versions published while the watcher is
+    /// busy are skipped for the newest one. Ends when the server drops `self`.
+    pub fn watch(&self) -> impl Stream<Item = Result<ClientConfig, Status>> + Send + Sync + 'static {
+        let mut receiver = self.receiver.clone();
+        let current = self.current();
+        async_stream::stream! {
+            let mut sent = current.version;
+            yield Ok(current);
+            while let Some(config) = receiver.recv().await {
+                if config.version > sent {
+                    sent = config.version;
+                    yield Ok(config);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use thiserror::Error;
+use tonic::{Code, Status};
+
+use crate::client_metadata::InvalidMetadata;
+#[cfg(feature = "tls")]
+use crate::client_tls::TlsError;
+
+
+/// Everything that can go wrong in the client, so callers can tell failures apart.
+#[derive(Debug, Error)]
+pub enum ClientError {
+    /// Connecting to, or talking to, an endpoint failed below gRPC.
+    #[error("transport error: {0}")]
+    Transport(#[from] tonic::transport::Error),
+
+    #[cfg(feature = "tls")]
+    #[error("TLS configuration error: {0}")]
+    Tls(#[from] TlsError),
+
+    #[error(transparent)]
+    InvalidMetadata(#[from] InvalidMetadata),
+
+    #[error("proxy error: {0}")]
+    Proxy(String),
+
+    /// No endpoint took a connection before `--wait-for-server` gave up.
+    #[error("server unreachable: {0}")]
+    Unreachable(String),
+
+    /// The endpoints couldn't be looked up.
+    #[error("discovery error: {0}")]
+    Discovery(String),
+
+    /// The server answered with an error status.
+    #[error("call failed with {:?}: {}", .0.code(), .0.message())]
+    Status(Status),
+
+    /// A response couldn't be decoded.
+    #[error("failed to decode response: {0}")]
+    Decode(String),
+
+    /// An export had features missing or changed, or no trailer, or couldn't be written.
+    #[error("export failed: {0}")]
+    Export(String),
+
+    /// The blocking client's runtime couldn't be started.
+    #[error("failed to start the client runtime: {0}")]
+    Runtime(String),
+
+    #[error("no response within {0:?}")]
+    Timeout(Duration),
+
+    /// Reading or writing the offline route journal failed.
+    #[error("route journal error: {0}")]
+    Journal(#[from] std::io::Error),
+}
+
+impl ClientError {
+    /// The gRPC status code, for errors reported by the server.
+    pub fn code(&self) -> Option<Code> {
+        match self {
+            ClientError::Status(status) => Some(status.code()),
+            _ => None,
+        }
+    }
+}
+
+impl From<Status> for ClientError {
+    fn from(status: Status) -> Self {
+        // tonic reports messages it can't decode as INTERNAL statuses created on the client.
+        if status.code() == Code::Internal && status.message().starts_with("failed to decode") {
+            ClientError::Decode(status.message().to_string())
+        } else {
+            ClientError::Status(status)
+        }
+    }
+}
+
+impl From<prost::DecodeError> for ClientError {
+    fn from(e: prost::DecodeError) -> Self {
+        ClientError::Decode(e.to_string())
+    }
+}
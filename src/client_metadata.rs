@@ -0,0 +1,138 @@
+use std::fmt;
+
+use tonic::metadata::{Ascii, MetadataKey, MetadataValue};
+use tonic::{Request, Status};
+
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+pub const LOCALE_HEADER: &str = "accept-language";
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidMetadata {
+    key: String,
+}
+
+impl InvalidMetadata {
+    pub fn new(key: &str) -> Self {
+        InvalidMetadata { key: key.to_string() }
+    }
+}
+
+impl fmt::Display for InvalidMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid metadata for '{}'", self.key)
+    }
+}
+
+impl std::error::Error for InvalidMetadata {}
+
+
+/// Default metadata installed on every outgoing request by `interceptor`.
+///
+/// Values already present on a request are left alone, so a single call can override any default
+/// by setting the header itself (see `with_token` and friends).
+#[derive(Debug, Clone, Default)]
+pub struct ClientMetadata {
+    defaults: Vec<(MetadataKey<Ascii>, MetadataValue<Ascii>)>,
+    request_id: bool,
+}
+
+impl ClientMetadata {
+    pub fn builder() -> ClientMetadataBuilder {
+        ClientMetadataBuilder::default()
+    }
+
+    /// Adds the defaults to the request's metadata.
+    pub fn apply<T>(&self, request: &mut Request<T>) {
+        let metadata = request.metadata_mut();
+
+        for (key, value) in &self.defaults {
+            if !metadata.contains_key(key) {
+                metadata.insert(key.clone(), value.clone());
+            }
+        }
+
+        if self.request_id && !metadata.contains_key(REQUEST_ID_HEADER) {
+            let id = format!("{:016x}", rand::random::<u64>());
+            metadata.insert(REQUEST_ID_HEADER, MetadataValue::from_str(&id).unwrap());
+        }
+    }
+
+    /// An interceptor for `RouteGuideClient::with_interceptor`.
+    pub fn interceptor(&self) -> impl Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static {
+        let metadata = self.clone();
+        move |mut request: Request<()>| {
+            metadata.apply(&mut request);
+            Ok(request)
+        }
+    }
+}
+
+
+#[derive(Debug, Default)]
+pub struct ClientMetadataBuilder {
+    defaults: Vec<(String, String)>,
+    request_id: bool,
+}
+
+impl ClientMetadataBuilder {
+    /// Appended to the user-agent; the transport puts its own product token in front of it.
+    pub fn user_agent_suffix(self, suffix: &str) -> Self {
+        self.header("user-agent", suffix)
+    }
+
+    /// Sent as `authorization: Bearer <token>`.
+    pub fn token(self, token: &str) -> Self {
+        self.header("authorization", &format!("Bearer {}", token))
+    }
+
+    pub fn locale(self, locale: &str) -> Self {
+        self.header(LOCALE_HEADER, locale)
+    }
+
+    /// Whether every request gets a random `x-request-id`.
+    pub fn request_id(mut self, enabled: bool) -> Self {
+        self.request_id = enabled;
+        self
+    }
+
+    /// Any other default header. Setting the same key twice keeps the last value.
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        let key = key.to_ascii_lowercase();
+        self.defaults.retain(|(k, _)| *k != key);
+        self.defaults.push((key, value.to_string()));
+        self
+    }
+
+    pub fn build(self) -> Result<ClientMetadata, InvalidMetadata> {
+        let defaults = self.defaults
+            .into_iter()
+            .map(|(key, value)| {
+                let invalid = || InvalidMetadata { key: key.clone() };
+                let k = MetadataKey::from_bytes(key.as_bytes()).map_err(|_| invalid())?;
+                let v = MetadataValue::from_str(&value).map_err(|_| invalid())?;
+                Ok((k, v))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(ClientMetadata { defaults, request_id: self.request_id })
+    }
+}
+
+
+/// Overrides the default token for a single call.
+pub fn with_token<T>(mut request: Request<T>, token: &str) -> Result<Request<T>, InvalidMetadata> {
+    let value = MetadataValue::from_str(&format!("Bearer {}", token))
+        .map_err(|_| InvalidMetadata { key: "authorization".to_string() })?;
+    request.metadata_mut().insert("authorization", value);
+    Ok(request)
+}
+
+/// Overrides the default locale for a single call.
+pub fn with_locale<T>(mut request: Request<T>, locale: &str) -> Result<Request<T>, InvalidMetadata> {
+    let value = MetadataValue::from_str(locale)
+        .map_err(|_| InvalidMetadata { key: LOCALE_HEADER.to_string() })?;
+    request.metadata_mut().insert(LOCALE_HEADER, value);
+    Ok(request)
+}
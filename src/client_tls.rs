@@ -0,0 +1,248 @@
+use std::fmt;
+use std::io::BufReader;
+use std::sync::Arc;
+
+use rustls::internal::pemfile;
+use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError, WebPKIVerifier};
+use sha2::{Digest, Sha256};
+use tonic::transport::ClientTlsConfig;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsError(String);
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+
+/// A SHA-256 hash of a certificate's SubjectPublicKeyInfo, written as `sha256/<base64>` (the format
+/// used by HPKP and `curl --pinnedpubkey`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpkiPin([u8; 32]);
+
+impl std::str::FromStr for SpkiPin {
+    type Err = TlsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoded = s.strip_prefix("sha256/")
+            .ok_or_else(|| TlsError(format!("pin '{}' must start with sha256/", s)))?;
+        let decoded = base64::decode(encoded)
+            .map_err(|e| TlsError(format!("pin '{}' is not valid base64: {}", s, e)))?;
+
+        if decoded.len() != 32 {
+            return Err(TlsError(format!("pin '{}' is not a SHA-256 hash", s)));
+        }
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&decoded);
+        Ok(SpkiPin(hash))
+    }
+}
+
+impl fmt::Display for SpkiPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sha256/{}", base64::encode(self.0))
+    }
+}
+
+/// The pin of a DER encoded certificate.
+pub fn spki_pin(certificate: &[u8]) -> Result<SpkiPin, TlsError> {
+    x509_parser::parse_x509_certificate(certificate).map_err(|e| TlsError(format!("failed to parse certificate: {:?}", e)))?;
+    let spki = subject_public_key_info(certificate)
+        .ok_or_else(|| TlsError("failed to find the certificate's public key".to_string()))?;
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&Sha256::digest(spki));
+    Ok(SpkiPin(hash))
+}
+
+/// The DER of the SubjectPublicKeyInfo, which x509-parser doesn't keep: the seventh element of
+/// the TBSCertificate, or the sixth without the optional version.
+fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    let (_, certificate, _) = der_element(certificate)?;
+    let (_, tbs, _) = der_element(certificate)?;
+    let mut rest = tbs;
+    if rest.first() == Some(&0xa0) {
+        rest = der_element(rest)?.2;
+    }
+    // The serial number, signature algorithm, issuer, validity and subject.
+    for _ in 0..5 {
+        rest = der_element(rest)?.2;
+    }
+    Some(der_element(rest)?.0)
+}
+
+/// The first element of `input`, whole and as its contents, and what follows it. Only single
+/// byte tags, as certificates use.
+fn der_element(input: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let first = *input.get(1)? as usize;
+    let (header, length) = if first < 0x80 {
+        (2, first)
+    } else {
+        let bytes = first & 0x7f;
+        if bytes == 0 || bytes > 4 {
+            return None;
+        }
+        let length = input.get(2..2 + bytes)?.iter().fold(0usize, |length, &b| length << 8 | b as usize);
+        (2 + bytes, length)
+    };
+    let end = header.checked_add(length)?;
+    let element = input.get(..end)?;
+    Some((element, &element[header..], &input[end..]))
+}
+
+
+/// Runs the normal WebPKI verification, then requires the server's leaf certificate to match one
+/// of the pins.
+struct PinningVerifier {
+    inner: WebPKIVerifier,
+    pins: Vec<SpkiPin>,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        dns_name: webpki::DNSNameRef<'_>,
+        ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let verified = self.inner.verify_server_cert(roots, presented_certs, dns_name, ocsp_response)?;
+
+        let leaf = presented_certs.first().ok_or(TLSError::NoCertificatesPresented)?;
+        let pin = spki_pin(&leaf.0).map_err(|e| TLSError::General(e.to_string()))?;
+
+        if self.pins.contains(&pin) {
+            Ok(verified)
+        } else {
+            Err(TLSError::General(format!("server key {} matches none of the pinned keys", pin)))
+        }
+    }
+}
+
+
+/// Where the client gets its trust roots from, which keys it pins, and the certificate it shows
+/// servers that ask for one (mutual TLS).
+#[derive(Debug, Clone, Default)]
+pub struct ClientTlsOptions {
+    pub ca_file: Option<String>,
+    pub native_roots: bool,
+    pub pins: Vec<SpkiPin>,
+    pub domain: Option<String>,
+    /// PEM file with the client's certificate chain, and `key_file` its PKCS #8 key.
+    pub cert_file: Option<String>,
+    pub key_file: Option<String>,
+}
+
+impl ClientTlsOptions {
+    pub async fn build(&self) -> Result<ClientTlsConfig, TlsError> {
+        let mut config = ClientConfig::new();
+        config.set_protocols(&[b"h2".to_vec()]);
+
+        if let Some(path) = &self.ca_file {
+            // Read once at startup, so blocking is fine and the client doesn't need tokio's fs.
+            let pem = std::fs::read(path)
+                .map_err(|e| TlsError(format!("failed to read {}: {}", path, e)))?;
+            let (added, _) = config.root_store.add_pem_file(&mut BufReader::new(&pem[..]))
+                .map_err(|_| TlsError(format!("{} is not a PEM file", path)))?;
+            if added == 0 {
+                return Err(TlsError(format!("{} has no usable certificates", path)));
+            }
+        }
+
+        if self.native_roots {
+            let roots = match rustls_native_certs::load_native_certs() {
+                Ok(roots) => roots,
+                // Some certificates couldn't be loaded; use the ones that could.
+                Err((Some(roots), e)) => {
+                    tracing::warn!("some native root certificates were skipped: {}", e);
+                    roots
+                },
+                Err((None, e)) => return Err(TlsError(format!("failed to load native root certificates: {}", e))),
+            };
+            config.root_store.roots.extend(roots.roots);
+        }
+
+        if config.root_store.is_empty() {
+            return Err(TlsError("no trust roots configured".to_string()));
+        }
+
+        match (&self.cert_file, &self.key_file) {
+            (Some(cert_file), Some(key_file)) => {
+                let (certs, key) = read_identity(cert_file, key_file)?;
+                config.set_single_client_cert(certs, key)
+                    .map_err(|e| TlsError(format!("{} doesn't go with {}: {}", key_file, cert_file, e)))?;
+            },
+            (None, None) => {},
+            _ => return Err(TlsError("a client certificate needs both a certificate and a key file".to_string())),
+        }
+
+        if !self.pins.is_empty() {
+            config.dangerous().set_certificate_verifier(Arc::new(PinningVerifier {
+                inner: WebPKIVerifier::new(),
+                pins: self.pins.clone(),
+            }));
+        }
+
+        let mut tls = ClientTlsConfig::new().rustls_client_config(config);
+        if let Some(domain) = &self.domain {
+            tls = tls.domain_name(domain.clone());
+        }
+        Ok(tls)
+    }
+}
+
+/// The certificate chain and PKCS #8 key of a client identity, from PEM files like
+/// `data/tls/client.pem` and `data/tls/client.key`.
+fn read_identity(cert_file: &str, key_file: &str) -> Result<(Vec<Certificate>, PrivateKey), TlsError> {
+    let read = |path: &str| std::fs::read(path).map_err(|e| TlsError(format!("failed to read {}: {}", path, e)));
+    let (cert_pem, key_pem) = (read(cert_file)?, read(key_file)?);
+
+    let certs = pemfile::certs(&mut &cert_pem[..]).map_err(|_| TlsError(format!("{} is not a PEM file", cert_file)))?;
+    if certs.is_empty() {
+        return Err(TlsError(format!("{} has no certificates", cert_file)));
+    }
+    let key = pemfile::pkcs8_private_keys(&mut &key_pem[..])
+        .map_err(|_| TlsError(format!("{} is not a PEM file", key_file)))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| TlsError(format!("{} has no PKCS #8 private key", key_file)))?;
+    Ok((certs, key))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> ClientTlsOptions {
+        ClientTlsOptions { ca_file: Some("data/tls/ca.pem".to_string()), ..ClientTlsOptions::default() }
+    }
+
+    #[tokio::test]
+    async fn client_identity() {
+        let with_identity = ClientTlsOptions {
+            cert_file: Some("data/tls/client.pem".to_string()),
+            key_file: Some("data/tls/client.key".to_string()),
+            ..options()
+        };
+        assert!(with_identity.build().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn client_identity_needs_both_files() {
+        let without_key = ClientTlsOptions { cert_file: Some("data/tls/client.pem".to_string()), ..options() };
+        assert!(without_key.build().await.is_err());
+
+        // The certificate isn't a key.
+        let wrong_key = ClientTlsOptions {
+            cert_file: Some("data/tls/client.pem".to_string()),
+            key_file: Some("data/tls/client.pem".to_string()),
+            ..options()
+        };
+        assert!(wrong_key.build().await.is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use bytes::BufMut;
+use once_cell::sync::Lazy;
+use prost::Message;
+use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder};
+use tonic::Status;
+
+use crate::metrics;
+
+
+/// Where messages of each method start out when encoding, by full method path, like
+/// `/routeguide.v2.RouteGuide/ListFeatures`, or method name, like `ListFeatures`.
+#[derive(Debug, Clone, Default)]
+pub struct BufferSizes {
+    sizes: HashMap<String, usize>,
+}
+
+impl BufferSizes {
+    /// Large buffers for the streams that send many or large messages, small ones for notes.
+    pub fn recommended() -> Self {
+        BufferSizes::default()
+            .with("ListFeatures", 64 << 10)
+            .with("ExportFeatures", 256 << 10)
+            .with("ListChanges", 64 << 10)
+            .with("Replicate", 64 << 10)
+            .with("RouteChat", 1 << 10)
+            .with("GetNotesAt", 1 << 10)
+    }
+
+    pub fn with(mut self, method: &str, bytes: usize) -> Self {
+        self.sizes.insert(method.to_string(), bytes);
+        self
+    }
+
+    /// The size for `path`, or 0 for methods left out, whose buffer only grows as their
+    /// messages need.
+    pub fn get(&self, path: &str) -> usize {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        self.sizes.get(path).or_else(|| self.sizes.get(name)).copied().unwrap_or(0)
+    }
+}
+
+/// One `METHOD=BYTES` setting, as given on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BufferSize {
+    pub method: String,
+    pub bytes: usize,
+}
+
+impl FromStr for BufferSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+        let method = parts.next().unwrap_or("").trim();
+        let bytes = parts.next().ok_or_else(|| format!("expected METHOD=BYTES, got {:?}", s))?;
+        if method.is_empty() {
+            return Err(format!("missing method in {:?}", s));
+        }
+        let bytes = bytes.trim().parse().map_err(|_| format!("invalid buffer size in {:?}", s))?;
+        Ok(BufferSize { method: method.to_string(), bytes })
+    }
+}
+
+static SIZES: Lazy<RwLock<BufferSizes>> = Lazy::new(|| RwLock::new(BufferSizes::recommended()));
+
+/// Replaces the sizes, for calls started from now on.
+pub fn configure(sizes: BufferSizes) {
+    *SIZES.write().unwrap() = sizes;
+}
+
+pub fn buffer_size(path: &str) -> usize {
+    SIZES.read().unwrap().get(path)
+}
+
+
+/// tonic's protobuf codec, with the encode buffer sized for the method. build.rs puts it in the
+/// generated stubs in place of `ProstCodec`, as tonic-build has no setting for it.
+///
+/// tonic keeps one buffer for all the messages of a stream and hands each encoded message on as
+/// a split off part of it. When a message doesn't fit in what's left, the buffer reserves the
+/// method's size rather than the message's, so the messages after it fit too; and once the
+/// earlier ones are sent, that reserve takes back their space instead of allocating.
+/// `grpc_codec_buffer_reserves_total` counts how often it came to that, by method. Decoding is
+/// left to tonic's own buffer.
+#[derive(Debug)]
+pub struct TunedCodec<T, U> {
+    method: &'static str,
+    buffer_size: usize,
+    _types: PhantomData<(T, U)>,
+}
+
+impl<T, U> TunedCodec<T, U> {
+    pub fn new(method: &'static str) -> Self {
+        TunedCodec { method, buffer_size: buffer_size(method), _types: PhantomData }
+    }
+}
+
+/// For an unknown method, so buffers grow as messages need.
+impl<T, U> Default for TunedCodec<T, U> {
+    fn default() -> Self {
+        TunedCodec { method: "", buffer_size: 0, _types: PhantomData }
+    }
+}
+
+impl<T, U> Codec for TunedCodec<T, U>
+    where
+        T: Message + Send + Sync + 'static,
+        U: Message + Default + Send + Sync + 'static,
+{
+    type Encode = T;
+    type Decode = U;
+    type Encoder = TunedEncoder<T>;
+    type Decoder = TunedDecoder<U>;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        TunedEncoder { method: self.method, buffer_size: self.buffer_size, _type: PhantomData }
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        TunedDecoder { _type: PhantomData }
+    }
+}
+
+#[derive(Debug)]
+pub struct TunedEncoder<T> {
+    method: &'static str,
+    buffer_size: usize,
+    _type: PhantomData<T>,
+}
+
+impl<T: Message> Encoder for TunedEncoder<T> {
+    type Item = T;
+    type Error = Status;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut EncodeBuf<'_>) -> Result<(), Self::Error> {
+        let needed = item.encoded_len();
+        // The spare capacity; a full buffer grows by a little to have some.
+        if dst.bytes_mut().len() < needed {
+            metrics::registry()
+                .counter(
+                    "grpc_codec_buffer_reserves_total",
+                    "Messages that didn't fit the rest of their stream's encode buffer, which grew or took back sent space.",
+                    &[("method", self.method)],
+                )
+                .inc();
+            dst.reserve(needed.max(self.buffer_size));
+        }
+        // Only fails without enough room, which was just made.
+        item.encode(dst).map_err(|e| Status::internal(e.to_string()))
+    }
+}
+
+#[derive(Debug)]
+pub struct TunedDecoder<U> {
+    _type: PhantomData<U>,
+}
+
+impl<U: Message + Default> Decoder for TunedDecoder<U> {
+    type Item = U;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<Self::Item>, Self::Error> {
+        U::decode(src).map(Some).map_err(|e| Status::internal(e.to_string()))
+    }
+}
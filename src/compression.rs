@@ -0,0 +1,124 @@
+use std::io;
+
+// The stream encoders are deprecated for the `AsyncBufRead` ones, which would need the body
+// turned into a reader and back.
+#[allow(deprecated)]
+use async_compression::stream::{BrotliEncoder, GzipEncoder};
+use futures::TryStreamExt as _;
+use hyper::body::HttpBody as _;
+use hyper::header::{HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY};
+use hyper::{Body, Response, StatusCode};
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    Identity,
+}
+
+impl Encoding {
+    fn token(self) -> &'static str {
+        match self {
+            Encoding::Brotli   => "br",
+            Encoding::Gzip     => "gzip",
+            Encoding::Identity => "identity",
+        }
+    }
+}
+
+/// Picks the encoding with the highest q-value in `Accept-Encoding`, preferring brotli on ties.
+pub fn negotiate(headers: &HeaderMap) -> Encoding {
+    let accept = match headers.get(ACCEPT_ENCODING).and_then(|value| value.to_str().ok()) {
+        Some(accept) => accept,
+        None => return Encoding::Identity,
+    };
+
+    let mut best = (Encoding::Identity, 0.0);
+    for entry in accept.split(',') {
+        let mut parts = entry.split(';').map(str::trim);
+        let token = parts.next().unwrap_or("");
+        let quality = parts
+            .find_map(|param| param.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        let candidates: &[Encoding] = match token {
+            "br"   => &[Encoding::Brotli],
+            "gzip" => &[Encoding::Gzip],
+            "*"    => &[Encoding::Brotli, Encoding::Gzip],
+            _      => &[],
+        };
+        for &encoding in candidates {
+            let better = quality > best.1 || (quality == best.1 && quality > 0.0 && encoding == Encoding::Brotli);
+            if quality > 0.0 && better {
+                best = (encoding, quality);
+            }
+        }
+    }
+
+    best.0
+}
+
+
+/// Compression settings for a route.
+#[derive(Debug, Copy, Clone)]
+pub struct Compression {
+    pub enabled: bool,
+    /// Bodies whose size is known and smaller than this are sent as they are. Streamed bodies of
+    /// unknown size are always compressed.
+    pub min_size: u64,
+}
+
+impl Compression {
+    pub const OFF: Compression = Compression { enabled: false, min_size: 0 };
+
+    pub fn with_min_size(min_size: u64) -> Self {
+        Compression { enabled: true, min_size }
+    }
+
+    /// Compresses the response body as it streams, if the client accepts a supported encoding.
+    #[allow(deprecated)]
+    pub fn apply(&self, request_headers: &HeaderMap, response: Response<Body>) -> Response<Body> {
+        // Event streams must reach the client as they're written, not when a block fills up, and
+        // zips are compressed already.
+        let content_type = response.headers().get(CONTENT_TYPE).map_or(&b""[..], |value| value.as_bytes());
+        let event_stream = content_type.starts_with(b"text/event-stream");
+        let compressed = content_type.starts_with(b"application/zip");
+
+        if !self.enabled
+            || event_stream
+            || compressed
+            || response.headers().contains_key(CONTENT_ENCODING)
+            || response.status() == StatusCode::NO_CONTENT
+            || response.status() == StatusCode::NOT_MODIFIED
+        {
+            return response;
+        }
+
+        if let Some(size) = response.body().size_hint().exact() {
+            if size < self.min_size {
+                return response;
+            }
+        }
+
+        let encoding = negotiate(request_headers);
+        let (mut parts, body) = response.into_parts();
+        parts.headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+
+        if encoding == Encoding::Identity {
+            return Response::from_parts(parts, body);
+        }
+
+        let stream = body.map_err(io::Error::other);
+        let body = match encoding {
+            Encoding::Brotli   => Body::wrap_stream(BrotliEncoder::new(stream)),
+            Encoding::Gzip     => Body::wrap_stream(GzipEncoder::new(stream)),
+            Encoding::Identity => unreachable!(),
+        };
+
+        parts.headers.remove(CONTENT_LENGTH);
+        parts.headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.token()));
+        Response::from_parts(parts, body)
+    }
+}
@@ -0,0 +1,62 @@
+use prost::Message;
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::{Code, Request, Status};
+
+
+/// Response metadata with the digest of the answer, like an HTTP ETag: `"` and 16 hex digits
+/// and `"`. It depends only on the encoded answer, so it's the same from every replica.
+pub const ETAG_HEADER: &str = "etag";
+
+/// Request metadata with the ETags of answers the client already has, comma-separated, or `*`.
+pub const IF_NONE_MATCH_HEADER: &str = "if-none-match";
+
+/// Set on the status that answers a call whose answer hadn't changed.
+pub const NOT_MODIFIED_HEADER: &str = "x-not-modified";
+
+
+/// FNV-1a of the encoded message. Unlike the hashers of std it's the same in every build.
+pub fn etag<M: Message>(message: &M) -> String {
+    let mut bytes = Vec::with_capacity(message.encoded_len());
+    message.encode(&mut bytes).unwrap();
+
+    let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3));
+    format!("\"{:016x}\"", hash)
+}
+
+/// Whether the call's `if-none-match` has `etag`, so the client already has the answer.
+pub fn matches<T>(request: &Request<T>, etag: &str) -> bool {
+    let given = match request.metadata().get(IF_NONE_MATCH_HEADER).and_then(|value| value.to_str().ok()) {
+        Some(given) => given,
+        None => return false,
+    };
+    given.split(',').map(str::trim).any(|tag| tag == "*" || tag == etag)
+}
+
+/// The answer to a call whose answer hasn't changed: gRPC has no 304, so this is
+/// FAILED_PRECONDITION marked with `x-not-modified`, and with the ETag again. It has no
+/// message to encode.
+pub fn not_modified(etag: &str) -> Status {
+    let mut metadata = MetadataMap::new();
+    metadata.insert(NOT_MODIFIED_HEADER, MetadataValue::from_static("true"));
+    if let Ok(value) = MetadataValue::from_str(etag) {
+        metadata.insert(ETAG_HEADER, value);
+    }
+    Status::with_metadata(Code::FailedPrecondition, "not modified", metadata)
+}
+
+pub fn is_not_modified(status: &Status) -> bool {
+    status.code() == Code::FailedPrecondition
+        && status.metadata().get(NOT_MODIFIED_HEADER).and_then(|value| value.to_str().ok()) == Some("true")
+}
+
+/// The ETag in a response's metadata, if it has one.
+pub fn etag_of(metadata: &MetadataMap) -> Option<String> {
+    metadata.get(ETAG_HEADER).and_then(|value| value.to_str().ok()).map(str::to_string)
+}
+
+/// Asks for the answer only if it isn't the one with `etag`.
+pub fn if_none_match<T>(request: &mut Request<T>, etag: &str) {
+    if let Ok(value) = MetadataValue::from_str(etag) {
+        request.metadata_mut().insert(IF_NONE_MATCH_HEADER, value);
+    }
+}
@@ -0,0 +1,530 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use futures::Stream;
+use once_cell::sync::Lazy;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tonic::transport::server::Connected;
+use tonic::Request;
+
+use crate::ip_filter::IpFilter;
+use crate::metrics;
+
+
+/// Handshake bytes kept per direction while looking for the TLS hello messages. Hellos are much
+/// smaller; if nothing is found by then the connection isn't TLS as far as we can tell.
+const SNIFF_LIMIT: usize = 16 * 1024;
+
+/// The only protocol the gRPC server offers in ALPN.
+const SERVER_ALPN: &str = "h2";
+
+/// Distinct fingerprints given their own label in the connection metrics; the ones seen after
+/// are labelled `other`, so clients can't add series without end.
+const MAX_FINGERPRINT_LABELS: usize = 200;
+
+const MAX_CLIENT_LABEL_LENGTH: usize = 64;
+
+
+/// What the TLS handshake settled on, read from the hello messages as they pass by.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TlsInfo {
+    /// Like `TLSv1.3`. `None` until the server's hello is seen, or for plaintext connections.
+    pub version: Option<&'static str>,
+    pub alpn: Option<String>,
+    /// The client's hello as JA3 describes it: its version, cipher suites, extensions, groups
+    /// and point formats, in decimal. What the client's TLS library is, more or less, whatever
+    /// it says it is.
+    pub ja3: Option<String>,
+    /// FNV-1a of `ja3` (64 bits, as 16 hex digits), short enough for labels. Not the MD5 that
+    /// JA3 databases list; hash `ja3` for those.
+    pub fingerprint: Option<String>,
+}
+
+/// A snapshot of one connection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionStats {
+    pub id: u64,
+    pub peer: SocketAddr,
+    pub opened: SystemTime,
+    pub tls: TlsInfo,
+    /// From the first call, `None` before it.
+    pub user_agent: Option<String>,
+    /// Streaming calls currently open on the connection.
+    pub active_streams: u64,
+    pub total_rpcs: u64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+}
+
+
+#[derive(Debug)]
+struct Connection {
+    id: u64,
+    peer: SocketAddr,
+    opened: SystemTime,
+    tls: Mutex<TlsInfo>,
+    /// Set at the first call, to an empty string for a client that didn't say.
+    user_agent: Mutex<Option<String>>,
+    active_streams: AtomicU64,
+    total_rpcs: AtomicU64,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+impl Connection {
+    fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            id: self.id,
+            peer: self.peer,
+            opened: self.opened,
+            tls: self.tls.lock().unwrap().clone(),
+            user_agent: self.user_agent.lock().unwrap().clone().filter(|agent| !agent.is_empty()),
+            active_streams: self.active_streams.load(Ordering::Relaxed),
+            total_rpcs: self.total_rpcs.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+        }
+    }
+}
+
+
+/// The client part of a user agent, like `grpc-go/1.50.0` out of `my-app/2.1 grpc-go/1.50.0`:
+/// the gRPC library's token if there is one, else the first.
+fn client_label(user_agent: &str) -> String {
+    let mut tokens = user_agent.split_whitespace();
+    let first = tokens.clone().next();
+    let client = tokens.find(|token| token.starts_with("grpc-") || token.starts_with("tonic/")).or(first).unwrap_or("unknown");
+    client.chars().take(MAX_CLIENT_LABEL_LENGTH).collect()
+}
+
+
+/// The open connections of the gRPC servers, keyed by peer address since that's what calls know
+/// about their connection. Meant for debugging how a load balancer spreads its connections, and
+/// for finding outdated clients: each connection is logged once, at its first call, with its
+/// TLS version, ALPN, hello fingerprint and user agent, which label `client_connections_total`
+/// and `client_connections_open` too.
+#[derive(Debug, Default)]
+pub struct ConnectionRegistry {
+    next_id: AtomicU64,
+    connections: RwLock<HashMap<SocketAddr, Arc<Connection>>>,
+    fingerprint_labels: Mutex<HashSet<String>>,
+}
+
+impl ConnectionRegistry {
+    /// The open connections, oldest first.
+    pub fn list(&self) -> Vec<ConnectionStats> {
+        let mut connections: Vec<_> = self.connections.read().unwrap().values().map(|connection| connection.stats()).collect();
+        connections.sort_by_key(|connection| connection.id);
+        connections
+    }
+
+    fn get(&self, peer: Option<SocketAddr>) -> Option<Arc<Connection>> {
+        self.connections.read().unwrap().get(&peer?).cloned()
+    }
+
+    /// Counts a call from `peer`, e.g. `request.remote_addr()`.
+    pub fn record_rpc(&self, peer: Option<SocketAddr>) {
+        if let Some(connection) = self.get(peer) {
+            connection.total_rpcs.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts the call, as `record_rpc`, and identifies its connection by the call's
+    /// `user-agent` if it's the first.
+    pub fn record_call<T>(&self, request: &Request<T>) {
+        let connection = match self.get(request.remote_addr()) {
+            Some(connection) => connection,
+            None => return,
+        };
+        connection.total_rpcs.fetch_add(1, Ordering::Relaxed);
+
+        let user_agent = request.metadata().get("user-agent").and_then(|value| value.to_str().ok()).unwrap_or("");
+        let mut identified = connection.user_agent.lock().unwrap();
+        if identified.is_some() {
+            return;
+        }
+        *identified = Some(user_agent.to_string());
+        drop(identified);
+
+        let tls = connection.tls.lock().unwrap().clone();
+        tracing::info!(
+            id = connection.id,
+            peer = %connection.peer,
+            tls_version = tls.version.unwrap_or("none"),
+            alpn = %tls.alpn.as_deref().unwrap_or(""),
+            fingerprint = %tls.fingerprint.as_deref().unwrap_or(""),
+            ja3 = %tls.ja3.as_deref().unwrap_or(""),
+            user_agent,
+            "client connected"
+        );
+        let labels = self.labels(&connection);
+        let labels: Vec<_> = labels.iter().map(|(name, value)| (*name, value.as_str())).collect();
+        let registry = metrics::registry();
+        registry.counter("client_connections_total", "Connections that made a call, by what the client is.", &labels).inc();
+        registry.gauge("client_connections_open", "Open connections that made a call, by what the client is.", &labels).inc();
+    }
+
+    /// The labels of an identified connection in the connection metrics.
+    fn labels(&self, connection: &Connection) -> [(&'static str, String); 4] {
+        let tls = connection.tls.lock().unwrap().clone();
+        let user_agent = connection.user_agent.lock().unwrap().clone().unwrap_or_default();
+        let fingerprint = match tls.fingerprint {
+            Some(fingerprint) => {
+                let mut labels = self.fingerprint_labels.lock().unwrap();
+                if labels.contains(&fingerprint) || labels.len() < MAX_FINGERPRINT_LABELS {
+                    labels.insert(fingerprint.clone());
+                    fingerprint
+                } else {
+                    "other".to_string()
+                }
+            },
+            None => "none".to_string(),
+        };
+        [
+            ("tls_version", tls.version.unwrap_or("none").to_string()),
+            ("alpn", tls.alpn.unwrap_or_else(|| "none".to_string())),
+            ("fingerprint", fingerprint),
+            ("client", client_label(&user_agent)),
+        ]
+    }
+
+    /// Counts a streaming call from `peer` as active until the guard is dropped.
+    pub fn track_stream(&self, peer: Option<SocketAddr>) -> StreamGuard {
+        let connection = self.get(peer);
+        if let Some(connection) = &connection {
+            connection.active_streams.fetch_add(1, Ordering::Relaxed);
+        }
+        StreamGuard(connection)
+    }
+
+    pub(crate) fn open(&'static self, stream: TcpStream, peer: SocketAddr) -> TrackedConnection {
+        let connection = Arc::new(Connection {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            peer,
+            opened: SystemTime::now(),
+            tls: Mutex::default(),
+            user_agent: Mutex::default(),
+            active_streams: AtomicU64::new(0),
+            total_rpcs: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+        });
+        self.connections.write().unwrap().insert(peer, connection.clone());
+        tracing::debug!(id = connection.id, %peer, "connection opened");
+
+        TrackedConnection { stream, connection, registry: self, received: Some(vec![]), sent: Some(vec![]), offered_alpn: vec![] }
+    }
+
+    fn close(&self, connection: &Connection) {
+        tracing::debug!(
+            id = connection.id,
+            peer = %connection.peer,
+            rpcs = connection.total_rpcs.load(Ordering::Relaxed),
+            "connection closed"
+        );
+        if connection.user_agent.lock().unwrap().is_some() {
+            let labels = self.labels(connection);
+            let labels: Vec<_> = labels.iter().map(|(name, value)| (*name, value.as_str())).collect();
+            metrics::registry()
+                .gauge("client_connections_open", "Open connections that made a call, by what the client is.", &labels)
+                .dec();
+        }
+        let mut connections = self.connections.write().unwrap();
+        // The peer address may already belong to a newer connection.
+        if connections.get(&connection.peer).is_some_and(|current| current.id == connection.id) {
+            connections.remove(&connection.peer);
+        }
+    }
+}
+
+/// The process wide registry.
+pub fn registry() -> &'static ConnectionRegistry {
+    static REGISTRY: Lazy<ConnectionRegistry> = Lazy::new(ConnectionRegistry::default);
+    &REGISTRY
+}
+
+/// Accepts connections on the listener, registering each until it's closed. For
+/// `Router::serve_with_incoming`; TLS is still added by the server on top, so connections the
+/// filter refuses are closed before their handshake.
+pub fn incoming(listener: TcpListener, filter: Arc<IpFilter>) -> impl Stream<Item = io::Result<TrackedConnection>> {
+    async_stream::stream! {
+        let mut listener = listener;
+        loop {
+            match listener.accept().await {
+                Ok((_, peer)) if !filter.accepts(peer) => {},
+                Ok((stream, peer)) => {
+                    let _ = stream.set_nodelay(true);
+                    yield Ok(registry().open(stream, peer));
+                },
+                Err(e) => yield Err(e),
+            }
+        }
+    }
+}
+
+
+pub struct StreamGuard(Option<Arc<Connection>>);
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        if let Some(connection) = &self.0 {
+            connection.active_streams.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+
+/// Reads a TLS record with a handshake message of type `kind` from the start of `bytes`. Returns
+/// the message body, or `None` if `bytes` doesn't start with one (yet).
+fn handshake_message(bytes: &[u8], kind: u8) -> Option<&[u8]> {
+    if bytes.len() < 9 || bytes[0] != 0x16 || bytes[5] != kind {
+        return None;
+    }
+    let length = (bytes[6] as usize) << 16 | (bytes[7] as usize) << 8 | bytes[8] as usize;
+    bytes.get(9..9 + length)
+}
+
+/// Splits like a cursor over a hello message.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<usize> {
+        Some(self.take(1)?[0] as usize)
+    }
+
+    fn u16(&mut self) -> Option<usize> {
+        let bytes = self.take(2)?;
+        Some((bytes[0] as usize) << 8 | bytes[1] as usize)
+    }
+
+    /// The extensions as `(type, data)`.
+    fn extensions(&mut self) -> Option<Vec<(usize, &'a [u8])>> {
+        let length = self.u16()?;
+        let mut extensions = Reader(self.take(length)?);
+        let mut found = vec![];
+        while !extensions.0.is_empty() {
+            let kind = extensions.u16()?;
+            let length = extensions.u16()?;
+            found.push((kind, extensions.take(length)?));
+        }
+        Some(found)
+    }
+}
+
+const EXTENSION_SUPPORTED_GROUPS: usize = 10;
+const EXTENSION_EC_POINT_FORMATS: usize = 11;
+const EXTENSION_ALPN: usize = 16;
+const EXTENSION_SUPPORTED_VERSIONS: usize = 43;
+
+/// GREASE values (RFC 8701) are picked anew on every connection, so fingerprints leave them out.
+fn is_grease(value: usize) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+/// The values of a list with a length prefix of `prefix` bytes, each `size` bytes.
+fn values(data: &[u8], prefix: usize, size: usize) -> Vec<usize> {
+    let read = |reader: &mut Reader<'_>, n: usize| if n == 1 { reader.u8() } else { reader.u16() };
+    let mut reader = Reader(data);
+    let length = read(&mut reader, prefix);
+    let mut list = Reader(length.and_then(|length| reader.take(length)).unwrap_or(&[]));
+    std::iter::from_fn(|| read(&mut list, size)).collect()
+}
+
+fn dashed(values: impl IntoIterator<Item = usize>) -> String {
+    values.into_iter().filter(|&value| !is_grease(value)).map(|value| value.to_string()).collect::<Vec<_>>().join("-")
+}
+
+fn alpn_protocols(data: &[u8]) -> Vec<String> {
+    let mut reader = Reader(data);
+    let mut protocols = vec![];
+    if let Some(length) = reader.u16() {
+        let mut list = Reader(reader.take(length).unwrap_or(&[]));
+        while let Some(length) = list.u8() {
+            match list.take(length) {
+                Some(protocol) => protocols.push(String::from_utf8_lossy(protocol).into_owned()),
+                None => break,
+            }
+        }
+    }
+    protocols
+}
+
+fn version_name(version: usize) -> &'static str {
+    match version {
+        0x0304 => "TLSv1.3",
+        0x0303 => "TLSv1.2",
+        0x0302 => "TLSv1.1",
+        0x0301 => "TLSv1.0",
+        _ => "unknown",
+    }
+}
+
+/// The ALPN protocols the client offers, and its JA3 string.
+fn parse_client_hello(body: &[u8]) -> Option<(Vec<String>, String)> {
+    let mut reader = Reader(body);
+    let version = reader.u16()?;
+    reader.take(32)?;
+    let session_id = reader.u8()?;
+    reader.take(session_id)?;
+    let cipher_suites = reader.u16()?;
+    let mut cipher_suites = Reader(reader.take(cipher_suites)?);
+    let ciphers: Vec<_> = std::iter::from_fn(|| cipher_suites.u16()).collect();
+    let compression = reader.u8()?;
+    reader.take(compression)?;
+    let extensions = reader.extensions()?;
+
+    let (mut alpn, mut groups, mut point_formats) = (vec![], vec![], vec![]);
+    for (kind, data) in &extensions {
+        match *kind {
+            EXTENSION_ALPN => alpn = alpn_protocols(data),
+            EXTENSION_SUPPORTED_GROUPS => groups = values(data, 2, 2),
+            EXTENSION_EC_POINT_FORMATS => point_formats = values(data, 1, 1),
+            _ => {},
+        }
+    }
+    let ja3 = format!(
+        "{},{},{},{},{}",
+        version,
+        dashed(ciphers),
+        dashed(extensions.iter().map(|(kind, _)| *kind)),
+        dashed(groups),
+        dashed(point_formats),
+    );
+    Some((alpn, ja3))
+}
+
+/// The version and, before TLS 1.3 encrypted it, the ALPN protocol the server picked.
+fn parse_server_hello(body: &[u8]) -> Option<(&'static str, Option<String>)> {
+    let mut reader = Reader(body);
+    let mut version = reader.u16()?;
+    reader.take(32)?;
+    let session_id = reader.u8()?;
+    reader.take(session_id + 2 + 1)?;
+
+    let mut alpn = None;
+    for (kind, data) in reader.extensions().unwrap_or_default() {
+        match kind {
+            EXTENSION_SUPPORTED_VERSIONS => version = Reader(data).u16()?,
+            EXTENSION_ALPN => alpn = alpn_protocols(data).into_iter().next(),
+            _ => {},
+        }
+    }
+    Some((version_name(version), alpn))
+}
+
+
+/// A TCP connection that counts its bytes and reads the TLS handshake for its registry entry.
+pub struct TrackedConnection {
+    stream: TcpStream,
+    connection: Arc<Connection>,
+    registry: &'static ConnectionRegistry,
+    // The first bytes in each direction, until the hellos are found; then `None`.
+    received: Option<Vec<u8>>,
+    sent: Option<Vec<u8>>,
+    offered_alpn: Vec<String>,
+}
+
+impl TrackedConnection {
+    /// Reads the first bytes without consuming them, e.g. to tell TLS from plaintext.
+    pub async fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.peek(buf).await
+    }
+
+    fn sniff_received(&mut self, bytes: &[u8]) {
+        let received = match &mut self.received {
+            Some(received) => received,
+            None => return,
+        };
+        received.extend_from_slice(bytes);
+
+        if let Some(body) = handshake_message(received, 1) {
+            if let Some((alpn, ja3)) = parse_client_hello(body) {
+                let hash = ja3.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3));
+                let mut tls = self.connection.tls.lock().unwrap();
+                tls.fingerprint = Some(format!("{:016x}", hash));
+                tls.ja3 = Some(ja3);
+                self.offered_alpn = alpn;
+            }
+            self.received = None;
+        } else if received.len() >= SNIFF_LIMIT || received.first().is_some_and(|&byte| byte != 0x16) {
+            self.received = None;
+        }
+    }
+
+    fn sniff_sent(&mut self, bytes: &[u8]) {
+        let offered_h2 = self.offered_alpn.iter().any(|protocol| protocol == SERVER_ALPN);
+        let sent = match &mut self.sent {
+            Some(sent) => sent,
+            None => return,
+        };
+        sent.extend_from_slice(bytes);
+
+        if let Some(body) = handshake_message(sent, 2) {
+            if let Some((version, alpn)) = parse_server_hello(body) {
+                // TLS 1.3 sends the choice encrypted, but the server only offers h2, so it's h2
+                // whenever the client offered it.
+                let alpn = alpn.or_else(|| if offered_h2 { Some(SERVER_ALPN.to_string()) } else { None });
+                let mut tls = self.connection.tls.lock().unwrap();
+                tls.version = Some(version);
+                tls.alpn = alpn;
+            }
+            self.sent = None;
+        } else if sent.len() >= SNIFF_LIMIT || sent.first().is_some_and(|&byte| byte != 0x16) {
+            self.sent = None;
+        }
+    }
+}
+
+impl AsyncRead for TrackedConnection {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let read = futures::ready!(Pin::new(&mut self.stream).poll_read(cx, buf))?;
+        self.connection.bytes_received.fetch_add(read as u64, Ordering::Relaxed);
+        self.sniff_received(&buf[..read]);
+        Poll::Ready(Ok(read))
+    }
+}
+
+impl AsyncWrite for TrackedConnection {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let written = futures::ready!(Pin::new(&mut self.stream).poll_write(cx, buf))?;
+        self.connection.bytes_sent.fetch_add(written as u64, Ordering::Relaxed);
+        self.sniff_sent(&buf[..written]);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+impl Connected for TrackedConnection {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        Some(self.connection.peer)
+    }
+}
+
+impl Drop for TrackedConnection {
+    fn drop(&mut self) {
+        self.registry.close(&self.connection);
+    }
+}
@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use hyper::header::{
+    HeaderMap, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+    ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+    ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+};
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AllowedOrigins {
+    Any,
+    /// Exact origins, like `https://maps.example.com`.
+    List(Vec<String>),
+}
+
+/// Which cross-origin browser requests are allowed. The default allows none.
+#[derive(Debug, Clone)]
+pub struct Cors {
+    pub origins: AllowedOrigins,
+    pub methods: Vec<Method>,
+    /// Request headers scripts may send, lowercase.
+    pub headers: Vec<String>,
+    /// Response headers scripts may read.
+    pub expose_headers: Vec<String>,
+    /// How long browsers may cache a preflight answer.
+    pub max_age: Option<Duration>,
+    /// Whether requests may carry cookies and `authorization`.
+    pub credentials: bool,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors {
+            origins: AllowedOrigins::List(vec![]),
+            methods: vec![Method::GET, Method::POST],
+            headers: vec!["authorization".to_string(), "content-type".to_string()],
+            expose_headers: vec![],
+            max_age: None,
+            credentials: false,
+        }
+    }
+}
+
+fn join(values: impl Iterator<Item = impl AsRef<str>>) -> Option<HeaderValue> {
+    let joined = values.map(|value| value.as_ref().to_string()).collect::<Vec<_>>().join(", ");
+    if joined.is_empty() { None } else { HeaderValue::from_str(&joined).ok() }
+}
+
+impl Cors {
+    /// The value for `Access-Control-Allow-Origin`, if this origin is allowed.
+    fn allow_origin(&self, request_headers: &HeaderMap) -> Option<HeaderValue> {
+        let origin = request_headers.get(ORIGIN)?;
+        match &self.origins {
+            // Browsers reject `*` on requests with credentials, so the origin is echoed.
+            AllowedOrigins::Any if !self.credentials => Some(HeaderValue::from_static("*")),
+            AllowedOrigins::Any => Some(origin.clone()),
+            AllowedOrigins::List(origins) => {
+                let origin_str = origin.to_str().ok()?;
+                if origins.iter().any(|allowed| allowed == origin_str) { Some(origin.clone()) } else { None }
+            },
+        }
+    }
+
+    fn common_headers(&self, allow_origin: HeaderValue, headers: &mut HeaderMap) {
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        if self.credentials {
+            headers.insert(ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+        }
+        if self.origins != AllowedOrigins::Any || self.credentials {
+            headers.append(VARY, HeaderValue::from_static("origin"));
+        }
+    }
+
+    /// Answers a CORS preflight (`OPTIONS` with `Access-Control-Request-Method`). Returns `None`
+    /// for any other request, which should then be handled normally and passed to `apply`.
+    pub fn preflight<T>(&self, request: &Request<T>) -> Option<Response<Body>> {
+        if request.method() != Method::OPTIONS {
+            return None;
+        }
+        let requested_method = request.headers().get(ACCESS_CONTROL_REQUEST_METHOD)?;
+
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NO_CONTENT;
+
+        let allow_origin = match self.allow_origin(request.headers()) {
+            Some(allow_origin) => allow_origin,
+            // Without the CORS headers the browser fails the real request.
+            None => return Some(response),
+        };
+
+        let method_allowed = requested_method
+            .to_str()
+            .is_ok_and(|method| self.methods.iter().any(|allowed| allowed.as_str() == method));
+        let headers_allowed = request
+            .headers()
+            .get(ACCESS_CONTROL_REQUEST_HEADERS)
+            .and_then(|value| value.to_str().ok())
+            .is_none_or(|requested| {
+                requested
+                    .split(',')
+                    .map(|header| header.trim().to_ascii_lowercase())
+                    .filter(|header| !header.is_empty())
+                    .all(|header| self.headers.contains(&header))
+            });
+        if !method_allowed || !headers_allowed {
+            return Some(response);
+        }
+
+        let headers = response.headers_mut();
+        self.common_headers(allow_origin, headers);
+        if let Some(methods) = join(self.methods.iter().map(Method::as_str)) {
+            headers.insert(ACCESS_CONTROL_ALLOW_METHODS, methods);
+        }
+        if let Some(allowed) = join(self.headers.iter()) {
+            headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allowed);
+        }
+        if let Some(max_age) = self.max_age {
+            headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age.as_secs()));
+        }
+
+        Some(response)
+    }
+
+    /// Adds the CORS headers for an allowed origin to a normal response.
+    pub fn apply<B>(&self, request_headers: &HeaderMap, mut response: Response<B>) -> Response<B> {
+        if let Some(allow_origin) = self.allow_origin(request_headers) {
+            let headers = response.headers_mut();
+            self.common_headers(allow_origin, headers);
+            if let Some(exposed) = join(self.expose_headers.iter()) {
+                headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, exposed);
+            }
+        }
+        response
+    }
+}
@@ -1,33 +1,299 @@
-use serde::Deserialize;
+use serde::Serialize;
+use std::collections::hash_map::{Entry, HashMap};
+use std::fmt;
 use std::fs::File;
+use std::str::FromStr;
 
-#[derive(Debug, Deserialize)]
-struct Feature {
-    location: Location,
-    name: String,
-}
+use crate::proto_json::ProtoJson;
+use crate::route_guide::Feature;
 
-#[derive(Debug, Deserialize)]
-struct Location {
-    latitude: i32,
-    longitude: i32,
-}
+pub const DEFAULT_PATH: &str = "data/route_guide_db.json";
+
+/// Names the copy of `DEFAULT_PATH` compiled in with the `embedded-db` feature, where a database
+/// path is expected.
+pub const EMBEDDED_PATH: &str = "embedded";
+
+#[cfg(feature = "embedded-db")]
+const EMBEDDED: Option<&[u8]> = Some(crate::embedded::DATABASE);
+#[cfg(not(feature = "embedded-db"))]
+const EMBEDDED: Option<&[u8]> = None;
 
-#[allow(dead_code)]
-pub fn load() -> Vec<crate::route_guide::Feature> {
-    let file = File::open("data/route_guide_db.json").expect("failed to open data file");
+/// The database the server loads unless told otherwise: the embedded one if it was compiled in,
+/// so the server needs no files at all.
+#[cfg(feature = "embedded-db")]
+pub const DEFAULT_SOURCE: &str = EMBEDDED_PATH;
+#[cfg(not(feature = "embedded-db"))]
+pub const DEFAULT_SOURCE: &str = DEFAULT_PATH;
 
-    let decoded: Vec<Feature> =
+/// Records are features in the canonical protobuf JSON mapping (see `proto_json`), and must
+/// have a location. `clusterSize` is ignored.
+pub fn load() -> Vec<Feature> {
+    let file = File::open(DEFAULT_PATH).expect("failed to open data file");
+
+    let decoded: Vec<serde_json::Value> =
         serde_json::from_reader(&file).expect("failed to deserialize features");
 
     decoded
-        .into_iter()
-        .map(|feature| crate::route_guide::Feature {
-            name: feature.name,
-            location: Some(crate::route_guide::Point {
-                longitude: feature.location.longitude,
-                latitude: feature.location.latitude,
-            }),
-        })
+        .iter()
+        .map(|record| parse_record(record).expect("failed to deserialize features"))
         .collect()
-}
\ No newline at end of file
+}
+
+fn parse_record(record: &serde_json::Value) -> Result<Feature, String> {
+    let feature = Feature::from_json(record)?;
+    if feature.location.is_none() {
+        return Err("Feature.location is missing".to_string());
+    }
+    Ok(Feature { cluster_size: 0, ..feature })
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticKind {
+    /// The file isn't valid JSON or doesn't have the expected shape.
+    Syntax,
+    InvalidUtf8,
+    LatitudeOutOfRange,
+    LongitudeOutOfRange,
+    DuplicateLocation,
+    EmptyName,
+}
+
+/// A problem found in the feature database. `record` is the 0-based index in the top level array
+/// and `line` is 1-based.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub record: Option<usize>,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.record {
+            Some(record) => write!(f, "line {} (record {}): {}", self.line, record, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+
+/// Byte offsets of the records in the top level array, found by tracking nesting depth outside of
+/// strings. Used to map records back to line numbers, which serde doesn't report.
+fn record_offsets(text: &str) -> Vec<usize> {
+    let mut offsets = vec![];
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut expecting_record = false;
+
+    for (i, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"'  => in_string = false,
+                _ => {},
+            }
+            continue;
+        }
+
+        if depth == 1 && expecting_record && !c.is_whitespace() && c != ']' {
+            offsets.push(i);
+            expecting_record = false;
+        }
+
+        match c {
+            '"' => in_string = true,
+            '[' | '{' => {
+                depth += 1;
+                if depth == 1 {
+                    expecting_record = true;
+                }
+            },
+            ']' | '}' => depth -= 1,
+            ',' if depth == 1 => expecting_record = true,
+            _ => {},
+        }
+    }
+
+    offsets
+}
+
+fn line_at(text: &str, offset: usize) -> usize {
+    text[..offset].matches('\n').count() + 1
+}
+
+fn record_at(offsets: &[usize], offset: usize) -> Option<usize> {
+    match offsets.binary_search(&offset) {
+        Ok(i) => Some(i),
+        Err(0) => None,
+        Err(i) => Some(i - 1),
+    }
+}
+
+
+/// Checks the raw database for invalid UTF-8, syntax errors, out-of-range coordinates, duplicate
+/// locations and empty names.
+pub fn validate(bytes: &[u8]) -> Vec<Diagnostic> {
+    validate_and_parse(bytes).0
+}
+
+fn validate_and_parse(bytes: &[u8]) -> (Vec<Diagnostic>, Vec<Feature>) {
+    let mut diagnostics = vec![];
+    let text = String::from_utf8_lossy(bytes);
+    let offsets = record_offsets(&text);
+
+    // The lossy text has `\u{FFFD}` in place of each invalid sequence; report those and keep going.
+    if std::str::from_utf8(bytes).is_err() {
+        for (offset, _) in text.match_indices('\u{FFFD}') {
+            diagnostics.push(Diagnostic {
+                kind: DiagnosticKind::InvalidUtf8,
+                record: record_at(&offsets, offset),
+                line: line_at(&text, offset),
+                message: "invalid UTF-8 sequence".to_string(),
+            });
+        }
+    }
+
+    let records: Vec<serde_json::Value> = match serde_json::from_str(&text) {
+        Ok(records) => records,
+        Err(e) => {
+            diagnostics.push(Diagnostic {
+                kind: DiagnosticKind::Syntax,
+                record: None,
+                line: e.line(),
+                message: e.to_string(),
+            });
+            return (diagnostics, vec![]);
+        },
+    };
+
+    // A record that isn't a feature is a syntax error like any other, even if its line is known.
+    let mut features = Vec::with_capacity(records.len());
+    for (record, value) in records.iter().enumerate() {
+        match parse_record(value) {
+            Ok(feature) => features.push(feature),
+            Err(message) => {
+                diagnostics.push(Diagnostic {
+                    kind: DiagnosticKind::Syntax,
+                    record: None,
+                    line: offsets.get(record).map_or(0, |&offset| line_at(&text, offset)),
+                    message: format!("record {}: {}", record, message),
+                });
+                return (diagnostics, vec![]);
+            },
+        }
+    }
+
+    let mut seen = HashMap::new();
+    for (record, feature) in features.iter().enumerate() {
+        let line = offsets.get(record).map_or(0, |&offset| line_at(&text, offset));
+        let mut report = |kind, message: String| {
+            diagnostics.push(Diagnostic { kind, record: Some(record), line, message })
+        };
+
+        let (latitude, longitude) = feature.location.as_ref().map_or((0, 0), |point| (point.latitude, point.longitude));
+        if !(-900_000_000..=900_000_000).contains(&latitude) {
+            report(DiagnosticKind::LatitudeOutOfRange, format!("latitude {} is outside +/- 90 degrees", latitude));
+        }
+        if !(-1_800_000_000..=1_800_000_000).contains(&longitude) {
+            report(DiagnosticKind::LongitudeOutOfRange, format!("longitude {} is outside +/- 180 degrees", longitude));
+        }
+        if feature.name.trim().is_empty() {
+            report(DiagnosticKind::EmptyName, "feature has no name".to_string());
+        }
+        match seen.entry((latitude, longitude)) {
+            Entry::Occupied(first) =>
+                report(DiagnosticKind::DuplicateLocation, format!("location is already used by record {}", first.get())),
+            Entry::Vacant(slot) => { slot.insert(record); },
+        }
+    }
+
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.record));
+    (diagnostics, features)
+}
+
+
+/// What the server does when the database has problems.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum InvalidDataPolicy {
+    /// Fail to load.
+    Refuse,
+    /// Drop the offending records and load the rest. Syntax errors still fail.
+    Skip,
+}
+
+impl FromStr for InvalidDataPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "refuse" => Ok(InvalidDataPolicy::Refuse),
+            "skip"   => Ok(InvalidDataPolicy::Skip),
+            other    => Err(format!("unknown policy '{}' (expected refuse or skip)", other)),
+        }
+    }
+}
+
+/// Loads and validates the database. Returns the features and the diagnostics for the skipped
+/// records, or every diagnostic if the database was refused.
+///
+/// Binary databases (see `binary_db`) are loaded as they are; they're converted from databases
+/// that passed validation. `EMBEDDED_PATH` loads the embedded database.
+pub fn load_checked(path: &str, policy: InvalidDataPolicy)
+    -> Result<(Vec<Feature>, Vec<Diagnostic>), Vec<Diagnostic>> {
+    if path == EMBEDDED_PATH {
+        let bytes = EMBEDDED.ok_or_else(|| vec![Diagnostic {
+            kind: DiagnosticKind::Syntax,
+            record: None,
+            line: 0,
+            message: "no database is embedded; build with the embedded-db feature".to_string(),
+        }])?;
+        return load_bytes(bytes, policy);
+    }
+
+    if crate::binary_db::is_binary(path) {
+        let features = crate::binary_db::BinaryDb::open(path).and_then(|db| db.features());
+        return features.map(|features| (features, vec![])).map_err(|e| vec![Diagnostic {
+            kind: DiagnosticKind::Syntax,
+            record: None,
+            line: 0,
+            message: format!("failed to load {}: {}", path, e),
+        }]);
+    }
+
+    let bytes = std::fs::read(path).map_err(|e| vec![Diagnostic {
+        kind: DiagnosticKind::Syntax,
+        record: None,
+        line: 0,
+        message: format!("failed to read {}: {}", path, e),
+    }])?;
+
+    load_bytes(&bytes, policy)
+}
+
+/// Like `load_checked`, for a JSON database already in memory.
+pub fn load_bytes(bytes: &[u8], policy: InvalidDataPolicy)
+    -> Result<(Vec<Feature>, Vec<Diagnostic>), Vec<Diagnostic>> {
+    let (diagnostics, features) = validate_and_parse(bytes);
+
+    if diagnostics.is_empty() {
+        return Ok((features, diagnostics));
+    }
+    if policy == InvalidDataPolicy::Refuse || diagnostics.iter().any(|d| d.record.is_none()) {
+        return Err(diagnostics);
+    }
+
+    let skipped: std::collections::HashSet<_> = diagnostics.iter().filter_map(|d| d.record).collect();
+    let features = features
+        .into_iter()
+        .enumerate()
+        .filter(|(record, _)| !skipped.contains(record))
+        .map(|(_, feature)| feature)
+        .collect();
+
+    Ok((features, diagnostics))
+}
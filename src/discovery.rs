@@ -0,0 +1,199 @@
+use std::collections::{BTreeSet, HashSet};
+use std::convert::Infallible;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use tokio::sync::mpsc;
+use tower::discover::{Change, Discover};
+use trust_dns_resolver::TokioAsyncResolver;
+
+use crate::metrics;
+
+
+/// Records are looked up again when their TTL runs out, but no sooner than this after the last
+/// lookup (or a failed one)...
+pub const MIN_REFRESH: Duration = Duration::from_secs(5);
+/// ...and no later than this, however long their TTL.
+pub const MAX_REFRESH: Duration = Duration::from_secs(300);
+
+
+/// What to look up: `host:port` for its A and AAAA records, or `srv:<name>` (like
+/// `srv:_grpc._tcp.example.com`) for SRV records, whose targets are looked up in turn.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DnsTarget {
+    Host { host: String, port: u16 },
+    Srv(String),
+}
+
+impl FromStr for DnsTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix("dns:///").unwrap_or(s);
+        if let Some(name) = s.strip_prefix("srv:") {
+            if name.is_empty() {
+                return Err(format!("'{}' has no SRV name", s));
+            }
+            return Ok(DnsTarget::Srv(name.to_string()));
+        }
+
+        let invalid = || format!("'{}' isn't a target like example.com:50051 or srv:_grpc._tcp.example.com", s);
+        let i = s.rfind(':').ok_or_else(invalid)?;
+        let host = s[..i].trim_start_matches('[').trim_end_matches(']');
+        let port = s[i + 1..].parse().map_err(|_| invalid())?;
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(DnsTarget::Host { host: host.to_string(), port })
+    }
+}
+
+impl fmt::Display for DnsTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsTarget::Host { host, port } if host.contains(':') => write!(f, "[{}]:{}", host, port),
+            DnsTarget::Host { host, port } => write!(f, "{}:{}", host, port),
+            DnsTarget::Srv(name) => write!(f, "srv:{}", name),
+        }
+    }
+}
+
+
+/// The addresses one lookup found, and when the first of its records expires.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolved {
+    pub addresses: BTreeSet<SocketAddr>,
+    pub valid_until: Instant,
+}
+
+/// Looks `target` up once. Of SRV records only those with the lowest priority are used: the
+/// others are fallbacks, and the balancer already steers around endpoints that fail.
+pub async fn resolve(resolver: &TokioAsyncResolver, target: &DnsTarget) -> Result<Resolved, String> {
+    match target {
+        DnsTarget::Host { host, port } => {
+            // Addresses are used as they are, without asking the resolver.
+            if let Ok(ip) = host.parse::<IpAddr>() {
+                let addresses = std::iter::once(SocketAddr::new(ip, *port)).collect();
+                return Ok(Resolved { addresses, valid_until: Instant::now() + MAX_REFRESH });
+            }
+            let lookup = resolver.lookup_ip(host.as_str()).await.map_err(|e| format!("failed to resolve {}: {}", host, e))?;
+            let addresses = lookup.iter().map(|ip| SocketAddr::new(ip, *port)).collect();
+            Ok(Resolved { addresses, valid_until: lookup.valid_until() })
+        },
+        DnsTarget::Srv(name) => {
+            let lookup = resolver.srv_lookup(name.as_str()).await.map_err(|e| format!("failed to resolve {}: {}", name, e))?;
+            let mut valid_until = lookup.as_lookup().valid_until();
+            let priority = lookup.iter().map(|srv| srv.priority()).min();
+
+            let mut addresses = BTreeSet::new();
+            for srv in lookup.iter().filter(|srv| Some(srv.priority()) == priority) {
+                let host = srv.target().to_utf8();
+                let ips = resolver.lookup_ip(host.as_str()).await.map_err(|e| format!("failed to resolve {}: {}", host, e))?;
+                valid_until = valid_until.min(ips.valid_until());
+                addresses.extend(ips.iter().map(|ip| SocketAddr::new(ip, srv.port())));
+            }
+            Ok(Resolved { addresses, valid_until })
+        },
+    }
+}
+
+/// How long to wait before the next lookup, for records valid until `valid_until`.
+pub fn refresh_after(valid_until: Instant, now: Instant) -> Duration {
+    let ttl = if valid_until > now { valid_until - now } else { Duration::from_secs(0) };
+    ttl.max(MIN_REFRESH).min(MAX_REFRESH)
+}
+
+
+/// Makes the service for a newly found address, or `None` if it can't be reached (then it's tried
+/// again after the next lookup).
+pub type Connect<S> = Box<dyn Fn(SocketAddr) -> BoxFuture<'static, Option<S>> + Send + Sync>;
+
+/// The endpoints for a `Balancer`, keyed by their address: either a fixed list, or the addresses
+/// a DNS name resolves to, looked up again when their records expire.
+pub struct Discovery<S> {
+    changes: mpsc::UnboundedReceiver<Change<String, S>>,
+}
+
+impl<S: Send + 'static> Discovery<S> {
+    /// These services and no others.
+    pub fn fixed(services: Vec<(String, S)>) -> Self {
+        let (sender, changes) = mpsc::unbounded_channel();
+        for (key, service) in services {
+            // The receiver is right here.
+            let _ = sender.send(Change::Insert(key, service));
+        }
+        Discovery { changes }
+    }
+
+    /// Resolves `target` in the background for as long as the discovery is used. Addresses
+    /// that are no longer returned are removed; if a lookup fails, the known ones are kept.
+    pub fn dns(resolver: TokioAsyncResolver, target: DnsTarget, connect: Connect<S>) -> Self {
+        let (sender, changes) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let registry = metrics::registry();
+            let help = "DNS lookups of the client's endpoints, by result.";
+            let succeeded = registry.counter("client_dns_resolutions_total", help, &[("result", "ok")]);
+            let failed = registry.counter("client_dns_resolutions_total", help, &[("result", "error")]);
+            let mut known: HashSet<SocketAddr> = HashSet::new();
+
+            loop {
+                let wait = match resolve(&resolver, &target).await {
+                    Ok(resolved) if resolved.addresses.is_empty() => {
+                        failed.inc();
+                        tracing::warn!(%target, "no addresses found, keeping {} endpoints", known.len());
+                        MIN_REFRESH
+                    },
+                    Ok(resolved) => {
+                        succeeded.inc();
+                        for gone in known.iter().filter(|address| !resolved.addresses.contains(address)).copied().collect::<Vec<_>>() {
+                            known.remove(&gone);
+                            tracing::info!(%target, address = %gone, "endpoint removed");
+                            if sender.send(Change::Remove(gone.to_string())).is_err() {
+                                return;
+                            }
+                        }
+                        let added: Vec<SocketAddr> = resolved.addresses.iter().filter(|address| !known.contains(address)).copied().collect();
+                        for address in added {
+                            let service = match connect(address).await {
+                                Some(service) => service,
+                                None => continue,
+                            };
+                            known.insert(address);
+                            tracing::info!(%target, %address, "endpoint added");
+                            if sender.send(Change::Insert(address.to_string(), service)).is_err() {
+                                return;
+                            }
+                        }
+                        refresh_after(resolved.valid_until, Instant::now())
+                    },
+                    Err(e) => {
+                        failed.inc();
+                        tracing::warn!(%target, "{}, keeping {} endpoints", e, known.len());
+                        MIN_REFRESH
+                    },
+                };
+                tokio::time::delay_for(wait).await;
+            }
+        });
+        Discovery { changes }
+    }
+}
+
+impl<S> Discover for Discovery<S> {
+    type Key = String;
+    type Service = S;
+    type Error = Infallible;
+
+    fn poll_discover(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Change<String, S>, Infallible>> {
+        match self.changes.poll_recv(cx) {
+            Poll::Ready(Some(change)) => Poll::Ready(Ok(change)),
+            // A fixed list is done changing, and so is a lookup task that has stopped.
+            Poll::Ready(None) | Poll::Pending => Poll::Pending,
+        }
+    }
+}
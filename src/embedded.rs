@@ -0,0 +1,23 @@
+use crate::proto_json::ProtoJson;
+use crate::route_guide::Feature;
+
+
+/// `data/route_guide_db.json`, compiled in.
+pub const DATABASE: &[u8] = include_bytes!("../data/route_guide_db.json");
+
+/// The features of `DATABASE`. Each has to parse and have a location, which is all the minimal
+/// server checks; the full one validates them with `data::load_checked`.
+pub fn features() -> Result<Vec<Feature>, String> {
+    let records: Vec<serde_json::Value> = serde_json::from_slice(DATABASE).map_err(|e| e.to_string())?;
+    records
+        .iter()
+        .enumerate()
+        .map(|(i, record)| {
+            let feature = Feature::from_json(record).map_err(|e| format!("record {}: {}", i, e))?;
+            if feature.location.is_none() {
+                return Err(format!("record {}: Feature.location is missing", i));
+            }
+            Ok(Feature { cluster_size: 0, ..feature })
+        })
+        .collect()
+}
@@ -0,0 +1,120 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::metrics;
+use crate::route_guide::{Feature, RouteNote, RouteSummary};
+use crate::tenant::TenantId;
+
+
+#[derive(Debug, Clone)]
+pub struct FeatureAdded {
+    pub tenant: TenantId,
+    /// Who added it, like `token:default`; empty for changes copied from a primary.
+    pub actor: String,
+    pub feature: Feature,
+}
+
+/// Also published, before a `FeatureAdded`, for a feature an import replaces.
+#[derive(Debug, Clone)]
+pub struct FeatureDeleted {
+    pub tenant: TenantId,
+    pub actor: String,
+    pub feature: Feature,
+}
+
+#[derive(Debug, Clone)]
+pub struct RouteRecorded {
+    pub tenant: TenantId,
+    pub summary: RouteSummary,
+}
+
+/// A RouteChat note that got past moderation, with the server's `from_user` and
+/// `posted_at_ms`. Direct notes are posted too, though they aren't stored.
+#[derive(Debug, Clone)]
+pub struct NotePosted {
+    pub tenant: TenantId,
+    pub note: RouteNote,
+}
+
+/// The configuration file was read again; `changed` names what it changed, as
+/// `reload::Changes::names` does.
+#[derive(Debug, Clone)]
+pub struct ConfigReloaded {
+    pub changed: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    FeatureAdded(FeatureAdded),
+    FeatureDeleted(FeatureDeleted),
+    RouteRecorded(RouteRecorded),
+    NotePosted(NotePosted),
+    ConfigReloaded(ConfigReloaded),
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::FeatureAdded(_) => "feature_added",
+            Event::FeatureDeleted(_) => "feature_deleted",
+            Event::RouteRecorded(_) => "route_recorded",
+            Event::NotePosted(_) => "note_posted",
+            Event::ConfigReloaded(_) => "config_reloaded",
+        }
+    }
+}
+
+
+/// Hears about every event on the buses it's subscribed to. Called on the thread that
+/// publishes, after the change is made and, for feature changes, under the tenant's write lock,
+/// so events arrive in the order the changes were made; anything slow belongs on a task of its
+/// own.
+pub trait Subscriber: Debug + Send + Sync {
+    fn notify(&self, event: &Event);
+}
+
+/// Where the server announces what happened, so the parts that react to it, like metrics and
+/// the replication feed, subscribe here instead of each handler calling them.
+///
+/// Side effects that can refuse a change don't go through the bus: the audit log and the chat
+/// history are written by the call itself, and failing to write them fails it.
+#[derive(Debug, Default, Clone)]
+pub struct EventBus {
+    subscribers: Vec<Arc<dyn Subscriber>>,
+}
+
+impl EventBus {
+    pub fn with(mut self, subscriber: Arc<dyn Subscriber>) -> Self {
+        self.subscribers.push(subscriber);
+        self
+    }
+
+    /// Tells every subscriber, in the order they subscribed.
+    pub fn publish(&self, event: Event) {
+        for subscriber in &self.subscribers {
+            subscriber.notify(&event);
+        }
+    }
+}
+
+
+/// Counts the events, taking over the counters handlers used to keep themselves.
+#[derive(Debug, Default)]
+pub struct EventMetrics;
+
+impl Subscriber for EventMetrics {
+    fn notify(&self, event: &Event) {
+        let registry = metrics::registry();
+        match event {
+            Event::FeatureAdded(_) => registry
+                .counter("feature_changes_total", "Features added and deleted, including copies from a primary.", &[("change", "added")])
+                .inc(),
+            Event::FeatureDeleted(_) => registry
+                .counter("feature_changes_total", "Features added and deleted, including copies from a primary.", &[("change", "deleted")])
+                .inc(),
+            Event::RouteRecorded(_) => registry.counter("routes_recorded_total", "Routes recorded by RecordRoute.", &[]).inc(),
+            Event::NotePosted(_) => registry.counter("route_chat_notes_total", "Notes posted to RouteChat.", &[]).inc(),
+            Event::ConfigReloaded(_) => registry.counter("config_reloads_total", "Times the configuration file was applied again.", &[]).inc(),
+        }
+    }
+}
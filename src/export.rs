@@ -0,0 +1,122 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use prost::Message;
+
+use crate::geo;
+use crate::proto_json::ProtoJson;
+use crate::route_guide::{ExportRequest, ExportTrailer, Feature};
+
+
+pub const DEFAULT_BATCH_SIZE: usize = 500;
+
+pub const MAX_BATCH_SIZE: usize = 10_000;
+
+/// `binary_db::MAGIC`, which is only there with the server feature.
+const BINARY_MAGIC: &[u8; 8] = b"RGFEAT01";
+
+
+/// The chunk size an ExportFeatures call asked for.
+pub fn batch_size(request: &ExportRequest) -> usize {
+    match request.batch_size as usize {
+        0 => DEFAULT_BATCH_SIZE,
+        size => size.min(MAX_BATCH_SIZE),
+    }
+}
+
+
+/// Adds up the features of an export, for its trailer on the server and to check it against the
+/// trailer on the client.
+#[derive(Debug, Clone, Copy)]
+pub struct Checksum {
+    hash: u64,
+    count: u64,
+}
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Checksum { hash: 0xcbf2_9ce4_8422_2325, count: 0 }
+    }
+}
+
+impl Checksum {
+    pub fn add(&mut self, feature: &Feature) {
+        let mut bytes = Vec::with_capacity(feature.encoded_len() + 4);
+        feature.encode_length_delimited(&mut bytes).unwrap();
+        self.hash = bytes.iter().fold(self.hash, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3));
+        self.count += 1;
+    }
+
+    pub fn trailer(&self) -> ExportTrailer {
+        ExportTrailer { feature_count: self.count, checksum: format!("{:016x}", self.hash) }
+    }
+
+    /// Whether the features added are the ones the trailer describes.
+    pub fn verify(&self, trailer: &ExportTrailer) -> Result<(), String> {
+        let own = self.trailer();
+        if own.feature_count != trailer.feature_count {
+            return Err(format!("received {} features, but the export has {}", own.feature_count, trailer.feature_count));
+        }
+        if own.checksum != trailer.checksum {
+            return Err(format!("the checksum is {}, but the export's is {}", own.checksum, trailer.checksum));
+        }
+        Ok(())
+    }
+}
+
+
+/// How an export is written: `json` like the feature database, `geojson` as a
+/// FeatureCollection, or `binary` as a binary database (see `binary_db`).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExportFormat {
+    Json,
+    GeoJson,
+    Binary,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ExportFormat::Json),
+            "geojson" => Ok(ExportFormat::GeoJson),
+            "binary" => Ok(ExportFormat::Binary),
+            other => Err(format!("unknown export format '{}', expected json, geojson or binary", other)),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ExportFormat::Json => "json",
+            ExportFormat::GeoJson => "geojson",
+            ExportFormat::Binary => "binary",
+        })
+    }
+}
+
+impl ExportFormat {
+    pub fn write(self, out: &mut impl Write, features: &[Feature]) -> io::Result<()> {
+        let invalid = |e: serde_json::Error| io::Error::new(io::ErrorKind::InvalidData, e);
+        match self {
+            ExportFormat::Json => {
+                let records: Vec<_> = features.iter().map(ProtoJson::to_json).collect();
+                serde_json::to_writer_pretty(&mut *out, &records).map_err(invalid)?;
+            },
+            ExportFormat::GeoJson => serde_json::to_writer_pretty(&mut *out, &geo::geojson(features)).map_err(invalid)?,
+            ExportFormat::Binary => {
+                out.write_all(BINARY_MAGIC)?;
+                let mut buffer = Vec::new();
+                for feature in features {
+                    buffer.clear();
+                    feature.encode_length_delimited(&mut buffer).unwrap();
+                    out.write_all(&buffer)?;
+                }
+            },
+        }
+        out.flush()
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tonic::Status;
+
+use crate::conditional;
+use crate::route_guide::{Feature, Point};
+
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Misses the server answered with "not modified", so the expired answer was used again.
+    pub revalidated: u64,
+}
+
+impl CacheStats {
+    /// Fraction of lookups answered from the cache, 0 before the first lookup.
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 { 0.0 } else { self.hits as f64 / total as f64 }
+    }
+}
+
+
+#[derive(Debug)]
+struct Entry {
+    feature: Feature,
+    /// The server's ETag for it; expired entries with one are kept to revalidate.
+    etag: Option<String>,
+    expires: Instant,
+    used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Lru {
+    entries: HashMap<Point, Entry>,
+    // Last use -> point, so the least recently used entry is the first one.
+    by_use: BTreeMap<u64, Point>,
+    clock: u64,
+}
+
+impl Lru {
+    fn touch(&mut self, point: &Point) {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(point) {
+            self.by_use.remove(&entry.used);
+            entry.used = self.clock;
+            self.by_use.insert(self.clock, point.clone());
+        }
+    }
+
+    fn remove(&mut self, point: &Point) {
+        if let Some(entry) = self.entries.remove(point) {
+            self.by_use.remove(&entry.used);
+        }
+    }
+}
+
+
+/// Remembers `GetFeature` answers by point for `ttl`, keeping at most `capacity` of them and
+/// evicting the least recently used first. Only successful answers are cached. Expired answers
+/// the server gave an ETag are revalidated rather than fetched again (see `get_or_revalidate`).
+#[derive(Debug)]
+pub struct FeatureCache {
+    lru: Mutex<Lru>,
+    capacity: usize,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    revalidated: AtomicU64,
+}
+
+impl FeatureCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        FeatureCache {
+            lru: Mutex::default(),
+            capacity,
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            revalidated: AtomicU64::new(0),
+        }
+    }
+
+    /// The cached feature at the point, if it hasn't expired.
+    pub fn get(&self, point: &Point) -> Option<Feature> {
+        let mut lru = self.lru.lock().unwrap();
+        let now = Instant::now();
+
+        let (fresh, tagged) = match lru.entries.get(point) {
+            Some(entry) => (entry.expires > now, entry.etag.is_some()),
+            None => (false, false),
+        };
+        if !fresh {
+            if !tagged {
+                lru.remove(point);
+            }
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        lru.touch(point);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        lru.entries.get(point).map(|entry| entry.feature.clone())
+    }
+
+    pub fn insert(&self, point: Point, feature: Feature) {
+        self.insert_tagged(point, feature, None);
+    }
+
+    /// Caches the feature with the ETag the server sent with it.
+    pub fn insert_tagged(&self, point: Point, feature: Feature, etag: Option<String>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut lru = self.lru.lock().unwrap();
+        lru.remove(&point);
+        while lru.entries.len() >= self.capacity {
+            let oldest = match lru.by_use.values().next() {
+                Some(oldest) => oldest.clone(),
+                None => break,
+            };
+            lru.remove(&oldest);
+        }
+
+        let expires = Instant::now() + self.ttl;
+        lru.entries.insert(point.clone(), Entry { feature, etag, expires, used: 0 });
+        lru.touch(&point);
+    }
+
+    /// The ETag of the expired answer at the point, to revalidate it with.
+    fn stale_etag(&self, point: &Point) -> Option<String> {
+        self.lru.lock().unwrap().entries.get(point).and_then(|entry| entry.etag.clone())
+    }
+
+    /// Uses the expired answer at the point for another `ttl`.
+    fn renew(&self, point: &Point) -> Option<Feature> {
+        let mut lru = self.lru.lock().unwrap();
+        let entry = lru.entries.get_mut(point)?;
+        entry.expires = Instant::now() + self.ttl;
+        let feature = entry.feature.clone();
+        lru.touch(point);
+        self.revalidated.fetch_add(1, Ordering::Relaxed);
+        Some(feature)
+    }
+
+    /// Answers from the cache, or runs `fetch` and caches its answer.
+    pub async fn get_or_fetch<F>(&self, point: &Point, fetch: F) -> Result<Feature, Status>
+        where F: Future<Output = Result<Feature, Status>>
+    {
+        if let Some(feature) = self.get(point) {
+            return Ok(feature);
+        }
+
+        let feature = fetch.await?;
+        self.insert(point.clone(), feature.clone());
+        Ok(feature)
+    }
+
+    /// Answers from the cache, or runs `fetch` with the ETag of the expired answer, if there is
+    /// one. `fetch` answers with the feature and its ETag, or fails with
+    /// `conditional::not_modified`, in which case the expired answer is used again.
+    pub async fn get_or_revalidate<F, Fut>(&self, point: &Point, fetch: F) -> Result<Feature, Status>
+        where
+            F: FnOnce(Option<String>) -> Fut,
+            Fut: Future<Output = Result<(Feature, Option<String>), Status>>,
+    {
+        if let Some(feature) = self.get(point) {
+            return Ok(feature);
+        }
+
+        let stale = self.stale_etag(point);
+        let revalidating = stale.is_some();
+        match fetch(stale).await {
+            Ok((feature, etag)) => {
+                self.insert_tagged(point.clone(), feature.clone(), etag);
+                Ok(feature)
+            },
+            Err(status) if revalidating && conditional::is_not_modified(&status) => match self.renew(point) {
+                Some(feature) => Ok(feature),
+                None => Err(Status::internal("the server says the feature is unchanged, but it's no longer cached")),
+            },
+            Err(status) => Err(status),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            revalidated: self.revalidated.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lru.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
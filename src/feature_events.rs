@@ -0,0 +1,121 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use tokio::sync::broadcast;
+
+use crate::event_bus::{Event, Subscriber};
+use crate::ids::{IdGenerator, Ulids};
+use crate::route_guide::Feature;
+
+
+/// How many past events are kept for subscribers that resume.
+pub const HISTORY: usize = 1024;
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+}
+
+impl ChangeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FeatureEvent {
+    /// Increases by one with every event of a tenant, starting at 1.
+    pub id: u64,
+    pub kind: ChangeKind,
+    pub feature: Feature,
+}
+
+
+/// What a subscriber gets: the events it missed, then the live ones.
+pub struct Subscription {
+    pub missed: Vec<FeatureEvent>,
+    /// True if events after the requested one are no longer kept, so the subscriber has to
+    /// reload everything instead of relying on `missed`.
+    pub gap: bool,
+    pub live: broadcast::Receiver<FeatureEvent>,
+}
+
+
+/// Publishes changes to a tenant's features and keeps the recent ones so subscribers can resume
+/// where they left off.
+#[derive(Debug)]
+pub struct FeatureEvents {
+    /// Made up when the events are created, so a subscriber can tell ids from before a restart.
+    epoch: String,
+    sender: broadcast::Sender<FeatureEvent>,
+    // Publishing and subscribing both hold this lock, so no event falls between `missed` and
+    // `live`.
+    history: Mutex<(u64, VecDeque<FeatureEvent>)>,
+}
+
+impl Default for FeatureEvents {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(HISTORY);
+        FeatureEvents { epoch: Ulids::default().next_id(), sender, history: Mutex::new((0, VecDeque::with_capacity(HISTORY))) }
+    }
+}
+
+impl FeatureEvents {
+    pub fn epoch(&self) -> &str {
+        &self.epoch
+    }
+
+    /// The id of the last event published, 0 before the first.
+    pub fn last_id(&self) -> u64 {
+        self.history.lock().unwrap().0
+    }
+
+    pub fn publish(&self, kind: ChangeKind, feature: Feature) {
+        let mut history = self.history.lock().unwrap();
+        let (last_id, events) = &mut *history;
+
+        *last_id += 1;
+        let event = FeatureEvent { id: *last_id, kind, feature };
+        if events.len() == HISTORY {
+            events.pop_front();
+        }
+        events.push_back(event.clone());
+
+        // Fails only when nobody is subscribed.
+        let _ = self.sender.send(event);
+    }
+
+    /// Subscribes to events after `last_seen`, or only to new ones if `None`.
+    pub fn subscribe(&self, last_seen: Option<u64>) -> Subscription {
+        let history = self.history.lock().unwrap();
+        let (last_id, events) = &*history;
+        let live = self.sender.subscribe();
+
+        let last_seen = match last_seen {
+            // Ids start over when the server restarts, so this is from before a restart.
+            Some(last_seen) if last_seen > *last_id => return Subscription { missed: vec![], gap: true, live },
+            Some(last_seen) => last_seen,
+            None => return Subscription { missed: vec![], gap: false, live },
+        };
+
+        let oldest_kept = events.front().map_or(*last_id + 1, |event| event.id);
+        let missed = events.iter().filter(|event| event.id > last_seen).cloned().collect();
+        Subscription { missed, gap: last_seen + 1 < oldest_kept, live }
+    }
+}
+
+/// A tenant's feed of changes subscribes to its feature events.
+impl Subscriber for FeatureEvents {
+    fn notify(&self, event: &Event) {
+        match event {
+            Event::FeatureAdded(added) => self.publish(ChangeKind::Added, added.feature.clone()),
+            Event::FeatureDeleted(deleted) => self.publish(ChangeKind::Removed, deleted.feature.clone()),
+            _ => {},
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use prost_types::FieldMask;
+
+use crate::route_guide::{Feature, Point};
+
+
+/// The paths a Feature read mask can name. `id` is always sent, so naming it changes nothing.
+pub const FEATURE_PATHS: &[&str] = &[
+    "name", "location", "location.latitude", "location.longitude", "description", "tags", "cluster_size",
+    "names_by_locale", "id", "attributes",
+];
+
+
+/// Which fields of a Feature to send, parsed from a read mask.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FeatureMask {
+    name: bool,
+    latitude: bool,
+    longitude: bool,
+    description: bool,
+    tags: bool,
+    cluster_size: bool,
+    names_by_locale: bool,
+    attributes: bool,
+}
+
+impl FeatureMask {
+    /// Keeps every field.
+    pub const ALL: FeatureMask = FeatureMask {
+        name: true, latitude: true, longitude: true, description: true, tags: true, cluster_size: true,
+        names_by_locale: true, attributes: true,
+    };
+
+    /// An unset or empty mask keeps every field. Fails with the first path that isn't one of
+    /// `FEATURE_PATHS`.
+    pub fn parse(mask: Option<&FieldMask>) -> Result<Self, String> {
+        let paths = match mask {
+            Some(mask) if !mask.paths.is_empty() => &mask.paths,
+            _ => return Ok(FeatureMask::ALL),
+        };
+
+        let mut parsed = FeatureMask {
+            name: false, latitude: false, longitude: false, description: false, tags: false, cluster_size: false,
+            names_by_locale: false, attributes: false,
+        };
+        for path in paths {
+            match path.as_str() {
+                "name" => parsed.name = true,
+                "location" => {
+                    parsed.latitude = true;
+                    parsed.longitude = true;
+                },
+                "location.latitude" => parsed.latitude = true,
+                "location.longitude" => parsed.longitude = true,
+                "description" => parsed.description = true,
+                "tags" => parsed.tags = true,
+                "cluster_size" => parsed.cluster_size = true,
+                "names_by_locale" => parsed.names_by_locale = true,
+                "attributes" => parsed.attributes = true,
+                "id" => {},
+                other => return Err(format!("unknown Feature field '{}'", other)),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Clears the fields the mask leaves out, so they aren't encoded.
+    pub fn apply(&self, mut feature: Feature) -> Feature {
+        if *self == FeatureMask::ALL {
+            return feature;
+        }
+
+        if !self.name {
+            feature.name.clear();
+        }
+        feature.location = match feature.location.take() {
+            Some(location) if self.latitude || self.longitude => Some(Point {
+                latitude: if self.latitude { location.latitude } else { 0 },
+                longitude: if self.longitude { location.longitude } else { 0 },
+                read_mask: None,
+            }),
+            _ => None,
+        };
+        if !self.description {
+            feature.description.clear();
+        }
+        if !self.tags {
+            feature.tags.clear();
+        }
+        if !self.cluster_size {
+            feature.cluster_size = 0;
+        }
+        if !self.names_by_locale {
+            feature.names_by_locale.clear();
+        }
+        if !self.attributes {
+            feature.attributes.clear();
+        }
+        feature
+    }
+}
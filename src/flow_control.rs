@@ -0,0 +1,184 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{Stream, StreamExt};
+
+
+/// Counts of a streaming call's messages in both directions, to tell how far the sender has got
+/// ahead of what came back.
+#[derive(Debug, Clone, Default)]
+pub struct Meter {
+    produced: Arc<AtomicU64>,
+    acknowledged: Arc<AtomicU64>,
+}
+
+impl Meter {
+    /// Messages taken from the outbound stream, which is as far as the transport let it run.
+    pub fn produced(&self) -> u64 {
+        self.produced.load(Ordering::SeqCst)
+    }
+
+    pub fn acknowledged(&self) -> u64 {
+        self.acknowledged.load(Ordering::SeqCst)
+    }
+
+    /// Marks the first `count` messages as answered. Counts lower than an earlier one are ignored.
+    pub fn acknowledge(&self, count: u64) {
+        self.acknowledged.fetch_max(count, Ordering::SeqCst);
+    }
+
+    /// Messages sent but not answered yet: held in the transport's buffers, the HTTP/2 windows
+    /// or the server.
+    pub fn buffered(&self) -> u64 {
+        self.produced().saturating_sub(self.acknowledged())
+    }
+}
+
+/// Passes `messages` through, counting in the meter each one the transport pulls.
+pub fn metered<S>(messages: S, meter: &Meter) -> impl Stream<Item = S::Item>
+    where
+        S: Stream + Send + 'static,
+{
+    let produced = meter.produced.clone();
+    messages.inspect(move |_| {
+        produced.fetch_add(1, Ordering::SeqCst);
+    })
+}
+
+
+/// Samples of a meter's produced count, taken at a fixed interval.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Samples {
+    pub interval: Duration,
+    pub produced: Vec<u64>,
+}
+
+impl Samples {
+    /// Takes a sample every `interval` for `duration`.
+    pub async fn take(meter: &Meter, interval: Duration, duration: Duration) -> Samples {
+        let mut samples = Samples { interval, produced: vec![meter.produced()] };
+        let mut ticks = tokio::time::interval(interval);
+        ticks.tick().await;
+
+        let count = (duration.as_secs_f64() / interval.as_secs_f64()).ceil() as usize;
+        for _ in 0..count {
+            ticks.tick().await;
+            samples.produced.push(meter.produced());
+        }
+        samples
+    }
+
+    /// Whether the count stopped growing for the last `window` of the samples, which is what a
+    /// backpressured sender looks like.
+    pub fn plateaued(&self, window: Duration) -> bool {
+        let needed = (window.as_secs_f64() / self.interval.as_secs_f64()).ceil() as usize + 1;
+        if self.produced.len() < needed {
+            return false;
+        }
+        let tail = &self.produced[self.produced.len() - needed..];
+        tail.iter().all(|&count| count == tail[0])
+    }
+
+    pub fn last(&self) -> u64 {
+        self.produced.last().copied().unwrap_or(0)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    const INTERVAL: Duration = Duration::from_millis(10);
+
+    /// A transport that holds up to `window` messages, as HTTP/2's windows and buffers do, and
+    /// takes more only as the other end reads.
+    fn transport<S>(messages: S, window: usize) -> mpsc::Receiver<S::Item>
+        where
+            S: Stream + Send + 'static,
+            S::Item: Send + 'static,
+    {
+        let (mut sender, receiver) = mpsc::channel(window);
+        tokio::spawn(async move {
+            futures::pin_mut!(messages);
+            while let Some(message) = messages.next().await {
+                if sender.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+
+    #[tokio::test]
+    async fn unread_window_holds_the_sender_back() {
+        let meter = Meter::default();
+        let mut inbound = transport(metered(futures::stream::iter(1u64..), &meter), 64);
+
+        let stalled = Samples::take(&meter, INTERVAL, Duration::from_millis(200)).await;
+        assert!(stalled.plateaued(Duration::from_millis(100)), "{:?}", stalled.produced);
+        // The window, and the message waiting for room in it.
+        assert_eq!(stalled.last(), 65);
+        assert_eq!(meter.buffered(), 65);
+
+        // Reading makes room, and sending resumes as far as the window again.
+        for _ in 0..100 {
+            let index = inbound.recv().await.unwrap();
+            meter.acknowledge(index);
+        }
+        tokio::time::delay_for(INTERVAL).await;
+        assert_eq!(meter.acknowledged(), 100);
+        assert_eq!(meter.produced(), 165);
+        assert_eq!(meter.buffered(), 65);
+    }
+
+    #[tokio::test]
+    async fn read_window_doesnt_plateau() {
+        let meter = Meter::default();
+        let ticks = tokio::time::interval(Duration::from_millis(1)).map(|_| ());
+        let mut inbound = transport(metered(ticks, &meter), 64);
+        // Stopped with the test's runtime.
+        {
+            let meter = meter.clone();
+            tokio::spawn(async move {
+                let mut read = 0;
+                while inbound.recv().await.is_some() {
+                    read += 1;
+                    meter.acknowledge(read);
+                }
+            });
+        }
+
+        let running = Samples::take(&meter, INTERVAL, Duration::from_millis(200)).await;
+        assert!(!running.plateaued(Duration::from_millis(50)), "{:?}", running.produced);
+        assert!(running.last() > running.produced[0]);
+        assert!(meter.buffered() <= 65, "{} buffered", meter.buffered());
+    }
+
+    #[test]
+    fn acknowledging_never_goes_back() {
+        let meter = Meter::default();
+        meter.produced.store(10, Ordering::SeqCst);
+        meter.acknowledge(7);
+        meter.acknowledge(3);
+        assert_eq!(meter.acknowledged(), 7);
+        assert_eq!(meter.buffered(), 3);
+
+        // Answers can be counted before their messages are.
+        meter.acknowledge(12);
+        assert_eq!(meter.buffered(), 0);
+    }
+
+    #[test]
+    fn plateau_needs_the_whole_window() {
+        let interval = Duration::from_millis(100);
+        let samples = |produced: &[u64]| Samples { interval, produced: produced.to_vec() };
+
+        assert!(samples(&[1, 5, 9, 9, 9]).plateaued(Duration::from_millis(200)));
+        assert!(!samples(&[1, 5, 9, 9, 9]).plateaued(Duration::from_millis(300)));
+        assert!(!samples(&[9, 9]).plateaued(Duration::from_millis(200)));
+        assert_eq!(Samples::default().last(), 0);
+    }
+}
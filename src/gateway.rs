@@ -0,0 +1,620 @@
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use futures::{Stream, StreamExt};
+use hyper::body::HttpBody;
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::json;
+use tokio::sync::broadcast;
+use tonic::Status;
+
+use crate::admin_ui;
+use crate::compression::Compression;
+use crate::cors::Cors;
+use crate::feature_events::{FeatureEvent, Subscription};
+use crate::geo;
+#[cfg(feature = "graphql")]
+use crate::graphql;
+use crate::http::{self, middleware::TooLarge};
+use crate::index::FeatureIndex;
+use crate::ip_filter::IpFilter;
+use crate::openapi::{self, Content, Parameter, Route};
+use crate::policy::{Call, PolicyEngine, Resource};
+use crate::proto_json::{self, ProtoJson};
+use crate::recorder::{RecordError, RecorderLimits, RouteRecorder};
+use crate::route_guide::{Feature, Point, RouteSummary};
+use crate::streaming::StreamingExt;
+use crate::tenant::{TenantData, Tenants};
+use crate::validation;
+use crate::zip_stream::ZipWriter;
+
+
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// The largest request body, which bounds a route uploaded to `/routes/stream`.
+pub const MAX_BODY_SIZE: u64 = 16 << 20;
+
+const TOKEN_PREFIX: &str = "features:";
+
+/// Idle event streams get a comment this often, so proxies don't close them.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+const RESET_EVENT: &str = "event: reset\ndata: {}\n\n";
+
+/// Features or routes written to `/export.zip` at a time.
+const EXPORT_BATCH: usize = 256;
+
+
+/// A position in the feature listing. Clients get it as an opaque string and hand it back
+/// unchanged; it's only meaningful to this gateway.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PageToken {
+    offset: usize,
+}
+
+impl PageToken {
+    pub fn encode(&self) -> String {
+        base64::encode_config(format!("{}{}", TOKEN_PREFIX, self.offset), base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn decode(token: &str) -> Option<Self> {
+        let decoded = base64::decode_config(token, base64::URL_SAFE_NO_PAD).ok()?;
+        let offset = std::str::from_utf8(&decoded).ok()?.strip_prefix(TOKEN_PREFIX)?.parse().ok()?;
+        Some(PageToken { offset })
+    }
+}
+
+
+/// The first value of a query string parameter, undecoded.
+pub fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            (parts.next().unwrap_or(""), parts.next().unwrap_or(""))
+        })
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+
+#[derive(Debug, Default, PartialEq)]
+struct PageRequest {
+    page_size: usize,
+    token: Option<PageToken>,
+}
+
+fn parse_page_request(query: Option<&str>) -> Result<PageRequest, String> {
+    let mut request = PageRequest { page_size: DEFAULT_PAGE_SIZE, token: None };
+
+    for pair in query.unwrap_or("").split('&').filter(|pair| !pair.is_empty()) {
+        let mut parts = pair.splitn(2, '=');
+        let (key, value) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+        match key {
+            "page_size" => {
+                request.page_size = match value.parse() {
+                    Ok(size) if (1..=MAX_PAGE_SIZE).contains(&size) => size,
+                    _ => return Err(format!("page_size must be between 1 and {}", MAX_PAGE_SIZE)),
+                };
+            },
+            // An empty token is the first page, so clients can pass back whatever they got.
+            "page_token" if value.is_empty() => request.token = None,
+            "page_token" => {
+                request.token = Some(PageToken::decode(value).ok_or_else(|| "invalid page_token".to_string())?);
+            },
+            _ => {},
+        }
+    }
+
+    Ok(request)
+}
+
+
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = status;
+    response.headers_mut().insert("content-type", "application/json".parse().unwrap());
+    response
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, json!({ "error": message }))
+}
+
+fn list_features(tenant: &TenantData, query: Option<&str>) -> Response<Body> {
+    let request = match parse_page_request(query) {
+        Ok(request) => request,
+        Err(message) => return error_response(StatusCode::BAD_REQUEST, &message),
+    };
+
+    let features = tenant.features();
+    let offset = request.token.map_or(0, |token| token.offset);
+    let page = features.page(offset, request.page_size);
+
+    let next = offset + page.len();
+    let next_page_token = if next < features.len() { PageToken { offset: next }.encode() } else { String::new() };
+
+    json_response(StatusCode::OK, json!({
+        "features": page.iter().map(ProtoJson::to_json).collect::<Vec<_>>(),
+        "nextPageToken": next_page_token,
+    }))
+}
+
+/// `GET /features/{id}`: the feature with the id, or 404.
+fn get_feature(tenant: &TenantData, id: &str) -> Response<Body> {
+    match tenant.features().get_by_id(id) {
+        Some(feature) => json_response(StatusCode::OK, feature.to_json()),
+        None => error_response(StatusCode::NOT_FOUND, "no feature with this id"),
+    }
+}
+
+/// All of the tenant's features as a GeoJSON FeatureCollection, with coordinates in degrees.
+fn features_geojson(tenant: &TenantData) -> Response<Body> {
+    let mut response = json_response(StatusCode::OK, geo::geojson(tenant.features().iter()));
+    response.headers_mut().insert("content-type", "application/geo+json".parse().unwrap());
+    response
+}
+
+/// All of the tenant's features as newline-delimited JSON, written from a snapshot as the client
+/// reads them rather than all at once.
+fn features_ndjson(tenant: &TenantData) -> Response<Body> {
+    let features = tenant.features();
+    let lines = futures::stream::iter(0..features.len()).map(move |i| Ok::<Feature, Status>(features.page(i, 1)[0].clone()));
+    let mut response = Response::new(lines.into_ndjson_body());
+    response.headers_mut().insert("content-type", "application/x-ndjson".parse().unwrap());
+    response
+}
+
+/// A zip of `features.geojson`, the tenant's features as in `/features.geojson`, and
+/// `routes.csv`, the summaries of its recorded routes. Written from snapshots as the client reads
+/// it, a batch at a time, so only a batch and the compressor's window are held at once.
+fn export_zip(tenant: &TenantData) -> Response<Body> {
+    let mut response = Response::new(Body::wrap_stream(export_archive(tenant.features(), tenant.routes())));
+    response.headers_mut().insert("content-type", "application/zip".parse().unwrap());
+    response.headers_mut().insert("content-disposition", "attachment; filename=\"export.zip\"".parse().unwrap());
+    response
+}
+
+fn export_archive(features: Arc<FeatureIndex>, routes: Vec<RouteSummary>) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+    // `yield` takes a plain value: try_stream! doesn't see a `?` inside one.
+    async_stream::try_stream! {
+        let mut zip = ZipWriter::new();
+
+        let chunk = zip.start("features.geojson")?;
+        yield chunk;
+        let chunk = zip.write(br#"{"type":"FeatureCollection","features":["#)?;
+        yield chunk;
+        let mut first = true;
+        for offset in (0..features.len()).step_by(EXPORT_BATCH) {
+            let mut batch = String::new();
+            for value in features.page(offset, EXPORT_BATCH).iter().filter_map(geo::geojson_feature) {
+                if !first {
+                    batch.push(',');
+                }
+                first = false;
+                batch.push_str(&value.to_string());
+            }
+            let chunk = zip.write(batch.as_bytes())?;
+            yield chunk;
+        }
+        let chunk = zip.write(b"]}")?;
+        yield chunk;
+
+        // Polylines only use characters from '?' to '~', so they need no quoting.
+        let chunk = zip.start("routes.csv")?;
+        yield chunk;
+        let chunk = zip.write(b"point_count,feature_count,distance_m,elapsed_time_s,polyline\n")?;
+        yield chunk;
+        for batch in routes.chunks(EXPORT_BATCH) {
+            let mut lines = String::new();
+            for route in batch {
+                lines.push_str(&format!("{},{},{},{},{}\n", route.point_count, route.feature_count, route.distance, route.elapsed_time, route.polyline));
+            }
+            let chunk = zip.write(lines.as_bytes())?;
+            yield chunk;
+        }
+
+        let chunk = zip.finish()?;
+        yield chunk;
+    }
+}
+
+fn sse_event(event: &FeatureEvent) -> String {
+    format!("id: {}\nevent: {}\ndata: {}\n\n", event.id, event.kind.name(), event.feature.to_json())
+}
+
+/// Server-sent events for feature changes. A client reconnecting with `Last-Event-ID` gets what
+/// it missed; if that's no longer known it gets a `reset` event and should reload `/features`.
+fn feature_events(tenant: &TenantData, request: &Request<Body>) -> Response<Body> {
+    let last_event_id = request
+        .headers()
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok()?.parse().ok());
+    let Subscription { missed, gap, mut live } = tenant.feature_events().subscribe(last_event_id);
+
+    let events = async_stream::stream! {
+        if gap {
+            yield Ok::<_, Infallible>(RESET_EVENT.to_string());
+        }
+        for event in &missed {
+            yield Ok(sse_event(event));
+        }
+        loop {
+            match tokio::time::timeout(KEEP_ALIVE, live.recv()).await {
+                Ok(Ok(event)) => yield Ok(sse_event(&event)),
+                // Events were dropped because this client is too slow.
+                Ok(Err(broadcast::RecvError::Lagged(_))) => yield Ok(RESET_EVENT.to_string()),
+                Ok(Err(broadcast::RecvError::Closed)) => break,
+                Err(_) => yield Ok(": keep-alive\n\n".to_string()),
+            }
+        }
+    };
+
+    let mut response = Response::new(Body::wrap_stream(events));
+    response.headers_mut().insert("content-type", "text/event-stream".parse().unwrap());
+    response.headers_mut().insert("cache-control", "no-cache".parse().unwrap());
+    response
+}
+
+/// Records a route like the RecordRoute RPC, from a body of newline-delimited JSON points in E7
+/// degrees: `{"latitude": 409146138, "longitude": -746188906}`. Points are recorded as they
+/// arrive, so the body can be streamed while walking. `?polyline=true` adds the path to the
+/// summary as an encoded polyline.
+async fn record_route(tenant: &TenantData, limits: RecorderLimits, query: Option<&str>, mut body: Body) -> Response<Body> {
+    let mut recorder = RouteRecorder::new(tenant.features(), limits);
+    if query_param(query, "polyline") == Some("true") {
+        recorder = recorder.keep_path();
+    }
+    let mut buffer = Vec::new();
+    let mut line = 0;
+
+    loop {
+        let chunk = match limits.idle_timeout {
+            Some(idle) => match tokio::time::timeout(idle, body.data()).await {
+                Ok(chunk) => chunk,
+                Err(_) => return error_response(
+                    StatusCode::REQUEST_TIMEOUT, &format!("no point received for {}s", idle.as_secs()),
+                ),
+            },
+            None => body.data().await,
+        };
+        match chunk {
+            Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+            Some(Err(e)) => return match TooLarge::of(&e) {
+                Some(too_large) => error_response(StatusCode::PAYLOAD_TOO_LARGE, &too_large.to_string()),
+                None => error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            },
+            None => break,
+        }
+
+        while let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+            let text: Vec<u8> = buffer.drain(..=end).collect();
+            line += 1;
+            if let Err(response) = record_point(&mut recorder, &text, line) {
+                return response;
+            }
+        }
+    }
+    // The last line doesn't need a newline.
+    if let Err(response) = record_point(&mut recorder, &buffer, line + 1) {
+        return response;
+    }
+
+    let summary = match recorder.finish() {
+        Ok(summary) => summary,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    tenant.add_route(summary.clone());
+    json_response(StatusCode::OK, summary.to_json())
+}
+
+fn record_point(recorder: &mut RouteRecorder, text: &[u8], line: usize) -> Result<(), Response<Body>> {
+    if text.iter().all(u8::is_ascii_whitespace) {
+        return Ok(());
+    }
+    let invalid = |message: String| error_response(StatusCode::BAD_REQUEST, &format!("line {}: {}", line, message));
+
+    let parsed: Point = proto_json::parse(text).map_err(invalid)?;
+    let mut point = Point { read_mask: None, ..parsed };
+    validation::validate(&mut point).map_err(|status| invalid(status.message().to_string()))?;
+
+    recorder.push(point).map_err(|e| match e {
+        RecordError::TooManyPoints { .. } => error_response(StatusCode::PAYLOAD_TOO_LARGE, &e.to_string()),
+        RecordError::Spool { .. } => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        _ => invalid(e.to_string()),
+    })
+}
+
+/// The tenant of an `authorization: Bearer <token>` header. Event streams may pass the token as
+/// `?token=` instead, since browsers can't set headers on an `EventSource`.
+fn authenticate(tenants: &Tenants, request: &Request<Body>) -> Option<Arc<TenantData>> {
+    let header = request
+        .headers()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let token = match header {
+        Some(token) => token,
+        None if request.uri().path().starts_with("/events/") => query_param(request.uri().query(), "token")?,
+        None => return None,
+    };
+
+    tenants.get(&tenants.authenticate(token)?)
+}
+
+/// Whether the policy lets the tenant's token make the RouteGuide call a request stands for.
+pub fn authorize(policy: &PolicyEngine, tenant: &TenantData, method: &str, resource: &Resource) -> Result<(), Status> {
+    let subject = format!("token:{}", tenant.id);
+    policy.check_call(&Call { method, subject: &subject, tenant: tenant.id.as_str(), resource })
+}
+
+/// The tenant of an authenticated request the policy allows as `method`, or the error response.
+fn authorized(tenants: &Tenants, policy: &PolicyEngine, request: &Request<Body>, method: &str, resource: Resource) -> Result<Arc<TenantData>, Response<Body>> {
+    let tenant = authenticate(tenants, request).ok_or_else(|| error_response(StatusCode::UNAUTHORIZED, "No valid auth token"))?;
+    authorize(policy, &tenant, method, &resource).map_err(|status| error_response(StatusCode::FORBIDDEN, status.message()))?;
+    Ok(tenant)
+}
+
+const PAGE_PARAMETERS: &[Parameter] = &[
+    Parameter { name: "page_size", location: "query", kind: "integer", description: "Features per page, 1 to 1000; 100 if not given." },
+    Parameter { name: "page_token", location: "query", kind: "string", description: "The nextPageToken of the previous page." },
+];
+
+/// The routes `gateway_service` serves, for the OpenAPI document at `/openapi.json`. A route
+/// added there goes here too.
+pub const ROUTES: &[Route] = &[
+    Route {
+        method: "GET",
+        path: "/features",
+        summary: "Lists the tenant's features in load order, a page at a time.",
+        authenticated: true,
+        parameters: PAGE_PARAMETERS,
+        request: None,
+        response: Content::Page { field: "features", message: "Feature" },
+    },
+    Route {
+        method: "GET",
+        path: "/features/{id}",
+        summary: "The feature with this id.",
+        authenticated: true,
+        parameters: &[
+            Parameter { name: "id", location: "path", kind: "string", description: "The feature's id, as in its `id` field." },
+        ],
+        request: None,
+        response: Content::Message("Feature"),
+    },
+    Route {
+        method: "GET",
+        path: "/features.geojson",
+        summary: "All of the tenant's features as a GeoJSON FeatureCollection, in degrees.",
+        authenticated: true,
+        parameters: &[],
+        request: None,
+        response: Content::Other { content_type: "application/geo+json", description: "A GeoJSON FeatureCollection." },
+    },
+    Route {
+        method: "GET",
+        path: "/features.ndjson",
+        summary: "All of the tenant's features, streamed one per line.",
+        authenticated: true,
+        parameters: &[],
+        request: None,
+        response: Content::Lines("Feature"),
+    },
+    Route {
+        method: "GET",
+        path: "/export.zip",
+        summary: "The tenant's features as GeoJSON and its recorded routes as CSV, in a zip streamed as it's written.",
+        authenticated: true,
+        parameters: &[],
+        request: None,
+        response: Content::Other { content_type: "application/zip", description: "features.geojson and routes.csv." },
+    },
+    Route {
+        method: "GET",
+        path: "/events/features",
+        summary: "Streams changes to the tenant's features as server-sent events.",
+        authenticated: true,
+        parameters: &[
+            Parameter { name: "token", location: "query", kind: "string", description: "The auth token, for clients that can't set headers." },
+            Parameter { name: "Last-Event-ID", location: "header", kind: "string", description: "Resumes after this event." },
+        ],
+        request: None,
+        response: Content::Other {
+            content_type: "text/event-stream",
+            description: "`added` and `removed` events with a Feature as JSON, and `reset` when events were missed.",
+        },
+    },
+    Route {
+        method: "POST",
+        path: "/routes/stream",
+        summary: "Records a route like RecordRoute, from points sent as they're walked.",
+        authenticated: true,
+        parameters: &[
+            Parameter { name: "polyline", location: "query", kind: "boolean", description: "Adds the path to the summary as an encoded polyline." },
+        ],
+        request: Some(Content::Lines("Point")),
+        response: Content::Message("RouteSummary"),
+    },
+    #[cfg(feature = "graphql")]
+    Route {
+        method: "POST",
+        path: graphql::PATH,
+        summary: "Answers GraphQL queries and subscriptions over the tenant's features.",
+        authenticated: true,
+        parameters: &[],
+        request: Some(Content::Other { content_type: "application/json", description: "A GraphQL request." }),
+        response: Content::Other { content_type: "application/json", description: "A GraphQL response." },
+    },
+];
+
+/// Request ids, logging, metrics and the body limit, around every request.
+fn stack() -> http::Stack {
+    http::Stack::standard("gateway", MAX_BODY_SIZE)
+}
+
+async fn gateway_service(tenants: Arc<Tenants>, policy: Arc<PolicyEngine>, cors: Arc<Cors>, limits: RecorderLimits, mut request: Request<Body>) -> Response<Body> {
+    if let Some(response) = cors.preflight(&request) {
+        return response;
+    }
+    // For operators with curl rather than browsers, so neither CORS nor compression apply.
+    if request.uri().path() == "/admin/log-filter" {
+        return admin_ui::log_filter(request).await;
+    }
+
+    // Each route is authorized as the RouteGuide method it stands for.
+    let (method, path) = (request.method().clone(), request.uri().path().to_string());
+    let response = match (&method, path.as_str()) {
+        (&Method::GET, openapi::PATH) => json_response(StatusCode::OK, openapi::gateway_document().clone()),
+        (&Method::GET, "/features") => match authorized(&tenants, &policy, &request, "ListFeatures", Resource::everywhere()) {
+            Ok(tenant) => list_features(&tenant, request.uri().query()),
+            Err(response) => response,
+        },
+        (&Method::GET, "/features.geojson") => match authorized(&tenants, &policy, &request, "ListFeatures", Resource::everywhere()) {
+            Ok(tenant) => features_geojson(&tenant),
+            Err(response) => response,
+        },
+        (&Method::GET, "/features.ndjson") => match authorized(&tenants, &policy, &request, "ListFeatures", Resource::everywhere()) {
+            Ok(tenant) => features_ndjson(&tenant),
+            Err(response) => response,
+        },
+        (&Method::GET, "/export.zip") => match authorized(&tenants, &policy, &request, "ExportFeatures", Resource::default()) {
+            Ok(tenant) => export_zip(&tenant),
+            Err(response) => response,
+        },
+        (&Method::GET, path) if path.starts_with("/features/") => match authorized(&tenants, &policy, &request, "GetFeature", Resource::default()) {
+            Ok(tenant) => get_feature(&tenant, &path["/features/".len()..]),
+            Err(response) => response,
+        },
+        (&Method::GET, "/events/features") => match authorized(&tenants, &policy, &request, "ListChanges", Resource::default()) {
+            Ok(tenant) => feature_events(&tenant, &request),
+            Err(response) => response,
+        },
+        (&Method::POST, "/routes/stream") => match authorized(&tenants, &policy, &request, "RecordRoute", Resource::default()) {
+            Ok(tenant) => {
+                let query = request.uri().query().map(str::to_string);
+                record_route(&tenant, limits, query.as_deref(), std::mem::take(request.body_mut())).await
+            },
+            Err(response) => response,
+        },
+        #[cfg(feature = "graphql")]
+        (&Method::GET, graphql::PATH) => graphql::playground(),
+        // Each query is authorized as it's resolved.
+        #[cfg(feature = "graphql")]
+        (&Method::POST, graphql::PATH) => match authenticate(&tenants, &request) {
+            Some(tenant) => {
+                let body = std::mem::take(request.body_mut());
+                graphql::handle(tenant, policy, request.headers(), body).await
+            },
+            None => error_response(StatusCode::UNAUTHORIZED, "No valid auth token"),
+        },
+        _ => admin_ui::handle(&request).unwrap_or_else(|| error_response(StatusCode::NOT_FOUND, "not found")),
+    };
+
+    let response = cors.apply(request.headers(), response);
+    Compression::with_min_size(1024).apply(request.headers(), response)
+}
+
+/// The REST API as a request handler, for serving it on a port shared with gRPC (see
+/// `multiplex`).
+pub fn handler(tenants: Arc<Tenants>, policy: Arc<PolicyEngine>, cors: Cors, limits: RecorderLimits) -> impl Fn(Request<Body>) -> BoxFuture<'static, Response<Body>> + Clone + Send + Sync + 'static {
+    let (cors, stack) = (Arc::new(cors), stack());
+    move |request| {
+        let (tenants, policy, cors, stack) = (tenants.clone(), policy.clone(), cors.clone(), stack.clone());
+        Box::pin(async move {
+            stack.serve(request, |request| gateway_service(tenants, policy, cors, limits, request)).await
+        })
+    }
+}
+
+/// Serves the REST API on the address until the process exits.
+///
+/// `GET /features?page_size=&page_token=` lists the tenant's features in load order. Pass the
+/// returned `nextPageToken` to get the next page; it's empty on the last page. `GET /features/{id}`
+/// has a single feature by its id.
+/// `GET /features.geojson` has all of them at once, for maps, and `GET /events/features` streams
+/// changes to them as server-sent events. `GET /export.zip` has the features and the recorded
+/// routes, written as it's downloaded. `POST /routes/stream` records a route from
+/// newline-delimited JSON points, like RecordRoute, and answers with its summary. With the
+/// `graphql` feature, `POST /graphql` answers GraphQL queries and subscriptions over the same
+/// features (see `graphql`). `GET /openapi.json` describes all of these for client generators
+/// (see `ROUTES`). Browsers on other origins may call these as far as `cors` allows. The admin page is at `/ui` and the log filter at
+/// `/admin/log-filter` (see `admin_ui`).
+///
+/// Requests are authorized by `policy` as the RouteGuide calls they stand for, and refused
+/// with 403 when it denies them (see `policy::Policy`). Routes are checked against `limits`, as
+/// in RecordRoute. Bodies over `MAX_BODY_SIZE` are refused with 413, and every request is logged
+/// and counted (see `http::Stack::standard`).
+///
+/// Connections `ip_filter` refuses are closed as soon as they're accepted; requests from clients
+/// it refuses behind a trusted proxy get 403.
+pub async fn serve(address: SocketAddr, tenants: Arc<Tenants>, policy: Arc<PolicyEngine>, cors: Cors, ip_filter: Arc<IpFilter>, limits: RecorderLimits) -> Result<(), hyper::Error> {
+    let (cors, stack) = (Arc::new(cors), stack());
+    let make_service = make_service_fn(move |connection: &AddrStream| {
+        let peer = connection.remote_addr();
+        let (tenants, policy, cors, ip_filter, stack) = (tenants.clone(), policy.clone(), cors.clone(), ip_filter.clone(), stack.clone());
+        async move {
+            // hyper closes the connection when making its service fails.
+            if !ip_filter.accepts(peer) {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, "address not allowed"));
+            }
+            Ok(service_fn(move |request: Request<Body>| {
+                let (tenants, policy, cors, stack) = (tenants.clone(), policy.clone(), cors.clone(), stack.clone());
+                let allowed = ip_filter.allows_request(Some(peer), request.headers());
+                async move {
+                    let response = stack.serve(request, |request| async move {
+                        if !allowed {
+                            return error_response(StatusCode::FORBIDDEN, "address not allowed");
+                        }
+                        gateway_service(tenants, policy, cors, limits, request).await
+                    }).await;
+                    Ok::<_, Infallible>(response)
+                }
+            }))
+        }
+    });
+
+    Server::bind(&address).serve(make_service).await
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::policy::Policy;
+    use crate::tenant::TenantId;
+
+    async fn get(path: &str, policy: &str) -> StatusCode {
+        let tenants = Arc::new(Tenants::default());
+        tenants.provision(TenantId::new("test").unwrap(), "secret", vec![]).unwrap();
+        let policy = Arc::new(PolicyEngine::with_policy(Policy::parse(policy).unwrap()));
+        let request = Request::get(path).header("authorization", "Bearer secret").body(Body::empty()).unwrap();
+        gateway_service(tenants, policy, Arc::new(Cors::default()), RecorderLimits::default(), request).await.status()
+    }
+
+    #[tokio::test]
+    async fn listings_are_checked_as_list_features() {
+        let policy = "allow ListFeatures max-area-km2=10000\ndeny ListFeatures subject=token:*";
+        for path in &["/features", "/features.geojson", "/features.ndjson"] {
+            assert_eq!(get(path, policy).await, StatusCode::FORBIDDEN, "{}", path);
+            assert_eq!(get(path, "").await, StatusCode::OK, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn other_routes_are_checked_as_their_methods() {
+        assert_eq!(get("/export.zip", "deny ExportFeatures").await, StatusCode::FORBIDDEN);
+        assert_eq!(get("/features/nope", "deny GetFeature subject=token:test").await, StatusCode::FORBIDDEN);
+        assert_eq!(get("/features/nope", "deny GetFeature tenant=other").await, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,368 @@
+use std::cmp;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+
+use serde_json::{json, Value};
+
+use crate::route_guide::{Feature, Point, Rectangle};
+
+
+/// Factor between degrees and the E7 representation used by `Point`.
+pub const CORD_FACTOR: f64 = 1e7;
+
+/// Mean radius of the earth in meters.
+pub const EARTH_RADIUS: f64 = 6_371_000.0;
+
+
+impl Hash for Point {
+    fn hash<H>(&self, state: &mut H) where H: Hasher {
+        self.latitude.hash(state);
+        self.longitude.hash(state);
+    }
+}
+
+impl Eq for Point {}
+
+
+/// The largest longitude, in E7 degrees. -180° and 180° are the same meridian, the antimeridian.
+pub const MAX_LONGITUDE: i32 = 1_800_000_000;
+
+
+/// The edges of a rectangle, in E7 degrees. A rectangle whose west edge is east of its east edge
+/// crosses the antimeridian.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Bounds {
+    pub south: i32,
+    pub north: i32,
+    pub west: i32,
+    pub east: i32,
+}
+
+impl Bounds {
+    /// `lo` is the western corner and `hi` the eastern one; their latitudes can come in any order.
+    /// So `lo` east of `hi` describes a rectangle across the antimeridian, like from 170° to
+    /// -170°. A rectangle with missing corners has no bounds.
+    pub fn of(rect: &Rectangle) -> Option<Bounds> {
+        let (lo, hi) = match (rect.lo.as_ref(), rect.hi.as_ref()) {
+            (Some(lo), Some(hi)) => (lo, hi),
+            _ => return None,
+        };
+
+        Some(Bounds {
+            south: cmp::min(lo.latitude, hi.latitude),
+            north: cmp::max(lo.latitude, hi.latitude),
+            west: lo.longitude,
+            east: hi.longitude,
+        })
+    }
+
+    pub fn crosses_antimeridian(&self) -> bool {
+        self.west > self.east
+    }
+
+    /// The rectangle as parts that don't cross the antimeridian: itself, or the parts west and
+    /// east of it.
+    pub fn split(&self) -> Vec<Bounds> {
+        if !self.crosses_antimeridian() {
+            return vec![*self];
+        }
+        vec![
+            Bounds { east: MAX_LONGITUDE, ..*self },
+            Bounds { west: -MAX_LONGITUDE, ..*self },
+        ]
+    }
+
+    /// Whether the point lies inside (inclusive).
+    pub fn contains(&self, point: &Point) -> bool {
+        let longitude = if self.crosses_antimeridian() {
+            point.longitude >= self.west || point.longitude <= self.east
+        } else {
+            point.longitude >= self.west && point.longitude <= self.east
+        };
+        longitude && point.latitude >= self.south && point.latitude <= self.north
+    }
+
+    /// The area in square meters, on a spherical earth.
+    pub fn area(&self) -> f64 {
+        let width = if self.crosses_antimeridian() {
+            2 * MAX_LONGITUDE as i64 - (self.west as i64 - self.east as i64)
+        } else {
+            self.east as i64 - self.west as i64
+        };
+        let width = (width as f64 / CORD_FACTOR).to_radians();
+        let south = (self.south as f64 / CORD_FACTOR).to_radians();
+        let north = (self.north as f64 / CORD_FACTOR).to_radians();
+
+        EARTH_RADIUS * EARTH_RADIUS * width * (north.sin() - south.sin())
+    }
+
+    /// The south-west and north-east corners, the normalized `lo` and `hi` of a Rectangle.
+    pub fn corners(&self) -> (Point, Point) {
+        (
+            Point { latitude: self.south, longitude: self.west, read_mask: None },
+            Point { latitude: self.north, longitude: self.east, read_mask: None },
+        )
+    }
+}
+
+
+/// Whether the point lies inside the rectangle (inclusive), as described by `Bounds::of`. A
+/// rectangle with missing corners contains nothing.
+pub fn in_range(point: &Point, rect: &Rectangle) -> bool {
+    Bounds::of(rect).is_some_and(|bounds| bounds.contains(point))
+}
+
+/// Calculates the distance in meters between two points using the "haversine" formula.
+/// This code was taken from http://www.movable-type.co.uk/scripts/latlong.html.
+pub fn distance(p1: &Point, p2: &Point) -> f64 {
+    let lat1 = p1.latitude as f64 / CORD_FACTOR;
+    let lat2 = p2.latitude as f64 / CORD_FACTOR;
+    let lng1 = p1.longitude as f64 / CORD_FACTOR;
+    let lng2 = p2.longitude as f64 / CORD_FACTOR;
+
+    let lat_rad1 = lat1.to_radians();
+    let lat_rad2 = lat2.to_radians();
+
+    let delta_lat = (lat2 - lat1).to_radians();
+    let delta_lng = (lng2 - lng1).to_radians();
+
+    let a = (delta_lat / 2f64).sin() * (delta_lat / 2f64).sin()
+        + (lat_rad1).cos() * (lat_rad2).cos() * (delta_lng / 2f64).sin() * (delta_lng / 2f64).sin();
+
+    let c = 2f64 * a.sqrt().atan2((1f64 - a).sqrt());
+
+    EARTH_RADIUS * c
+}
+
+
+/// Request metadata asking RecordRoute for the path in its summary: `true` fills in
+/// `RouteSummary.polyline`.
+pub const POLYLINE_HEADER: &str = "x-route-polyline";
+
+/// Polylines carry coordinates to 5 decimal places, 100 times coarser than E7.
+const POLYLINE_FACTOR: i64 = 100;
+
+/// A path written as an encoded polyline, as on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polyline(pub Vec<Point>);
+
+impl std::str::FromStr for Polyline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        decode_polyline(s).map(Polyline)
+    }
+}
+
+impl std::fmt::Display for Polyline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&encode_polyline(&self.0))
+    }
+}
+
+/// The points as an encoded polyline (Google's format), for map tools. Coordinates are rounded
+/// to 5 decimal places, about a metre.
+pub fn encode_polyline(points: &[Point]) -> String {
+    let mut encoder = PolylineEncoder::default();
+    let mut encoded = String::new();
+    for point in points {
+        encoder.push(point, &mut encoded);
+    }
+    encoded
+}
+
+/// Encodes a polyline a point at a time, for paths too long to keep as points.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PolylineEncoder {
+    last_latitude: i64,
+    last_longitude: i64,
+}
+
+impl PolylineEncoder {
+    /// Appends `point` to `encoded`, which holds what came before it or has been taken away.
+    pub fn push(&mut self, point: &Point, encoded: &mut String) {
+        let latitude = (point.latitude as i64 + POLYLINE_FACTOR / 2).div_euclid(POLYLINE_FACTOR);
+        let longitude = (point.longitude as i64 + POLYLINE_FACTOR / 2).div_euclid(POLYLINE_FACTOR);
+        encode_polyline_value(latitude - self.last_latitude, encoded);
+        encode_polyline_value(longitude - self.last_longitude, encoded);
+        self.last_latitude = latitude;
+        self.last_longitude = longitude;
+    }
+}
+
+/// Each value is a difference from the one before, zig-zag encoded and written 5 bits at a time
+/// from the lowest, offset by 63 into printable ASCII.
+fn encode_polyline_value(value: i64, encoded: &mut String) {
+    let mut bits = (if value < 0 { !(value << 1) } else { value << 1 }) as u64;
+    while bits >= 0x20 {
+        encoded.push((((bits & 0x1f) | 0x20) as u8 + 63) as char);
+        bits >>= 5;
+    }
+    encoded.push((bits as u8 + 63) as char);
+}
+
+/// The points of an encoded polyline, in E7 degrees.
+pub fn decode_polyline(encoded: &str) -> Result<Vec<Point>, String> {
+    let mut bytes = encoded.bytes().enumerate().peekable();
+    let mut points = vec![];
+    let (mut latitude, mut longitude) = (0i64, 0i64);
+
+    while bytes.peek().is_some() {
+        latitude += decode_polyline_value(&mut bytes)?;
+        longitude += decode_polyline_value(&mut bytes)?;
+        let point = Point {
+            latitude: i32::try_from(latitude * POLYLINE_FACTOR).map_err(|_| format!("latitude {} is out of range", latitude))?,
+            longitude: i32::try_from(longitude * POLYLINE_FACTOR).map_err(|_| format!("longitude {} is out of range", longitude))?,
+            read_mask: None,
+        };
+        if point.latitude.abs() > 900_000_000 || point.longitude.abs() > MAX_LONGITUDE {
+            return Err(format!("point {} is off the map", points.len()));
+        }
+        points.push(point);
+    }
+    Ok(points)
+}
+
+fn decode_polyline_value(bytes: &mut impl Iterator<Item = (usize, u8)>) -> Result<i64, String> {
+    let mut bits = 0u64;
+    let mut shift = 0;
+    loop {
+        let (position, byte) = bytes.next().ok_or_else(|| "the polyline ends inside a value".to_string())?;
+        let chunk = match byte.checked_sub(63) {
+            Some(chunk) if chunk < 0x40 => chunk as u64,
+            _ => return Err(format!("'{}' at {} isn't a polyline character", byte as char, position)),
+        };
+        if shift > 60 {
+            return Err(format!("the value ending at {} is too long", position));
+        }
+        bits |= (chunk & 0x1f) << shift;
+        shift += 5;
+        if chunk < 0x20 {
+            break;
+        }
+    }
+    Ok(if bits & 1 == 1 { !(bits >> 1) as i64 } else { (bits >> 1) as i64 })
+}
+
+
+/// The features as a GeoJSON FeatureCollection, with coordinates in degrees. Features without a
+/// location are left out.
+pub fn geojson<'a>(features: impl IntoIterator<Item = &'a Feature>) -> Value {
+    let features: Vec<_> = features.into_iter().filter_map(geojson_feature).collect();
+    json!({ "type": "FeatureCollection", "features": features })
+}
+
+/// One feature of `geojson`, or `None` without a location.
+pub fn geojson_feature(feature: &Feature) -> Option<Value> {
+    let location = feature.location.as_ref()?;
+    Some(json!({
+        "type": "Feature",
+        "geometry": {
+            "type": "Point",
+            "coordinates": [location.longitude as f64 / CORD_FACTOR, location.latitude as f64 / CORD_FACTOR],
+        },
+        "id": feature.id,
+        "properties": { "name": feature.name, "description": feature.description, "tags": feature.tags },
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(latitude: f64, longitude: f64) -> Point {
+        Point::from_degrees(latitude, longitude)
+    }
+
+    fn bounds(lo: Point, hi: Point) -> Bounds {
+        Bounds::of(&Rectangle::new(lo, hi)).unwrap()
+    }
+
+    #[test]
+    fn split_leaves_plain_rectangles_whole() {
+        let plain = bounds(point(10.0, 20.0), point(30.0, 40.0));
+        assert!(!plain.crosses_antimeridian());
+        assert_eq!(plain.split(), vec![plain]);
+    }
+
+    #[test]
+    fn split_across_the_antimeridian() {
+        let across = bounds(point(-10.0, 170.0), point(10.0, -170.0));
+        assert!(across.crosses_antimeridian());
+
+        let parts = across.split();
+        assert_eq!(parts, vec![
+            Bounds { south: -100_000_000, north: 100_000_000, west: 1_700_000_000, east: MAX_LONGITUDE },
+            Bounds { south: -100_000_000, north: 100_000_000, west: -MAX_LONGITUDE, east: -1_700_000_000 },
+        ]);
+        assert!(parts.iter().all(|part| !part.crosses_antimeridian()));
+
+        let area: f64 = parts.iter().map(Bounds::area).sum();
+        assert!((area - across.area()).abs() < 1.0);
+        assert!((across.area() - bounds(point(-10.0, -10.0), point(10.0, 10.0)).area()).abs() < 1.0);
+    }
+
+    #[test]
+    fn swapped_latitudes_are_normalized() {
+        let swapped = bounds(point(30.0, 20.0), point(10.0, 40.0));
+        assert_eq!(swapped, bounds(point(10.0, 20.0), point(30.0, 40.0)));
+        assert_eq!(swapped.corners(), (point(10.0, 20.0), point(30.0, 40.0)));
+    }
+
+    #[test]
+    fn swapped_longitudes_cross_the_antimeridian() {
+        // The western corner east of the eastern one goes the long way round.
+        let long_way = bounds(point(0.0, 40.0), point(10.0, 20.0));
+        assert!(long_way.crosses_antimeridian());
+        assert!(long_way.contains(&point(5.0, 180.0)));
+        assert!(long_way.contains(&point(5.0, 0.0)));
+        assert!(!long_way.contains(&point(5.0, 30.0)));
+    }
+
+    #[test]
+    fn missing_corners_have_no_bounds() {
+        let rect = Rectangle { lo: Some(point(0.0, 0.0)), ..Rectangle::default() };
+        assert_eq!(Bounds::of(&rect), None);
+        assert!(!in_range(&point(0.0, 0.0), &rect));
+    }
+
+    #[test]
+    fn degenerate_rectangles() {
+        // A single point.
+        let dot = bounds(point(1.0, 2.0), point(1.0, 2.0));
+        assert_eq!(dot.split(), vec![dot]);
+        assert_eq!(dot.area(), 0.0);
+        assert!(dot.contains(&point(1.0, 2.0)));
+        assert!(!dot.contains(&point(1.0, 2.0000001)));
+
+        // A line along a parallel, and one along a meridian.
+        let parallel = bounds(point(1.0, 2.0), point(1.0, 3.0));
+        let meridian = bounds(point(1.0, 2.0), point(5.0, 2.0));
+        assert_eq!(parallel.area(), 0.0);
+        assert_eq!(meridian.area(), 0.0);
+        assert!(parallel.contains(&point(1.0, 2.5)));
+        assert!(meridian.contains(&point(3.0, 2.0)));
+        assert!(!meridian.contains(&point(3.0, 2.5)));
+    }
+
+    #[test]
+    fn edges_are_inside() {
+        let plain = bounds(point(10.0, 20.0), point(30.0, 40.0));
+        for edge in &[point(10.0, 30.0), point(30.0, 30.0), point(20.0, 20.0), point(20.0, 40.0), point(10.0, 20.0), point(30.0, 40.0)] {
+            assert!(plain.contains(edge), "{:?}", edge);
+        }
+        assert!(!plain.contains(&point(9.9999999, 30.0)));
+        assert!(!plain.contains(&point(20.0, 40.0000001)));
+
+        // Both sides of the antimeridian, which is the edge of each part.
+        let across = bounds(point(-10.0, 170.0), point(10.0, -170.0));
+        for edge in &[point(0.0, 170.0), point(0.0, -170.0), point(0.0, 180.0), point(0.0, -180.0), point(-10.0, 175.0), point(10.0, -175.0)] {
+            assert!(across.contains(edge), "{:?}", edge);
+            // In one part only, so a split query doesn't find it twice.
+            assert_eq!(across.split().iter().filter(|part| part.contains(edge)).count(), 1, "{:?}", edge);
+        }
+        assert!(!across.contains(&point(0.0, 169.9999999)));
+        assert!(!across.contains(&point(0.0, 0.0)));
+    }
+}
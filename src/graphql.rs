@@ -0,0 +1,356 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
+use async_graphql::{
+    Context, EmptyMutation, Enum, InputObject, Object, Result, Schema, SimpleObject, Subscription,
+};
+use futures::{Stream, StreamExt};
+use hyper::body::HttpBody;
+use hyper::{Body, HeaderMap, Response, StatusCode};
+use once_cell::sync::Lazy;
+use tokio::sync::broadcast;
+
+use crate::feature_events::{self, ChangeKind};
+use crate::gateway;
+use crate::i18n::{Languages, ACCEPT_LANGUAGE};
+use crate::policy::{PolicyEngine, Resource};
+use crate::route_guide::{Feature, Point, Rectangle};
+use crate::tenant::TenantData;
+use crate::validation;
+
+
+/// The path of the endpoint. `GET` has a playground for trying queries.
+pub const PATH: &str = "/graphql";
+
+/// `searchFeatures` returns at most this many unless asked for fewer.
+pub const MAX_SEARCH_RESULTS: usize = 100;
+
+/// Requests larger than this are refused before parsing.
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+
+pub type RouteGuideSchema = Schema<QueryRoot, EmptyMutation, SubscriptionRoot>;
+
+/// The schema. It holds no state: resolvers find the caller's tenant in the request data.
+pub fn schema() -> &'static RouteGuideSchema {
+    static SCHEMA: Lazy<RouteGuideSchema> = Lazy::new(|| {
+        Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot).limit_depth(8).finish()
+    });
+    &SCHEMA
+}
+
+
+/// A feature, with coordinates in E7 degrees like everywhere else in the API.
+pub struct GqlFeature(Feature);
+
+#[Object(name = "Feature")]
+impl GqlFeature {
+    /// Stable across edits and restarts, unlike the location.
+    async fn id(&self) -> String {
+        self.0.id.clone()
+    }
+
+    /// In the language of the request's `accept-language` header, if the feature has it.
+    async fn name(&self, ctx: &Context<'_>) -> String {
+        match ctx.data_opt::<Languages>() {
+            Some(languages) => languages.select(&self.0).to_string(),
+            None => self.0.name.clone(),
+        }
+    }
+
+    async fn latitude(&self) -> i32 {
+        self.0.location.as_ref().map_or(0, |location| location.latitude)
+    }
+
+    async fn longitude(&self) -> i32 {
+        self.0.location.as_ref().map_or(0, |location| location.longitude)
+    }
+
+    async fn description(&self) -> String {
+        self.0.description.clone()
+    }
+
+    async fn tags(&self) -> Vec<String> {
+        self.0.tags.clone()
+    }
+}
+
+#[derive(InputObject)]
+pub struct PointInput {
+    pub latitude: i32,
+    pub longitude: i32,
+}
+
+impl From<PointInput> for Point {
+    fn from(point: PointInput) -> Self {
+        Point { latitude: point.latitude, longitude: point.longitude, read_mask: None }
+    }
+}
+
+fn tenant(ctx: &Context<'_>) -> Result<Arc<TenantData>> {
+    Ok(ctx.data::<Arc<TenantData>>()?.clone())
+}
+
+/// The tenant, if the policy lets it make the RouteGuide call a field stands for.
+fn authorized(ctx: &Context<'_>, method: &str, resource: &Resource) -> Result<Arc<TenantData>> {
+    let tenant = tenant(ctx)?;
+    gateway::authorize(ctx.data::<Arc<PolicyEngine>>()?, &tenant, method, resource).map_err(|status| status.message().to_string())?;
+    Ok(tenant)
+}
+
+/// Validation failures as GraphQL errors, with the same messages as over gRPC.
+fn validated<T: validation::Validate>(mut message: T) -> Result<T> {
+    validation::validate(&mut message).map_err(|status| status.message().to_string())?;
+    Ok(message)
+}
+
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// The feature at exactly this point, like GetFeature, or null.
+    async fn feature_at(&self, ctx: &Context<'_>, latitude: i32, longitude: i32) -> Result<Option<GqlFeature>> {
+        let point = validated(Point { latitude, longitude, read_mask: None })?;
+        Ok(authorized(ctx, "GetFeature", &Resource::default())?.features().get(&point).cloned().map(GqlFeature))
+    }
+
+    /// The feature with this id, or null.
+    async fn feature(&self, ctx: &Context<'_>, id: String) -> Result<Option<GqlFeature>> {
+        Ok(authorized(ctx, "GetFeature", &Resource::default())?.features().get_by_id(&id).cloned().map(GqlFeature))
+    }
+
+    /// The features inside the rectangle, like ListFeatures: `lo` is the western corner and `hi`
+    /// the eastern one, so `lo` east of `hi` crosses the antimeridian.
+    async fn features_in(&self, ctx: &Context<'_>, lo: PointInput, hi: PointInput) -> Result<Vec<GqlFeature>> {
+        let rect = validated(Rectangle { lo: Some(lo.into()), hi: Some(hi.into()), ..Rectangle::default() })?;
+        Ok(authorized(ctx, "ListFeatures", &Resource::rectangle(&rect))?.features().in_rectangle(&rect).cloned().map(GqlFeature).collect())
+    }
+
+    /// Features whose name or description contains `text`, ignoring case, and that have `tag` if
+    /// given. In load order, at most `limit` of them (and never more than 100).
+    async fn search_features(
+        &self,
+        ctx: &Context<'_>,
+        text: String,
+        tag: Option<String>,
+        #[graphql(default = 20)] limit: i32,
+    ) -> Result<Vec<GqlFeature>> {
+        let text = text.to_lowercase();
+        let matches = |feature: &&Feature| {
+            let tagged = tag.as_ref().is_none_or(|tag| feature.tags.iter().any(|t| t == tag));
+            tagged && (feature.name.to_lowercase().contains(&text) || feature.description.to_lowercase().contains(&text))
+        };
+
+        Ok(authorized(ctx, "ListFeatures", &Resource::everywhere())?
+            .features()
+            .iter()
+            .filter(matches)
+            .take((limit.max(0) as usize).min(MAX_SEARCH_RESULTS))
+            .cloned()
+            .map(GqlFeature)
+            .collect())
+    }
+}
+
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum FeatureChange {
+    Added,
+    Removed,
+}
+
+#[derive(SimpleObject)]
+pub struct FeatureUpdate {
+    /// Increases by one with every change; pass the last one seen as `after` to resume.
+    id: u64,
+    change: FeatureChange,
+    feature: GqlFeature,
+}
+
+fn update(event: feature_events::FeatureEvent) -> FeatureUpdate {
+    let change = match event.kind {
+        ChangeKind::Added => FeatureChange::Added,
+        ChangeKind::Removed => FeatureChange::Removed,
+    };
+    FeatureUpdate { id: event.id, change, feature: GqlFeature(event.feature) }
+}
+
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Features added and deleted from now on, or from after `after`. Fails if changes after
+    /// `after` are no longer known, and ends if the subscriber falls behind; either way it
+    /// should reload and subscribe again.
+    async fn feature_updates(&self, ctx: &Context<'_>, after: Option<u64>) -> Result<impl Stream<Item = FeatureUpdate>> {
+        let feature_events::Subscription { missed, gap, mut live } = authorized(ctx, "ListChanges", &Resource::default())?.feature_events().subscribe(after);
+        if gap {
+            return Err("changes after `after` are no longer known; reload and subscribe without it".into());
+        }
+
+        Ok(async_stream::stream! {
+            for event in missed {
+                yield update(event);
+            }
+            loop {
+                match live.recv().await {
+                    Ok(event) => yield update(event),
+                    Err(broadcast::RecvError::Lagged(_)) | Err(broadcast::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+}
+
+
+fn response(status: StatusCode, content_type: &'static str, body: Body) -> Response<Body> {
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    response.headers_mut().insert("content-type", content_type.parse().unwrap());
+    response
+}
+
+fn json_error(status: StatusCode, message: &str) -> Response<Body> {
+    let body = serde_json::json!({ "errors": [{ "message": message }] });
+    response(status, "application/json", Body::from(body.to_string()))
+}
+
+/// The playground page, for `GET /graphql`.
+pub fn playground() -> Response<Body> {
+    response(StatusCode::OK, "text/html; charset=utf-8", Body::from(playground_source(GraphQLPlaygroundConfig::new(PATH))))
+}
+
+/// Runs a `POST /graphql` request, `{"query": ..., "variables": ...}`, for the tenant.
+/// Subscriptions need `accept: text/event-stream`: each result is sent as a server-sent event,
+/// and so are those of queries asked for that way.
+///
+/// Each field is authorized by `policy` as the RouteGuide call it stands for: `featureAt` and
+/// `feature` as GetFeature, `featuresIn` as ListFeatures of its rectangle, `searchFeatures` as
+/// ListFeatures of the whole world and `featureUpdates` as ListChanges.
+pub async fn handle(tenant: Arc<TenantData>, policy: Arc<PolicyEngine>, headers: &HeaderMap, mut body: Body) -> Response<Body> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        match chunk {
+            Ok(chunk) if bytes.len() + chunk.len() <= MAX_REQUEST_SIZE => bytes.extend_from_slice(&chunk),
+            Ok(_) => return json_error(StatusCode::PAYLOAD_TOO_LARGE, "request too large"),
+            Err(e) => return json_error(StatusCode::BAD_REQUEST, &e.to_string()),
+        }
+    }
+    let request: async_graphql::Request = match serde_json::from_slice(&bytes) {
+        Ok(request) => request,
+        Err(e) => return json_error(StatusCode::BAD_REQUEST, &format!("invalid GraphQL request: {}", e)),
+    };
+
+    let languages = headers
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .map(Languages::parse)
+        .unwrap_or_default();
+    let request = request.data(tenant).data(policy).data(languages);
+
+    let streaming = headers
+        .get("accept")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/event-stream"));
+    if !streaming {
+        let result = schema().execute(request).await;
+        return response(StatusCode::OK, "application/json", Body::from(serde_json::to_string(&result).unwrap_or_default()));
+    }
+
+    let events = schema().execute_stream(request).map(|result| {
+        Ok::<_, Infallible>(format!("event: next\ndata: {}\n\n", serde_json::to_string(&result).unwrap_or_default()))
+    });
+    let events = events.chain(futures::stream::once(async { Ok("event: complete\ndata: {}\n\n".to_string()) }));
+    let mut response = response(StatusCode::OK, "text/event-stream", Body::wrap_stream(events));
+    response.headers_mut().insert("cache-control", "no-cache".parse().unwrap());
+    response
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::policy::Policy;
+    use crate::tenant::{TenantId, Tenants};
+
+    fn tenant() -> Arc<TenantData> {
+        let feature = |name: &str, latitude, longitude, tags: &[&str]| Feature {
+            name: name.to_string(),
+            location: Some(Point { latitude, longitude, read_mask: None }),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            ..Feature::default()
+        };
+        let features = vec![
+            feature("Patriots Path", 407838351, -746143763, &["trail"]),
+            feature("Berkshire Valley Management Area Trail", 409146138, -746188906, &["trail", "park"]),
+            feature("101 New Jersey 10", 413628156, -749015468, &[]),
+        ];
+        Tenants::default().provision(TenantId::new("test").unwrap(), "token", features).unwrap()
+    }
+
+    fn request(query: &str, policy: &str) -> async_graphql::Request {
+        let policy = Arc::new(PolicyEngine::with_policy(Policy::parse(policy).unwrap()));
+        async_graphql::Request::new(query).data(tenant()).data(policy)
+    }
+
+    async fn query(query: &str) -> serde_json::Value {
+        let response = schema().execute(request(query, "")).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        serde_json::to_value(&response.data).unwrap()
+    }
+
+    #[tokio::test]
+    async fn feature_at() {
+        let data = query("{ featureAt(latitude: 407838351, longitude: -746143763) { name tags } }").await;
+        assert_eq!(data["featureAt"], serde_json::json!({ "name": "Patriots Path", "tags": ["trail"] }));
+        let data = query("{ featureAt(latitude: 1, longitude: 1) { name } }").await;
+        assert!(data["featureAt"].is_null());
+    }
+
+    #[tokio::test]
+    async fn features_in() {
+        let data = query(
+            "{ featuresIn(lo: {latitude: 400000000, longitude: -750000000}, hi: {latitude: 410000000, longitude: -740000000}) { name } }",
+        ).await;
+        let names: Vec<&str> = data["featuresIn"].as_array().unwrap().iter().map(|feature| feature["name"].as_str().unwrap()).collect();
+        assert_eq!(names, vec!["Patriots Path", "Berkshire Valley Management Area Trail"]);
+    }
+
+    #[tokio::test]
+    async fn search_features() {
+        let data = query(r#"{ searchFeatures(text: "TRAIL", tag: "park") { name } }"#).await;
+        assert_eq!(data["searchFeatures"], serde_json::json!([{ "name": "Berkshire Valley Management Area Trail" }]));
+        let data = query(r#"{ searchFeatures(text: "", limit: -1) { name } }"#).await;
+        assert_eq!(data["searchFeatures"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn invalid_points_are_errors() {
+        let response = schema().execute(request("{ featureAt(latitude: 1000000000, longitude: 0) { name } }", "")).await;
+        assert_eq!(response.errors.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn the_policy_applies() {
+        let policy = "allow ListFeatures max-area-km2=100000\ndeny ListFeatures subject=token:*";
+        let small = "{ featuresIn(lo: {latitude: 400000000, longitude: -750000000}, hi: {latitude: 410000000, longitude: -740000000}) { name } }";
+        assert!(schema().execute(request(small, policy)).await.errors.is_empty());
+        let everything = r#"{ searchFeatures(text: "trail") { name } }"#;
+        let response = schema().execute(request(everything, policy)).await;
+        assert!(response.errors[0].message.contains("denied by rule 2"), "{:?}", response.errors);
+    }
+
+    #[tokio::test]
+    async fn over_http() {
+        let body = Body::from(r#"{"query": "{ searchFeatures(text: \"jersey\") { name } }"}"#);
+        let response = handle(tenant(), Arc::new(PolicyEngine::default()), &HeaderMap::new(), body).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let result: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(result["data"]["searchFeatures"], serde_json::json!([{ "name": "101 New Jersey 10" }]));
+    }
+}
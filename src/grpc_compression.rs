@@ -0,0 +1,324 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use futures::future::BoxFuture;
+use http_body::Body as HttpBody;
+use hyper::header::HeaderValue;
+use hyper::{Body, HeaderMap, Request, Response};
+use tonic::body::BoxBody;
+use tonic::transport::NamedService;
+use tonic::Status;
+use tower::Service;
+
+
+pub const ENCODING_HEADER: &str = "grpc-encoding";
+pub const ACCEPT_ENCODING_HEADER: &str = "grpc-accept-encoding";
+
+/// Client metadata that overrides `ClientCompression`'s default for one call, `gzip` or
+/// `identity`. It's taken off before the request is sent.
+pub const COMPRESSION_HEADER: &str = "x-grpc-compression";
+
+/// Smaller messages are sent as they are, since gzip's header and trailer alone take 18 bytes.
+pub const MIN_COMPRESSED_SIZE: usize = 256;
+
+/// The largest message a compressed one may inflate to, so a small message can't take all the
+/// memory.
+pub const MAX_DECOMPRESSED_SIZE: usize = 4 << 20;
+
+const FRAME_HEADER: usize = 5;
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Identity,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gzip"     => Ok(Compression::Gzip),
+            "identity" => Ok(Compression::Identity),
+            other      => Err(format!("unknown compression '{}' (expected gzip or identity)", other)),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::Gzip     => write!(f, "gzip"),
+            Compression::Identity => write!(f, "identity"),
+        }
+    }
+}
+
+/// Sets the compression of one call, whatever the client's default.
+pub fn with_compression<T>(mut request: tonic::Request<T>, compression: Compression) -> tonic::Request<T> {
+    let value = tonic::metadata::MetadataValue::from_static(match compression {
+        Compression::Gzip     => "gzip",
+        Compression::Identity => "identity",
+    });
+    request.metadata_mut().insert(COMPRESSION_HEADER, value);
+    request
+}
+
+/// Whether a `grpc-accept-encoding` list in `headers` has gzip.
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING_HEADER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| encoding.trim() == "gzip")
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Direction {
+    /// Compresses the messages worth it and sets their compressed flag.
+    Compress,
+    /// Inflates the messages with the compressed flag and clears it, for tonic, which can't.
+    Decompress,
+}
+
+fn to_status(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Status {
+    match error.into().downcast::<Status>() {
+        Ok(status) => *status,
+        Err(error) => Status::unknown(error.to_string()),
+    }
+}
+
+/// A gRPC body with each length-prefixed message compressed or decompressed. Messages are
+/// buffered until they're complete; trailers are passed on as they are.
+pub struct Reframed<B> {
+    inner: B,
+    direction: Direction,
+    buffer: BytesMut,
+    done: bool,
+}
+
+impl<B> Reframed<B> {
+    fn new(inner: B, direction: Direction) -> Self {
+        Reframed { inner, direction, buffer: BytesMut::new(), done: false }
+    }
+
+    /// The next complete message, transformed, if the buffer has one.
+    fn next_message(&mut self) -> Result<Option<Bytes>, Status> {
+        if self.buffer.len() < FRAME_HEADER {
+            return Ok(None);
+        }
+        let length = u32::from_be_bytes([self.buffer[1], self.buffer[2], self.buffer[3], self.buffer[4]]) as usize;
+        if self.buffer.len() < FRAME_HEADER + length {
+            return Ok(None);
+        }
+        let frame = self.buffer.split_to(FRAME_HEADER + length);
+        let (compressed, message) = (frame[0] == 1, &frame[FRAME_HEADER..]);
+
+        let (compressed, message) = match (self.direction, compressed) {
+            (Direction::Compress, false) if message.len() >= MIN_COMPRESSED_SIZE => {
+                let mut encoder = GzEncoder::new(Vec::with_capacity(message.len() / 2), flate2::Compression::fast());
+                encoder.write_all(message).map_err(|e| Status::internal(format!("failed to compress a message: {}", e)))?;
+                let deflated = encoder.finish().map_err(|e| Status::internal(format!("failed to compress a message: {}", e)))?;
+                // Data that doesn't compress is sent as it is.
+                if deflated.len() < message.len() { (true, deflated) } else { (false, message.to_vec()) }
+            },
+            (Direction::Decompress, true) => {
+                let mut inflated = Vec::with_capacity(message.len() * 2);
+                GzDecoder::new(message)
+                    .take(MAX_DECOMPRESSED_SIZE as u64 + 1)
+                    .read_to_end(&mut inflated)
+                    .map_err(|e| Status::internal(format!("failed to decompress a message: {}", e)))?;
+                if inflated.len() > MAX_DECOMPRESSED_SIZE {
+                    return Err(Status::resource_exhausted(format!("a message decompresses to more than {} bytes", MAX_DECOMPRESSED_SIZE)));
+                }
+                (false, inflated)
+            },
+            (_, compressed) => (compressed, message.to_vec()),
+        };
+
+        let mut reframed = BytesMut::with_capacity(FRAME_HEADER + message.len());
+        reframed.put_u8(compressed as u8);
+        reframed.put_u32(message.len() as u32);
+        reframed.put_slice(&message);
+        Ok(Some(reframed.freeze()))
+    }
+}
+
+impl<B> HttpBody for Reframed<B>
+    where
+        B: HttpBody + Unpin,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Status>>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(message) = this.next_message()? {
+                return Poll::Ready(Some(Ok(message)));
+            }
+            if this.done {
+                if this.buffer.is_empty() {
+                    return Poll::Ready(None);
+                }
+                this.buffer.clear();
+                return Poll::Ready(Some(Err(Status::internal("the body ended inside a message"))));
+            }
+            match Pin::new(&mut this.inner).poll_data(cx) {
+                Poll::Ready(Some(Ok(data))) => this.buffer.put(data),
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(to_status(e)))),
+                Poll::Ready(None) => this.done = true,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    fn poll_trailers(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Status>> {
+        Pin::new(&mut self.get_mut().inner).poll_trailers(cx).map_err(to_status)
+    }
+}
+
+
+/// Compresses a client's requests with gzip once the server has said it takes them, and
+/// decompresses the responses the server compressed. Every request says the client accepts gzip;
+/// requests go out uncompressed until a response's `grpc-accept-encoding` has gzip, so servers
+/// without compression aren't sent what they can't read.
+pub struct ClientCompression<S> {
+    inner: S,
+    default: Compression,
+    server_accepts_gzip: Arc<AtomicBool>,
+}
+
+impl<S> ClientCompression<S> {
+    /// `default` is the compression of calls that don't pick one with `with_compression`.
+    pub fn new(inner: S, default: Compression) -> Self {
+        ClientCompression { inner, default, server_accepts_gzip: Arc::new(AtomicBool::new(false)) }
+    }
+
+    /// Whether the server has said it takes gzip.
+    pub fn negotiated(&self) -> bool {
+        self.server_accepts_gzip.load(Ordering::Relaxed)
+    }
+}
+
+impl<S, RB> Service<Request<BoxBody>> for ClientCompression<S>
+    where
+        S: Service<Request<BoxBody>, Response = Response<RB>>,
+        S::Future: Send + 'static,
+        RB: HttpBody + Unpin,
+{
+    type Response = Response<Reframed<RB>>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<BoxBody>) -> Self::Future {
+        let compression = request
+            .headers_mut()
+            .remove(COMPRESSION_HEADER)
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+            .unwrap_or(self.default);
+        request.headers_mut().insert(ACCEPT_ENCODING_HEADER, HeaderValue::from_static("gzip"));
+
+        let request = if compression == Compression::Gzip && self.negotiated() {
+            request.headers_mut().insert(ENCODING_HEADER, HeaderValue::from_static("gzip"));
+            request.map(|body| BoxBody::new(Reframed::new(body, Direction::Compress)))
+        } else {
+            request
+        };
+
+        let server_accepts_gzip = self.server_accepts_gzip.clone();
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await?;
+            if accepts_gzip(response.headers()) {
+                server_accepts_gzip.store(true, Ordering::Relaxed);
+            }
+            Ok(response.map(|body| Reframed::new(body, Direction::Decompress)))
+        })
+    }
+}
+
+
+/// The trailers-only answer for a request in an encoding the server doesn't have.
+fn unsupported(encoding: &str) -> Response<BoxBody> {
+    Response::builder()
+        .status(200)
+        .header("content-type", "application/grpc")
+        .header(ACCEPT_ENCODING_HEADER, "gzip")
+        .header("grpc-status", "12")
+        .header("grpc-message", format!("unsupported grpc-encoding {}", encoding))
+        .body(BoxBody::empty())
+        .unwrap()
+}
+
+/// Decompresses gzip requests for a tonic service, which can't, and compresses its responses for
+/// clients that accept gzip. Every response says the server takes gzip.
+#[derive(Debug, Clone)]
+pub struct ServerCompression<S> {
+    pub inner: S,
+}
+
+impl<S> Service<Request<Body>> for ServerCompression<S>
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>>
+        + NamedService
+        + Clone
+        + Send
+        + 'static,
+        S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let encoding = request.headers().get(ENCODING_HEADER).map(|value| value.to_str().unwrap_or("").to_string());
+        let request = match encoding.as_deref() {
+            None | Some("identity") => request,
+            Some("gzip") => request.map(|body| {
+                let mut messages = Reframed::new(body, Direction::Decompress);
+                Body::wrap_stream(futures::stream::poll_fn(move |cx| Pin::new(&mut messages).poll_data(cx)))
+            }),
+            Some(other) => {
+                let response = unsupported(other);
+                return Box::pin(async { Ok(response) });
+            },
+        };
+        let compress = accepts_gzip(request.headers());
+
+        let mut svc = self.inner.clone();
+        Box::pin(async move {
+            let mut response = svc.call(request).await?;
+            response.headers_mut().insert(ACCEPT_ENCODING_HEADER, HeaderValue::from_static("gzip"));
+            if !compress {
+                return Ok(response);
+            }
+            response.headers_mut().insert(ENCODING_HEADER, HeaderValue::from_static("gzip"));
+            Ok(response.map(|body| BoxBody::new(Reframed::new(body, Direction::Compress))))
+        })
+    }
+}
+
+impl<S: NamedService> NamedService for ServerCompression<S> {
+    const NAME: &'static str = S::NAME;
+}
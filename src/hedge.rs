@@ -0,0 +1,136 @@
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::future::{self, Either};
+use tonic::body::BoxBody;
+use tonic::client::GrpcService;
+use tonic::codegen::{Body, HttpBody, StdError};
+use tonic::{Request, Response, Status};
+
+use crate::metrics;
+use crate::route_guide::route_guide_client::RouteGuideClient;
+use crate::route_guide::{Feature, Point};
+
+
+/// When a `Hedger` sends a second call, and how many it may send.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HedgePolicy {
+    /// How long the first call has to answer before the second one is sent.
+    pub delay: Duration,
+    /// The most second calls, as a share of all calls from 0 to 1, so hedging can't double the
+    /// load when every call is slow.
+    pub max_ratio: f64,
+    /// How many second calls can be sent in a row when calls have been fast for a while.
+    pub burst: u32,
+}
+
+impl Default for HedgePolicy {
+    fn default() -> Self {
+        HedgePolicy { delay: Duration::from_millis(50), max_ratio: 0.1, burst: 10 }
+    }
+}
+
+
+/// Sends a second copy of a call that's slow to answer and takes whichever answers first with a
+/// success. Each call earns `max_ratio` of a token, up to `burst`, and each second call spends
+/// one, like a retry budget.
+///
+/// Through a `Balancer` the second call usually goes to another endpoint, since the first one
+/// counts against the endpoint it's in flight on.
+#[derive(Debug)]
+pub struct Hedger {
+    policy: HedgePolicy,
+    tokens: Mutex<f64>,
+}
+
+impl Hedger {
+    pub fn new(policy: HedgePolicy) -> Self {
+        Hedger { policy, tokens: Mutex::new(policy.burst as f64) }
+    }
+
+    pub fn policy(&self) -> &HedgePolicy {
+        &self.policy
+    }
+
+    // Not `clamp`, which would keep a NaN ratio; this makes it 0.
+    #[allow(clippy::manual_clamp)]
+    fn deposit(&self) {
+        let mut tokens = self.tokens.lock().unwrap();
+        *tokens = (*tokens + self.policy.max_ratio.max(0.0).min(1.0)).min(self.policy.burst as f64);
+    }
+
+    fn withdraw(&self) -> bool {
+        let mut tokens = self.tokens.lock().unwrap();
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+
+    /// Runs `call`, and runs it again if the first one hasn't answered after the delay and the
+    /// budget allows. An error from one call is only returned if the other fails too.
+    pub async fn run<F, Fut, T>(&self, call: F) -> Result<T, Status>
+        where
+            F: Fn() -> Fut,
+            Fut: Future<Output = Result<T, Status>>,
+    {
+        self.deposit();
+        let original = call();
+        let delay = tokio::time::delay_for(self.policy.delay);
+        futures::pin_mut!(original, delay);
+
+        let original = match future::select(original, delay).await {
+            Either::Left((result, _)) => return result,
+            Either::Right(((), original)) => original,
+        };
+        if !self.withdraw() {
+            count("skipped");
+            return original.await;
+        }
+
+        let hedge = call();
+        futures::pin_mut!(hedge);
+        let (result, winner) = match future::select(original, hedge).await {
+            Either::Left((Ok(response), _)) => (Ok(response), "original"),
+            Either::Right((Ok(response), _)) => (Ok(response), "hedge"),
+            Either::Left((Err(error), hedge)) => match hedge.await {
+                Ok(response) => (Ok(response), "hedge"),
+                Err(_) => (Err(error), "none"),
+            },
+            Either::Right((Err(_), original)) => match original.await {
+                Ok(response) => (Ok(response), "original"),
+                Err(error) => (Err(error), "none"),
+            },
+        };
+        count(winner);
+        result
+    }
+
+    /// GetFeature, hedged. Both calls carry the request's metadata.
+    pub async fn get_feature<T>(&self, client: &RouteGuideClient<T>, request: Request<Point>) -> Result<Response<Feature>, Status>
+        where
+            T: GrpcService<BoxBody> + Clone,
+            T::ResponseBody: Body + HttpBody + Send + 'static,
+            T::Error: Into<StdError>,
+            <T::ResponseBody as HttpBody>::Error: Into<StdError> + Send,
+    {
+        let metadata = request.metadata().clone();
+        let point = request.into_inner();
+        self.run(|| {
+            let mut client = client.clone();
+            let mut request = Request::new(point.clone());
+            *request.metadata_mut() = metadata.clone();
+            async move { client.get_feature(request).await }
+        }).await
+    }
+}
+
+/// `winner` is the call that answered, `none` if both failed, or `skipped` if the budget had
+/// no token for a second call.
+fn count(winner: &str) {
+    metrics::registry()
+        .counter("client_hedged_calls_total", "Calls that were slow enough to be hedged, by which call answered.", &[("winner", winner)])
+        .inc();
+}
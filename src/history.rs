@@ -0,0 +1,88 @@
+use crate::admin::{audit_entry::Action as AuditAction, AuditEntry, AuditedFeature};
+use crate::route_guide::change_event::Action;
+use crate::route_guide::{ChangeEvent, Feature, Point};
+
+
+/// The feature as the audit log kept it. Entries from before the log kept ids, localized names
+/// and attributes restore without them.
+pub fn restored(audited: &AuditedFeature) -> Feature {
+    Feature {
+        name: audited.name.clone(),
+        location: Some(Point { latitude: audited.latitude, longitude: audited.longitude, read_mask: None }),
+        description: audited.description.clone(),
+        tags: audited.tags.clone(),
+        names_by_locale: audited.names_by_locale.clone(),
+        id: audited.id.clone(),
+        attributes: audited.attributes.clone(),
+        ..Feature::default()
+    }
+}
+
+fn at(audited: Option<&AuditedFeature>, point: &Point) -> bool {
+    audited.is_some_and(|audited| audited.latitude == point.latitude && audited.longitude == point.longitude)
+}
+
+/// Whether the entry changed the feature at the point.
+fn touches(entry: &AuditEntry, point: &Point) -> bool {
+    at(entry.before.as_ref(), point) || at(entry.after.as_ref(), point)
+}
+
+/// The feature that was at `point` at some moment, given the tenant's log entries after that
+/// moment, oldest first, and the feature there now.
+///
+/// Rather than replaying the log from the start, which would need the features as they were
+/// loaded, this goes back from now: the first later change at the point says what was there
+/// before it. If nothing at the point changed since, it's what is there now.
+pub fn feature_as_of(later: &[AuditEntry], point: &Point, current: Option<&Feature>) -> Option<Feature> {
+    match later.iter().find(|entry| touches(entry, point)) {
+        Some(entry) => entry.before.as_ref().map(restored),
+        None => current.cloned(),
+    }
+}
+
+pub fn change_event(entry: &AuditEntry) -> ChangeEvent {
+    let mut event = ChangeEvent {
+        id: entry.id,
+        at_ms: entry.at_ms,
+        actor: entry.actor.clone(),
+        action: 0,
+        before: entry.before.as_ref().map(restored),
+        after: entry.after.as_ref().map(restored),
+    };
+    event.set_action(match entry.action() {
+        AuditAction::AddFeature    => Action::Added,
+        AuditAction::DeleteFeature => Action::Deleted,
+        AuditAction::UpdateFeature => Action::Updated,
+        AuditAction::Unknown       => Action::Unknown,
+    });
+    event
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attributes;
+    use crate::audit;
+    use crate::route_guide::Rating;
+    use crate::tenant::TenantId;
+
+    #[test]
+    fn restored_features_are_whole() {
+        let mut feature = Feature {
+            name: "Tower".to_string(),
+            location: Some(Point { latitude: 1, longitude: 2, read_mask: None }),
+            description: "tall".to_string(),
+            tags: vec!["view".to_string()],
+            id: "01HF3Z8Q6V6MZ4X9D2K7T1B5RC".to_string(),
+            ..Feature::default()
+        };
+        feature.names_by_locale.insert("fr".to_string(), "Tour".to_string());
+        attributes::set(&mut feature, &Rating { stars: 4.5, count: 10 });
+
+        let tenant = TenantId::new("default").unwrap();
+        let entry = audit::entry(&tenant, "token:default", AuditAction::DeleteFeature, Some(&feature), None);
+        assert_eq!(restored(entry.before.as_ref().unwrap()), feature);
+        assert_eq!(feature_as_of(&[entry], &Point { latitude: 1, longitude: 2, read_mask: None }, None), Some(feature));
+    }
+}
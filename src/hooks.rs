@@ -0,0 +1,39 @@
+use std::fmt::Debug;
+
+use tonic::Status;
+
+use crate::request_context::RequestContext;
+use crate::route_guide::{Feature, ImportSummary, Point, RouteNote, RouteSummary};
+
+
+/// Side effects on top of the stock RouteGuide service, like analytics, without implementing
+/// RouteGuide again. Everything does nothing by default, so an implementation only has what it
+/// needs. The hooks are called on the call's own task; anything slow should be spawned.
+pub trait RouteGuideHooks: Debug + Send + Sync {
+    /// Before each call does anything, with the method's name, like `GetFeature`. An error
+    /// fails the call.
+    fn before_call(&self, _context: &RequestContext, _method: &str) -> Result<(), Status> {
+        Ok(())
+    }
+
+    /// The feature answered, the default one if there's none at `point`.
+    fn on_get_feature(&self, _context: &RequestContext, _point: &Point, _feature: &Feature) {}
+
+    /// A route that was recorded, or replayed with its idempotency key.
+    fn on_record_route_complete(&self, _context: &RequestContext, _summary: &RouteSummary) {}
+
+    /// A RouteChat note as it's passed on, after moderation, with the server's fields set.
+    fn on_note(&self, _context: &RequestContext, _note: &RouteNote) {}
+
+    fn on_feature_added(&self, _context: &RequestContext, _feature: &Feature) {}
+
+    fn on_feature_deleted(&self, _context: &RequestContext, _feature: &Feature) {}
+
+    fn on_import_complete(&self, _context: &RequestContext, _summary: &ImportSummary) {}
+}
+
+/// The hooks of a server without any.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoHooks;
+
+impl RouteGuideHooks for NoHooks {}
@@ -0,0 +1,75 @@
+use bytes::BytesMut;
+use futures::TryStreamExt as _;
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+use crate::http::middleware::TooLarge;
+
+
+/// The largest body the echo server takes; `/echo/reverse` holds all of it in memory.
+pub const MAX_BODY_SIZE: u64 = 1 << 20;
+
+
+async fn reverse_response(request: Request<Body>) -> Result<Body, hyper::Error> {
+    // Await the full body to be concatenated into a single `Bytes`...
+    let full_body = hyper::body::to_bytes(request.into_body()).await?;
+
+    // Iterate the full body in reverse order and collect into a new Vec.
+    let reversed = full_body.iter()
+        .rev()
+        .cloned()
+        .collect::<Vec<u8>>();
+
+    Ok(reversed.into())
+}
+
+
+fn uppercase_response(request: Request<Body>) -> Body {
+    // Reuse one buffer for all chunks instead of collecting every chunk into a new `Vec`. Once
+    // hyper has written a chunk and dropped it, `reserve` reclaims the same allocation.
+    let mut buffer = BytesMut::new();
+    let mapping = request
+        .into_body()
+        .map_ok(move |chunk| {
+            buffer.reserve(chunk.len());
+            buffer.extend_from_slice(&chunk);
+            buffer.make_ascii_uppercase();
+            buffer.split().freeze()
+        });
+
+    // Use `Body::wrap_stream` to convert it to a `Body`...
+    Body::wrap_stream(mapping)
+}
+
+
+/// `POST /echo` answers with the body, `/echo/uppercase` with it in upper case and
+/// `/echo/reverse` with it backwards. Streamed bodies over the limit end with an error instead.
+pub async fn handle(request: Request<Body>) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/") => {
+            *response.body_mut() = Body::from("Try POSTing data to /echo");
+        },
+        (&Method::POST, "/echo") => {
+            *response.body_mut() = request.into_body();
+        },
+        (&Method::POST, "/echo/uppercase") => {
+            *response.body_mut() = uppercase_response(request);
+        },
+        (&Method::POST, "/echo/reverse") => match reverse_response(request).await {
+            Ok(body) => *response.body_mut() = body,
+            Err(e) => match TooLarge::of(&e) {
+                Some(too_large) => return too_large.response(),
+                None => {
+                    *response.status_mut() = StatusCode::BAD_REQUEST;
+                    *response.body_mut() = Body::from(format!("failed to read the body: {}", e));
+                },
+            },
+        },
+        _ => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+        },
+    };
+
+    response
+}
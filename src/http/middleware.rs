@@ -0,0 +1,180 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use futures::StreamExt;
+use hyper::header::{HeaderValue, CONTENT_LENGTH};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use once_cell::sync::Lazy;
+
+use crate::http::{Exchange, Middleware};
+use crate::metrics::{self, DURATION_BUCKETS};
+
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+const MAX_REQUEST_ID_LENGTH: usize = 64;
+
+
+/// Gives every request an id, in `x-request-id` on the request and its response. A client's own
+/// id is kept if it's 1 to 64 visible ASCII characters, so one id can follow a call through
+/// proxies.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RequestId;
+
+/// Ids unique to this process: a counter, offset by the start time so restarts don't repeat ids.
+pub fn next_request_id() -> String {
+    static NEXT: Lazy<AtomicU64> = Lazy::new(|| {
+        let start = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos() as u64);
+        AtomicU64::new(start)
+    });
+    format!("{:016x}", NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+impl Middleware for RequestId {
+    fn before(&self, request: &mut Request<Body>, exchange: &mut Exchange) -> Option<Response<Body>> {
+        let given = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH);
+        exchange.request_id = match given {
+            Some(id) => id.to_string(),
+            None => next_request_id(),
+        };
+        if let Ok(value) = HeaderValue::from_str(&exchange.request_id) {
+            request.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+        None
+    }
+
+    fn after(&self, exchange: &Exchange, response: &mut Response<Body>) {
+        if let Ok(value) = HeaderValue::from_str(&exchange.request_id) {
+            response.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+    }
+}
+
+
+/// Logs every request under the `http` target: at debug level, or warn for server errors.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Logging;
+
+impl Middleware for Logging {
+    fn after(&self, exchange: &Exchange, response: &mut Response<Body>) {
+        let status = response.status().as_u16();
+        let elapsed_ms = exchange.started.elapsed().as_millis() as u64;
+        if response.status().is_server_error() {
+            tracing::warn!(
+                target: "http", server = exchange.server, request_id = %exchange.request_id,
+                method = %exchange.method, path = %exchange.path, status, elapsed_ms, "request failed"
+            );
+        } else {
+            tracing::debug!(
+                target: "http", server = exchange.server, request_id = %exchange.request_id,
+                method = %exchange.method, path = %exchange.path, status, elapsed_ms, "request served"
+            );
+        }
+    }
+}
+
+
+/// Counts requests by server, method and status in `http_requests_total`, and times them in
+/// `http_request_duration_seconds`. Streamed responses are timed until their headers are ready.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Metrics;
+
+/// Methods outside the standard ones are counted together, so clients can't add series.
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET     => "GET",
+        Method::HEAD    => "HEAD",
+        Method::POST    => "POST",
+        Method::PUT     => "PUT",
+        Method::DELETE  => "DELETE",
+        Method::PATCH   => "PATCH",
+        Method::OPTIONS => "OPTIONS",
+        _               => "other",
+    }
+}
+
+impl Middleware for Metrics {
+    fn after(&self, exchange: &Exchange, response: &mut Response<Body>) {
+        let registry = metrics::registry();
+        let status = response.status().as_u16().to_string();
+        let labels = [("server", exchange.server), ("method", method_label(&exchange.method)), ("status", status.as_str())];
+        registry.counter("http_requests_total", "HTTP requests served.", &labels).inc();
+        registry
+            .histogram("http_request_duration_seconds", "Time to answer an HTTP request.", &[("server", exchange.server)], DURATION_BUCKETS)
+            .observe(exchange.started.elapsed().as_secs_f64());
+    }
+}
+
+
+/// Refuses request bodies over `max` bytes: with 413 up front if `content-length` says so, or
+/// else with a `TooLarge` error from the body once it's read past the limit.
+#[derive(Debug, Copy, Clone)]
+pub struct BodyLimit {
+    pub max: u64,
+}
+
+impl Middleware for BodyLimit {
+    fn before(&self, request: &mut Request<Body>, _exchange: &mut Exchange) -> Option<Response<Body>> {
+        let limit = TooLarge { limit: self.max };
+        let declared = request
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+        if declared.is_some_and(|length| length > self.max) {
+            return Some(limit.response());
+        }
+
+        let mut read = 0;
+        let body = std::mem::take(request.body_mut()).map(move |chunk| -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+            let chunk = chunk?;
+            read += chunk.len() as u64;
+            if read > limit.limit {
+                return Err(Box::new(limit));
+            }
+            Ok(chunk)
+        });
+        *request.body_mut() = Body::wrap_stream(body);
+        None
+    }
+}
+
+/// A request body read past `BodyLimit`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TooLarge {
+    pub limit: u64,
+}
+
+impl TooLarge {
+    /// The `TooLarge` that `error`, from reading a body, comes from, if it does.
+    pub fn of(error: &hyper::Error) -> Option<TooLarge> {
+        let mut source = error.source();
+        while let Some(error) = source {
+            if let Some(&too_large) = error.downcast_ref::<TooLarge>() {
+                return Some(too_large);
+            }
+            source = error.source();
+        }
+        None
+    }
+
+    pub fn response(&self) -> Response<Body> {
+        let mut response = Response::new(Body::from(self.to_string()));
+        *response.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+        response
+    }
+}
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the request body is over {} bytes", self.limit)
+    }
+}
+
+impl Error for TooLarge {}
@@ -0,0 +1,103 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use hyper::{Body, Method, Request, Response};
+
+pub mod echo;
+pub mod middleware;
+
+pub use middleware::{BodyLimit, Logging, Metrics, RequestId};
+
+
+/// What the middleware knows about the request being served, from its `before` hooks to its
+/// `after` hooks.
+#[derive(Debug, Clone)]
+pub struct Exchange {
+    /// Which server is serving, like `gateway`; logs and metrics are labelled with it.
+    pub server: &'static str,
+    pub method: Method,
+    pub path: String,
+    pub started: Instant,
+    /// Set by `RequestId`; empty without it.
+    pub request_id: String,
+}
+
+/// Work done around every request of a server.
+///
+/// `before` hooks run in the order the middleware was added, and `after` hooks in the reverse
+/// order, as if each one wrapped the ones added after it.
+pub trait Middleware: Send + Sync + 'static {
+    /// May change the request, or answer it with a response of its own. The handler and the
+    /// later middleware are then skipped, but the `after` hooks of this and the earlier
+    /// middleware still run.
+    fn before(&self, _request: &mut Request<Body>, _exchange: &mut Exchange) -> Option<Response<Body>> {
+        None
+    }
+
+    fn after(&self, _exchange: &Exchange, _response: &mut Response<Body>) {}
+}
+
+
+/// The middleware a server runs its handler in.
+#[derive(Clone)]
+pub struct Stack {
+    server: &'static str,
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Stack {
+    pub fn new(server: &'static str) -> Self {
+        Stack { server, middleware: vec![] }
+    }
+
+    /// Request ids, then metrics and logging of every request, and bodies up to `max_body`
+    /// bytes.
+    pub fn standard(server: &'static str, max_body: u64) -> Self {
+        Stack::new(server)
+            .with(RequestId)
+            .with(Metrics)
+            .with(Logging)
+            .with(BodyLimit { max: max_body })
+    }
+
+    /// Adds `middleware` inside what's already there.
+    pub fn with(mut self, middleware: impl Middleware) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Serves `request` with `handler`, inside the middleware.
+    pub async fn serve<F, Fut>(&self, mut request: Request<Body>, handler: F) -> Response<Body>
+        where
+            F: FnOnce(Request<Body>) -> Fut,
+            Fut: Future<Output = Response<Body>>,
+    {
+        let mut exchange = Exchange {
+            server: self.server,
+            method: request.method().clone(),
+            path: request.uri().path().to_string(),
+            started: Instant::now(),
+            request_id: String::new(),
+        };
+
+        let mut ran = 0;
+        let mut answered = None;
+        for middleware in &self.middleware {
+            ran += 1;
+            answered = middleware.before(&mut request, &mut exchange);
+            if answered.is_some() {
+                break;
+            }
+        }
+
+        let mut response = match answered {
+            Some(response) => response,
+            None => handler(request).await,
+        };
+        for middleware in self.middleware[..ran].iter().rev() {
+            middleware.after(&exchange, &mut response);
+        }
+        response
+    }
+}
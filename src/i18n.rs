@@ -0,0 +1,119 @@
+use tonic::Request;
+
+use crate::route_guide::Feature;
+
+
+/// Request metadata with the caller's preferred languages, as in HTTP: `fr-CH, fr;q=0.9, en;q=0.8`.
+pub const ACCEPT_LANGUAGE: &str = "accept-language";
+
+
+/// The languages a caller accepts, most preferred first. Empty if they didn't say.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Languages {
+    /// Lowercase language ranges, like `fr-ch`. `*` accepts any language.
+    ranges: Vec<String>,
+}
+
+impl Languages {
+    /// Parses an `accept-language` value. Ranges with `q=0` are left out, as are malformed ones;
+    /// ranges of equal weight keep their order.
+    pub fn parse(header: &str) -> Self {
+        let mut weighted: Vec<(String, f32)> = header
+            .split(',')
+            .filter_map(|item| {
+                let mut parts = item.split(';');
+                let range = parts.next()?.trim().to_ascii_lowercase();
+                if range.is_empty() || !range.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '*') {
+                    return None;
+                }
+                let weight = match parts.find_map(|param| param.trim().strip_prefix("q=")) {
+                    Some(q) => q.trim().parse::<f32>().ok()?,
+                    None => 1.0,
+                };
+                Some((range, weight))
+            })
+            .filter(|(_, weight)| *weight > 0.0)
+            .collect();
+        // Stable, so equal weights keep the caller's order.
+        weighted.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        Languages { ranges: weighted.into_iter().map(|(range, _)| range).collect() }
+    }
+
+    /// The languages in the request's `accept-language` metadata.
+    pub fn of<T>(request: &Request<T>) -> Self {
+        request
+            .metadata()
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .map(Languages::parse)
+            .unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The feature's name in the most preferred language it has one in. For each range, most
+    /// preferred first:
+    ///
+    /// 1. the locale itself, ignoring case (`fr-CH`),
+    /// 2. the range with subtags dropped from the end (`zh-Hant-TW`, then `zh-Hant`, then `zh`),
+    /// 3. any other locale of the same language (`fr-FR` for `fr-CH`), the first by name.
+    ///
+    /// `name` is the fallback, also for `*`.
+    pub fn select<'a>(&self, feature: &'a Feature) -> &'a str {
+        if feature.names_by_locale.is_empty() {
+            return &feature.name;
+        }
+
+        for range in &self.ranges {
+            if range == "*" {
+                break;
+            }
+
+            let mut prefix = range.as_str();
+            loop {
+                if let Some(name) = name_for(feature, |locale| locale.eq_ignore_ascii_case(prefix)) {
+                    return name;
+                }
+                match prefix.rfind('-') {
+                    Some(i) => prefix = &prefix[..i],
+                    None => break,
+                }
+            }
+
+            let language = prefix;
+            if let Some(name) = name_for(feature, |locale| primary_language(locale).eq_ignore_ascii_case(language)) {
+                return name;
+            }
+        }
+
+        &feature.name
+    }
+
+    /// The feature with `name` set to `select`'s choice. `names_by_locale` is kept, so callers
+    /// can still show the others.
+    pub fn localize(&self, mut feature: Feature) -> Feature {
+        if !self.is_empty() {
+            let name = self.select(&feature).to_string();
+            feature.name = name;
+        }
+        feature
+    }
+}
+
+fn primary_language(locale: &str) -> &str {
+    locale.split('-').next().unwrap_or(locale)
+}
+
+/// The name under the first matching locale, by locale, so the choice doesn't depend on the
+/// map's order.
+fn name_for(feature: &Feature, matches: impl Fn(&str) -> bool) -> Option<&str> {
+    feature
+        .names_by_locale
+        .iter()
+        .filter(|(locale, name)| matches(locale) && !name.trim().is_empty())
+        .min_by(|a, b| a.0.cmp(b.0))
+        .map(|(_, name)| name.as_str())
+}
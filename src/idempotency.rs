@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use prost::Message;
+use tonic::{Code, Request, Status};
+
+
+/// Metadata key clients put a unique value under to make a mutating call safe to retry.
+pub const IDEMPOTENCY_HEADER: &str = "idempotency-key";
+
+
+/// Hash of the encoded request, so a key reused for a different request can be told apart from a
+/// retry.
+pub fn fingerprint<M: Message>(message: &M) -> u64 {
+    let mut bytes = Vec::with_capacity(message.encoded_len());
+    message.encode(&mut bytes).unwrap();
+
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The request's idempotency key, if it has one.
+pub fn key<T>(request: &Request<T>) -> Result<Option<String>, Status> {
+    match request.metadata().get(IDEMPOTENCY_HEADER) {
+        None => Ok(None),
+        Some(value) => match value.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= 128 => Ok(Some(key.to_string())),
+            _ => Err(Status::invalid_argument("idempotency-key must be 1 to 128 visible ASCII characters")),
+        },
+    }
+}
+
+
+fn is_transient(code: Code) -> bool {
+    matches!(code, Code::Unavailable | Code::ResourceExhausted | Code::Aborted | Code::DeadlineExceeded | Code::Internal | Code::Unknown)
+}
+
+
+#[derive(Debug)]
+enum Entry<T> {
+    InProgress { fingerprint: u64 },
+    Done { fingerprint: u64, at: Instant, result: Result<T, (Code, String)> },
+}
+
+/// Remembers the outcome of recent calls by idempotency key. A retry with the same key and request
+/// gets the stored outcome instead of running the call again.
+#[derive(Debug)]
+pub struct IdempotencyCache<T> {
+    entries: Arc<Mutex<HashMap<String, Entry<T>>>>,
+    ttl: Duration,
+    capacity: usize,
+}
+
+/// Forgets an in-progress entry if the call is cancelled before it completes, so a retry can run.
+struct PendingGuard<T> {
+    entries: Arc<Mutex<HashMap<String, Entry<T>>>>,
+    key: Option<String>,
+}
+
+impl<T> Drop for PendingGuard<T> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            let mut entries = self.entries.lock().unwrap();
+            if let Some(Entry::InProgress { .. }) = entries.get(&key) {
+                entries.remove(&key);
+            }
+        }
+    }
+}
+
+impl<T: Clone> IdempotencyCache<T> {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        IdempotencyCache { entries: Arc::default(), ttl, capacity }
+    }
+
+    /// Runs `call` unless a call with the same key already completed within the ttl, in which case
+    /// its outcome is returned. Keys should be scoped by the caller (e.g. prefixed with the tenant).
+    pub async fn run<F, Fut>(&self, key: Option<String>, fingerprint: u64, call: F) -> Result<T, Status>
+        where
+            F: FnOnce() -> Fut,
+            Fut: Future<Output = Result<T, Status>>,
+    {
+        let key = match key {
+            Some(key) => key,
+            None => return call().await,
+        };
+
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let now = Instant::now();
+            let ttl = self.ttl;
+            entries.retain(|_, entry| match entry {
+                Entry::Done { at, .. } => now.duration_since(*at) < ttl,
+                Entry::InProgress { .. } => true,
+            });
+
+            match entries.get(&key) {
+                Some(Entry::InProgress { fingerprint: f }) | Some(Entry::Done { fingerprint: f, .. }) if *f != fingerprint =>
+                    return Err(Status::failed_precondition("idempotency-key was already used for a different request")),
+                Some(Entry::InProgress { .. }) =>
+                    return Err(Status::aborted("a request with this idempotency-key is still in progress")),
+                Some(Entry::Done { result, .. }) =>
+                    return result.clone().map_err(|(code, message)| Status::new(code, message)),
+                None => {},
+            }
+
+            if entries.len() >= self.capacity {
+                let oldest = entries
+                    .iter()
+                    .filter_map(|(key, entry)| match entry {
+                        Entry::Done { at, .. } => Some((*at, key.clone())),
+                        Entry::InProgress { .. } => None,
+                    })
+                    .min();
+                match oldest {
+                    Some((_, oldest)) => { entries.remove(&oldest); },
+                    None => return Err(Status::resource_exhausted("too many requests in progress")),
+                }
+            }
+
+            entries.insert(key.clone(), Entry::InProgress { fingerprint });
+        }
+
+        let mut guard = PendingGuard { entries: self.entries.clone(), key: Some(key.clone()) };
+        let result = call().await;
+        guard.key = None;
+
+        // Transient failures aren't stored, so a retry runs the call again.
+        let stored = match &result {
+            Ok(value) => Some(Ok(value.clone())),
+            Err(status) if is_transient(status.code()) => None,
+            Err(status) => Some(Err((status.code(), status.message().to_string()))),
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        match stored {
+            Some(result) => { entries.insert(key, Entry::Done { fingerprint, at: Instant::now(), result }); },
+            None => { entries.remove(&key); },
+        }
+
+        result
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::hash_map::RandomState;
+use std::fmt::{self, Debug};
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+/// Makes up the ids of new features. Ids are never reused, so they can be handed out in URLs.
+pub trait IdGenerator: Debug + Send + Sync {
+    fn next_id(&self) -> String;
+}
+
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+const RANDOM_BITS: u32 = 80;
+
+/// ULIDs: 26 characters of Crockford base32, the creation time in milliseconds followed by 80
+/// random bits. They sort by creation time, and ids made in the same millisecond sort in the
+/// order they were made. The random bits come from std's hasher keys, so they're unguessable
+/// enough for ids but not for secrets.
+#[derive(Debug, Default)]
+pub struct Ulids {
+    // The time and random bits of the last id.
+    last: Mutex<(u64, u128)>,
+}
+
+fn random_bits() -> u128 {
+    let word = || {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos()));
+        hasher.finish() as u128
+    };
+    ((word() << 64) | word()) & ((1 << RANDOM_BITS) - 1)
+}
+
+impl IdGenerator for Ulids {
+    fn next_id(&self) -> String {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64);
+        let mut last = self.last.lock().unwrap();
+        let (time, random) = match *last {
+            // Later than the last id even if the clock went back.
+            (time, random) if now <= time => match random.checked_add(1).filter(|next| *next < (1 << RANDOM_BITS)) {
+                Some(next) => (time, next),
+                None => (time + 1, 0),
+            },
+            _ => (now, random_bits()),
+        };
+        *last = (time, random);
+
+        let value = ((time as u128) << RANDOM_BITS) | random;
+        (0..26).map(|i| CROCKFORD[((value >> (125 - 5 * i)) & 31) as usize] as char).collect()
+    }
+}
+
+
+/// `prefix` and a counter from 1, like `f-00000001`; for demos and recordings that have to come
+/// out the same on every run.
+#[derive(Debug)]
+pub struct Sequential {
+    prefix: String,
+    next: AtomicU64,
+}
+
+impl Sequential {
+    pub fn new(prefix: &str) -> Self {
+        Sequential { prefix: prefix.to_string(), next: AtomicU64::new(1) }
+    }
+}
+
+impl IdGenerator for Sequential {
+    fn next_id(&self) -> String {
+        format!("{}{:08}", self.prefix, self.next.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+
+/// Which `IdGenerator` to use, as given on the command line: `ulid` or `sequential`.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum IdScheme {
+    #[default]
+    Ulid,
+    Sequential,
+}
+
+impl IdScheme {
+    pub fn generator(self) -> Arc<dyn IdGenerator> {
+        match self {
+            IdScheme::Ulid => Arc::new(Ulids::default()),
+            IdScheme::Sequential => Arc::new(Sequential::new("f-")),
+        }
+    }
+}
+
+
+impl FromStr for IdScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ulid" => Ok(IdScheme::Ulid),
+            "sequential" => Ok(IdScheme::Sequential),
+            other => Err(format!("unknown id scheme '{}', expected ulid or sequential", other)),
+        }
+    }
+}
+
+impl fmt::Display for IdScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IdScheme::Ulid => "ulid",
+            IdScheme::Sequential => "sequential",
+        })
+    }
+}
@@ -0,0 +1,200 @@
+use std::str::FromStr;
+
+use futures::{Stream, StreamExt};
+use tonic::{Request, Status};
+
+use crate::policy::Resource;
+use crate::route_guide::{Feature, ImportFailure, ImportSummary};
+use crate::tenant::TenantData;
+use crate::validation;
+
+
+/// Metadata that picks what ImportFeatures does with features whose location is taken.
+pub const DUPLICATES_HEADER: &str = "x-import-duplicates";
+
+/// Features are written this many at a time, under one lock and one copy of the index.
+pub const BATCH_SIZE: usize = 500;
+
+/// The summary explains at most this many failures; the rest are only counted.
+pub const MAX_REPORTED_FAILURES: usize = 100;
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum DuplicatePolicy {
+    #[default]
+    Skip,
+    Replace,
+    Fail,
+}
+
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip"    => Ok(DuplicatePolicy::Skip),
+            "replace" => Ok(DuplicatePolicy::Replace),
+            "fail"    => Ok(DuplicatePolicy::Fail),
+            _ => Err(format!("unknown duplicate policy '{}', expected skip, replace or fail", s)),
+        }
+    }
+}
+
+impl DuplicatePolicy {
+    /// The policy asked for in the call's metadata, `Skip` if none.
+    pub fn of<T>(request: &Request<T>) -> Result<Self, Status> {
+        match request.metadata().get(DUPLICATES_HEADER) {
+            Some(value) => value
+                .to_str()
+                .map_err(|_| Status::invalid_argument(format!("{} is not valid ASCII", DUPLICATES_HEADER)))?
+                .parse()
+                .map_err(Status::invalid_argument),
+            None => Ok(DuplicatePolicy::default()),
+        }
+    }
+}
+
+
+/// What a batch write did with one feature.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Imported {
+    Inserted,
+    Replaced,
+    Skipped,
+    /// Its location was taken and the policy is `Fail`.
+    Duplicate,
+}
+
+
+/// Counts what happens to the features of one import.
+#[derive(Debug, Default)]
+struct Tally {
+    summary: ImportSummary,
+}
+
+impl Tally {
+    fn fail(&mut self, index: u64, message: String) {
+        self.summary.failed += 1;
+        if self.summary.failures.len() < MAX_REPORTED_FAILURES {
+            self.summary.failures.push(ImportFailure { index, message });
+        }
+    }
+
+    fn count(&mut self, index: u64, imported: Imported) {
+        match imported {
+            Imported::Inserted  => self.summary.inserted += 1,
+            Imported::Replaced  => self.summary.replaced += 1,
+            Imported::Skipped   => self.summary.skipped += 1,
+            Imported::Duplicate => self.fail(index, "a feature already exists at this location".to_string()),
+        }
+    }
+}
+
+/// Whether a feature is already where this one goes.
+fn replaces(tenant: &TenantData, feature: &Feature) -> bool {
+    feature.location.as_ref().is_some_and(|location| tenant.features().contains(location))
+}
+
+/// Writes the batch and counts the outcomes, clearing it.
+fn flush(tenant: &TenantData, batch: &mut Vec<(u64, Feature)>, policy: DuplicatePolicy, actor: &str, tally: &mut Tally) -> Result<(), Status> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let (indexes, features): (Vec<u64>, Vec<Feature>) = batch.drain(..).unzip();
+    let outcomes = tenant.import_features(features, policy, actor)?;
+    for (index, imported) in indexes.into_iter().zip(outcomes) {
+        tally.count(index, imported);
+    }
+    Ok(())
+}
+
+/// Validates the features as they arrive and adds them to the tenant in batches of
+/// `BATCH_SIZE`. Batches written before a transport error or a failure to record a change
+/// stay written.
+///
+/// `check` authorizes each feature by method and resource, as the policy would the call
+/// (see `policy::PolicyEngine::check_context`): adding it as an AddFeature and, when it replaces
+/// one, removing that one as a DeleteFeature. Features it refuses fail, like invalid ones.
+pub async fn import<S, C>(tenant: &TenantData, actor: &str, policy: DuplicatePolicy, features: S, check: C) -> Result<ImportSummary, Status>
+    where
+        S: Stream<Item = Result<Feature, Status>>,
+        C: Fn(&str, &Resource) -> Result<(), Status>,
+{
+    futures::pin_mut!(features);
+    let mut tally = Tally::default();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut index = 0;
+
+    while let Some(feature) = features.next().await {
+        let mut feature = feature?;
+        let allowed = validation::validate(&mut feature)
+            .and_then(|()| check("AddFeature", &Resource::feature(&feature)))
+            .and_then(|()| match policy {
+                DuplicatePolicy::Replace if replaces(tenant, &feature) => check("DeleteFeature", &Resource::default()),
+                _ => Ok(()),
+            });
+        match allowed {
+            Ok(()) => batch.push((index, feature)),
+            Err(status) => tally.fail(index, status.message().to_string()),
+        }
+        index += 1;
+
+        if batch.len() >= BATCH_SIZE {
+            flush(tenant, &mut batch, policy, actor, &mut tally)?;
+        }
+    }
+    flush(tenant, &mut batch, policy, actor, &mut tally)?;
+
+    Ok(tally.summary)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::policy::{Call, Policy};
+    use crate::route_guide::Point;
+    use crate::tenant::{TenantId, Tenants};
+
+    fn feature(name: &str, latitude: i32, tags: &[&str]) -> Feature {
+        Feature {
+            name: name.to_string(),
+            location: Some(Point { latitude, longitude: -746143763, read_mask: None }),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            ..Feature::default()
+        }
+    }
+
+    async fn import_as(policy: &str, duplicates: DuplicatePolicy, existing: Vec<Feature>, features: Vec<Feature>) -> (ImportSummary, Vec<String>) {
+        let tenant = Tenants::default().provision(TenantId::new("test").unwrap(), "token", existing).unwrap();
+        let policy = Policy::parse(policy).unwrap();
+        let check = |method: &str, resource: &Resource| {
+            policy.check(&Call { method, subject: "token:test", tenant: "test", resource }).map_err(Status::permission_denied)
+        };
+        let stream = futures::stream::iter(features.into_iter().map(Ok));
+        let summary = import(&tenant, "token:test", duplicates, stream, check).await.unwrap();
+        let names = tenant.features().iter().map(|feature| feature.name.clone()).collect();
+        (summary, names)
+    }
+
+    #[tokio::test]
+    async fn each_feature_is_checked_as_added() {
+        let features = vec![feature("open", 407838351, &[]), feature("secret", 407838352, &["restricted"])];
+        let (summary, names) = import_as("deny AddFeature tag=restricted subject=token:*", DuplicatePolicy::Skip, vec![], features).await;
+        assert_eq!((summary.inserted, summary.failed), (1, 1));
+        assert_eq!(summary.failures[0].index, 1);
+        assert!(summary.failures[0].message.contains("denied by rule 1"));
+        assert_eq!(names, vec!["open"]);
+    }
+
+    #[tokio::test]
+    async fn replacing_is_checked_as_deleting() {
+        let existing = vec![feature("old", 407838351, &[])];
+        let features = vec![feature("new", 407838351, &[]), feature("elsewhere", 407838352, &[])];
+        let (summary, names) = import_as("deny DeleteFeature", DuplicatePolicy::Replace, existing, features).await;
+        assert_eq!((summary.inserted, summary.replaced, summary.failed), (1, 0, 1));
+        assert_eq!(names, vec!["old", "elsewhere"]);
+    }
+}
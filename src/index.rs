@@ -0,0 +1,263 @@
+use std::collections::{BTreeMap, HashMap};
+
+use tonic::Status;
+
+use crate::geo;
+use crate::route_guide::{Feature, Point, Rectangle};
+use crate::scan_report::FeatureSource;
+
+
+/// The width of a zoom 0 grid cell, in E7 degrees: the whole longitude range.
+const WORLD_WIDTH: i64 = 3_600_000_000;
+
+
+/// The loaded features, indexed by their id and by their exact location.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureIndex {
+    features: Vec<Feature>,
+    by_id: HashMap<String, usize>,
+    by_location: HashMap<Point, usize>,
+}
+
+impl FeatureIndex {
+    /// Builds the index. Features without an id or a location are kept (they show up in `iter`)
+    /// but can't be looked up by it. If several features share an id or a location the first one
+    /// wins.
+    pub fn new(features: Vec<Feature>) -> Self {
+        let mut by_id = HashMap::with_capacity(features.len());
+        let mut by_location = HashMap::with_capacity(features.len());
+
+        for (i, feature) in features.iter().enumerate() {
+            if !feature.id.is_empty() {
+                by_id.entry(feature.id.clone()).or_insert(i);
+            }
+            if let Some(location) = feature.location.as_ref() {
+                by_location.entry(location.clone()).or_insert(i);
+            }
+        }
+
+        FeatureIndex { features, by_id, by_location }
+    }
+
+    pub fn len(&self) -> usize {
+        self.features.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Feature> {
+        self.features.iter()
+    }
+
+    /// The feature at exactly this point, if any.
+    pub fn get(&self, point: &Point) -> Option<&Feature> {
+        self.by_location.get(point).map(|&i| &self.features[i])
+    }
+
+    pub fn contains(&self, point: &Point) -> bool {
+        self.by_location.contains_key(point)
+    }
+
+    /// The feature with this id, if any.
+    pub fn get_by_id(&self, id: &str) -> Option<&Feature> {
+        self.by_id.get(id).map(|&i| &self.features[i])
+    }
+
+    /// Adds a feature. Returns false, leaving the index unchanged, if the feature has no id or no
+    /// location, or either is already taken.
+    pub fn insert(&mut self, feature: Feature) -> bool {
+        if feature.id.is_empty() || self.by_id.contains_key(&feature.id) {
+            return false;
+        }
+        let location = match feature.location.as_ref() {
+            Some(location) if !self.by_location.contains_key(location) => location.clone(),
+            _ => return false,
+        };
+
+        self.by_id.insert(feature.id.clone(), self.features.len());
+        self.by_location.insert(location, self.features.len());
+        self.features.push(feature);
+        true
+    }
+
+    /// Puts the feature in the place of the one at its location and returns that one, which
+    /// keeps its id. Returns `None`, leaving the index unchanged, if there is none.
+    pub fn replace(&mut self, mut feature: Feature) -> Option<Feature> {
+        let i = *self.by_location.get(feature.location.as_ref()?)?;
+        feature.id = self.features[i].id.clone();
+        Some(std::mem::replace(&mut self.features[i], feature))
+    }
+
+    /// Removes and returns the feature at exactly this point, if any. Later features move down
+    /// by one.
+    pub fn remove(&mut self, point: &Point) -> Option<Feature> {
+        let i = *self.by_location.get(point)?;
+        Some(self.remove_at(i))
+    }
+
+    /// Removes and returns the feature with this id, if any.
+    pub fn remove_by_id(&mut self, id: &str) -> Option<Feature> {
+        let i = *self.by_id.get(id)?;
+        Some(self.remove_at(i))
+    }
+
+    fn remove_at(&mut self, i: usize) -> Feature {
+        let feature = self.features.remove(i);
+        self.by_id.retain(|_, index| *index != i);
+        self.by_location.retain(|_, index| *index != i);
+        for index in self.by_id.values_mut().chain(self.by_location.values_mut()) {
+            if *index > i {
+                *index -= 1;
+            }
+        }
+        feature
+    }
+
+    /// Up to `limit` features starting at `offset`, in load order. Features are appended, so an
+    /// offset keeps pointing at the same feature unless an earlier one is removed.
+    pub fn page(&self, offset: usize, limit: usize) -> &[Feature] {
+        let start = offset.min(self.features.len());
+        let end = start.saturating_add(limit).min(self.features.len());
+        &self.features[start..end]
+    }
+
+    /// All features inside the rectangle, in load order. A rectangle across the antimeridian is
+    /// searched as its two halves.
+    pub fn in_rectangle<'a>(&'a self, rect: &'a Rectangle) -> impl Iterator<Item = &'a Feature> + 'a {
+        let parts = geo::Bounds::of(rect).map_or_else(Vec::new, |bounds| bounds.split());
+        self.features.iter().filter(move |feature| match feature.location.as_ref() {
+            Some(location) => parts.iter().any(|part| part.contains(location)),
+            None => false,
+        })
+    }
+
+    /// The features inside the rectangle grouped on a grid with cells `360° / 2^zoom` wide. A cell
+    /// holding one feature yields that feature; fuller cells yield a cluster feature located at
+    /// the centroid of its members. Cells are in a fixed order, south-west first.
+    pub fn clusters(&self, rect: &Rectangle, zoom: u32) -> Vec<Feature> {
+        let cell_size = (WORLD_WIDTH >> zoom.min(32)).max(1);
+        let mut cells: BTreeMap<(i64, i64), Vec<&Feature>> = BTreeMap::new();
+
+        for feature in self.in_rectangle(rect) {
+            let location = feature.location.as_ref().unwrap();
+            let cell = (
+                (location.latitude as i64).div_euclid(cell_size),
+                (location.longitude as i64).div_euclid(cell_size),
+            );
+            cells.entry(cell).or_default().push(feature);
+        }
+
+        cells.into_values().map(|members| cluster(&members)).collect()
+    }
+}
+
+fn cluster(members: &[&Feature]) -> Feature {
+    if let [feature] = members {
+        return (*feature).clone();
+    }
+
+    let count = members.len() as i64;
+    let (latitude, longitude) = members
+        .iter()
+        .filter_map(|feature| feature.location.as_ref())
+        .fold((0, 0), |(lat, lon), location| (lat + location.latitude as i64, lon + location.longitude as i64));
+
+    Feature {
+        name: format!("{} features", count),
+        location: Some(Point { latitude: (latitude / count) as i32, longitude: (longitude / count) as i32, read_mask: None }),
+        cluster_size: count as u32,
+        ..Feature::default()
+    }
+}
+
+
+/// In memory, so reading never fails.
+impl FeatureSource for FeatureIndex {
+    fn read(&self, range: &Rectangle) -> Result<Vec<Feature>, Status> {
+        Ok(self.in_rectangle(range).cloned().collect())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feature(name: &str, latitude: f64, longitude: f64) -> Feature {
+        Feature { name: name.to_string(), location: Some(Point::from_degrees(latitude, longitude)), ..Feature::default() }
+    }
+
+    fn index() -> FeatureIndex {
+        FeatureIndex::new(vec![
+            feature("west of the antimeridian", 0.0, 175.0),
+            feature("on the antimeridian", 0.0, 180.0),
+            feature("east of the antimeridian", 0.0, -175.0),
+            feature("greenwich", 51.4778, 0.0),
+            feature("south edge", -10.0, 179.0),
+            Feature { name: "no location".to_string(), ..Feature::default() },
+        ])
+    }
+
+    fn names<'a>(features: impl Iterator<Item = &'a Feature>) -> Vec<&'a str> {
+        features.map(|feature| feature.name.as_str()).collect()
+    }
+
+    fn rectangle(lo: (f64, f64), hi: (f64, f64)) -> Rectangle {
+        Rectangle::new(Point::from_degrees(lo.0, lo.1), Point::from_degrees(hi.0, hi.1))
+    }
+
+    #[test]
+    fn across_the_antimeridian() {
+        let (index, rect) = (index(), rectangle((-10.0, 170.0), (10.0, -170.0)));
+        let found = names(index.in_rectangle(&rect));
+        assert_eq!(found, vec!["west of the antimeridian", "on the antimeridian", "east of the antimeridian", "south edge"]);
+    }
+
+    #[test]
+    fn the_long_way_round() {
+        // Corners given east to west: everything but the strip between them.
+        let (index, rect) = (index(), rectangle((-10.0, 10.0), (60.0, -10.0)));
+        let found = names(index.in_rectangle(&rect));
+        assert_eq!(found, vec!["west of the antimeridian", "on the antimeridian", "east of the antimeridian", "south edge"]);
+    }
+
+    #[test]
+    fn swapped_latitudes() {
+        let index = index();
+        let (upright, swapped) = (rectangle((-10.0, 170.0), (10.0, 180.0)), rectangle((10.0, 170.0), (-10.0, 180.0)));
+        assert_eq!(names(index.in_rectangle(&upright)), vec!["west of the antimeridian", "on the antimeridian", "south edge"]);
+        assert_eq!(names(index.in_rectangle(&swapped)), names(index.in_rectangle(&upright)));
+    }
+
+    #[test]
+    fn degenerate_rectangles() {
+        let index = index();
+        let (dot, line, empty) = (rectangle((51.4778, 0.0), (51.4778, 0.0)), rectangle((0.0, 175.0), (0.0, -175.0)), rectangle((1.0, 1.0), (1.0, 1.0)));
+        assert_eq!(names(index.in_rectangle(&dot)), vec!["greenwich"]);
+        assert_eq!(names(index.in_rectangle(&line)), vec!["west of the antimeridian", "on the antimeridian", "east of the antimeridian"]);
+        assert!(index.in_rectangle(&empty).next().is_none());
+        let cornerless = Rectangle::default();
+        assert!(index.in_rectangle(&cornerless).next().is_none());
+    }
+
+    #[test]
+    fn clusters_across_the_antimeridian() {
+        let clusters = index().clusters(&rectangle((-10.0, 170.0), (10.0, -170.0)), 0);
+        // Every feature of both halves, each in one cell.
+        let members: u32 = clusters.iter().map(|cluster| cluster.cluster_size.max(1)).sum();
+        assert_eq!(members, 4);
+    }
+
+    #[test]
+    fn bands_read_what_the_rectangle_has() {
+        let (index, rect) = (index(), rectangle((-10.0, 170.0), (10.0, -170.0)));
+        let mut read = vec![];
+        for band in crate::scan_report::bands(&rect, 4) {
+            read.extend(index.read(&band).unwrap());
+        }
+        // South first: the south edge comes before the features on the equator.
+        assert_eq!(names(read.iter()), vec!["south edge", "west of the antimeridian", "on the antimeridian", "east of the antimeridian"]);
+    }
+}
@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hyper::body::HttpBody as _;
+use hyper::header::{ACCEPT, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, HOST};
+use hyper::{Body, Request as HyperRequest, Response as HyperResponse, StatusCode, Uri};
+use rustls::ClientConfig;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tonic::body::BoxBody;
+use tonic::transport::NamedService;
+use tonic::Status;
+use tower::Service;
+
+use crate::metrics;
+use crate::tenant::{TenantId, Tenants, TokenAuthority};
+
+
+/// Cached answers stop being kept past this many tokens; expired ones go first, then all.
+const MAX_CACHED: usize = 10_000;
+
+/// The largest introspection response read.
+const MAX_RESPONSE_BYTES: usize = 64 << 10;
+
+
+/// What to do with a token while the introspection endpoint can't be reached.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UnreachablePolicy {
+    /// Refuse the call with UNAVAILABLE.
+    Reject,
+    /// Accept the token if it's a JWT signed with the local secret (HS256) and not expired.
+    /// Opaque tokens are still refused.
+    LocalJwt,
+}
+
+impl FromStr for UnreachablePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(UnreachablePolicy::Reject),
+            "local-jwt" => Ok(UnreachablePolicy::LocalJwt),
+            other => Err(format!("unknown unreachable policy '{}', expected reject or local-jwt", other)),
+        }
+    }
+}
+
+
+#[derive(Clone)]
+pub struct IntrospectionConfig {
+    /// The OAuth2 token introspection endpoint (RFC 7662), http or https.
+    pub endpoint: Uri,
+    /// Sent with HTTP basic auth, as the resource server introspecting.
+    pub client_id: String,
+    pub client_secret: String,
+    /// The claim in the answer that names the tenant, like `tenant`.
+    pub tenant_claim: String,
+    /// How long an answer is trusted; never past the token's `exp`.
+    pub cache_ttl: Duration,
+    /// How long an inactive token, or a failure to reach the endpoint, is remembered.
+    pub negative_ttl: Duration,
+    pub timeout: Duration,
+    pub on_unreachable: UnreachablePolicy,
+    /// The key of `UnreachablePolicy::LocalJwt`.
+    pub jwt_secret: Option<Vec<u8>>,
+}
+
+/// Without the client secret and JWT key.
+impl fmt::Debug for IntrospectionConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IntrospectionConfig")
+            .field("endpoint", &self.endpoint)
+            .field("client_id", &self.client_id)
+            .field("tenant_claim", &self.tenant_claim)
+            .field("cache_ttl", &self.cache_ttl)
+            .field("negative_ttl", &self.negative_ttl)
+            .field("timeout", &self.timeout)
+            .field("on_unreachable", &self.on_unreachable)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Verdict {
+    Active { tenant: TenantId, subject: String },
+    Inactive,
+    Unreachable,
+}
+
+
+/// Checks opaque tokens with an identity provider's introspection endpoint, remembering the
+/// answers. tonic's interceptors can't wait, so the work is split: `Introspected`, in front of
+/// the service, introspects tokens it has no fresh answer for, and the interceptor only reads
+/// the answers, as the `TokenAuthority` of `Tenants`. Tokens are remembered by their SHA-256.
+pub struct Introspector {
+    config: IntrospectionConfig,
+    tls: Arc<ClientConfig>,
+    cache: Mutex<HashMap<[u8; 32], (Instant, Verdict)>>,
+}
+
+/// Without the TLS configuration, which has no `Debug`.
+impl fmt::Debug for Introspector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Introspector")
+            .field("endpoint", &self.config.endpoint)
+            .field("client_id", &self.config.client_id)
+            .field("on_unreachable", &self.config.on_unreachable)
+            .field("cached", &self.cache.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl Introspector {
+    /// Trusts the platform's root certificates for https endpoints.
+    pub fn new(config: IntrospectionConfig) -> Result<Self, String> {
+        let mut tls = ClientConfig::new();
+        tls.set_protocols(&[b"http/1.1".to_vec()]);
+        if config.endpoint.scheme_str() == Some("https") {
+            let roots = match rustls_native_certs::load_native_certs() {
+                Ok(roots) => roots,
+                Err((Some(roots), _)) => roots,
+                Err((None, e)) => return Err(format!("failed to load native root certificates: {}", e)),
+            };
+            tls.root_store.roots.extend(roots.roots);
+        }
+        if config.on_unreachable == UnreachablePolicy::LocalJwt && config.jwt_secret.is_none() {
+            return Err("falling back to local JWTs needs a JWT secret".to_string());
+        }
+        Ok(Introspector { config, tls: Arc::new(tls), cache: Mutex::default() })
+    }
+
+    fn cached(&self, token: &str) -> Option<Verdict> {
+        let cache = self.cache.lock().unwrap();
+        let (expires, verdict) = cache.get(&key(token))?;
+        if *expires > Instant::now() { Some(verdict.clone()) } else { None }
+    }
+
+    fn remember(&self, token: &str, verdict: Verdict, ttl: Duration) {
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_CACHED {
+            cache.retain(|_, (expires, _)| *expires > now);
+            if cache.len() >= MAX_CACHED {
+                cache.clear();
+            }
+        }
+        cache.insert(key(token), (now + ttl, verdict));
+    }
+
+    /// Makes sure there's a fresh answer for `token`, asking the endpoint if there isn't.
+    pub async fn introspect(&self, token: &str) {
+        if self.cached(token).is_some() {
+            metrics::registry().counter("token_introspection_cache_hits_total", "Tokens checked against a remembered answer.", &[]).inc();
+            return;
+        }
+
+        let (verdict, ttl, outcome) = match tokio::time::timeout(self.config.timeout, self.ask(token)).await {
+            // RFC 7662 answers say `active`; JWT claims don't.
+            Ok(Ok(answer)) if answer.get("active") != Some(&Value::Bool(true)) => (Verdict::Inactive, self.config.negative_ttl, "inactive"),
+            Ok(Ok(answer)) => self.judge(&answer),
+            failed => {
+                let error = match failed {
+                    Ok(Err(e)) => e,
+                    _ => "timed out".to_string(),
+                };
+                tracing::warn!(endpoint = %self.config.endpoint, %error, "token introspection failed");
+                match self.config.jwt_secret.as_ref().filter(|_| self.config.on_unreachable == UnreachablePolicy::LocalJwt) {
+                    Some(secret) => match verify_hs256(token, secret) {
+                        Some(claims) => {
+                            let (verdict, ttl, _) = self.judge(&claims);
+                            (verdict, ttl.min(self.config.negative_ttl), "local_jwt")
+                        },
+                        None => (Verdict::Unreachable, self.config.negative_ttl, "unreachable"),
+                    },
+                    None => (Verdict::Unreachable, self.config.negative_ttl, "unreachable"),
+                }
+            },
+        };
+        metrics::registry()
+            .counter("token_introspections_total", "Tokens checked with the introspection endpoint, by outcome.", &[("outcome", outcome)])
+            .inc();
+        self.remember(token, verdict, ttl);
+    }
+
+    /// The verdict on the claims of an active token, from the endpoint or a JWT, how long to
+    /// keep it and the outcome it's counted as.
+    fn judge(&self, claims: &Value) -> (Verdict, Duration, &'static str) {
+        let inactive = (Verdict::Inactive, self.config.negative_ttl, "inactive");
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let ttl = match claims.get("exp").and_then(Value::as_u64) {
+            Some(exp) if exp <= now => return inactive,
+            Some(exp) => self.config.cache_ttl.min(Duration::from_secs(exp - now)),
+            None => self.config.cache_ttl,
+        };
+        if claims.get("nbf").and_then(Value::as_u64).is_some_and(|nbf| nbf > now) {
+            return inactive;
+        }
+
+        let tenant = match claims.get(&self.config.tenant_claim).and_then(Value::as_str).map(TenantId::new) {
+            Some(Ok(tenant)) => tenant,
+            _ => {
+                tracing::warn!(claim = %self.config.tenant_claim, "introspected token names no valid tenant");
+                return inactive;
+            },
+        };
+        let subject = claims.get("sub").or_else(|| claims.get("client_id")).and_then(Value::as_str).unwrap_or("unknown");
+        (Verdict::Active { tenant, subject: format!("introspected:{}", subject) }, ttl, "active")
+    }
+
+    /// Posts the token to the endpoint, on a connection of its own.
+    async fn ask(&self, token: &str) -> Result<Value, String> {
+        let endpoint = &self.config.endpoint;
+        let https = endpoint.scheme_str() == Some("https");
+        let host = endpoint.host().ok_or("the endpoint has no host")?;
+        let port = endpoint.port_u16().unwrap_or(if https { 443 } else { 80 });
+        let authority = endpoint.authority().map_or(host, |authority| authority.as_str());
+        let path = endpoint.path_and_query().map_or("/", |path| path.as_str());
+
+        let credentials = base64::encode(format!("{}:{}", self.config.client_id, self.config.client_secret));
+        let request = HyperRequest::post(path)
+            .header(HOST, authority)
+            .header(AUTHORIZATION, format!("Basic {}", credentials))
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(ACCEPT, "application/json")
+            .body(Body::from(format!("token={}&token_type_hint=access_token", form_encode(token))))
+            .map_err(|e| e.to_string())?;
+
+        let stream = TcpStream::connect((host, port)).await.map_err(|e| e.to_string())?;
+        let response = if https {
+            let name = webpki::DNSNameRef::try_from_ascii_str(host).map_err(|_| format!("invalid host name {}", host))?;
+            let stream = TlsConnector::from(self.tls.clone()).connect(name, stream).await.map_err(|e| e.to_string())?;
+            send(stream, request).await?
+        } else {
+            send(stream, request).await?
+        };
+
+        if response.status() != StatusCode::OK {
+            return Err(format!("the endpoint answered {}", response.status()));
+        }
+        let body = read_limited(response, MAX_RESPONSE_BYTES).await?;
+        serde_json::from_slice(&body).map_err(|e| format!("the answer isn't JSON: {}", e))
+    }
+}
+
+/// The body of `response`, refused as soon as it's known to be over `limit` bytes: by its
+/// `content-length`, or a chunk at a time, so an endpoint that keeps sending is cut off there.
+async fn read_limited(response: HyperResponse<Body>, limit: usize) -> Result<Vec<u8>, String> {
+    let too_large = || format!("the answer is over {} bytes", limit);
+    let declared = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+    if declared.is_some_and(|length| length > limit as u64) {
+        return Err(too_large());
+    }
+
+    let mut body = response.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+impl TokenAuthority for Introspector {
+    fn authenticate(&self, token: &str) -> Result<Option<(TenantId, String)>, Status> {
+        match self.cached(token) {
+            Some(Verdict::Active { tenant, subject }) => Ok(Some((tenant, subject))),
+            Some(Verdict::Unreachable) => Err(Status::unavailable("the token can't be checked right now")),
+            Some(Verdict::Inactive) | None => Ok(None),
+        }
+    }
+}
+
+async fn send<T>(io: T, request: HyperRequest<Body>) -> Result<HyperResponse<Body>, String>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::handshake(io).await.map_err(|e| e.to_string())?;
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+    sender.send_request(request).await.map_err(|e| e.to_string())
+}
+
+fn key(token: &str) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&Sha256::digest(token.as_bytes()));
+    key
+}
+
+/// `application/x-www-form-urlencoded`, for a token.
+fn form_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+
+/// HMAC-SHA256, as RFC 2104 has it.
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.iter().map(|byte| byte ^ 0x36).collect::<Vec<_>>());
+    inner.update(message);
+    let mut outer = Sha256::new();
+    outer.update(block.iter().map(|byte| byte ^ 0x5c).collect::<Vec<_>>());
+    outer.update(inner.finalize());
+    let mut mac = [0u8; 32];
+    mac.copy_from_slice(&outer.finalize());
+    mac
+}
+
+/// The claims of an HS256 JWT signed with `secret`. Expiry is left to `Introspector::judge`.
+fn verify_hs256(token: &str, secret: &[u8]) -> Option<Value> {
+    let mut parts = token.split('.');
+    let (header, payload, signature) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let decode = |part: &str| base64::decode_config(part, base64::URL_SAFE_NO_PAD).ok();
+    let header: Value = serde_json::from_slice(&decode(header)?).ok()?;
+    if header.get("alg").and_then(Value::as_str) != Some("HS256") {
+        return None;
+    }
+    let expected = hmac_sha256(secret, &token.as_bytes()[..token.len() - signature.len() - 1]);
+    let signature = decode(signature)?;
+    // Compared in full either way, so the time taken doesn't tell how much matched.
+    let differences = expected.iter().zip(&signature).fold(signature.len() ^ expected.len(), |differences, (a, b)| differences | (a ^ b) as usize);
+    if differences != 0 {
+        return None;
+    }
+    serde_json::from_slice(&decode(payload)?).ok()
+}
+
+
+/// Introspects the bearer token of each call that isn't a tenant's own before passing the call
+/// on, so the interceptor behind finds the answer (see `Introspector`). Without an
+/// introspector, calls pass straight through.
+#[derive(Debug, Clone)]
+pub struct Introspected<S> {
+    pub inner: S,
+    pub introspector: Option<Arc<Introspector>>,
+    pub tenants: Arc<Tenants>,
+}
+
+impl<S> Service<HyperRequest<Body>> for Introspected<S>
+    where
+        S: Service<HyperRequest<Body>, Response = HyperResponse<BoxBody>>
+        + NamedService
+        + Clone
+        + Send
+        + 'static,
+        S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: HyperRequest<Body>) -> Self::Future {
+        let token = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .filter(|token| self.tenants.authenticate(token).is_none())
+            .map(str::to_string);
+        let pending = self.introspector.clone().zip(token);
+
+        let mut svc = self.inner.clone();
+        Box::pin(async move {
+            if let Some((introspector, token)) = pending {
+                introspector.introspect(&token).await;
+            }
+            svc.call(request).await
+        })
+    }
+}
+
+impl<S: NamedService> NamedService for Introspected<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::StreamExt;
+
+    fn response(body: Body) -> HyperResponse<Body> {
+        HyperResponse::new(body)
+    }
+
+    #[tokio::test]
+    async fn small_answers_are_read() {
+        let body = read_limited(response(Body::from(r#"{"active":true}"#)), 100).await.unwrap();
+        assert_eq!(body, br#"{"active":true}"#);
+    }
+
+    #[tokio::test]
+    async fn declared_length_over_the_limit_is_refused() {
+        let mut declared = response(Body::from("{}"));
+        declared.headers_mut().insert(CONTENT_LENGTH, "1000".parse().unwrap());
+        assert!(read_limited(declared, 100).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn endless_answers_are_cut_off() {
+        let chunks = futures::stream::repeat(Bytes::from_static(&[b' '; 1024])).map(Ok::<_, std::io::Error>);
+        let error = read_limited(response(Body::wrap_stream(chunks)), 64 << 10).await.unwrap_err();
+        assert_eq!(error, "the answer is over 65536 bytes");
+    }
+}
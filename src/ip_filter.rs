@@ -0,0 +1,253 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use hyper::HeaderMap;
+use tonic::{Request, Status};
+
+use crate::metrics::{self, Counter};
+
+
+/// Set by proxies in front of the server: the client's address, followed by that of each proxy
+/// the request passed before the last one.
+pub const FORWARDED_FOR: &str = "x-forwarded-for";
+
+
+/// A range of addresses like `10.0.0.0/8` or `2001:db8::/32`. A bare address is a range of one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, canonical(address)) {
+            (IpAddr::V4(network), IpAddr::V4(address)) =>
+                masked(u32::from(address) as u128, self.prefix, 32) == u32::from(network) as u128,
+            (IpAddr::V6(network), IpAddr::V6(address)) =>
+                masked(u128::from(address), self.prefix, 128) == u128::from(network),
+            _ => false,
+        }
+    }
+}
+
+/// The address with the bits past `prefix` cleared, for an address `bits` long.
+fn masked(address: u128, prefix: u8, bits: u8) -> u128 {
+    if prefix == 0 {
+        return 0;
+    }
+    let mask = !0u128 << (bits - prefix);
+    address & mask & (!0u128 >> (128 - bits as u32))
+}
+
+/// IPv4 clients of a dual-stack listener show up as IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`);
+/// those are matched as the IPv4 address they are.
+fn canonical(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, high, low] =>
+                IpAddr::V4(Ipv4Addr::from(((high as u32) << 16) | low as u32)),
+            _ => address,
+        },
+        IpAddr::V4(_) => address,
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match s.find('/') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        let address = canonical(address.parse::<IpAddr>().map_err(|_| format!("'{}' isn't an IP address or CIDR range", s))?);
+        let bits = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(prefix) if prefix <= bits => prefix,
+                _ => return Err(format!("'{}' has a prefix length outside 0 to {}", s, bits)),
+            },
+            None => bits,
+        };
+
+        // Host bits are dropped, so `10.1.2.3/8` is `10.0.0.0/8`.
+        let network = match address {
+            IpAddr::V4(v4) => IpAddr::V4(Ipv4Addr::from(masked(u32::from(v4) as u128, prefix, 32) as u32)),
+            IpAddr::V6(v6) => IpAddr::V6(Ipv6Addr::from(masked(u128::from(v6), prefix, 128))),
+        };
+        Ok(Cidr { network, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+
+/// Which clients may connect. Denied ranges win over allowed ones; with no allowed ranges every
+/// address that isn't denied is allowed.
+///
+/// Connections from `trusted_proxies` are let through when they're accepted, and each of their
+/// requests is judged by the client address in its `x-forwarded-for` instead.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IpRules {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+    pub trusted_proxies: Vec<Cidr>,
+}
+
+impl IpRules {
+    /// Parses rules with one `allow`, `deny` or `trust` and a range per line, like
+    /// `deny 10.0.0.0/8`. Empty lines and `#` comments are skipped.
+    pub fn parse(text: &str) -> Result<IpRules, String> {
+        let mut rules = IpRules::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let (kind, range) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+            if words.next().is_some() {
+                return Err(format!("line {}: expected a rule and one range", i + 1));
+            }
+            let range = range.parse().map_err(|e| format!("line {}: {}", i + 1, e))?;
+            match kind {
+                "allow" => rules.allow.push(range),
+                "deny"  => rules.deny.push(range),
+                "trust" => rules.trusted_proxies.push(range),
+                other   => return Err(format!("line {}: unknown rule '{}' (expected allow, deny or trust)", i + 1, other)),
+            }
+        }
+        Ok(rules)
+    }
+
+    pub fn allows(&self, address: IpAddr) -> bool {
+        if self.deny.iter().any(|range| range.contains(address)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|range| range.contains(address))
+    }
+
+    fn is_trusted(&self, address: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|range| range.contains(address))
+    }
+
+    /// The client behind `peer`: `peer` itself unless it's a trusted proxy, otherwise the last
+    /// address in `forwarded_for` that isn't one. `None` if a trusted proxy sent an address that
+    /// doesn't parse, since the client can't be told then.
+    pub fn client(&self, peer: IpAddr, forwarded_for: &[&str]) -> Option<IpAddr> {
+        if !self.is_trusted(peer) {
+            return Some(peer);
+        }
+
+        // Proxies append, so the right end is the one the nearest proxy wrote.
+        let mut client = peer;
+        for entry in forwarded_for.iter().rev().flat_map(|value| value.rsplit(',')) {
+            client = entry.trim().parse().ok()?;
+            if !self.is_trusted(client) {
+                break;
+            }
+        }
+        Some(client)
+    }
+}
+
+
+/// The IP rules of the servers, shared by all of them and replaceable while serving.
+#[derive(Debug)]
+pub struct IpFilter {
+    rules: RwLock<IpRules>,
+    rejected_connections: Arc<Counter>,
+    rejected_calls: Arc<Counter>,
+}
+
+impl Default for IpFilter {
+    fn default() -> Self {
+        IpFilter::new(IpRules::default())
+    }
+}
+
+impl IpFilter {
+    pub fn new(rules: IpRules) -> Self {
+        let registry = metrics::registry();
+        let help = "Connections and calls refused by the IP allow and deny lists.";
+        IpFilter {
+            rules: RwLock::new(rules),
+            rejected_connections: registry.counter("ip_filter_rejected_total", help, &[("stage", "accept")]),
+            rejected_calls: registry.counter("ip_filter_rejected_total", help, &[("stage", "request")]),
+        }
+    }
+
+    pub fn rules(&self) -> IpRules {
+        self.rules.read().unwrap().clone()
+    }
+
+    /// Replaces the rules. Open connections are judged by them from their next request on.
+    pub fn set_rules(&self, rules: IpRules) {
+        *self.rules.write().unwrap() = rules;
+    }
+
+    pub fn rejected_count(&self) -> u64 {
+        self.rejected_connections.get() + self.rejected_calls.get()
+    }
+
+    /// Whether to keep a connection just accepted from `peer`, before reading anything from it.
+    pub fn accepts(&self, peer: SocketAddr) -> bool {
+        let rules = self.rules.read().unwrap();
+        let accepted = rules.is_trusted(peer.ip()) || rules.allows(peer.ip());
+        if !accepted {
+            self.rejected_connections.inc();
+            tracing::debug!(%peer, "connection refused by the IP filter");
+        }
+        accepted
+    }
+
+    /// Whether a request from `peer` with these headers may go on, judged by the client behind
+    /// any trusted proxies. Requests without a peer address aren't filtered.
+    pub fn allows_request(&self, peer: Option<SocketAddr>, headers: &HeaderMap) -> bool {
+        let forwarded_for: Vec<&str> = headers
+            .get_all(FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+        self.allows_client(peer, &forwarded_for)
+    }
+
+    /// `allows_request` for gRPC calls, failing with PERMISSION_DENIED.
+    pub fn check<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let forwarded_for: Vec<&str> = request
+            .metadata()
+            .get_all(FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+
+        if self.allows_client(request.remote_addr(), &forwarded_for) {
+            Ok(())
+        } else {
+            Err(Status::permission_denied("address not allowed"))
+        }
+    }
+
+    fn allows_client(&self, peer: Option<SocketAddr>, forwarded_for: &[&str]) -> bool {
+        let peer = match peer {
+            Some(peer) => peer.ip(),
+            None => return true,
+        };
+
+        let rules = self.rules.read().unwrap();
+        let allowed = rules.client(peer, forwarded_for).is_some_and(|client| rules.allows(client));
+        if !allowed {
+            self.rejected_calls.inc();
+            tracing::debug!(%peer, ?forwarded_for, "request refused by the IP filter");
+        }
+        allowed
+    }
+}
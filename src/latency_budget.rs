@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::admin::Quota;
+use crate::geo::Bounds;
+use crate::metrics;
+use crate::route_guide::route_guide_server::RouteGuide;
+use crate::route_guide::{ChangeEvent, ExportRequest, Feature, ImportSummary, Point, PointWithTimestamp, Rectangle, ReplicateRequest, Room, RoomMembers, RouteNote, RouteSummary, TimeRange};
+use crate::request_context::RequestContext;
+
+
+type BoxStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + Sync + 'static>>;
+
+
+/// How long a method may take, from the call until its last response message, like
+/// `ListFeatures=1000` for a second. From `--latency-budget`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyBudget {
+    pub method: String,
+    pub budget: Duration,
+}
+
+impl FromStr for LatencyBudget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let usage = || format!("'{}' isn't a budget like GetFeature=100 (in milliseconds)", s);
+        let i = s.find('=').ok_or_else(usage)?;
+        let (method, millis) = (s[..i].trim(), s[i + 1..].trim());
+        if method.is_empty() {
+            return Err(usage());
+        }
+        let millis = millis.parse::<u64>().map_err(|_| usage())?;
+        Ok(LatencyBudget { method: method.to_string(), budget: Duration::from_millis(millis) })
+    }
+}
+
+/// The budgets of the RouteGuide methods. RecordRoute, RouteChat and ImportFeatures have none by
+/// default: they last as long as the client keeps sending. ExportFeatures and Replicate are never
+/// watched, since they last as long as the client takes to read them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyBudgets {
+    budgets: HashMap<String, Duration>,
+}
+
+impl Default for LatencyBudgets {
+    fn default() -> Self {
+        let budgets = [
+            ("GetFeature", 100),
+            ("ListFeatures", 1000),
+            ("AddFeature", 200),
+            ("DeleteFeature", 200),
+            ("GetNotesAt", 500),
+            ("GetFeatureAsOf", 1000),
+            ("ListChanges", 2000),
+            ("ListRoomMembers", 100),
+            ("GetQuota", 100),
+        ];
+        LatencyBudgets {
+            budgets: budgets.iter().map(|&(method, millis)| (method.to_string(), Duration::from_millis(millis))).collect(),
+        }
+    }
+}
+
+impl LatencyBudgets {
+    /// The defaults, with these budgets replacing theirs. A budget of 0 turns the method's off.
+    pub fn with(budgets: &[LatencyBudget]) -> Self {
+        let mut merged = LatencyBudgets::default();
+        for budget in budgets {
+            if budget.budget == Duration::from_secs(0) {
+                merged.budgets.remove(&budget.method);
+            } else {
+                merged.budgets.insert(budget.method.clone(), budget.budget);
+            }
+        }
+        merged
+    }
+
+    pub fn get(&self, method: &str) -> Option<Duration> {
+        self.budgets.get(method).copied()
+    }
+}
+
+
+/// What the slow-RPC log says about a call besides its timing.
+#[derive(Debug, Clone, Default)]
+struct CallSummary {
+    tenant: String,
+    request_id: String,
+    point: Option<(i32, i32)>,
+    area_km2: Option<f64>,
+    /// Messages streamed, in whichever direction the method streams.
+    messages: Option<u64>,
+}
+
+/// Times one call. If it went over its budget when dropped, it's logged and counted.
+struct Watch {
+    method: &'static str,
+    budget: Option<Duration>,
+    started: Instant,
+    summary: CallSummary,
+}
+
+impl Watch {
+    fn start<T>(budgets: &LatencyBudgets, method: &'static str, request: &Request<T>) -> Self {
+        let summary = match RequestContext::of(request) {
+            Ok(context) => CallSummary { tenant: context.tenant.as_str().to_string(), request_id: context.request_id, ..CallSummary::default() },
+            Err(_) => CallSummary::default(),
+        };
+        Watch { method, budget: budgets.get(method), started: Instant::now(), summary }
+    }
+
+    fn at(mut self, point: Option<&Point>) -> Self {
+        self.summary.point = point.map(|point| (point.latitude, point.longitude));
+        self
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return,
+        };
+        let elapsed = self.started.elapsed();
+        if elapsed <= budget {
+            return;
+        }
+
+        metrics::registry()
+            .counter("grpc_over_budget_total", "RouteGuide calls that took longer than their latency budget.", &[("method", self.method)])
+            .inc();
+        let summary = &self.summary;
+        tracing::warn!(
+            target: "slow_rpc",
+            method = self.method,
+            elapsed_ms = elapsed.as_millis() as u64,
+            budget_ms = budget.as_millis() as u64,
+            tenant = %summary.tenant,
+            request_id = %summary.request_id,
+            point = ?summary.point,
+            area_km2 = ?summary.area_km2,
+            messages = ?summary.messages,
+            "slow RPC"
+        );
+    }
+}
+
+/// The stream with the watch stopped at its end, counting its messages.
+fn watched<T: Send + Sync + Unpin + 'static>(stream: impl Stream<Item = Result<T, Status>> + Send + Sync + 'static, watch: Watch) -> BoxStream<T> {
+    Box::pin(async_stream::stream! {
+        let mut watch = watch;
+        futures::pin_mut!(stream);
+        let mut messages = 0;
+        while let Some(item) = stream.next().await {
+            messages += 1;
+            watch.summary.messages = Some(messages);
+            yield item;
+        }
+    })
+}
+
+/// The response with its message replaced, keeping the metadata.
+fn rewrapped<T, U>(response: Response<T>, wrap: impl FnOnce(T) -> U) -> Response<U> {
+    let metadata = response.metadata().clone();
+    let mut rewrapped = Response::new(wrap(response.into_inner()));
+    *rewrapped.metadata_mut() = metadata;
+    rewrapped
+}
+
+
+/// Logs RouteGuide calls that take longer than their budget, under the `slow_rpc` target, and
+/// counts them in `grpc_over_budget_total`.
+///
+/// Streaming responses are timed until their last message, so a client that reads slowly makes
+/// the call slow too.
+#[derive(Debug)]
+pub struct Budgeted<S> {
+    pub inner: S,
+    pub budgets: Arc<LatencyBudgets>,
+}
+
+#[tonic::async_trait]
+impl<S: RouteGuide> RouteGuide for Budgeted<S> {
+    type ListFeaturesStream = BoxStream<Feature>;
+    type RouteChatStream = S::RouteChatStream;
+    type GetNotesAtStream = BoxStream<RouteNote>;
+    type ListChangesStream = BoxStream<ChangeEvent>;
+    type ExportFeaturesStream = S::ExportFeaturesStream;
+    type ReplicateStream = S::ReplicateStream;
+    type WatchConfigStream = S::WatchConfigStream;
+
+    async fn get_feature(&self, request: Request<Point>) -> Result<Response<Feature>, Status> {
+        let _watch = Watch::start(&self.budgets, "GetFeature", &request).at(Some(request.get_ref()));
+        self.inner.get_feature(request).await
+    }
+
+    async fn list_features(&self, request: Request<Rectangle>) -> Result<Response<Self::ListFeaturesStream>, Status> {
+        let mut watch = Watch::start(&self.budgets, "ListFeatures", &request);
+        watch.summary.area_km2 = Bounds::of(request.get_ref()).map(|bounds| bounds.area() / 1e6);
+        let response = self.inner.list_features(request).await?;
+        Ok(rewrapped(response, |stream| watched(stream, watch)))
+    }
+
+    async fn record_route(&self, request: Request<Streaming<Point>>) -> Result<Response<RouteSummary>, Status> {
+        let mut watch = Watch::start(&self.budgets, "RecordRoute", &request);
+        let response = self.inner.record_route(request).await?;
+        watch.summary.messages = Some(response.get_ref().point_count as u64);
+        Ok(response)
+    }
+
+    async fn route_chat(&self, request: Request<Streaming<RouteNote>>) -> Result<Response<Self::RouteChatStream>, Status> {
+        self.inner.route_chat(request).await
+    }
+
+    async fn add_feature(&self, request: Request<Feature>) -> Result<Response<Feature>, Status> {
+        let _watch = Watch::start(&self.budgets, "AddFeature", &request).at(request.get_ref().location.as_ref());
+        self.inner.add_feature(request).await
+    }
+
+    async fn delete_feature(&self, request: Request<Point>) -> Result<Response<Feature>, Status> {
+        let _watch = Watch::start(&self.budgets, "DeleteFeature", &request).at(Some(request.get_ref()));
+        self.inner.delete_feature(request).await
+    }
+
+    async fn get_notes_at(&self, request: Request<Point>) -> Result<Response<Self::GetNotesAtStream>, Status> {
+        let watch = Watch::start(&self.budgets, "GetNotesAt", &request).at(Some(request.get_ref()));
+        let response = self.inner.get_notes_at(request).await?;
+        Ok(rewrapped(response, |stream| watched(stream, watch)))
+    }
+
+    async fn import_features(&self, request: Request<Streaming<Feature>>) -> Result<Response<ImportSummary>, Status> {
+        let mut watch = Watch::start(&self.budgets, "ImportFeatures", &request);
+        let response = self.inner.import_features(request).await?;
+        let summary = response.get_ref();
+        watch.summary.messages = Some((summary.inserted + summary.replaced + summary.skipped + summary.failed) as u64);
+        Ok(response)
+    }
+
+    async fn get_feature_as_of(&self, request: Request<PointWithTimestamp>) -> Result<Response<Feature>, Status> {
+        let _watch = Watch::start(&self.budgets, "GetFeatureAsOf", &request).at(request.get_ref().point.as_ref());
+        self.inner.get_feature_as_of(request).await
+    }
+
+    async fn list_changes(&self, request: Request<TimeRange>) -> Result<Response<Self::ListChangesStream>, Status> {
+        let watch = Watch::start(&self.budgets, "ListChanges", &request);
+        let response = self.inner.list_changes(request).await?;
+        Ok(rewrapped(response, |stream| watched(stream, watch)))
+    }
+
+    async fn export_features(&self, request: Request<ExportRequest>) -> Result<Response<Self::ExportFeaturesStream>, Status> {
+        self.inner.export_features(request).await
+    }
+
+    async fn replicate(&self, request: Request<ReplicateRequest>) -> Result<Response<Self::ReplicateStream>, Status> {
+        self.inner.replicate(request).await
+    }
+
+    async fn watch_config(&self, request: Request<()>) -> Result<Response<Self::WatchConfigStream>, Status> {
+        self.inner.watch_config(request).await
+    }
+
+    async fn list_room_members(&self, request: Request<Room>) -> Result<Response<RoomMembers>, Status> {
+        let _watch = Watch::start(&self.budgets, "ListRoomMembers", &request);
+        self.inner.list_room_members(request).await
+    }
+
+    async fn get_quota(&self, request: Request<()>) -> Result<Response<Quota>, Status> {
+        let _watch = Watch::start(&self.budgets, "GetQuota", &request);
+        self.inner.get_quota(request).await
+    }
+}
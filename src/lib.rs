@@ -0,0 +1,134 @@
+//! The route guide: a gRPC service for features on a map, a REST gateway in front of it, and a
+//! client.
+//!
+//! Most of it is behind cargo features, so a client doesn't compile the server and the server
+//! doesn't compile the command line tools:
+//!
+//! - `server`: the RouteGuide and admin services and everything they're built from.
+//! - `rest`: the REST gateway and the admin page.
+//! - `graphql`: a GraphQL endpoint on the gateway, for the same features.
+//! - `client`: load balancing, DNS discovery, proxies, caching and compression for RouteGuide
+//!   clients.
+//! - `tls`: certificate pinning for clients and mutual TLS for the server.
+//! - `metrics`: serving `GET /metrics`. Metrics are collected either way.
+//! - `sqlite`: chat history in an SQLite database.
+//! - `embedded-db`: the feature database compiled into the server, for running without files.
+//! - `minimal-server`: a RouteGuide server with only the embedded features, which needs none of
+//!   the optional dependencies, for small static builds.
+//! - `cli`: what the example binaries need on top (argument parsing, files, signals, line
+//!   editing).
+
+// For the `stream!` of replica::replicate.
+#![recursion_limit = "256"]
+// Handlers and interceptors fail with tonic's `Status`, which is large; boxing it everywhere
+// would fight tonic's own signatures.
+#![allow(clippy::result_large_err)]
+
+// So the generated routeguide.v2 can name the admin messages it returns (see build.rs).
+extern crate self as rust_server;
+
+// Generated from the .proto files by build.rs.
+pub mod route_guide {
+    tonic::include_proto!("routeguide.v2"); /* The string must match the proto package name */
+
+    /// Descriptors of every proto in proto/, written by build.rs.
+    pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/descriptor_set.bin"));
+}
+pub mod admin {tonic::include_proto!("admin");}
+pub mod google_rpc {tonic::include_proto!("google.rpc");}
+
+// Shared by the client and server.
+pub mod attributes;
+pub mod chat;
+pub mod codec;
+pub mod conditional;
+pub mod export;
+pub mod geo;
+pub mod http;
+pub mod message_ext;
+pub mod metrics;
+pub mod output;
+pub mod proto_json;
+pub mod scan_report;
+pub mod streaming;
+
+#[cfg(any(feature = "rest", feature = "metrics"))]
+pub mod compression;
+#[cfg(any(feature = "embedded-db", feature = "minimal-server"))]
+pub mod embedded;
+#[cfg(any(feature = "client", feature = "server"))]
+pub mod grpc_compression;
+#[cfg(feature = "minimal-server")]
+pub mod minimal;
+
+#[cfg(feature = "server")] pub mod audit;
+#[cfg(feature = "server")] pub mod binary_db;
+#[cfg(feature = "server")] pub mod chat_hub;
+#[cfg(feature = "server")] pub mod client_config;
+#[cfg(feature = "server")] pub mod connections;
+#[cfg(feature = "server")] pub mod data;
+#[cfg(feature = "server")] pub mod event_bus;
+#[cfg(feature = "server")] pub mod feature_events;
+#[cfg(feature = "server")] pub mod field_mask;
+#[cfg(feature = "server")] pub mod history;
+#[cfg(feature = "server")] pub mod hooks;
+#[cfg(feature = "server")] pub mod i18n;
+#[cfg(feature = "server")] pub mod idempotency;
+#[cfg(feature = "server")] pub mod ids;
+#[cfg(feature = "server")] pub mod import;
+#[cfg(feature = "server")] pub mod index;
+#[cfg(all(feature = "server", feature = "tls"))] pub mod introspection;
+#[cfg(feature = "server")] pub mod ip_filter;
+#[cfg(feature = "server")] pub mod latency_budget;
+#[cfg(feature = "server")] pub mod lifecycle;
+#[cfg(feature = "server")] pub mod load_shed;
+#[cfg(feature = "server")] pub mod local;
+#[cfg(feature = "server")] pub mod log_filter;
+#[cfg(feature = "server")] pub mod message_metrics;
+#[cfg(feature = "server")] pub mod moderation;
+#[cfg(feature = "server")] pub mod note_store;
+#[cfg(all(feature = "server", feature = "tls"))] pub mod peer_identity;
+#[cfg(feature = "server")] pub mod panics;
+#[cfg(feature = "server")] pub mod policy;
+#[cfg(feature = "server")] pub mod quota;
+#[cfg(feature = "server")] pub mod recorder;
+#[cfg(feature = "server")] pub mod recording;
+#[cfg(feature = "server")] pub mod reload;
+#[cfg(feature = "server")] pub mod replica;
+#[cfg(feature = "server")] pub mod request_context;
+#[cfg(feature = "server")] pub mod route_spool;
+#[cfg(feature = "server")] pub mod runtime_metrics;
+#[cfg(feature = "server")] pub mod service_alias;
+#[cfg(feature = "server")] pub mod shutdown;
+#[cfg(feature = "server")] pub mod slo;
+#[cfg(feature = "server")] pub mod storage_metrics;
+#[cfg(feature = "server")] pub mod tasks;
+#[cfg(feature = "server")] pub mod tenant;
+#[cfg(feature = "server")] pub mod validation;
+#[cfg(feature = "server")] pub mod wal;
+#[cfg(feature = "server")] pub mod zip_stream;
+
+#[cfg(feature = "rest")] pub mod admin_ui;
+#[cfg(feature = "rest")] pub mod cors;
+#[cfg(feature = "rest")] pub mod gateway;
+#[cfg(feature = "graphql")] pub mod graphql;
+#[cfg(all(feature = "rest", feature = "tls"))] pub mod multiplex;
+#[cfg(feature = "rest")] pub mod openapi;
+
+#[cfg(feature = "client")] pub mod auto_tune;
+#[cfg(feature = "client")] pub mod balance;
+#[cfg(all(feature = "client", feature = "transport"))] pub mod blocking;
+#[cfg(feature = "client")] pub mod canary;
+#[cfg(feature = "client")] pub mod chat_session;
+#[cfg(feature = "client")] pub mod client_error;
+#[cfg(feature = "client")] pub mod client_metadata;
+#[cfg(all(feature = "client", feature = "tls"))] pub mod client_tls;
+#[cfg(feature = "client")] pub mod discovery;
+#[cfg(feature = "client")] pub mod feature_cache;
+#[cfg(feature = "client")] pub mod flow_control;
+#[cfg(feature = "client")] pub mod hedge;
+#[cfg(feature = "client")] pub mod paging;
+#[cfg(feature = "client")] pub mod proxy;
+#[cfg(feature = "client")] pub mod route_journal;
+#[cfg(feature = "client")] pub mod startup;
+#[cfg(feature = "client")] pub mod upload_progress;
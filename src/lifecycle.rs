@@ -0,0 +1,135 @@
+use std::convert::Infallible;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use tokio::sync::watch;
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+
+
+/// Where the server is in its life. States only move forward.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum State {
+    /// Starting up; the feature database isn't loaded yet.
+    Loading,
+    Serving,
+    /// Shutting down: calls in flight finish, but no new traffic should be sent.
+    Draining,
+    Stopped,
+}
+
+impl State {
+    fn name(self) -> &'static str {
+        match self {
+            State::Loading  => "loading",
+            State::Serving  => "serving",
+            State::Draining => "draining",
+            State::Stopped  => "stopped",
+        }
+    }
+
+    pub fn is_ready(self) -> bool {
+        self == State::Serving
+    }
+
+    pub fn is_alive(self) -> bool {
+        self != State::Stopped
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+
+/// The server's state, mirrored to the gRPC health service for the given services.
+pub struct Lifecycle {
+    sender: Mutex<watch::Sender<State>>,
+    receiver: watch::Receiver<State>,
+    health: HealthReporter,
+    services: Vec<&'static str>,
+}
+
+impl Lifecycle {
+    pub async fn new(health: HealthReporter, services: Vec<&'static str>) -> Self {
+        let (sender, receiver) = watch::channel(State::Loading);
+        let lifecycle = Lifecycle { sender: Mutex::new(sender), receiver, health, services };
+        lifecycle.report(State::Loading).await;
+        lifecycle
+    }
+
+    pub fn state(&self) -> State {
+        *self.receiver.borrow()
+    }
+
+    /// Moves to `state`. Returns false, changing nothing, if the server is already there or past it.
+    pub async fn advance(&self, state: State) -> bool {
+        {
+            let sender = self.sender.lock().unwrap();
+            if *self.receiver.borrow() >= state {
+                return false;
+            }
+            // Only fails when there are no receivers, and `self` holds one.
+            let _ = sender.broadcast(state);
+        }
+
+        self.report(state).await;
+        true
+    }
+
+    async fn report(&self, state: State) {
+        let status = if state.is_ready() { ServingStatus::Serving } else { ServingStatus::NotServing };
+        let mut health = self.health.clone();
+        for service in &self.services {
+            health.set_service_status(*service, status).await;
+        }
+    }
+
+    /// Resolves once the server has reached `state` or gone past it.
+    pub async fn reached(&self, state: State) {
+        let mut receiver = self.receiver.clone();
+        while let Some(current) = receiver.recv().await {
+            if current >= state {
+                return;
+            }
+        }
+    }
+}
+
+
+async fn probe_service(lifecycle: Arc<Lifecycle>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let state = lifecycle.state();
+    let ok = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/livez")  => state.is_alive(),
+        (&Method::GET, "/readyz") => state.is_ready(),
+        _ => {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return Ok(response);
+        },
+    };
+
+    let mut response = Response::new(Body::from(format!("{}\n", state)));
+    if !ok {
+        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    }
+    Ok(response)
+}
+
+/// Serves `GET /livez` and `GET /readyz` on the address. Liveness holds until the server has
+/// stopped; readiness only while it's serving.
+pub async fn serve(address: SocketAddr, lifecycle: Arc<Lifecycle>) -> Result<(), hyper::Error> {
+    let make_service = make_service_fn(move |_conn| {
+        let lifecycle = lifecycle.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| probe_service(lifecycle.clone(), request)))
+        }
+    });
+
+    Server::bind(&address).serve(make_service).await
+}
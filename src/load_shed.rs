@@ -0,0 +1,191 @@
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use hyper::{Body, Request as HyperRequest, Response as HyperResponse};
+use tonic::body::BoxBody;
+use tonic::transport::NamedService;
+use tower::Service;
+
+use crate::metrics::{self, Counter, Gauge};
+
+
+/// How the concurrency limit adapts. Can be changed while serving with `set_config`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShedConfig {
+    pub enabled: bool,
+    pub min_limit: usize,
+    pub max_limit: usize,
+    /// Calls slower than this shrink the limit, faster ones grow it.
+    pub target_latency: Duration,
+}
+
+impl Default for ShedConfig {
+    fn default() -> Self {
+        ShedConfig { enabled: true, min_limit: 8, max_limit: 1000, target_latency: Duration::from_millis(250) }
+    }
+}
+
+
+#[derive(Debug)]
+struct LimiterState {
+    config: ShedConfig,
+    limit: f64,
+    in_flight: usize,
+}
+
+/// Estimates how many calls can run at once without latency going over the target: the limit
+/// grows by one every `limit` fast calls and shrinks by 10% on every slow one. Calls over the
+/// limit are rejected straight away instead of queueing.
+#[derive(Debug)]
+pub struct AdaptiveLimiter {
+    state: Mutex<LimiterState>,
+    shed: Arc<Counter>,
+    limit_gauge: Arc<Gauge>,
+    in_flight_gauge: Arc<Gauge>,
+}
+
+impl AdaptiveLimiter {
+    pub fn new(config: ShedConfig) -> Self {
+        let registry = metrics::registry();
+        let limiter = AdaptiveLimiter {
+            state: Mutex::new(LimiterState { config, limit: config.max_limit as f64, in_flight: 0 }),
+            shed: registry.counter("load_shed_rejected_total", "Calls rejected because the server was overloaded.", &[]),
+            limit_gauge: registry.gauge("load_shed_concurrency_limit", "Current estimate of how many calls can run at once.", &[]),
+            in_flight_gauge: registry.gauge("load_shed_in_flight", "Calls currently running.", &[]),
+        };
+        limiter.limit_gauge.set(config.max_limit as i64);
+        limiter
+    }
+
+    pub fn config(&self) -> ShedConfig {
+        self.state.lock().unwrap().config
+    }
+
+    /// Replaces the configuration, keeping the current limit if it's still within bounds.
+    pub fn set_config(&self, config: ShedConfig) {
+        let mut state = self.state.lock().unwrap();
+        state.config = config;
+        state.limit = state.limit.max(config.min_limit as f64).min(config.max_limit as f64);
+        self.limit_gauge.set(state.limit as i64);
+    }
+
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit as usize
+    }
+
+    pub fn shed_count(&self) -> u64 {
+        self.shed.get()
+    }
+
+    /// A permit for one call, or `None` if the call should be rejected.
+    pub fn try_acquire(self: &Arc<Self>) -> Option<Permit> {
+        let mut state = self.state.lock().unwrap();
+        if state.config.enabled && state.in_flight >= state.limit as usize {
+            self.shed.inc();
+            return None;
+        }
+
+        state.in_flight += 1;
+        self.in_flight_gauge.set(state.in_flight as i64);
+        Some(Permit { limiter: self.clone(), started: Instant::now(), finished: false })
+    }
+
+    fn release(&self, latency: Option<Duration>) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= 1;
+        self.in_flight_gauge.set(state.in_flight as i64);
+
+        if let Some(latency) = latency {
+            let config = state.config;
+            state.limit = if latency > config.target_latency {
+                state.limit * 0.9
+            } else {
+                state.limit + 1.0 / state.limit
+            };
+            state.limit = state.limit.max(config.min_limit as f64).min(config.max_limit as f64);
+            self.limit_gauge.set(state.limit as i64);
+        }
+    }
+}
+
+
+/// A running call. Finishing it feeds its latency to the limiter; dropping it unfinished (when
+/// the call is cancelled) only frees the slot.
+#[derive(Debug)]
+pub struct Permit {
+    limiter: Arc<AdaptiveLimiter>,
+    started: Instant,
+    finished: bool,
+}
+
+impl Permit {
+    pub fn finish(mut self) {
+        self.finished = true;
+        self.limiter.release(Some(self.started.elapsed()));
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.limiter.release(None);
+        }
+    }
+}
+
+
+/// The trailers-only answer for a rejected call.
+fn overloaded() -> HyperResponse<BoxBody> {
+    HyperResponse::builder()
+        .status(200)
+        .header("content-type", "application/grpc")
+        .header("grpc-status", "14")
+        .header("grpc-message", "server overloaded, retry later")
+        .body(BoxBody::empty())
+        .unwrap()
+}
+
+/// Rejects calls with UNAVAILABLE while `limiter` says the server is overloaded. Streaming calls
+/// count until their response starts, not until the stream ends.
+#[derive(Debug, Clone)]
+pub struct LoadShedService<S> {
+    pub inner: S,
+    pub limiter: Arc<AdaptiveLimiter>,
+}
+
+impl<S> Service<HyperRequest<Body>> for LoadShedService<S>
+    where
+        S: Service<HyperRequest<Body>, Response = HyperResponse<BoxBody>>
+        + NamedService
+        + Clone
+        + Send
+        + 'static,
+        S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: HyperRequest<Body>) -> Self::Future {
+        let permit = match self.limiter.try_acquire() {
+            Some(permit) => permit,
+            None => return Box::pin(async { Ok(overloaded()) }),
+        };
+
+        let mut svc = self.inner.clone();
+        Box::pin(async move {
+            let response = svc.call(request).await;
+            permit.finish();
+            response
+        })
+    }
+}
+
+impl<S: NamedService> NamedService for LoadShedService<S> {
+    const NAME: &'static str = S::NAME;
+}
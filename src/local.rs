@@ -0,0 +1,180 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use futures::future::{self, Ready};
+use futures::StreamExt;
+use hyper::{Body, Request, Response};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tonic::body::BoxBody;
+use tonic::codegen::StdError;
+use tonic::transport::server::Connected;
+use tonic::transport::{Channel, Endpoint, NamedService, Server, Uri};
+use tower::Service;
+
+
+/// How many bytes a `LocalStream` holds before writes wait for the other end to read.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+
+/// One direction of a `LocalStream`.
+#[derive(Debug, Default)]
+struct Pipe {
+    buffer: VecDeque<u8>,
+    /// The writer shut down or went away.
+    closed: bool,
+    reader: Option<Waker>,
+    writer: Option<Waker>,
+}
+
+impl Pipe {
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(waker) = self.reader.take() {
+            waker.wake();
+        }
+        if let Some(waker) = self.writer.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// One end of an in-memory connection; bytes written to it are read from the other end. Tokio
+/// 0.2 has no duplex stream of its own.
+#[derive(Debug)]
+pub struct LocalStream {
+    read: Arc<Mutex<Pipe>>,
+    write: Arc<Mutex<Pipe>>,
+}
+
+/// The two ends of a new in-memory connection.
+pub fn local_stream() -> (LocalStream, LocalStream) {
+    let (a, b) = (Arc::new(Mutex::new(Pipe::default())), Arc::new(Mutex::new(Pipe::default())));
+    (LocalStream { read: a.clone(), write: b.clone() }, LocalStream { read: b, write: a })
+}
+
+impl AsyncRead for LocalStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let mut pipe = self.read.lock().unwrap();
+        if pipe.buffer.is_empty() {
+            if pipe.closed {
+                return Poll::Ready(Ok(0));
+            }
+            pipe.reader = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let read = buf.len().min(pipe.buffer.len());
+        for (byte, slot) in pipe.buffer.drain(..read).zip(buf.iter_mut()) {
+            *slot = byte;
+        }
+        if let Some(waker) = pipe.writer.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(read))
+    }
+}
+
+impl AsyncWrite for LocalStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let mut pipe = self.write.lock().unwrap();
+        if pipe.closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        let room = PIPE_CAPACITY - pipe.buffer.len();
+        if room == 0 {
+            pipe.writer = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let written = buf.len().min(room);
+        pipe.buffer.extend(&buf[..written]);
+        if let Some(waker) = pipe.reader.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.write.lock().unwrap().close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for LocalStream {
+    fn drop(&mut self) {
+        self.write.lock().unwrap().close();
+        // So the other end's writes fail instead of filling a buffer no one reads.
+        self.read.lock().unwrap().close();
+    }
+}
+
+impl Connected for LocalStream {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+
+/// Connector for `Endpoint::connect_with_connector` that hands each connection's other end to
+/// the server `local_channel` started.
+#[derive(Debug, Clone)]
+struct LocalConnector {
+    connections: mpsc::UnboundedSender<LocalStream>,
+}
+
+impl Service<Uri> for LocalConnector {
+    type Response = LocalStream;
+    type Error = io::Error;
+    type Future = Ready<io::Result<LocalStream>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _uri: Uri) -> Self::Future {
+        let (client, server) = local_stream();
+        future::ready(match self.connections.send(server) {
+            Ok(()) => Ok(client),
+            Err(_) => Err(io::Error::new(io::ErrorKind::BrokenPipe, "the local server has stopped")),
+        })
+    }
+}
+
+/// A channel to `service`, served in this process over in-memory connections instead of TCP,
+/// for tests and for embedding the service in another program:
+///
+/// ```ignore
+/// let channel = local_channel(RouteGuideServer::new(service)).await?;
+/// let mut client = RouteGuideClient::new(channel);
+/// ```
+///
+/// The server runs on a task of its own until the channel and its clones are dropped. Calls
+/// have no peer address, so services that need one see `None`.
+pub async fn local_channel<S>(service: S) -> Result<Channel, tonic::transport::Error>
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>> + NamedService + Clone + Send + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<StdError> + Send,
+{
+    let (connections, incoming) = mpsc::unbounded_channel();
+    let server = Server::builder().add_service(service).serve_with_incoming(incoming.map(Ok::<_, io::Error>));
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            tracing::error!("local server error: {}", e);
+        }
+    });
+
+    // Never resolved; every connection is a new pipe to the server.
+    Endpoint::from_static("http://local.invalid")
+        .connect_with_connector(LocalConnector { connections })
+        .await
+}
@@ -0,0 +1,55 @@
+use std::sync::Mutex;
+
+use once_cell::sync::OnceCell;
+use tracing_subscriber::EnvFilter;
+
+
+/// The filter used when `RUST_LOG` isn't set.
+pub const DEFAULT_DIRECTIVES: &str = "info";
+
+
+struct LogFilter {
+    directives: Mutex<String>,
+    // The reload handle's type names the formatter, so it's kept behind a closure.
+    reload: Box<dyn Fn(EnvFilter) -> Result<(), String> + Send + Sync>,
+}
+
+static FILTER: OnceCell<LogFilter> = OnceCell::new();
+
+
+/// Installs the global `tracing` subscriber, logging to stderr with the directives in `RUST_LOG`,
+/// or `DEFAULT_DIRECTIVES`. The filter can be replaced later with `set`.
+pub fn init() -> Result<(), String> {
+    let directives = std::env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_DIRECTIVES.to_string());
+    let filter = EnvFilter::try_new(&directives).map_err(|e| format!("invalid RUST_LOG '{}': {}", directives, e))?;
+
+    let builder = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(filter)
+        .with_filter_reloading();
+    let handle = builder.reload_handle();
+    builder.try_init().map_err(|e| e.to_string())?;
+
+    let filter = LogFilter {
+        directives: Mutex::new(directives),
+        reload: Box::new(move |filter| handle.reload(filter).map_err(|e| e.to_string())),
+    };
+    FILTER.set(filter).map_err(|_| "the log filter is already installed".to_string())
+}
+
+/// The directives in effect, like `info,rust_server::chat_hub=debug`. `None` before `init`.
+pub fn current() -> Option<String> {
+    FILTER.get().map(|filter| filter.directives.lock().unwrap().clone())
+}
+
+/// Replaces the filter, in `EnvFilter` syntax. The old one stays if the directives don't parse.
+pub fn set(directives: &str) -> Result<(), String> {
+    let filter = FILTER.get().ok_or_else(|| "no log filter is installed".to_string())?;
+    let parsed = EnvFilter::try_new(directives).map_err(|e| format!("invalid directives '{}': {}", directives, e))?;
+
+    let mut current = filter.directives.lock().unwrap();
+    (filter.reload)(parsed)?;
+    tracing::info!(from = %*current, to = %directives, "log filter changed");
+    *current = directives.to_string();
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use crate::geo::{self, CORD_FACTOR, EARTH_RADIUS, MAX_LONGITUDE};
+use crate::route_guide::{Clustering, Feature, Point, Rectangle, RouteNote};
+
+
+/// The largest latitude, in E7 degrees.
+const MAX_LATITUDE: i32 = 900_000_000;
+
+
+/// Degrees for points, so code using the messages needn't do E7 arithmetic itself.
+impl Point {
+    /// The point at `latitude` and `longitude` in degrees, rounded to E7. The latitude is clamped
+    /// to ±90° and the longitude wrapped into ±180°, so arithmetic on degrees can't leave the
+    /// map.
+    pub fn from_degrees(latitude: f64, longitude: f64) -> Point {
+        let latitude = (latitude * CORD_FACTOR).round().max(-MAX_LATITUDE as f64).min(MAX_LATITUDE as f64) as i32;
+        let longitude = wrap_longitude((longitude * CORD_FACTOR).round() as i64);
+        Point { latitude, longitude, read_mask: None }
+    }
+
+    pub fn latitude_degrees(&self) -> f64 {
+        self.latitude as f64 / CORD_FACTOR
+    }
+
+    pub fn longitude_degrees(&self) -> f64 {
+        self.longitude as f64 / CORD_FACTOR
+    }
+
+    /// Latitude and longitude, in degrees.
+    pub fn degrees(&self) -> (f64, f64) {
+        (self.latitude_degrees(), self.longitude_degrees())
+    }
+
+    /// In meters, as `geo::distance`.
+    pub fn distance_to(&self, other: &Point) -> f64 {
+        geo::distance(self, other)
+    }
+}
+
+/// Into ±180°. 180° itself stays as it is, rather than becoming -180°, the same meridian, so the
+/// east edge of a rectangle reaching the antimeridian stays east.
+fn wrap_longitude(longitude: i64) -> i32 {
+    let max = MAX_LONGITUDE as i64;
+    let wrapped = (longitude + max).rem_euclid(2 * max) - max;
+    if wrapped == -max && longitude > 0 {
+        return MAX_LONGITUDE;
+    }
+    wrapped as i32
+}
+
+
+impl Rectangle {
+    /// From the western corner `lo` to the eastern one `hi`, as `geo::Bounds::of` reads them.
+    pub fn new(lo: Point, hi: Point) -> Rectangle {
+        Rectangle { lo: Some(lo), hi: Some(hi), ..Rectangle::default() }
+    }
+
+    /// The smallest rectangle holding everything within `radius_m` meters of `center`. Near
+    /// the antimeridian it crosses it; reaching a pole, it spans every longitude.
+    pub fn around(center: &Point, radius_m: f64) -> Rectangle {
+        let (latitude, longitude) = center.degrees();
+        // The angle at the earth's center between the center and the edge of the circle.
+        let arc = radius_m.max(0.0) / EARTH_RADIUS;
+        let (south, north) = (latitude - arc.to_degrees(), latitude + arc.to_degrees());
+
+        // How far east and west the circle reaches, at its widest, as the sine of the angle.
+        let reach = arc.sin() / latitude.to_radians().cos();
+        if north >= 90.0 || south <= -90.0 || reach >= 1.0 {
+            return Rectangle::new(Point::from_degrees(south, -180.0), Point::from_degrees(north, 180.0));
+        }
+        let width = reach.asin().to_degrees();
+        Rectangle::new(Point::from_degrees(south, longitude - width), Point::from_degrees(north, longitude + width))
+    }
+
+    /// Features in cells of `zoom` come back as clusters, unless there are at most
+    /// `max_features` of them.
+    pub fn clustered(mut self, zoom: u32, max_features: u32) -> Self {
+        self.cluster = Some(Clustering { zoom, max_features });
+        self
+    }
+}
+
+
+impl Feature {
+    pub fn named(name: impl Into<String>, location: Point) -> Feature {
+        Feature { name: name.into(), location: Some(location), ..Feature::default() }
+    }
+
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// The name in another language, by a locale like `fr` or `pt-BR`.
+    pub fn with_name_in(mut self, locale: &str, name: impl Into<String>) -> Self {
+        self.names_by_locale.insert(locale.to_string(), name.into());
+        self
+    }
+}
+
+
+impl RouteNote {
+    /// A note posted at `location`.
+    pub fn at(location: Point, message: impl Into<String>) -> RouteNote {
+        RouteNote { location: Some(location), message: message.into(), ..RouteNote::default() }
+    }
+
+    /// A note posted to a chat room.
+    pub fn in_room(room: impl Into<String>, message: impl Into<String>) -> RouteNote {
+        RouteNote { room: room.into(), message: message.into(), ..RouteNote::default() }
+    }
+
+    /// Only for `user`, like `certificate:alice`.
+    pub fn to(mut self, user: impl Into<String>) -> Self {
+        self.to_user = user.into();
+        self
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures::TryStreamExt as _;
+use http_body::Body as HttpBody;
+use hyper::{Body, HeaderMap, Request, Response};
+use once_cell::sync::Lazy;
+use prost::Message;
+use prost_types::FileDescriptorSet;
+use tonic::body::BoxBody;
+use tonic::transport::NamedService;
+use tonic::Status;
+use tower::Service;
+
+use crate::metrics::{self, Histogram};
+use crate::recording::GrpcFrames;
+use crate::route_guide::FILE_DESCRIPTOR_SET;
+
+
+/// Message sizes, from 64 bytes to the 4 MiB tonic takes by default.
+pub const SIZE_BUCKETS: &[f64] = &[64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0];
+
+/// Messages per stream, from unary calls up to long uploads and chats.
+pub const COUNT_BUCKETS: &[f64] = &[0.0, 1.0, 2.0, 5.0, 10.0, 50.0, 100.0, 500.0, 1000.0, 10000.0];
+
+
+/// The `/package.Service/Method` paths of every method in the protos. Calls to other paths
+/// are counted together, so clients can't add series.
+fn known_methods() -> &'static HashSet<String> {
+    static METHODS: Lazy<HashSet<String>> = Lazy::new(|| {
+        let set = FileDescriptorSet::decode(FILE_DESCRIPTOR_SET).expect("the built-in descriptor set is valid");
+        set.file
+            .iter()
+            .flat_map(|file| file.service.iter().map(move |service| (file.package(), service)))
+            .flat_map(|(package, service)| {
+                service.method.iter().map(move |method| format!("/{}.{}/{}", package, service.name(), method.name()))
+            })
+            .collect()
+    });
+    &METHODS
+}
+
+pub(crate) fn method_label(path: &str) -> &str {
+    if known_methods().contains(path) { path } else { "other" }
+}
+
+
+/// The messages of one direction of a call: each one's size goes into
+/// `grpc_message_size_bytes`, and their number into `grpc_stream_messages` once the stream is
+/// dropped, whether it ended or the call was cancelled.
+struct Tally {
+    frames: GrpcFrames,
+    sizes: Arc<Histogram>,
+    counts: Arc<Histogram>,
+    messages: u64,
+}
+
+impl Tally {
+    fn new(method: &str, direction: &str) -> Self {
+        let registry = metrics::registry();
+        let labels = [("method", method), ("direction", direction)];
+        Tally {
+            frames: GrpcFrames::default(),
+            sizes: registry.histogram("grpc_message_size_bytes", "Encoded size of gRPC messages, before compression.", &labels, SIZE_BUCKETS),
+            counts: registry.histogram("grpc_stream_messages", "Number of messages in a gRPC request or response stream.", &labels, COUNT_BUCKETS),
+            messages: 0,
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8]) {
+        for message in self.frames.push(chunk) {
+            self.sizes.observe(message.len() as f64);
+            self.messages += 1;
+        }
+    }
+}
+
+impl Drop for Tally {
+    fn drop(&mut self) {
+        self.counts.observe(self.messages as f64);
+    }
+}
+
+
+/// Response body that tallies the messages as they pass through.
+struct MeasuredBody {
+    inner: BoxBody,
+    tally: Tally,
+}
+
+impl HttpBody for MeasuredBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &result {
+            this.tally.push(chunk);
+        }
+        result
+    }
+
+    fn poll_trailers(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+
+/// Measures the messages of every call to `inner`, per method and direction. Meant to go
+/// inside `ServerCompression`, so sizes are of the encoded messages rather than what gzip made
+/// of them, which is what window sizes and compression thresholds are tuned against.
+#[derive(Debug, Clone)]
+pub struct MessageMetrics<S> {
+    pub inner: S,
+}
+
+impl<S> Service<Request<Body>> for MessageMetrics<S>
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>>
+        + NamedService
+        + Clone
+        + Send
+        + 'static,
+        S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let mut svc = self.inner.clone();
+        let method = method_label(request.uri().path()).to_string();
+
+        let mut requests = Tally::new(&method, "request");
+        let request = request.map(|body| Body::wrap_stream(body.map_ok(move |chunk| {
+            requests.push(&chunk);
+            chunk
+        })));
+
+        Box::pin(async move {
+            let response = svc.call(request).await?;
+            let tally = Tally::new(&method, "response");
+            Ok(response.map(|body| BoxBody::new(MeasuredBody { inner: body, tally })))
+        })
+    }
+}
+
+impl<S: NamedService> NamedService for MessageMetrics<S> {
+    const NAME: &'static str = S::NAME;
+}
@@ -0,0 +1,282 @@
+use std::collections::BTreeMap;
+#[cfg(feature = "metrics")]
+use std::convert::Infallible;
+use std::fmt::Write;
+#[cfg(feature = "metrics")]
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+#[cfg(feature = "metrics")]
+use hyper::service::{make_service_fn, service_fn};
+#[cfg(feature = "metrics")]
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use once_cell::sync::Lazy;
+
+#[cfg(feature = "metrics")]
+use crate::compression::Compression;
+
+
+/// Default histogram buckets for durations in seconds.
+pub const DURATION_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Increments the gauge until the returned guard is dropped, e.g. to count open streams.
+    pub fn track(self: Arc<Self>) -> GaugeGuard {
+        self.inc();
+        GaugeGuard(self)
+    }
+}
+
+#[derive(Debug)]
+pub struct GaugeGuard(Arc<Gauge>);
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+
+#[derive(Debug)]
+pub struct Histogram {
+    buckets: &'static [f64],
+    counts: Vec<AtomicU64>,
+    sum: Mutex<f64>,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Self {
+        Histogram {
+            buckets,
+            counts: buckets.iter().map(|_| AtomicU64::new(0)).collect(),
+            sum: Mutex::new(0.0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        for (bound, count) in self.buckets.iter().zip(&self.counts) {
+            if value <= *bound {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        *self.sum.lock().unwrap() += value;
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn sum(&self) -> f64 {
+        *self.sum.lock().unwrap()
+    }
+}
+
+
+#[derive(Debug, Clone)]
+enum Metric {
+    Counter(Arc<Counter>),
+    Gauge(Arc<Gauge>),
+    Histogram(Arc<Histogram>),
+}
+
+impl Metric {
+    fn kind(&self) -> &'static str {
+        match self {
+            Metric::Counter(_)   => "counter",
+            Metric::Gauge(_)     => "gauge",
+            Metric::Histogram(_) => "histogram",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Family {
+    help: &'static str,
+    series: BTreeMap<String, Metric>,
+}
+
+
+/// A set of named metrics, rendered in the Prometheus text format. Metrics are created on first
+/// use and shared afterwards, so callers can look them up wherever they need them.
+#[derive(Debug, Default)]
+pub struct Registry {
+    families: RwLock<BTreeMap<&'static str, Family>>,
+}
+
+fn label_string(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+
+    let labels: Vec<_> = labels
+        .iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key, value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+impl Registry {
+    fn get_or_insert(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)], create: impl FnOnce() -> Metric) -> Metric {
+        let labels = label_string(labels);
+
+        if let Some(metric) = self.families.read().unwrap().get(name).and_then(|family| family.series.get(&labels)) {
+            return metric.clone();
+        }
+
+        let mut families = self.families.write().unwrap();
+        let family = families.entry(name).or_insert_with(|| Family { help, series: BTreeMap::new() });
+        family.series.entry(labels).or_insert_with(create).clone()
+    }
+
+    pub fn counter(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)]) -> Arc<Counter> {
+        match self.get_or_insert(name, help, labels, || Metric::Counter(Arc::default())) {
+            Metric::Counter(counter) => counter,
+            other => panic!("metric {} is a {}, not a counter", name, other.kind()),
+        }
+    }
+
+    pub fn gauge(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)]) -> Arc<Gauge> {
+        match self.get_or_insert(name, help, labels, || Metric::Gauge(Arc::default())) {
+            Metric::Gauge(gauge) => gauge,
+            other => panic!("metric {} is a {}, not a gauge", name, other.kind()),
+        }
+    }
+
+    pub fn histogram(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)], buckets: &'static [f64]) -> Arc<Histogram> {
+        match self.get_or_insert(name, help, labels, || Metric::Histogram(Arc::new(Histogram::new(buckets)))) {
+            Metric::Histogram(histogram) => histogram,
+            other => panic!("metric {} is a {}, not a histogram", name, other.kind()),
+        }
+    }
+
+    /// The current value of every counter and gauge, and the count of every histogram, keyed by
+    /// series as it's rendered (`name{labels}`).
+    pub fn values(&self) -> BTreeMap<String, f64> {
+        let mut values = BTreeMap::new();
+
+        for (name, family) in self.families.read().unwrap().iter() {
+            for (labels, metric) in &family.series {
+                let (key, value) = match metric {
+                    Metric::Counter(counter)     => (format!("{}{}", name, labels), counter.get() as f64),
+                    Metric::Gauge(gauge)         => (format!("{}{}", name, labels), gauge.get() as f64),
+                    Metric::Histogram(histogram) => (format!("{}_count{}", name, labels), histogram.count() as f64),
+                };
+                values.insert(key, value);
+            }
+        }
+
+        values
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        for (name, family) in self.families.read().unwrap().iter() {
+            let kind = match family.series.values().next() {
+                Some(metric) => metric.kind(),
+                None => continue,
+            };
+            let _ = writeln!(out, "# HELP {} {}", name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+
+            for (labels, metric) in &family.series {
+                match metric {
+                    Metric::Counter(counter) => { let _ = writeln!(out, "{}{} {}", name, labels, counter.get()); },
+                    Metric::Gauge(gauge)     => { let _ = writeln!(out, "{}{} {}", name, labels, gauge.get()); },
+                    Metric::Histogram(histogram) => {
+                        // Bucket labels go next to the series labels.
+                        let inner = labels.trim_start_matches('{').trim_end_matches('}');
+                        let separator = if inner.is_empty() { "" } else { "," };
+                        for (bound, count) in histogram.buckets.iter().zip(&histogram.counts) {
+                            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, inner, separator, bound, count.load(Ordering::Relaxed));
+                        }
+                        let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, inner, separator, histogram.count());
+                        let _ = writeln!(out, "{}_sum{} {}", name, labels, histogram.sum());
+                        let _ = writeln!(out, "{}_count{} {}", name, labels, histogram.count());
+                    },
+                }
+            }
+        }
+
+        out
+    }
+}
+
+
+/// The process wide registry.
+pub fn registry() -> &'static Registry {
+    static REGISTRY: Lazy<Registry> = Lazy::new(Registry::default);
+    &REGISTRY
+}
+
+
+#[cfg(feature = "metrics")]
+async fn metrics_service(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let mut response = Response::new(Body::empty());
+    let mut compression = Compression::OFF;
+
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => {
+            response.headers_mut().insert("content-type", "text/plain; version=0.0.4".parse().unwrap());
+            *response.body_mut() = Body::from(registry().render());
+            compression = Compression::with_min_size(1024);
+        },
+        _ => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+        },
+    }
+
+    Ok(compression.apply(request.headers(), response))
+}
+
+/// Serves `GET /metrics` on the address until the process exits.
+#[cfg(feature = "metrics")]
+pub async fn serve(address: SocketAddr) -> Result<(), hyper::Error> {
+    let make_service = make_service_fn(|_conn| async {
+        Ok::<_, Infallible>(service_fn(metrics_service))
+    });
+
+    Server::bind(&address).serve(make_service).await
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::time::Instant;
+
+use futures::stream::{self, Empty, Iter};
+use futures::{Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::admin::Quota;
+use crate::embedded;
+use crate::geo;
+use crate::route_guide::route_guide_server::RouteGuide;
+use crate::route_guide::{
+    ChangeEvent, ClientConfig, ExportRequest, Feature, FeatureChunk, ImportSummary, Point, PointWithTimestamp, Rectangle,
+    ReplicateRequest, ReplicationEvent, Room, RoomMembers, RouteNote, RouteSummary, TimeRange,
+};
+
+type Unserved<T> = Empty<Result<T, Status>>;
+
+fn read_only() -> Status {
+    Status::unimplemented("the minimal server's features are read-only")
+}
+
+fn unserved(method: &str) -> Status {
+    Status::unimplemented(format!("{} isn't served by the minimal server", method))
+}
+
+
+/// The RouteGuide service with the embedded features and nothing else, for the `minimal-server`
+/// feature: no tenants, authentication, metrics or storage, so it builds without any of the
+/// optional dependencies. GetFeature, ListFeatures, RecordRoute and RouteChat are served, the
+/// latter like the original route guide, for one call at a time; the rest are UNIMPLEMENTED.
+#[derive(Debug)]
+pub struct MinimalRouteGuide {
+    features: HashMap<Point, Feature>,
+}
+
+impl MinimalRouteGuide {
+    pub fn new(features: Vec<Feature>) -> Self {
+        let features = features
+            .into_iter()
+            .filter_map(|feature| Some((Point { read_mask: None, ..feature.location.clone()? }, feature)))
+            .collect();
+        MinimalRouteGuide { features }
+    }
+
+    /// With the features of `data/route_guide_db.json`, compiled in.
+    pub fn embedded() -> Result<Self, String> {
+        embedded::features().map(MinimalRouteGuide::new)
+    }
+
+    pub fn len(&self) -> usize {
+        self.features.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty()
+    }
+
+    fn get(&self, point: &Point) -> Option<&Feature> {
+        self.features.get(&Point { read_mask: None, ..point.clone() })
+    }
+}
+
+#[tonic::async_trait]
+impl RouteGuide for MinimalRouteGuide {
+    type ListFeaturesStream = Iter<std::vec::IntoIter<Result<Feature, Status>>>;
+    type RouteChatStream = Pin<Box<dyn Stream<Item = Result<RouteNote, Status>> + Send + Sync + 'static>>;
+    type GetNotesAtStream = Unserved<RouteNote>;
+    type ListChangesStream = Unserved<ChangeEvent>;
+    type ExportFeaturesStream = Unserved<FeatureChunk>;
+    type ReplicateStream = Unserved<ReplicationEvent>;
+    type WatchConfigStream = Unserved<ClientConfig>;
+
+    async fn get_feature(&self, request: Request<Point>) -> Result<Response<Feature>, Status> {
+        Ok(Response::new(self.get(request.get_ref()).cloned().unwrap_or_default()))
+    }
+
+    async fn list_features(&self, request: Request<Rectangle>) -> Result<Response<Self::ListFeaturesStream>, Status> {
+        let rect = request.get_ref();
+        let features: Vec<_> = self.features
+            .values()
+            .filter(|feature| feature.location.as_ref().is_some_and(|location| geo::in_range(location, rect)))
+            .map(|feature| Ok(feature.clone()))
+            .collect();
+        Ok(Response::new(stream::iter(features)))
+    }
+
+    async fn record_route(&self, request: Request<Streaming<Point>>) -> Result<Response<RouteSummary>, Status> {
+        let mut stream = request.into_inner();
+        let started = Instant::now();
+        let mut summary = RouteSummary::default();
+        let mut last: Option<Point> = None;
+
+        while let Some(point) = stream.next().await {
+            let point = point?;
+            summary.point_count += 1;
+            if self.get(&point).map_or(false, |feature| !feature.name.is_empty()) {
+                summary.feature_count += 1;
+            }
+            if let Some(last) = &last {
+                summary.distance += geo::distance(last, &point) as i32;
+            }
+            last = Some(point);
+        }
+        summary.elapsed_time = started.elapsed().as_secs() as i32;
+        Ok(Response::new(summary))
+    }
+
+    /// Answers each note with the notes sent earlier in the same call to its location.
+    async fn route_chat(&self, request: Request<Streaming<RouteNote>>) -> Result<Response<Self::RouteChatStream>, Status> {
+        let mut stream = request.into_inner();
+        let output = async_stream::try_stream! {
+            let mut notes: HashMap<Point, Vec<RouteNote>> = HashMap::new();
+            while let Some(note) = stream.next().await {
+                let note = note?;
+                let location = Point { read_mask: None, ..note.location.clone().unwrap_or_default() };
+                let earlier = notes.entry(location).or_insert_with(Vec::new);
+                for reply in earlier.iter() {
+                    yield reply.clone();
+                }
+                earlier.push(note);
+            }
+        };
+        Ok(Response::new(Box::pin(output) as Self::RouteChatStream))
+    }
+
+    async fn add_feature(&self, _: Request<Feature>) -> Result<Response<Feature>, Status> {
+        Err(read_only())
+    }
+
+    async fn delete_feature(&self, _: Request<Point>) -> Result<Response<Feature>, Status> {
+        Err(read_only())
+    }
+
+    async fn get_notes_at(&self, _: Request<Point>) -> Result<Response<Self::GetNotesAtStream>, Status> {
+        Err(unserved("GetNotesAt"))
+    }
+
+    async fn import_features(&self, _: Request<Streaming<Feature>>) -> Result<Response<ImportSummary>, Status> {
+        Err(read_only())
+    }
+
+    async fn get_feature_as_of(&self, _: Request<PointWithTimestamp>) -> Result<Response<Feature>, Status> {
+        Err(unserved("GetFeatureAsOf"))
+    }
+
+    async fn list_changes(&self, _: Request<TimeRange>) -> Result<Response<Self::ListChangesStream>, Status> {
+        Err(unserved("ListChanges"))
+    }
+
+    async fn export_features(&self, _: Request<ExportRequest>) -> Result<Response<Self::ExportFeaturesStream>, Status> {
+        Err(unserved("ExportFeatures"))
+    }
+
+    async fn replicate(&self, _: Request<ReplicateRequest>) -> Result<Response<Self::ReplicateStream>, Status> {
+        Err(unserved("Replicate"))
+    }
+
+    async fn watch_config(&self, _: Request<()>) -> Result<Response<Self::WatchConfigStream>, Status> {
+        Err(unserved("WatchConfig"))
+    }
+
+    async fn list_room_members(&self, _: Request<Room>) -> Result<Response<RoomMembers>, Status> {
+        Err(unserved("ListRoomMembers"))
+    }
+
+    async fn get_quota(&self, _: Request<()>) -> Result<Response<Quota>, Status> {
+        Err(unserved("GetQuota"))
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use tonic::{Code, Status};
+
+use crate::metrics;
+use crate::route_guide::{Rejection, RouteNote};
+
+
+/// What a `Moderator` makes of a note.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Accept,
+    /// Passed on with this message instead.
+    Redact(String),
+    /// Not passed on; the poster is told why.
+    Reject(Code, String),
+}
+
+/// Looks at every RouteChat note before it's passed on, with the server's `from_user` and
+/// `posted_at_ms` set. Checks that don't wait for anything just don't await.
+#[tonic::async_trait]
+pub trait Moderator: Debug + Send + Sync {
+    async fn moderate(&self, note: &RouteNote) -> Verdict;
+}
+
+
+/// Rejects notes longer than `max_chars` characters.
+#[derive(Debug, Clone, Copy)]
+pub struct LengthLimit {
+    pub max_chars: usize,
+}
+
+#[tonic::async_trait]
+impl Moderator for LengthLimit {
+    async fn moderate(&self, note: &RouteNote) -> Verdict {
+        let length = note.message.chars().count();
+        if length > self.max_chars {
+            return Verdict::Reject(Code::InvalidArgument, format!("the note is {} characters long; the limit is {}", length, self.max_chars));
+        }
+        Verdict::Accept
+    }
+}
+
+
+/// What `BannedWords` does with a note that has one.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BannedWordAction {
+    /// Replaces each banned word with as many `*`.
+    Redact,
+    Reject,
+}
+
+impl FromStr for BannedWordAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "redact" => Ok(BannedWordAction::Redact),
+            "reject" => Ok(BannedWordAction::Reject),
+            other => Err(format!("unknown banned word action '{}', expected redact or reject", other)),
+        }
+    }
+}
+
+/// Words that mustn't be posted, matched as whole words and ignoring case.
+#[derive(Debug, Clone)]
+pub struct BannedWords {
+    words: BTreeSet<String>,
+    action: BannedWordAction,
+}
+
+impl BannedWords {
+    pub fn new(words: impl IntoIterator<Item = String>, action: BannedWordAction) -> Self {
+        BannedWords { words: words.into_iter().map(|word| word.to_lowercase()).collect(), action }
+    }
+
+    /// One word per line; `#` starts a comment.
+    pub fn parse(text: &str, action: BannedWordAction) -> Result<Self, String> {
+        let mut words = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.split_whitespace().count() > 1 {
+                return Err(format!("line {}: '{}' is more than one word", i + 1, line));
+            }
+            words.push(line.to_string());
+        }
+        Ok(BannedWords::new(words, action))
+    }
+
+    fn is_banned(&self, word: &str) -> bool {
+        !word.is_empty() && self.words.contains(&word.to_lowercase())
+    }
+}
+
+#[tonic::async_trait]
+impl Moderator for BannedWords {
+    async fn moderate(&self, note: &RouteNote) -> Verdict {
+        let mut redacted = String::with_capacity(note.message.len());
+        let mut found = false;
+        let mut word = String::new();
+        // One pass over the message, with a word ending at every character that isn't part of
+        // one.
+        for c in note.message.chars().chain(std::iter::once(' ')) {
+            if c.is_alphanumeric() || c == '\'' || c == '-' {
+                word.push(c);
+                continue;
+            }
+            if self.is_banned(&word) {
+                found = true;
+                redacted.extend(std::iter::repeat_n('*', word.chars().count()));
+            } else {
+                redacted.push_str(&word);
+            }
+            word.clear();
+            redacted.push(c);
+        }
+        redacted.pop();
+
+        match (found, self.action) {
+            (false, _) => Verdict::Accept,
+            (true, BannedWordAction::Redact) => Verdict::Redact(redacted),
+            (true, BannedWordAction::Reject) => Verdict::Reject(Code::InvalidArgument, "the note has a banned word".to_string()),
+        }
+    }
+}
+
+
+/// Users whose notes are rejected, by `from_user`. Changed through the admin service.
+#[derive(Debug, Default)]
+pub struct MuteList {
+    users: RwLock<BTreeSet<String>>,
+}
+
+impl MuteList {
+    pub fn set(&self, user: &str, muted: bool) {
+        let mut users = self.users.write().unwrap();
+        if muted {
+            users.insert(user.to_string());
+        } else {
+            users.remove(user);
+        }
+    }
+
+    pub fn is_muted(&self, user: &str) -> bool {
+        self.users.read().unwrap().contains(user)
+    }
+
+    /// Sorted.
+    pub fn users(&self) -> Vec<String> {
+        self.users.read().unwrap().iter().cloned().collect()
+    }
+}
+
+#[tonic::async_trait]
+impl Moderator for MuteList {
+    async fn moderate(&self, note: &RouteNote) -> Verdict {
+        if self.is_muted(&note.from_user) {
+            return Verdict::Reject(Code::PermissionDenied, "you are muted in this chat".to_string());
+        }
+        Verdict::Accept
+    }
+}
+
+
+/// The moderators of the RouteChat notes, asked in order. A redaction is what later ones see,
+/// and the first rejection stops the note.
+#[derive(Debug, Default, Clone)]
+pub struct Moderation {
+    moderators: Vec<Arc<dyn Moderator>>,
+}
+
+impl Moderation {
+    pub fn with(mut self, moderator: Arc<dyn Moderator>) -> Self {
+        self.moderators.push(moderator);
+        self
+    }
+
+    /// Redacts the note in place, or says why it's rejected.
+    pub async fn check(&self, note: &mut RouteNote) -> Result<(), Status> {
+        if self.moderators.is_empty() {
+            return Ok(());
+        }
+        // On a task of its own, since the moderators' futures aren't Sync and the RouteChat
+        // stream waiting for them has to be.
+        let (moderators, mut moderated) = (self.moderators.clone(), note.clone());
+        let checked = tokio::spawn(async move {
+            for moderator in &moderators {
+                match moderator.moderate(&moderated).await {
+                    Verdict::Accept => {},
+                    Verdict::Redact(message) => {
+                        count("redacted");
+                        moderated.message = message;
+                    },
+                    Verdict::Reject(code, message) => {
+                        count("rejected");
+                        return Err(Status::new(code, message));
+                    },
+                }
+            }
+            Ok(moderated)
+        });
+        *note = checked.await.map_err(|e| Status::internal(format!("moderation failed: {}", e)))??;
+        Ok(())
+    }
+}
+
+fn count(outcome: &str) {
+    metrics::registry()
+        .counter("route_chat_moderated_notes_total", "RouteChat notes redacted or rejected by moderation.", &[("outcome", outcome)])
+        .inc();
+}
+
+/// What the poster of a rejected note gets back instead of it.
+pub fn rejection(note: &RouteNote, status: &Status) -> RouteNote {
+    RouteNote {
+        location: note.location.clone(),
+        room: note.room.clone(),
+        sequence: note.sequence,
+        rejection: Some(Rejection { code: status.code() as i32, message: status.message().to_string() }),
+        ..RouteNote::default()
+    }
+}
@@ -0,0 +1,383 @@
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::future::BoxFuture;
+use http_body::Body as HttpBody;
+use hyper::header::{HeaderValue, CONTENT_TYPE, UPGRADE};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, HeaderMap, Request, Response, StatusCode, Version};
+use rustls::internal::pemfile;
+use rustls::{NoClientAuth, ServerConfig, Session};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tonic::body::BoxBody;
+use tonic::transport::NamedService;
+use tonic::{Code, Status};
+use tower::{Service, ServiceExt};
+
+use crate::connections::{self, TrackedConnection};
+use crate::ip_filter::IpFilter;
+
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+type GrpcHandler = Arc<dyn Fn(Request<Body>) -> BoxFuture<'static, Result<Response<BoxBody>, BoxError>> + Send + Sync>;
+
+/// Offered in ALPN, preferred first.
+pub const ALPN: &[&[u8]] = &[b"h2", b"http/1.1"];
+
+/// The first byte of a TLS connection: a handshake record.
+const TLS_HANDSHAKE: u8 = 0x16;
+
+const GRPC: &str = "application/grpc";
+const GRPC_WEB: &str = "application/grpc-web";
+const GRPC_WEB_TEXT: &str = "application/grpc-web-text";
+
+
+/// A TLS configuration offering `ALPN`, from PEM files like `data/tls/server.pem` and the
+/// PKCS #8 `data/tls/server.key`. Clients aren't asked for certificates: the services behind a
+/// shared port couldn't see them anyway.
+pub fn tls_config(cert_pem: &[u8], key_pem: &[u8]) -> io::Result<ServerConfig> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let certs = pemfile::certs(&mut &cert_pem[..]).map_err(|_| invalid("invalid certificate PEM"))?;
+    let key = pemfile::pkcs8_private_keys(&mut &key_pem[..])
+        .map_err(|_| invalid("invalid key PEM"))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| invalid("no PKCS #8 private key found"))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(certs, key).map_err(|e| invalid(&e.to_string()))?;
+    config.set_protocols(&ALPN.iter().map(|protocol| protocol.to_vec()).collect::<Vec<_>>());
+    Ok(config)
+}
+
+
+/// What a shared port accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PortMode {
+    /// TLS only, with HTTP/2 or HTTP/1.1 picked in ALPN.
+    Tls,
+    /// Plaintext only: HTTP/2 with prior knowledge, which is how gRPC clients speak h2c, and
+    /// HTTP/1.1. For running behind a proxy that terminates TLS.
+    H2c,
+    /// Either, told apart by the first byte.
+    #[default]
+    Auto,
+}
+
+
+impl std::str::FromStr for PortMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tls" => Ok(PortMode::Tls),
+            "h2c" => Ok(PortMode::H2c),
+            "auto" => Ok(PortMode::Auto),
+            _ => Err(format!("unknown port mode {:?}, expected tls, h2c or auto", s)),
+        }
+    }
+}
+
+impl fmt::Display for PortMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PortMode::Tls => "tls",
+            PortMode::H2c => "h2c",
+            PortMode::Auto => "auto",
+        })
+    }
+}
+
+
+/// gRPC services by name, like tonic's `Router`, to serve next to other HTTP handlers.
+#[derive(Clone, Default)]
+pub struct GrpcRoutes {
+    routes: Vec<(String, GrpcHandler)>,
+}
+
+impl GrpcRoutes {
+    pub fn new() -> Self {
+        GrpcRoutes::default()
+    }
+
+    pub fn add_service<S>(mut self, service: S) -> Self
+        where
+            S: Service<Request<Body>, Response = Response<BoxBody>> + NamedService + Clone + Send + 'static,
+            S::Future: Send + 'static,
+            S::Error: Into<BoxError>,
+    {
+        // Each call gets a clone, like tonic's server does.
+        let service = Mutex::new(service);
+        let handler: GrpcHandler = Arc::new(move |request| {
+            let service = service.lock().unwrap().clone();
+            Box::pin(async move { service.oneshot(request).await.map_err(Into::into) })
+        });
+        self.routes.push((format!("/{}/", S::NAME), handler));
+        self
+    }
+
+    async fn call(&self, request: Request<Body>) -> Result<Response<BoxBody>, BoxError> {
+        let path = request.uri().path();
+        match self.routes.iter().find(|(prefix, _)| path.starts_with(prefix.as_str())) {
+            Some((_, handler)) => handler(request).await,
+            None => Ok(status_response(Code::Unimplemented, "unknown service")),
+        }
+    }
+}
+
+/// A trailers-only gRPC response.
+fn status_response(code: Code, message: &'static str) -> Response<BoxBody> {
+    let mut response = Response::new(BoxBody::empty());
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(GRPC));
+    headers.insert("grpc-status", HeaderValue::from(code as i32));
+    headers.insert("grpc-message", HeaderValue::from_static(message));
+    response
+}
+
+
+/// The gRPC services and another HTTP handler, like the REST gateway, on one port. Requests are
+/// told apart by content type: `application/grpc` goes to the services, `application/grpc-web`
+/// too after translating it to gRPC, and everything else to the handler.
+///
+/// Connections may be TLS, with HTTP/2 or HTTP/1.1 picked in ALPN, or plaintext HTTP/1.1 or
+/// HTTP/2 with prior knowledge, as `mode` allows; the first byte tells which. gRPC needs
+/// HTTP/2, gRPC-Web and the handler work over either.
+///
+/// An HTTP/1.1 request asking to upgrade to h2c is answered over HTTP/1.1, as RFC 7540 lets a
+/// server do: hyper can't take over a connection as HTTP/2 with the first request already
+/// read. gRPC clients don't ask anyway, they use prior knowledge; one that sends gRPC over
+/// HTTP/1.1 gets an error saying so.
+pub struct Multiplexer<H> {
+    pub grpc: GrpcRoutes,
+    pub http: H,
+    /// Without one, TLS connections are refused.
+    pub tls: Option<Arc<ServerConfig>>,
+    pub mode: PortMode,
+    /// Checked when a connection is accepted, before TLS, and on every request.
+    pub ip_filter: Arc<IpFilter>,
+}
+
+impl<H, F> Multiplexer<H>
+    where
+        H: Fn(Request<Body>) -> F + Clone + Send + Sync + 'static,
+        F: Future<Output = Response<Body>> + Send + 'static,
+{
+    /// Accepts connections until `shutdown` completes. Connections are in the connection
+    /// registry like those of the gRPC server.
+    pub async fn serve(self, address: SocketAddr, shutdown: impl Future<Output = ()>) -> io::Result<()> {
+        let mut listener = TcpListener::bind(address).await?;
+        let acceptor = self.tls.clone().map(TlsAcceptor::from);
+        let this = Arc::new(self);
+
+        futures::pin_mut!(shutdown);
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = &mut shutdown => return Ok(()),
+            };
+            if !this.ip_filter.accepts(peer) {
+                continue;
+            }
+            let _ = stream.set_nodelay(true);
+
+            let (this, acceptor) = (this.clone(), acceptor.clone());
+            tokio::spawn(async move {
+                if let Err(e) = this.serve_connection(connections::registry().open(stream, peer), peer, acceptor).await {
+                    tracing::debug!(%peer, error = %e, "shared port connection failed");
+                }
+            });
+        }
+    }
+
+    async fn serve_connection(self: Arc<Self>, mut connection: TrackedConnection, peer: SocketAddr, acceptor: Option<TlsAcceptor>)
+        -> Result<(), BoxError> {
+        let mut first = [0u8; 1];
+        if connection.peek(&mut first).await? == 0 {
+            return Ok(());
+        }
+
+        let this = self.clone();
+        // A concrete error type: with `BoxError` the connection's future can't be proven `Send`
+        // for spawning.
+        let service = service_fn(move |request| {
+            let this = this.clone();
+            async move { this.dispatch(peer, request).await.map_err(io::Error::other) }
+        });
+
+        let is_tls = first[0] == TLS_HANDSHAKE;
+        match (self.mode, is_tls) {
+            (PortMode::Tls, false) => return Err("plaintext connection to a TLS-only port".into()),
+            (PortMode::H2c, true) => return Err("TLS connection to an h2c port".into()),
+            _ => {},
+        }
+
+        if !is_tls {
+            // hyper tells HTTP/2 with prior knowledge from HTTP/1.1 by the connection preface.
+            return Ok(Http::new().serve_connection(connection, service).await?);
+        }
+
+        let acceptor = acceptor.ok_or("TLS connection without a TLS configuration")?;
+        let stream = acceptor.accept(connection).await?;
+        let mut http = Http::new();
+        match stream.get_ref().1.get_alpn_protocol() {
+            Some(b"h2") => { http.http2_only(true); },
+            Some(b"http/1.1") => { http.http1_only(true); },
+            _ => {},
+        }
+        Ok(http.serve_connection(stream, service).await?)
+    }
+
+    async fn dispatch(&self, peer: SocketAddr, request: Request<Body>) -> Result<Response<BoxBody>, BoxError> {
+        let content_type = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+        let allowed = self.ip_filter.allows_request(Some(peer), request.headers());
+
+        let http1 = request.version() < Version::HTTP_2;
+        let is_grpc = content_type.starts_with(GRPC) && !content_type.starts_with(GRPC_WEB);
+
+        if !allowed && content_type.starts_with(GRPC) {
+            Ok(status_response(Code::PermissionDenied, "address not allowed"))
+        } else if !allowed {
+            let mut response = Response::new(BoxBody::empty());
+            *response.status_mut() = StatusCode::FORBIDDEN;
+            Ok(response)
+        } else if is_grpc && http1 && request.headers().contains_key(UPGRADE) {
+            Ok(status_response(Code::Unavailable, "upgrading to h2c isn't supported, connect with HTTP/2 prior knowledge"))
+        } else if is_grpc && http1 {
+            Ok(status_response(Code::Unavailable, "gRPC needs HTTP/2, connect with prior knowledge or over TLS"))
+        } else if content_type.starts_with(GRPC_WEB_TEXT) {
+            Ok(status_response(Code::Unimplemented, "grpc-web-text isn't supported, use application/grpc-web+proto"))
+        } else if content_type.starts_with(GRPC_WEB) {
+            let response = self.grpc.call(grpc_web_request(request)).await?;
+            Ok(grpc_web_response(response))
+        } else if content_type.starts_with(GRPC) {
+            self.grpc.call(request).await
+        } else {
+            let response = (self.http)(request).await;
+            Ok(response.map(|body| BoxBody::new(HyperBody(body))))
+        }
+    }
+}
+
+
+/// A gRPC-Web request as the gRPC request it stands for. The messages are framed the same way.
+fn grpc_web_request(mut request: Request<Body>) -> Request<Body> {
+    let headers = request.headers_mut();
+    let content_type = headers.get(CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or(GRPC_WEB);
+    let grpc = format!("{}{}", GRPC, &content_type[GRPC_WEB.len()..]);
+    if let Ok(value) = HeaderValue::from_str(&grpc) {
+        headers.insert(CONTENT_TYPE, value);
+    }
+    headers.remove("x-grpc-web");
+    request
+}
+
+/// A gRPC response as gRPC-Web: the trailers go at the end of the body, since browsers can't read
+/// HTTP trailers.
+fn grpc_web_response(response: Response<BoxBody>) -> Response<BoxBody> {
+    let (mut parts, body) = response.into_parts();
+    let content_type = parts.headers.get(CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or(GRPC);
+    let grpc_web = format!("{}{}", GRPC_WEB, content_type.get(GRPC.len()..).unwrap_or(""));
+    if let Ok(value) = HeaderValue::from_str(&grpc_web) {
+        parts.headers.insert(CONTENT_TYPE, value);
+    }
+    Response::from_parts(parts, BoxBody::new(GrpcWebBody { inner: body, data_done: false, done: false }))
+}
+
+struct GrpcWebBody {
+    inner: BoxBody,
+    data_done: bool,
+    done: bool,
+}
+
+/// The trailers frame of gRPC-Web: flag 0x80, the length and the trailers in HTTP/1 syntax.
+fn trailers_frame(trailers: &HeaderMap) -> Bytes {
+    let mut block = BytesMut::new();
+    for (name, value) in trailers {
+        block.put_slice(name.as_str().as_bytes());
+        block.put_slice(b":");
+        block.put_slice(value.as_bytes());
+        block.put_slice(b"\r\n");
+    }
+
+    let mut frame = BytesMut::with_capacity(5 + block.len());
+    frame.put_u8(0x80);
+    frame.put_u32(block.len() as u32);
+    frame.put_slice(&block);
+    frame.freeze()
+}
+
+impl HttpBody for GrpcWebBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = &mut *self;
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        if !this.data_done {
+            match futures::ready!(Pin::new(&mut this.inner).poll_data(cx)) {
+                Some(data) => return Poll::Ready(Some(data)),
+                None => this.data_done = true,
+            }
+        }
+
+        let trailers = futures::ready!(Pin::new(&mut this.inner).poll_trailers(cx));
+        this.done = true;
+        match trailers {
+            Ok(Some(trailers)) => Poll::Ready(Some(Ok(trailers_frame(&trailers)))),
+            Ok(None) => Poll::Ready(None),
+            Err(status) => Poll::Ready(Some(Err(status))),
+        }
+    }
+
+    fn poll_trailers(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done
+    }
+}
+
+
+/// A hyper body as a tonic one.
+struct HyperBody(Body);
+
+impl HttpBody for HyperBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        match futures::ready!(Pin::new(&mut self.0).poll_data(cx)) {
+            Some(Ok(data)) => Poll::Ready(Some(Ok(data))),
+            Some(Err(e)) => Poll::Ready(Some(Err(Status::internal(e.to_string())))),
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn poll_trailers(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.0).poll_trailers(cx).map_err(|e| Status::internal(e.to_string()))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.0.is_end_stream()
+    }
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use prost::Message;
+
+use crate::route_guide::{Point, RouteNote};
+use crate::tenant::TenantId;
+
+
+/// Size of a location bucket in E7 units (0.1 degrees, roughly 11 km). Notes are grouped into one
+/// log per bucket so a lookup only reads nearby notes.
+pub const BUCKET_SIZE: i32 = 1_000_000;
+
+
+/// Which notes are kept when the history is compacted.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RetentionPolicy {
+    /// Notes older than this are dropped.
+    pub ttl: Option<Duration>,
+    /// Only the newest notes at each location are kept.
+    pub max_notes_per_location: Option<usize>,
+}
+
+impl RetentionPolicy {
+    fn expired(&self, written: SystemTime, now: SystemTime) -> bool {
+        match self.ttl {
+            Some(ttl) => now.duration_since(written).is_ok_and(|age| age > ttl),
+            None => false,
+        }
+    }
+
+    /// Drops expired notes and the oldest notes over the per-location limit. Expects notes in
+    /// the order they were written.
+    fn apply(&self, notes: Vec<(SystemTime, RouteNote)>, now: SystemTime) -> Vec<(SystemTime, RouteNote)> {
+        let mut notes: Vec<_> = notes.into_iter().filter(|(written, _)| !self.expired(*written, now)).collect();
+
+        if let Some(limit) = self.max_notes_per_location {
+            let mut remaining: HashMap<Option<Point>, usize> = HashMap::new();
+            for (_, note) in notes.iter().rev() {
+                *remaining.entry(note.location.clone()).or_insert(0) += 1;
+            }
+            notes.retain(|(_, note)| {
+                let count = remaining.get_mut(&note.location).unwrap();
+                let keep = *count <= limit;
+                *count -= 1;
+                keep
+            });
+        }
+
+        notes
+    }
+}
+
+
+/// Where RouteChat notes are kept.
+pub trait NoteStore: Debug + Send + Sync {
+    fn append(&self, tenant: &TenantId, note: &RouteNote) -> io::Result<()>;
+
+    /// The notes at exactly this location, oldest first.
+    fn notes_at(&self, tenant: &TenantId, location: &Point) -> io::Result<Vec<RouteNote>>;
+
+    /// Applies the retention policy to everything stored.
+    fn compact(&self) -> io::Result<()>;
+}
+
+
+/// Each location's notes, with when they were written.
+type Notes = HashMap<(TenantId, Point), Vec<(SystemTime, RouteNote)>>;
+
+/// Keeps notes in memory only; they're gone after a restart.
+#[derive(Debug, Default)]
+pub struct MemoryNoteStore {
+    policy: RetentionPolicy,
+    notes: Mutex<Notes>,
+}
+
+impl MemoryNoteStore {
+    pub fn new(policy: RetentionPolicy) -> Self {
+        MemoryNoteStore { policy, notes: Mutex::default() }
+    }
+}
+
+impl NoteStore for MemoryNoteStore {
+    fn append(&self, tenant: &TenantId, note: &RouteNote) -> io::Result<()> {
+        let location = note.location.clone().unwrap_or_default();
+        self.notes.lock().unwrap()
+            .entry((tenant.clone(), location))
+            .or_default()
+            .push((SystemTime::now(), note.clone()));
+        Ok(())
+    }
+
+    fn notes_at(&self, tenant: &TenantId, location: &Point) -> io::Result<Vec<RouteNote>> {
+        let now = SystemTime::now();
+        let notes = self.notes.lock().unwrap();
+        let notes = notes.get(&(tenant.clone(), location.clone())).map_or(&[][..], |notes| &notes[..]);
+
+        Ok(notes.iter().filter(|(written, _)| !self.policy.expired(*written, now)).map(|(_, note)| note.clone()).collect())
+    }
+
+    fn compact(&self) -> io::Result<()> {
+        let now = SystemTime::now();
+        let mut notes = self.notes.lock().unwrap();
+        for location_notes in notes.values_mut() {
+            *location_notes = self.policy.apply(std::mem::take(location_notes), now);
+        }
+        notes.retain(|_, location_notes| !location_notes.is_empty());
+        Ok(())
+    }
+}
+
+
+/// Append-only logs on disk, one file per tenant and location bucket:
+/// `<root>/<tenant>/<lat bucket>_<lon bucket>.log`.
+///
+/// Each record is the write time in milliseconds since the epoch (u64, little endian), the length
+/// of the encoded note (u32, little endian) and the protobuf encoded `RouteNote`. Compaction
+/// rewrites each log to a temporary file and renames it over the original, so a crash leaves
+/// either the old or the new log.
+#[derive(Debug)]
+pub struct FileNoteStore {
+    root: PathBuf,
+    policy: RetentionPolicy,
+    // Serializes writers; appends and compaction of the same file must not interleave.
+    lock: Mutex<()>,
+}
+
+impl FileNoteStore {
+    pub fn open(root: impl Into<PathBuf>, policy: RetentionPolicy) -> io::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        Ok(FileNoteStore { root, policy, lock: Mutex::new(()) })
+    }
+
+    fn bucket_path(&self, tenant: &TenantId, location: &Point) -> PathBuf {
+        let lat = location.latitude.div_euclid(BUCKET_SIZE);
+        let lon = location.longitude.div_euclid(BUCKET_SIZE);
+        self.root.join(tenant.as_str()).join(format!("{}_{}.log", lat, lon))
+    }
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+fn write_record(out: &mut impl Write, written: SystemTime, note: &RouteNote) -> io::Result<()> {
+    let mut encoded = Vec::with_capacity(note.encoded_len());
+    note.encode(&mut encoded).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    out.write_all(&millis(written).to_le_bytes())?;
+    out.write_all(&(encoded.len() as u32).to_le_bytes())?;
+    out.write_all(&encoded)
+}
+
+/// Reads all records. A truncated record at the end (from a crash during an append) is ignored.
+fn read_log(path: &Path) -> io::Result<Vec<(SystemTime, RouteNote)>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let mut reader = BufReader::new(file);
+    let mut notes = vec![];
+
+    loop {
+        let mut header = [0u8; 12];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {},
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+
+        let mut time = [0u8; 8];
+        let mut length = [0u8; 4];
+        time.copy_from_slice(&header[..8]);
+        length.copy_from_slice(&header[8..]);
+
+        let mut encoded = vec![0u8; u32::from_le_bytes(length) as usize];
+        match reader.read_exact(&mut encoded) {
+            Ok(()) => {},
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+
+        let note = RouteNote::decode(&encoded[..]).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        notes.push((UNIX_EPOCH + Duration::from_millis(u64::from_le_bytes(time)), note));
+    }
+
+    Ok(notes)
+}
+
+impl NoteStore for FileNoteStore {
+    fn append(&self, tenant: &TenantId, note: &RouteNote) -> io::Result<()> {
+        let path = self.bucket_path(tenant, &note.location.clone().unwrap_or_default());
+        let _lock = self.lock.lock().unwrap();
+
+        fs::create_dir_all(path.parent().unwrap())?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        // One write per record, so a record is never split between two appends.
+        let mut record = Vec::new();
+        write_record(&mut record, SystemTime::now(), note)?;
+        file.write_all(&record)
+    }
+
+    fn notes_at(&self, tenant: &TenantId, location: &Point) -> io::Result<Vec<RouteNote>> {
+        let now = SystemTime::now();
+        let notes = read_log(&self.bucket_path(tenant, location))?;
+
+        Ok(notes
+            .into_iter()
+            .filter(|(written, note)| note.location.as_ref() == Some(location) && !self.policy.expired(*written, now))
+            .map(|(_, note)| note)
+            .collect())
+    }
+
+    fn compact(&self) -> io::Result<()> {
+        let now = SystemTime::now();
+        let _lock = self.lock.lock().unwrap();
+
+        for tenant in fs::read_dir(&self.root)? {
+            let tenant = tenant?;
+            if !tenant.file_type()?.is_dir() {
+                continue;
+            }
+
+            for log in fs::read_dir(tenant.path())? {
+                let path = log?.path();
+                if path.extension().is_none_or(|extension| extension != "log") {
+                    continue;
+                }
+
+                let notes = read_log(&path)?;
+                let kept = self.policy.apply(notes.clone(), now);
+                if kept.len() == notes.len() {
+                    continue;
+                }
+                if kept.is_empty() {
+                    fs::remove_file(&path)?;
+                    continue;
+                }
+
+                let temporary = path.with_extension("log.tmp");
+                {
+                    let mut out = BufWriter::new(File::create(&temporary)?);
+                    for (written, note) in &kept {
+                        write_record(&mut out, *written, note)?;
+                    }
+                    out.into_inner()?.sync_all()?;
+                }
+                fs::rename(&temporary, &path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(feature = "sqlite")]
+fn sqlite_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+/// Keeps notes in an SQLite database, in a single `notes` table indexed by tenant and location.
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct SqliteNoteStore {
+    policy: RetentionPolicy,
+    connection: Mutex<rusqlite::Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteNoteStore {
+    /// Opens the database, creating it and the table if needed.
+    pub fn open(path: impl AsRef<Path>, policy: RetentionPolicy) -> io::Result<Self> {
+        let connection = rusqlite::Connection::open(path).map_err(sqlite_error)?;
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS notes (
+                     id INTEGER PRIMARY KEY,
+                     tenant TEXT NOT NULL,
+                     latitude INTEGER NOT NULL,
+                     longitude INTEGER NOT NULL,
+                     written_ms INTEGER NOT NULL,
+                     note BLOB NOT NULL
+                 );
+                 CREATE INDEX IF NOT EXISTS notes_location ON notes (tenant, latitude, longitude);",
+            )
+            .map_err(sqlite_error)?;
+
+        Ok(SqliteNoteStore { policy, connection: Mutex::new(connection) })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl NoteStore for SqliteNoteStore {
+    fn append(&self, tenant: &TenantId, note: &RouteNote) -> io::Result<()> {
+        let location = note.location.clone().unwrap_or_default();
+        let mut encoded = Vec::with_capacity(note.encoded_len());
+        note.encode(&mut encoded).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        self.connection.lock().unwrap()
+            .execute(
+                "INSERT INTO notes (tenant, latitude, longitude, written_ms, note) VALUES (?1, ?2, ?3, ?4, ?5)",
+                rusqlite::params![tenant.as_str(), location.latitude, location.longitude, millis(SystemTime::now()) as i64, encoded],
+            )
+            .map_err(sqlite_error)?;
+        Ok(())
+    }
+
+    fn notes_at(&self, tenant: &TenantId, location: &Point) -> io::Result<Vec<RouteNote>> {
+        let now = SystemTime::now();
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare_cached("SELECT written_ms, note FROM notes WHERE tenant = ?1 AND latitude = ?2 AND longitude = ?3 ORDER BY id")
+            .map_err(sqlite_error)?;
+        let rows = statement
+            .query_map(rusqlite::params![tenant.as_str(), location.latitude, location.longitude], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })
+            .map_err(sqlite_error)?;
+
+        let mut notes = vec![];
+        for row in rows {
+            let (written_ms, encoded) = row.map_err(sqlite_error)?;
+            if self.policy.expired(UNIX_EPOCH + Duration::from_millis(written_ms as u64), now) {
+                continue;
+            }
+            notes.push(RouteNote::decode(&encoded[..]).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?);
+        }
+        Ok(notes)
+    }
+
+    fn compact(&self) -> io::Result<()> {
+        let connection = self.connection.lock().unwrap();
+
+        if let Some(ttl) = self.policy.ttl {
+            let cutoff = millis(SystemTime::now()).saturating_sub(ttl.as_millis() as u64);
+            connection
+                .execute("DELETE FROM notes WHERE written_ms < ?1", rusqlite::params![cutoff as i64])
+                .map_err(sqlite_error)?;
+        }
+        if let Some(limit) = self.policy.max_notes_per_location {
+            connection
+                .execute(
+                    "DELETE FROM notes WHERE id IN (
+                         SELECT id FROM (
+                             SELECT id, ROW_NUMBER() OVER (PARTITION BY tenant, latitude, longitude ORDER BY id DESC) AS newer
+                             FROM notes
+                         ) WHERE newer > ?1
+                     )",
+                    rusqlite::params![limit as i64],
+                )
+                .map_err(sqlite_error)?;
+        }
+
+        Ok(())
+    }
+}
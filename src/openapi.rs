@@ -0,0 +1,276 @@
+use std::collections::{BTreeMap, HashMap};
+
+use once_cell::sync::Lazy;
+use prost::Message;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{DescriptorProto, EnumDescriptorProto, FieldDescriptorProto, FileDescriptorSet};
+use serde_json::{json, Map, Value};
+
+use crate::proto_json::camel_case;
+use crate::route_guide::FILE_DESCRIPTOR_SET;
+
+
+/// Where the gateway serves its OpenAPI document.
+pub const PATH: &str = "/openapi.json";
+
+/// Messages of this package are named without it in the document, like `Feature`.
+const PACKAGE: &str = "routeguide.v2";
+
+
+/// A query parameter or header a route reads.
+#[derive(Debug, Copy, Clone)]
+pub struct Parameter {
+    pub name: &'static str,
+    /// `path`, `query` or `header`. Path parameters are named in the path, like `{id}`.
+    pub location: &'static str,
+    /// A JSON schema type: `string`, `integer` or `boolean`.
+    pub kind: &'static str,
+    pub description: &'static str,
+}
+
+/// The body a route takes or answers with.
+#[derive(Debug, Copy, Clone)]
+pub enum Content {
+    /// A message of the routeguide protos, as JSON.
+    Message(&'static str),
+    /// Messages as newline-delimited JSON, one per line.
+    Lines(&'static str),
+    /// A page of messages under `field`, with the `nextPageToken` of the next page.
+    Page { field: &'static str, message: &'static str },
+    /// Anything else, described in words.
+    Other { content_type: &'static str, description: &'static str },
+}
+
+/// A route of the gateway, as the document describes it.
+#[derive(Debug, Copy, Clone)]
+pub struct Route {
+    pub method: &'static str,
+    pub path: &'static str,
+    pub summary: &'static str,
+    /// Whether it needs `authorization: Bearer <token>`.
+    pub authenticated: bool,
+    pub parameters: &'static [Parameter],
+    pub request: Option<Content>,
+    pub response: Content,
+}
+
+
+/// The messages and enums of the descriptor set, by full name without the leading dot.
+struct Protos<'a> {
+    messages: HashMap<String, &'a DescriptorProto>,
+    enums: HashMap<String, &'a EnumDescriptorProto>,
+}
+
+impl<'a> Protos<'a> {
+    fn new(set: &'a FileDescriptorSet) -> Self {
+        fn add<'a>(protos: &mut Protos<'a>, prefix: &str, descriptor: &'a DescriptorProto) {
+            let name = format!("{}.{}", prefix, descriptor.name());
+            for nested in &descriptor.nested_type {
+                add(protos, &name, nested);
+            }
+            for nested in &descriptor.enum_type {
+                protos.enums.insert(format!("{}.{}", name, nested.name()), nested);
+            }
+            protos.messages.insert(name, descriptor);
+        }
+
+        let mut protos = Protos { messages: HashMap::new(), enums: HashMap::new() };
+        for file in &set.file {
+            for descriptor in &file.message_type {
+                add(&mut protos, file.package(), descriptor);
+            }
+            for descriptor in &file.enum_type {
+                protos.enums.insert(format!("{}.{}", file.package(), descriptor.name()), descriptor);
+            }
+        }
+        protos
+    }
+}
+
+/// The name a message goes by under `components/schemas`.
+fn schema_name(full_name: &str) -> &str {
+    let full_name = full_name.trim_start_matches('.');
+    full_name.strip_prefix(PACKAGE).and_then(|name| name.strip_prefix('.')).unwrap_or(full_name)
+}
+
+fn reference(full_name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", schema_name(full_name)) })
+}
+
+
+/// Builds the schemas of the messages the routes use, and of the messages those use in turn.
+struct Schemas<'a> {
+    protos: &'a Protos<'a>,
+    schemas: BTreeMap<String, Value>,
+}
+
+impl<'a> Schemas<'a> {
+    fn add(&mut self, full_name: &str) {
+        let full_name = full_name.trim_start_matches('.');
+        let name = schema_name(full_name).to_string();
+        if self.schemas.contains_key(&name) {
+            return;
+        }
+        let descriptor = match self.protos.messages.get(full_name) {
+            Some(descriptor) => *descriptor,
+            None => return,
+        };
+        // Taken first, so messages that contain themselves end.
+        self.schemas.insert(name.clone(), Value::Null);
+
+        let properties: Map<String, Value> = descriptor
+            .field
+            .iter()
+            .map(|field| (camel_case(field.name()), self.field(field)))
+            .collect();
+        self.schemas.insert(name, json!({ "type": "object", "properties": properties }));
+    }
+
+    /// Fields are written as `proto_json` writes them: 64-bit integers as strings, enums by
+    /// name and maps as objects.
+    fn field(&mut self, field: &FieldDescriptorProto) -> Value {
+        let single = match field.r#type() {
+            Type::Double => json!({ "type": "number", "format": "double" }),
+            Type::Float => json!({ "type": "number", "format": "float" }),
+            Type::Int32 | Type::Sint32 | Type::Sfixed32 => json!({ "type": "integer", "format": "int32" }),
+            Type::Uint32 | Type::Fixed32 => json!({ "type": "integer", "format": "int64", "minimum": 0 }),
+            Type::Int64 | Type::Sint64 | Type::Sfixed64 => json!({ "type": "string", "format": "int64" }),
+            Type::Uint64 | Type::Fixed64 => json!({ "type": "string", "format": "uint64" }),
+            Type::Bool => json!({ "type": "boolean" }),
+            Type::String => json!({ "type": "string" }),
+            Type::Bytes => json!({ "type": "string", "format": "byte" }),
+            Type::Enum => {
+                let names: Vec<&str> = self.protos.enums
+                    .get(field.type_name().trim_start_matches('.'))
+                    .map(|descriptor| descriptor.value.iter().map(|value| value.name()).collect())
+                    .unwrap_or_default();
+                json!({ "type": "string", "enum": names })
+            },
+            Type::Message => match field.type_name() {
+                ".google.protobuf.FieldMask" => json!({ "type": "string", "description": "Comma-separated field paths." }),
+                ".google.protobuf.Timestamp" => json!({ "type": "string", "format": "date-time" }),
+                ".google.protobuf.Any" => json!({
+                    "type": "object",
+                    "required": ["@type"],
+                    "properties": { "@type": { "type": "string" } },
+                    "description": "The type URL as @type, and the fields of the message next to it; base64 bytes as value for unknown types.",
+                }),
+                type_name => match self.map_entry(type_name) {
+                    Some(value) => return json!({ "type": "object", "additionalProperties": self.field(value) }),
+                    None => {
+                        self.add(type_name);
+                        reference(type_name)
+                    },
+                },
+            },
+            Type::Group => json!({}),
+        };
+
+        if field.label() == Label::Repeated {
+            json!({ "type": "array", "items": single })
+        } else {
+            single
+        }
+    }
+
+    /// The value field of a map's entry type.
+    fn map_entry(&self, type_name: &str) -> Option<&'a FieldDescriptorProto> {
+        let descriptor = self.protos.messages.get(type_name.trim_start_matches('.'))?;
+        if !descriptor.options.as_ref().is_some_and(|options| options.map_entry()) {
+            return None;
+        }
+        descriptor.field.iter().find(|field| field.name() == "value")
+    }
+
+    fn content(&mut self, content: Content) -> Value {
+        match content {
+            Content::Message(message) => {
+                self.add(&format!("{}.{}", PACKAGE, message));
+                json!({ "application/json": { "schema": reference(message) } })
+            },
+            Content::Lines(message) => {
+                self.add(&format!("{}.{}", PACKAGE, message));
+                json!({ "application/x-ndjson": { "schema": reference(message) } })
+            },
+            Content::Page { field, message } => {
+                self.add(&format!("{}.{}", PACKAGE, message));
+                json!({ "application/json": { "schema": {
+                    "type": "object",
+                    "properties": {
+                        field: { "type": "array", "items": reference(message) },
+                        "nextPageToken": { "type": "string", "description": "Empty on the last page." },
+                    },
+                } } })
+            },
+            Content::Other { content_type, description } => {
+                json!({ content_type: { "schema": { "type": "string", "description": description } } })
+            },
+        }
+    }
+}
+
+
+fn operation(schemas: &mut Schemas, route: &Route) -> Value {
+    let parameters: Vec<Value> = route.parameters
+        .iter()
+        .map(|parameter| json!({
+            "name": parameter.name,
+            "in": parameter.location,
+            "description": parameter.description,
+            "required": parameter.location == "path",
+            "schema": { "type": parameter.kind },
+        }))
+        .collect();
+
+    let mut operation = json!({
+        "summary": route.summary,
+        "parameters": parameters,
+        "responses": {
+            "200": { "description": "OK", "content": schemas.content(route.response) },
+            "default": { "description": "An error", "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Error" } } } },
+        },
+    });
+    if let Some(request) = route.request {
+        operation["requestBody"] = json!({ "required": true, "content": schemas.content(request) });
+    }
+    if route.authenticated {
+        operation["security"] = json!([{ "bearer": [] }]);
+    }
+    operation
+}
+
+/// An OpenAPI 3 document for `routes`. The schemas of the messages they use come from the
+/// protos' descriptor set.
+pub fn document(routes: &[Route]) -> Value {
+    let set = FileDescriptorSet::decode(FILE_DESCRIPTOR_SET).expect("the built-in descriptor set is valid");
+    let protos = Protos::new(&set);
+    let mut schemas = Schemas { protos: &protos, schemas: BTreeMap::new() };
+
+    let mut paths = Map::new();
+    for route in routes {
+        let operation = operation(&mut schemas, route);
+        let path = paths.entry(route.path).or_insert_with(|| json!({}));
+        path[route.method.to_lowercase()] = operation;
+    }
+
+    schemas.schemas.insert("Error".to_string(), json!({
+        "type": "object",
+        "properties": { "error": { "type": "string" } },
+    }));
+
+    json!({
+        "openapi": "3.0.3",
+        "info": { "title": "Route guide REST gateway", "version": env!("CARGO_PKG_VERSION") },
+        "paths": paths,
+        "components": {
+            "schemas": schemas.schemas,
+            "securitySchemes": { "bearer": { "type": "http", "scheme": "bearer" } },
+        },
+    })
+}
+
+/// The document of the gateway's routes, built once.
+pub fn gateway_document() -> &'static Value {
+    static DOCUMENT: Lazy<Value> = Lazy::new(|| document(crate::gateway::ROUTES));
+    &DOCUMENT
+}
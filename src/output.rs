@@ -0,0 +1,173 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::proto_json::ProtoJson;
+use crate::route_guide::{Feature, Point, RouteNote, RouteSummary};
+
+
+/// How the client prints the results it receives.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OutputFormat {
+    /// Newline-delimited JSON, one message per line, in the canonical protobuf JSON mapping.
+    Json,
+    /// Aligned columns with a header row.
+    Table,
+    /// Human readable lines.
+    Plain,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json"  => Ok(OutputFormat::Json),
+            "table" => Ok(OutputFormat::Table),
+            "plain" => Ok(OutputFormat::Plain),
+            other   => Err(format!("unknown output format '{}' (expected json, table or plain)", other)),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Json  => write!(f, "json"),
+            OutputFormat::Table => write!(f, "table"),
+            OutputFormat::Plain => write!(f, "plain"),
+        }
+    }
+}
+
+
+/// Which kind of rows the printer last emitted a table header for.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Header {
+    None,
+    Feature,
+    Summary,
+    Note,
+}
+
+/// Prints messages in the selected format. Streams are printed one message at a time, so the
+/// table widths are fixed rather than computed from the data.
+#[derive(Debug)]
+pub struct Printer {
+    format: OutputFormat,
+    header: Header,
+}
+
+impl Printer {
+    pub fn new(format: OutputFormat) -> Self {
+        Printer { format, header: Header::None }
+    }
+
+    /// Prints free-form text, e.g. section titles. Suppressed for JSON so the output stays
+    /// parseable.
+    pub fn message(&self, text: &str) {
+        if self.format != OutputFormat::Json {
+            println!("{}", text);
+        }
+    }
+
+    /// Redraws a single status line, like a progress bar, on stderr. Call `message` (or pass an
+    /// empty line) when done to move past it. Suppressed for JSON like `message`.
+    pub fn progress(&self, text: &str) {
+        if self.format != OutputFormat::Json {
+            eprint!("\r{}", text);
+        }
+    }
+
+    pub fn feature(&mut self, feature: &Feature) {
+        match self.format {
+            OutputFormat::Json => println!("{}", feature.to_json()),
+            OutputFormat::Table => {
+                self.table_header(Header::Feature, &format!("{:>12}  {:>12}  {}", "LATITUDE", "LONGITUDE", "NAME"));
+                let (latitude, longitude) = coordinates(feature.location.as_ref());
+                println!("{:>12}  {:>12}  {}", latitude, longitude, feature.name);
+            },
+            OutputFormat::Plain => {
+                let (latitude, longitude) = coordinates(feature.location.as_ref());
+                if feature.name.is_empty() {
+                    println!("No feature at ({}, {})", latitude, longitude);
+                } else {
+                    println!("{} at ({}, {})", feature.name, latitude, longitude);
+                }
+            },
+        }
+    }
+
+    pub fn summary(&mut self, summary: &RouteSummary) {
+        match self.format {
+            OutputFormat::Json => println!("{}", summary.to_json()),
+            OutputFormat::Table => {
+                self.table_header(Header::Summary, &format!(
+                    "{:>8}  {:>8}  {:>12}  {:>8}", "POINTS", "FEATURES", "DISTANCE (M)", "SECONDS"
+                ));
+                println!(
+                    "{:>8}  {:>8}  {:>12}  {:>8}",
+                    summary.point_count, summary.feature_count, summary.distance, summary.elapsed_time
+                );
+            },
+            OutputFormat::Plain => println!(
+                "Traversed {} points, passed {} features, covered {} metres in {} seconds",
+                summary.point_count, summary.feature_count, summary.distance, summary.elapsed_time
+            ),
+        }
+        if self.format != OutputFormat::Json && !summary.polyline.is_empty() {
+            println!("Path: {}", summary.polyline);
+            if summary.polyline_truncated {
+                println!("(The path stops early: the route was too long for the whole of it.)");
+            }
+        }
+    }
+
+    pub fn note(&mut self, note: &RouteNote) {
+        match self.format {
+            OutputFormat::Json => println!("{}", note.to_json()),
+            OutputFormat::Table => {
+                self.table_header(Header::Note, &format!(
+                    "{:>12}  {:>12}  {:8}  {:16}  {}", "LATITUDE", "LONGITUDE", "TIME", "SENDER", "MESSAGE"
+                ));
+                let (latitude, longitude) = coordinates(note.location.as_ref());
+                println!(
+                    "{:>12}  {:>12}  {:8}  {:16}  {}",
+                    latitude, longitude, time_of_day(note.posted_at_ms), note.sender, note.message
+                );
+            },
+            OutputFormat::Plain => {
+                let (latitude, longitude) = coordinates(note.location.as_ref());
+                if note.sender.is_empty() {
+                    println!("({}, {}): {}", latitude, longitude, note.message);
+                } else {
+                    println!(
+                        "[{}] {}{} at ({}, {}): {}",
+                        time_of_day(note.posted_at_ms), note.sender, if note.to_user.is_empty() { "" } else { " (direct)" },
+                        latitude, longitude, note.message
+                    );
+                }
+            },
+        }
+    }
+
+    fn table_header(&mut self, header: Header, text: &str) {
+        if self.header != header {
+            println!("{}", text);
+            self.header = header;
+        }
+    }
+}
+
+
+/// `HH:MM:SS` in UTC, or empty for 0 (unknown).
+fn time_of_day(millis: u64) -> String {
+    if millis == 0 {
+        return String::new();
+    }
+    let seconds = millis / 1000 % 86_400;
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+fn coordinates(point: Option<&Point>) -> (i32, i32) {
+    point.map_or((0, 0), |point| (point.latitude, point.longitude))
+}
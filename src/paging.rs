@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use tonic::body::BoxBody;
+use tonic::client::GrpcService;
+use tonic::codegen::{Body, HttpBody, StdError};
+use tonic::{Code, Status};
+
+use crate::route_guide::route_guide_client::RouteGuideClient;
+use crate::route_guide::{Feature, Rectangle};
+use crate::streaming::StreamingExt;
+
+
+/// How a resuming ListFeatures stream reconnects. The wait before each attempt doubles from
+/// `backoff` up to `max_backoff`, and starts over once a feature arrives.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ResumePolicy {
+    pub max_attempts: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for ResumePolicy {
+    fn default() -> Self {
+        ResumePolicy { max_attempts: 5, backoff: Duration::from_millis(200), max_backoff: Duration::from_secs(10) }
+    }
+}
+
+/// Errors from a broken connection, which another call may not have.
+fn is_transport_error(status: &Status) -> bool {
+    matches!(status.code(), Code::Unavailable | Code::Unknown | Code::DeadlineExceeded)
+}
+
+
+/// The features in `rectangle`, or RESOURCE_EXHAUSTED once there are more than `limit`.
+pub async fn list_features<T>(client: &mut RouteGuideClient<T>, rectangle: Rectangle, limit: usize) -> Result<Vec<Feature>, Status>
+    where
+        T: GrpcService<BoxBody>,
+        T::ResponseBody: Body + HttpBody + Send + 'static,
+        T::Error: Into<StdError>,
+        <T::ResponseBody as HttpBody>::Error: Into<StdError> + Send,
+{
+    client.list_features(rectangle).await?.into_inner().collect_limited(limit).await
+}
+
+/// The first `count` features in `rectangle`, or all of them if there are fewer. The call is
+/// cancelled once there are enough, so the server stops sending.
+pub async fn first_features<T>(client: &mut RouteGuideClient<T>, rectangle: Rectangle, count: usize) -> Result<Vec<Feature>, Status>
+    where
+        T: GrpcService<BoxBody>,
+        T::ResponseBody: Body + HttpBody + Send + 'static,
+        T::Error: Into<StdError>,
+        <T::ResponseBody as HttpBody>::Error: Into<StdError> + Send,
+{
+    let mut stream = client.list_features(rectangle).await?.into_inner();
+    let mut features = Vec::with_capacity(count.min(1024));
+    while features.len() < count {
+        match stream.message().await? {
+            Some(feature) => features.push(feature),
+            None => break,
+        }
+    }
+    Ok(features)
+}
+
+/// The features in `rectangle`, as ListFeatures sends them, calling again when the stream
+/// breaks with `resume_after` set to the last feature received, so every feature comes once.
+/// Errors other than a broken connection end the stream, as does running out of attempts.
+///
+/// A `read_mask` gets `id` added, since resuming needs the features' ids. Clustered listings
+/// can't resume; they're only called again if nothing had arrived yet. Resuming fails with
+/// FAILED_PRECONDITION if the last feature was removed in the meantime.
+pub fn resuming_features<T>(client: RouteGuideClient<T>, mut rectangle: Rectangle, policy: ResumePolicy) -> BoxStream<'static, Result<Feature, Status>>
+    where
+        T: GrpcService<BoxBody> + Send + 'static,
+        T::Future: Send,
+        T::ResponseBody: Body + HttpBody + Send + 'static,
+        T::Error: Into<StdError>,
+        <T::ResponseBody as HttpBody>::Error: Into<StdError> + Send,
+{
+    if let Some(mask) = &mut rectangle.read_mask {
+        if !mask.paths.iter().any(|path| path == "id") {
+            mask.paths.push("id".to_string());
+        }
+    }
+
+    async_stream::stream! {
+        let mut client = client;
+        let mut received = false;
+        let mut attempt = 0;
+        loop {
+            let error = match client.list_features(rectangle.clone()).await {
+                Ok(response) => {
+                    let mut stream = response.into_inner();
+                    loop {
+                        match stream.message().await {
+                            Ok(Some(feature)) => {
+                                attempt = 0;
+                                received = true;
+                                if !feature.id.is_empty() {
+                                    rectangle.resume_after = feature.id.clone();
+                                }
+                                yield Ok(feature);
+                            },
+                            Ok(None) => return,
+                            Err(error) => break error,
+                        }
+                    }
+                },
+                Err(error) => error,
+            };
+
+            let resumable = rectangle.cluster.is_none() || !received;
+            if !resumable || !is_transport_error(&error) || attempt >= policy.max_attempts {
+                yield Err(error);
+                return;
+            }
+            attempt += 1;
+            tracing::debug!(attempt, error = %error.message(), resume_after = %rectangle.resume_after, "resuming ListFeatures");
+            let backoff = policy.backoff * 2u32.saturating_pow(attempt - 1);
+            tokio::time::delay_for(backoff.min(policy.max_backoff)).await;
+        }
+    }.boxed()
+}
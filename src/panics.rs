@@ -0,0 +1,190 @@
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures::FutureExt;
+use http_body::Body as HttpBody;
+use hyper::header::HeaderValue;
+use hyper::{Body, HeaderMap, Request, Response};
+use once_cell::sync::Lazy;
+use tonic::body::BoxBody;
+use tonic::transport::NamedService;
+use tonic::Status;
+use tower::Service;
+
+use crate::ids::{IdGenerator, Ulids};
+use crate::message_metrics::method_label;
+use crate::metrics;
+
+
+static INCIDENTS: Lazy<Ulids> = Lazy::new(Ulids::default);
+
+thread_local! {
+    /// The incident of the last panic on this thread, for whoever catches it.
+    static LAST_INCIDENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Logs every panic at error level with its backtrace and a new incident ID, instead of
+/// printing it to stderr. `CatchPanic` answers with the same ID, so a client's report can be
+/// matched to the log.
+pub fn install_hook() {
+    panic::set_hook(Box::new(|info| {
+        let incident = INCIDENTS.next_id();
+        let location = info.location().map_or_else(|| "unknown".to_string(), |location| location.to_string());
+        tracing::error!(
+            incident = %incident,
+            location = %location,
+            backtrace = %Backtrace::force_capture(),
+            "panicked: {}", message(info.payload()),
+        );
+        LAST_INCIDENT.with(|last| *last.borrow_mut() = Some(incident));
+    }));
+}
+
+fn message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("(not a string)")
+}
+
+/// Counts a panic caught on this thread and makes the status for it. Without the hook there's
+/// no backtrace, and the panic is logged here.
+fn caught(path: &str, payload: &(dyn Any + Send)) -> Status {
+    let incident = LAST_INCIDENT.with(|last| last.borrow_mut().take()).unwrap_or_else(|| {
+        let incident = INCIDENTS.next_id();
+        tracing::error!(incident = %incident, "panicked: {}", message(payload));
+        incident
+    });
+    metrics::registry()
+        .counter("grpc_panics_total", "Panics in gRPC handlers, answered with INTERNAL.", &[("method", method_label(path))])
+        .inc();
+    Status::internal(format!("internal error, incident {}", incident))
+}
+
+fn status_headers(status: &Status) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("grpc-status", HeaderValue::from(status.code() as i32));
+    if let Ok(message) = HeaderValue::from_str(status.message()) {
+        headers.insert("grpc-message", message);
+    }
+    headers
+}
+
+/// The trailers-only answer for a call whose handler panicked.
+fn trailers_only(status: &Status) -> Response<BoxBody> {
+    let mut response = Response::builder()
+        .status(200)
+        .header("content-type", "application/grpc")
+        .body(BoxBody::empty())
+        .unwrap();
+    response.headers_mut().extend(status_headers(status));
+    response
+}
+
+
+/// Response body that ends with an INTERNAL status if producing the next message panics,
+/// instead of taking the connection down with it.
+struct CatchPanicBody {
+    inner: BoxBody,
+    path: String,
+    failed: Option<Status>,
+}
+
+impl HttpBody for CatchPanicBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = &mut *self;
+        if this.failed.is_some() {
+            return Poll::Ready(None);
+        }
+        match panic::catch_unwind(AssertUnwindSafe(|| Pin::new(&mut this.inner).poll_data(cx))) {
+            Ok(poll) => poll,
+            Err(payload) => {
+                this.failed = Some(caught(&this.path, &*payload));
+                Poll::Ready(None)
+            },
+        }
+    }
+
+    fn poll_trailers(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let this = &mut *self;
+        if this.failed.is_none() {
+            match panic::catch_unwind(AssertUnwindSafe(|| Pin::new(&mut this.inner).poll_trailers(cx))) {
+                Ok(poll) => return poll,
+                Err(payload) => this.failed = Some(caught(&this.path, &*payload)),
+            }
+        }
+        Poll::Ready(Ok(this.failed.as_ref().map(status_headers)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.failed.is_none() && self.inner.is_end_stream()
+    }
+}
+
+
+/// Answers calls to `inner` whose handler panics with INTERNAL, also when the panic comes while a
+/// response stream is being produced, and counts them in `grpc_panics_total`. The status names
+/// the incident the panic was logged under (see `install_hook`).
+///
+/// Tasks a handler spawns are on their own; `TaskTracker` catches and logs their panics.
+#[derive(Debug, Clone)]
+pub struct CatchPanic<S> {
+    pub inner: S,
+}
+
+impl<S> Service<Request<Body>> for CatchPanic<S>
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>>
+        + NamedService
+        + Clone
+        + Send
+        + 'static,
+        S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let mut svc = self.inner.clone();
+        let path = request.uri().path().to_string();
+
+        Box::pin(async move {
+            // `call` itself is inside, since handlers start running there.
+            match AssertUnwindSafe(async { svc.call(request).await }).catch_unwind().await {
+                Ok(response) => Ok(response?.map(|body| BoxBody::new(CatchPanicBody { inner: body, path, failed: None }))),
+                Err(payload) => Ok(trailers_only(&caught(&path, &*payload))),
+            }
+        })
+    }
+}
+
+impl<S: NamedService> NamedService for CatchPanic<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+/// Runs `future`, logging and counting a panic instead of letting it end the task unnoticed.
+pub async fn catch_task<F>(task: &'static str, future: F)
+    where F: std::future::Future<Output = ()>,
+{
+    if let Err(payload) = AssertUnwindSafe(future).catch_unwind().await {
+        let incident = LAST_INCIDENT.with(|last| last.borrow_mut().take()).unwrap_or_default();
+        tracing::error!(task, incident = %incident, "a background task panicked: {}", message(&*payload));
+        metrics::registry()
+            .counter("background_task_panics_total", "Panics in background tasks spawned by handlers.", &[("task", task)])
+            .inc();
+    }
+}
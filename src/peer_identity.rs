@@ -0,0 +1,53 @@
+use tonic::Request;
+use x509_parser::extensions::GeneralName;
+
+
+/// Who the client is, according to the certificate it presented with mutual TLS.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerIdentity {
+    /// The subject, like `CN=maps-backend,O=Example`.
+    pub subject: String,
+    pub common_name: Option<String>,
+    /// DNS names, email addresses and URIs from the subject alternative names.
+    pub alt_names: Vec<String>,
+}
+
+impl PeerIdentity {
+    /// Parses the client's leaf certificate.
+    pub fn from_der(certificate: &[u8]) -> Option<Self> {
+        let (_, parsed) = x509_parser::parse_x509_certificate(certificate).ok()?;
+        let tbs = &parsed.tbs_certificate;
+
+        let common_name = tbs.subject.iter_common_name().next().and_then(|cn| cn.as_str().ok()).map(str::to_string);
+        let alt_names = tbs.subject_alternative_name().map_or(vec![], |(_, san)| {
+            san.general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(name) | GeneralName::RFC822Name(name) | GeneralName::URI(name) => Some(name.to_string()),
+                    _ => None,
+                })
+                .collect()
+        });
+
+        Some(PeerIdentity { subject: tbs.subject.to_string(), common_name, alt_names })
+    }
+
+    /// The common name followed by the alternative names.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.common_name.iter().chain(&self.alt_names).map(String::as_str)
+    }
+}
+
+
+/// Typed access to the client certificate of a request.
+pub trait PeerIdentityExt {
+    /// The identity from the client's certificate, if the connection used mutual TLS.
+    fn peer_identity(&self) -> Option<PeerIdentity>;
+}
+
+impl<T> PeerIdentityExt for Request<T> {
+    fn peer_identity(&self) -> Option<PeerIdentity> {
+        let certificates = self.peer_certs()?;
+        PeerIdentity::from_der(certificates.first()?.as_ref())
+    }
+}
@@ -0,0 +1,428 @@
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::admin::Quota;
+use crate::geo::Bounds;
+use crate::metrics;
+use crate::request_context::RequestContext;
+use crate::route_guide::route_guide_server::RouteGuide;
+use crate::route_guide::{ExportRequest, Feature, ImportSummary, Point, PointWithTimestamp, Rectangle, ReplicateRequest, Room, RoomMembers, RouteNote, RouteSummary, TimeRange};
+
+
+/// What a call asks for, beyond who makes it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Resource {
+    /// The area of a ListFeatures rectangle, in square kilometres.
+    pub area_km2: Option<f64>,
+    /// The tags of the feature an AddFeature call adds.
+    pub tags: Vec<String>,
+}
+
+impl Resource {
+    /// Adding `feature`, whether by AddFeature or ImportFeatures.
+    pub fn feature(feature: &Feature) -> Resource {
+        Resource { tags: feature.tags.clone(), ..Resource::default() }
+    }
+
+    /// Listing the features in `rect`.
+    pub fn rectangle(rect: &Rectangle) -> Resource {
+        Resource { area_km2: Bounds::of(rect).map(|bounds| bounds.area() / 1e6), ..Resource::default() }
+    }
+
+    /// Listing every feature, as the whole world.
+    pub fn everywhere() -> Resource {
+        let corner = |latitude, longitude| Some(Point { latitude, longitude, read_mask: None });
+        Resource::rectangle(&Rectangle { lo: corner(-900_000_000, -1_800_000_000), hi: corner(900_000_000, 1_800_000_000), ..Rectangle::default() })
+    }
+}
+
+/// A call, as rules see it.
+#[derive(Debug, Clone, Copy)]
+pub struct Call<'a> {
+    /// The RouteGuide method, like `ListFeatures`.
+    pub method: &'a str,
+    /// Who makes it, like `token:default` or `certificate:alice`.
+    pub subject: &'a str,
+    pub tenant: &'a str,
+    pub resource: &'a Resource,
+}
+
+
+/// `*` matches anything, `prefix*` whatever starts with the prefix, anything else itself.
+fn matches(pattern: &str, value: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => value.starts_with(prefix),
+        None => pattern == value,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+    Subject(String),
+    Tenant(String),
+    Tag(String),
+    MaxAreaKm2(f64),
+}
+
+impl Condition {
+    fn parse(word: &str) -> Result<Condition, String> {
+        let i = word.find('=').ok_or_else(|| format!("'{}' isn't a condition like subject=token:*", word))?;
+        let (name, value) = (&word[..i], &word[i + 1..]);
+        if value.is_empty() {
+            return Err(format!("'{}' has no value", name));
+        }
+        match name {
+            "subject" => Ok(Condition::Subject(value.to_string())),
+            "tenant" => Ok(Condition::Tenant(value.to_string())),
+            "tag" => Ok(Condition::Tag(value.to_string())),
+            "max-area-km2" => value
+                .parse()
+                .ok()
+                .filter(|area: &f64| *area >= 0.0)
+                .map(Condition::MaxAreaKm2)
+                .ok_or_else(|| format!("max-area-km2 is a number of square kilometres, not '{}'", value)),
+            other => Err(format!("unknown condition '{}' (expected subject, tenant, tag or max-area-km2)", other)),
+        }
+    }
+
+    /// Conditions on a resource the call doesn't have, like an area for AddFeature, don't hold.
+    fn holds(&self, call: &Call) -> bool {
+        match self {
+            Condition::Subject(pattern) => matches(pattern, call.subject),
+            Condition::Tenant(pattern) => matches(pattern, call.tenant),
+            Condition::Tag(tag) => call.resource.tags.iter().any(|t| t == tag),
+            Condition::MaxAreaKm2(max) => call.resource.area_km2.is_some_and(|area| area <= *max),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    allow: bool,
+    method: String,
+    conditions: Vec<Condition>,
+    line: usize,
+}
+
+/// Who may call which RouteGuide methods, as rules tried in order: the first one whose method
+/// and conditions all match decides, and without one the default does.
+///
+/// ```text
+/// # <allow|deny> <method or *> [condition...]
+/// default allow
+/// allow ListFeatures max-area-km2=10000
+/// deny ListFeatures subject=token:*
+/// deny AddFeature tag=restricted subject=token:*
+/// deny DeleteFeature tenant=shared
+/// ```
+///
+/// Conditions are `subject=`, `tenant=` (both take a trailing `*`), `tag=`, which holds if the
+/// added feature has the tag, and `max-area-km2=`, which holds for ListFeatures rectangles up to
+/// that size.
+///
+/// Each feature of an ImportFeatures call is checked as an AddFeature, and one it replaces as a
+/// DeleteFeature. The REST gateway's requests are checked as the methods they stand for, and
+/// its listings of all features as ListFeatures of the whole world.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    rules: Vec<Rule>,
+    default_allow: bool,
+}
+
+impl Default for Policy {
+    /// Allows everything, as before there were policies.
+    fn default() -> Self {
+        Policy { rules: vec![], default_allow: true }
+    }
+}
+
+impl Policy {
+    pub fn parse(text: &str) -> Result<Policy, String> {
+        let mut policy = Policy::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let at = |e: String| format!("line {}: {}", i + 1, e);
+
+            let mut words = line.split_whitespace();
+            let (kind, method) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+            let allow = match kind {
+                "default" => {
+                    policy.default_allow = match (method, words.next()) {
+                        ("allow", None) => true,
+                        ("deny", None) => false,
+                        _ => return Err(at("expected `default allow` or `default deny`".to_string())),
+                    };
+                    continue;
+                },
+                "allow" => true,
+                "deny" => false,
+                other => return Err(at(format!("unknown rule '{}' (expected allow, deny or default)", other))),
+            };
+            if method.is_empty() {
+                return Err(at(format!("'{}' needs a method, or *", kind)));
+            }
+            let conditions = words.map(Condition::parse).collect::<Result<Vec<_>, _>>().map_err(at)?;
+            policy.rules.push(Rule { allow, method: method.to_string(), conditions, line: i + 1 });
+        }
+        Ok(policy)
+    }
+
+    /// Allows the call, or says which rule denied it.
+    pub fn check(&self, call: &Call) -> Result<(), String> {
+        let rule = self.rules
+            .iter()
+            .find(|rule| matches(&rule.method, call.method) && rule.conditions.iter().all(|condition| condition.holds(call)));
+        match rule {
+            Some(rule) if rule.allow => Ok(()),
+            Some(rule) => Err(format!("{} is denied by rule {} of the policy", call.method, rule.line)),
+            None if self.default_allow => Ok(()),
+            None => Err(format!("{} is denied by the default policy", call.method)),
+        }
+    }
+}
+
+
+/// The policy in force, and the file it was read from. Reloading swaps it whole, so a broken
+/// file leaves the old policy in place.
+#[derive(Debug, Default)]
+pub struct PolicyEngine {
+    path: Option<PathBuf>,
+    policy: RwLock<Arc<Policy>>,
+}
+
+impl PolicyEngine {
+    /// Reads the policy from `path`.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, String> {
+        let engine = PolicyEngine { path: Some(path.into()), policy: RwLock::default() };
+        engine.reload()?;
+        Ok(engine)
+    }
+
+    /// A fixed policy, with no file to reload.
+    pub fn with_policy(policy: Policy) -> Self {
+        PolicyEngine { path: None, policy: RwLock::new(Arc::new(policy)) }
+    }
+
+    /// Reads the file again. Without one this does nothing.
+    pub fn reload(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let policy = Policy::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        *self.policy.write().unwrap() = Arc::new(policy);
+        Ok(())
+    }
+
+    pub fn policy(&self) -> Arc<Policy> {
+        self.policy.read().unwrap().clone()
+    }
+
+    /// Fails with PERMISSION_DENIED if the policy denies the call.
+    pub fn check<T>(&self, request: &Request<T>, method: &str, resource: &Resource) -> Result<(), Status> {
+        self.check_context(&RequestContext::of(request)?, method, resource)
+    }
+
+    /// `check`, for the caller of a context, as for each feature of an ImportFeatures call.
+    pub fn check_context(&self, context: &RequestContext, method: &str, resource: &Resource) -> Result<(), Status> {
+        self.check_call(&Call { method, subject: &context.subject, tenant: context.tenant.as_str(), resource })
+    }
+
+    /// `check`, for callers that don't come in over gRPC, like the REST gateway's.
+    pub fn check_call(&self, call: &Call) -> Result<(), Status> {
+        self.policy().check(call).map_err(|e| {
+            metrics::registry()
+                .counter("policy_denied_calls_total", "RouteGuide calls denied by the authorization policy.", &[("method", call.method)])
+                .inc();
+            Status::permission_denied(e)
+        })
+    }
+}
+
+
+/// Checks each call to the RouteGuide methods against the policy before `inner` sees it. Meant
+/// to go inside `Validated`, so rectangles are checked ones.
+///
+/// The messages of client streams arrive after the call is checked, so RecordRoute, RouteChat
+/// and ImportFeatures are checked without a resource. The handler checks each imported feature
+/// as an AddFeature of it, with `check_context` (see `import::import`).
+#[derive(Debug)]
+pub struct Authorized<S> {
+    pub inner: S,
+    pub policy: Arc<PolicyEngine>,
+}
+
+impl<S> Authorized<S> {
+    fn check<T>(&self, request: &Request<T>, method: &str) -> Result<(), Status> {
+        self.policy.check(request, method, &Resource::default())
+    }
+}
+
+#[tonic::async_trait]
+impl<S: RouteGuide> RouteGuide for Authorized<S> {
+    type ListFeaturesStream = S::ListFeaturesStream;
+    type RouteChatStream = S::RouteChatStream;
+    type GetNotesAtStream = S::GetNotesAtStream;
+    type ListChangesStream = S::ListChangesStream;
+    type ExportFeaturesStream = S::ExportFeaturesStream;
+    type ReplicateStream = S::ReplicateStream;
+    type WatchConfigStream = S::WatchConfigStream;
+
+    async fn get_feature(&self, request: Request<Point>) -> Result<Response<Feature>, Status> {
+        self.check(&request, "GetFeature")?;
+        self.inner.get_feature(request).await
+    }
+
+    async fn list_features(&self, request: Request<Rectangle>) -> Result<Response<Self::ListFeaturesStream>, Status> {
+        let resource = Resource::rectangle(request.get_ref());
+        self.policy.check(&request, "ListFeatures", &resource)?;
+        self.inner.list_features(request).await
+    }
+
+    async fn record_route(&self, request: Request<Streaming<Point>>) -> Result<Response<RouteSummary>, Status> {
+        self.check(&request, "RecordRoute")?;
+        self.inner.record_route(request).await
+    }
+
+    async fn route_chat(&self, request: Request<Streaming<RouteNote>>) -> Result<Response<Self::RouteChatStream>, Status> {
+        self.check(&request, "RouteChat")?;
+        self.inner.route_chat(request).await
+    }
+
+    async fn add_feature(&self, request: Request<Feature>) -> Result<Response<Feature>, Status> {
+        let resource = Resource::feature(request.get_ref());
+        self.policy.check(&request, "AddFeature", &resource)?;
+        self.inner.add_feature(request).await
+    }
+
+    async fn delete_feature(&self, request: Request<Point>) -> Result<Response<Feature>, Status> {
+        self.check(&request, "DeleteFeature")?;
+        self.inner.delete_feature(request).await
+    }
+
+    async fn get_notes_at(&self, request: Request<Point>) -> Result<Response<Self::GetNotesAtStream>, Status> {
+        self.check(&request, "GetNotesAt")?;
+        self.inner.get_notes_at(request).await
+    }
+
+    async fn import_features(&self, request: Request<Streaming<Feature>>) -> Result<Response<ImportSummary>, Status> {
+        self.check(&request, "ImportFeatures")?;
+        self.inner.import_features(request).await
+    }
+
+    async fn get_feature_as_of(&self, request: Request<PointWithTimestamp>) -> Result<Response<Feature>, Status> {
+        self.check(&request, "GetFeatureAsOf")?;
+        self.inner.get_feature_as_of(request).await
+    }
+
+    async fn list_changes(&self, request: Request<TimeRange>) -> Result<Response<Self::ListChangesStream>, Status> {
+        self.check(&request, "ListChanges")?;
+        self.inner.list_changes(request).await
+    }
+
+    async fn export_features(&self, request: Request<ExportRequest>) -> Result<Response<Self::ExportFeaturesStream>, Status> {
+        self.check(&request, "ExportFeatures")?;
+        self.inner.export_features(request).await
+    }
+
+    async fn replicate(&self, request: Request<ReplicateRequest>) -> Result<Response<Self::ReplicateStream>, Status> {
+        self.check(&request, "Replicate")?;
+        self.inner.replicate(request).await
+    }
+
+    async fn watch_config(&self, request: Request<()>) -> Result<Response<Self::WatchConfigStream>, Status> {
+        self.check(&request, "WatchConfig")?;
+        self.inner.watch_config(request).await
+    }
+
+    async fn list_room_members(&self, request: Request<Room>) -> Result<Response<RoomMembers>, Status> {
+        self.check(&request, "ListRoomMembers")?;
+        self.inner.list_room_members(request).await
+    }
+
+    async fn get_quota(&self, request: Request<()>) -> Result<Response<Quota>, Status> {
+        self.check(&request, "GetQuota")?;
+        self.inner.get_quota(request).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = "
+        # Token holders list small areas at most, and can't add restricted features.
+        allow ListFeatures max-area-km2=10000
+        deny ListFeatures subject=token:*
+        deny AddFeature tag=restricted subject=token:*
+        deny DeleteFeature tenant=shared*
+        allow * subject=certificate:*
+        default deny
+    ";
+
+    fn check(method: &str, subject: &str, tenant: &str, resource: Resource) -> Result<(), String> {
+        Policy::parse(POLICY).unwrap().check(&Call { method, subject, tenant, resource: &resource })
+    }
+
+    fn area(km2: f64) -> Resource {
+        Resource { area_km2: Some(km2), ..Resource::default() }
+    }
+
+    fn tagged(tag: &str) -> Resource {
+        Resource { tags: vec![tag.to_string()], ..Resource::default() }
+    }
+
+    #[test]
+    fn the_first_matching_rule_decides() {
+        assert_eq!(check("ListFeatures", "token:default", "default", area(10000.0)), Ok(()));
+        assert_eq!(check("ListFeatures", "token:default", "default", area(10001.0)), Err("ListFeatures is denied by rule 4 of the policy".to_string()));
+        assert_eq!(check("ListFeatures", "certificate:alice", "default", area(1e9)), Ok(()));
+    }
+
+    #[test]
+    fn conditions_on_what_the_call_lacks_dont_hold() {
+        // No area, as for a listing of everything: the allow rule doesn't apply.
+        assert!(check("ListFeatures", "token:default", "default", Resource::default()).is_err());
+        assert!(check("ListFeatures", "token:default", "default", Resource::everywhere()).is_err());
+    }
+
+    #[test]
+    fn tags_and_patterns() {
+        assert!(check("AddFeature", "token:default", "default", tagged("restricted")).is_err());
+        assert_eq!(check("AddFeature", "certificate:alice", "default", tagged("restricted")), Ok(()));
+        assert!(check("DeleteFeature", "certificate:alice", "shared-eu", Resource::default()).is_err());
+        assert_eq!(check("DeleteFeature", "certificate:alice", "private", Resource::default()), Ok(()));
+    }
+
+    #[test]
+    fn the_default_decides_the_rest() {
+        assert_eq!(check("AddFeature", "token:default", "default", tagged("open")), Err("AddFeature is denied by the default policy".to_string()));
+        assert_eq!(Policy::parse("").unwrap(), Policy::default());
+        assert!(Policy::default().check(&Call { method: "AddFeature", subject: "", tenant: "", resource: &Resource::default() }).is_ok());
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        assert_eq!(Policy::parse("allow *\npermit GetFeature"), Err("line 2: unknown rule 'permit' (expected allow, deny or default)".to_string()));
+        assert_eq!(Policy::parse("deny"), Err("line 1: 'deny' needs a method, or *".to_string()));
+        assert_eq!(Policy::parse("default maybe"), Err("line 1: expected `default allow` or `default deny`".to_string()));
+        assert!(Policy::parse("allow ListFeatures max-area-km2=-1").unwrap_err().starts_with("line 1: max-area-km2"));
+        assert!(Policy::parse("allow * colour=red").unwrap_err().starts_with("line 1: unknown condition 'colour'"));
+        assert!(Policy::parse("allow * subject=").unwrap_err().contains("has no value"));
+        assert!(Policy::parse("allow * subject").unwrap_err().contains("isn't a condition"));
+    }
+
+    #[test]
+    fn everywhere_is_the_whole_world() {
+        let km2 = Resource::everywhere().area_km2.unwrap();
+        // The earth's surface is about 510 million square kilometres.
+        assert!((5.0e8..5.2e8).contains(&km2), "{}", km2);
+    }
+}
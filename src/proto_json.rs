@@ -0,0 +1,785 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use prost_types::{Any, FieldMask};
+use serde_json::{Map, Value};
+
+use crate::attributes;
+use crate::route_guide::change_event::Action;
+use crate::route_guide::opening_hours::Period;
+use crate::route_guide::replication_event::Kind;
+use crate::route_guide::{
+    ChangeEvent, ClientConfig, Clustering, ExportRequest, ExportTrailer, Feature, FeatureChunk, ImportFailure, ImportSummary,
+    OpeningHours, Photo, Point, PointWithTimestamp, Rating, Rectangle, Rejection, ReplicateRequest, ReplicationEvent,
+    RouteNote, RouteSummary, TimeRange,
+};
+
+
+/// The canonical protobuf JSON mapping of a message: fields in lowerCamelCase, fields with their
+/// default value left out, 64 bit integers as strings and field masks as one comma-separated
+/// string. Parsing also takes the proto field names and 64 bit integers as numbers, as the
+/// mapping requires.
+pub trait ProtoJson: Sized {
+    fn to_json(&self) -> Value;
+
+    fn from_json(value: &Value) -> Result<Self, String>;
+}
+
+/// Parses a message from JSON text.
+pub fn parse<T: ProtoJson>(text: &[u8]) -> Result<T, String> {
+    let value: Value = serde_json::from_slice(text).map_err(|e| e.to_string())?;
+    T::from_json(&value)
+}
+
+
+/// `names_by_locale` to `namesByLocale`.
+pub fn camel_case(name: &str) -> String {
+    let mut camel = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            camel.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+    camel
+}
+
+/// `namesByLocale` to `names_by_locale`.
+pub fn snake_case(name: &str) -> String {
+    let mut snake = String::with_capacity(name.len() + 4);
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            snake.push('_');
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+
+/// Collects the fields of a message being written, leaving out those with default values.
+#[derive(Default)]
+struct Writer(Map<String, Value>);
+
+impl Writer {
+    fn string(mut self, name: &str, value: &str) -> Self {
+        if !value.is_empty() {
+            self.0.insert(name.to_string(), Value::from(value));
+        }
+        self
+    }
+
+    fn boolean(mut self, name: &str, value: bool) -> Self {
+        if value {
+            self.0.insert(name.to_string(), Value::from(true));
+        }
+        self
+    }
+
+    fn int(mut self, name: &str, value: i64) -> Self {
+        if value != 0 {
+            self.0.insert(name.to_string(), Value::from(value));
+        }
+        self
+    }
+
+    fn float(mut self, name: &str, value: f32) -> Self {
+        if value != 0.0 {
+            self.0.insert(name.to_string(), Value::from(value as f64));
+        }
+        self
+    }
+
+    fn double(mut self, name: &str, value: f64) -> Self {
+        if value != 0.0 {
+            self.0.insert(name.to_string(), Value::from(value));
+        }
+        self
+    }
+
+    /// 64 bit integers are strings, since JSON numbers lose precision past 2^53.
+    fn uint64(mut self, name: &str, value: u64) -> Self {
+        if value != 0 {
+            self.0.insert(name.to_string(), Value::from(value.to_string()));
+        }
+        self
+    }
+
+    /// Enums are written by the name of their value, or its number if it has none.
+    fn enumeration(mut self, name: &str, value: i32, names: &[(i32, &str)]) -> Self {
+        if value != 0 {
+            let json = match names.iter().find(|&&(number, _)| number == value) {
+                Some(&(_, value_name)) => Value::from(value_name),
+                None => Value::from(value),
+            };
+            self.0.insert(name.to_string(), json);
+        }
+        self
+    }
+
+    fn message<T: ProtoJson>(mut self, name: &str, value: Option<&T>) -> Self {
+        if let Some(value) = value {
+            self.0.insert(name.to_string(), value.to_json());
+        }
+        self
+    }
+
+    fn repeated<T>(mut self, name: &str, values: &[T], to_json: impl Fn(&T) -> Value) -> Self {
+        if !values.is_empty() {
+            self.0.insert(name.to_string(), Value::from(values.iter().map(to_json).collect::<Vec<_>>()));
+        }
+        self
+    }
+
+    fn map(mut self, name: &str, values: &HashMap<String, String>) -> Self {
+        if !values.is_empty() {
+            let object = values.iter().map(|(key, value)| (key.clone(), Value::from(value.as_str()))).collect();
+            self.0.insert(name.to_string(), Value::Object(object));
+        }
+        self
+    }
+
+    fn uint32_map(mut self, name: &str, values: &HashMap<String, u32>) -> Self {
+        if !values.is_empty() {
+            let object = values.iter().map(|(key, value)| (key.clone(), Value::from(*value))).collect();
+            self.0.insert(name.to_string(), Value::Object(object));
+        }
+        self
+    }
+
+    /// Feature attributes, as `attributes::registry` writes them.
+    fn attributes(mut self, name: &str, values: &HashMap<String, Any>) -> Self {
+        if !values.is_empty() {
+            let types = attributes::registry();
+            let object = values.iter().map(|(key, value)| (key.clone(), types.to_json(value))).collect();
+            self.0.insert(name.to_string(), Value::Object(object));
+        }
+        self
+    }
+
+    fn field_mask(mut self, name: &str, mask: Option<&FieldMask>) -> Self {
+        if let Some(mask) = mask {
+            let paths: Vec<String> = mask.paths.iter().map(|path| path.split('.').map(camel_case).collect::<Vec<_>>().join(".")).collect();
+            self.0.insert(name.to_string(), Value::from(paths.join(",")));
+        }
+        self
+    }
+
+    fn done(self) -> Value {
+        Value::Object(self.0)
+    }
+}
+
+
+/// The fields of a message being read. Fields can be named in lowerCamelCase or as in the
+/// proto; `null` is the same as leaving a field out.
+struct Reader<'a> {
+    message: &'static str,
+    fields: &'a Map<String, Value>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(message: &'static str, value: &'a Value) -> Result<Self, String> {
+        match value {
+            Value::Object(fields) => Ok(Reader { message, fields }),
+            _ => Err(format!("{} must be a JSON object", message)),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&'a Value> {
+        self.fields
+            .get(&camel_case(name))
+            .or_else(|| self.fields.get(name))
+            .filter(|value| !value.is_null())
+    }
+
+    fn invalid(&self, name: &str, expected: &str) -> String {
+        format!("{}.{} must be {}", self.message, camel_case(name), expected)
+    }
+
+    fn string(&self, name: &str) -> Result<String, String> {
+        match self.get(name) {
+            None => Ok(String::new()),
+            Some(Value::String(value)) => Ok(value.clone()),
+            Some(_) => Err(self.invalid(name, "a string")),
+        }
+    }
+
+    fn boolean(&self, name: &str) -> Result<bool, String> {
+        match self.get(name) {
+            None => Ok(false),
+            Some(Value::Bool(value)) => Ok(*value),
+            Some(_) => Err(self.invalid(name, "true or false")),
+        }
+    }
+
+    /// Integers may also be sent as strings, as the mapping allows.
+    fn integer<T: TryFrom<i64> + TryFrom<u64> + Default>(&self, name: &str) -> Result<T, String> {
+        let value = match self.get(name) {
+            None => return Ok(T::default()),
+            Some(value) => value,
+        };
+        let number = match value {
+            Value::String(text) => text.parse::<Value>().ok(),
+            other => Some(other.clone()),
+        };
+        let parsed = match number {
+            Some(Value::Number(number)) => match (number.as_i64(), number.as_u64()) {
+                (_, Some(unsigned)) => <T as TryFrom<u64>>::try_from(unsigned).ok(),
+                (Some(signed), None) => <T as TryFrom<i64>>::try_from(signed).ok(),
+                (None, None) => None,
+            },
+            _ => None,
+        };
+        parsed.ok_or_else(|| self.invalid(name, "an integer in range"))
+    }
+
+    /// Numbers, or strings like `"1.5"` and `"NaN"`, as the mapping allows.
+    fn float(&self, name: &str) -> Result<f32, String> {
+        match self.get(name) {
+            None => Ok(0.0),
+            Some(Value::Number(number)) => number.as_f64().map(|value| value as f32).ok_or_else(|| self.invalid(name, "a number")),
+            Some(Value::String(text)) => text.parse().map_err(|_| self.invalid(name, "a number")),
+            Some(_) => Err(self.invalid(name, "a number")),
+        }
+    }
+
+    fn double(&self, name: &str) -> Result<f64, String> {
+        match self.get(name) {
+            None => Ok(0.0),
+            Some(Value::Number(number)) => number.as_f64().ok_or_else(|| self.invalid(name, "a number")),
+            Some(Value::String(text)) => text.parse().map_err(|_| self.invalid(name, "a number")),
+            Some(_) => Err(self.invalid(name, "a number")),
+        }
+    }
+
+    fn enumeration(&self, name: &str, names: &[(i32, &str)]) -> Result<i32, String> {
+        match self.get(name) {
+            Some(Value::String(value_name)) => names
+                .iter()
+                .find(|&&(_, known)| known == value_name)
+                .map(|&(number, _)| number)
+                .ok_or_else(|| self.invalid(name, "one of the enum's values")),
+            _ => self.integer(name),
+        }
+    }
+
+    fn message<T: ProtoJson>(&self, name: &str) -> Result<Option<T>, String> {
+        self.get(name).map(T::from_json).transpose()
+    }
+
+    fn strings(&self, name: &str) -> Result<Vec<String>, String> {
+        match self.get(name) {
+            None => Ok(vec![]),
+            Some(Value::Array(values)) => values
+                .iter()
+                .map(|value| value.as_str().map(str::to_string).ok_or_else(|| self.invalid(name, "a list of strings")))
+                .collect(),
+            Some(_) => Err(self.invalid(name, "a list of strings")),
+        }
+    }
+
+    fn messages<T: ProtoJson>(&self, name: &str) -> Result<Vec<T>, String> {
+        match self.get(name) {
+            None => Ok(vec![]),
+            Some(Value::Array(values)) => values.iter().map(T::from_json).collect(),
+            Some(_) => Err(self.invalid(name, "a list")),
+        }
+    }
+
+    fn map(&self, name: &str) -> Result<HashMap<String, String>, String> {
+        match self.get(name) {
+            None => Ok(HashMap::new()),
+            Some(Value::Object(entries)) => entries
+                .iter()
+                .map(|(key, value)| {
+                    let value = value.as_str().ok_or_else(|| self.invalid(name, "an object of strings"))?;
+                    Ok((key.clone(), value.to_string()))
+                })
+                .collect(),
+            Some(_) => Err(self.invalid(name, "an object of strings")),
+        }
+    }
+
+    fn uint32_map(&self, name: &str) -> Result<HashMap<String, u32>, String> {
+        match self.get(name) {
+            None => Ok(HashMap::new()),
+            Some(Value::Object(entries)) => entries
+                .iter()
+                .map(|(key, value)| {
+                    let value = value
+                        .as_u64()
+                        .and_then(|value| u32::try_from(value).ok())
+                        .ok_or_else(|| self.invalid(name, "an object of integers"))?;
+                    Ok((key.clone(), value))
+                })
+                .collect(),
+            Some(_) => Err(self.invalid(name, "an object of integers")),
+        }
+    }
+
+    fn attributes(&self, name: &str) -> Result<HashMap<String, Any>, String> {
+        match self.get(name) {
+            None => Ok(HashMap::new()),
+            Some(Value::Object(entries)) => {
+                let types = attributes::registry();
+                entries
+                    .iter()
+                    .map(|(key, value)| {
+                        let value = types.from_json(value).map_err(|e| format!("{}.{}.{}: {}", self.message, camel_case(name), key, e))?;
+                        Ok((key.clone(), value))
+                    })
+                    .collect()
+            },
+            Some(_) => Err(self.invalid(name, "an object of attributes")),
+        }
+    }
+
+    fn field_mask(&self, name: &str) -> Result<Option<FieldMask>, String> {
+        match self.get(name) {
+            None => Ok(None),
+            Some(Value::String(paths)) => {
+                let paths = paths
+                    .split(',')
+                    .filter(|path| !path.is_empty())
+                    .map(|path| path.split('.').map(snake_case).collect::<Vec<_>>().join("."))
+                    .collect();
+                Ok(Some(FieldMask { paths }))
+            },
+            Some(_) => Err(self.invalid(name, "a comma-separated string of paths")),
+        }
+    }
+}
+
+
+impl ProtoJson for Point {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .int("latitude", self.latitude.into())
+            .int("longitude", self.longitude.into())
+            .field_mask("readMask", self.read_mask.as_ref())
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("Point", value)?;
+        Ok(Point {
+            latitude: reader.integer("latitude")?,
+            longitude: reader.integer("longitude")?,
+            read_mask: reader.field_mask("read_mask")?,
+        })
+    }
+}
+
+impl ProtoJson for Clustering {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .int("zoom", self.zoom.into())
+            .int("maxFeatures", self.max_features.into())
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("Clustering", value)?;
+        Ok(Clustering { zoom: reader.integer("zoom")?, max_features: reader.integer("max_features")? })
+    }
+}
+
+impl ProtoJson for Rectangle {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .message("lo", self.lo.as_ref())
+            .message("hi", self.hi.as_ref())
+            .message("cluster", self.cluster.as_ref())
+            .field_mask("readMask", self.read_mask.as_ref())
+            .string("resumeAfter", &self.resume_after)
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("Rectangle", value)?;
+        Ok(Rectangle {
+            lo: reader.message("lo")?,
+            hi: reader.message("hi")?,
+            cluster: reader.message("cluster")?,
+            read_mask: reader.field_mask("read_mask")?,
+            resume_after: reader.string("resume_after")?,
+        })
+    }
+}
+
+impl ProtoJson for Feature {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .string("name", &self.name)
+            .message("location", self.location.as_ref())
+            .string("description", &self.description)
+            .repeated("tags", &self.tags, |tag| Value::from(tag.as_str()))
+            .int("clusterSize", self.cluster_size.into())
+            .map("namesByLocale", &self.names_by_locale)
+            .string("id", &self.id)
+            .attributes("attributes", &self.attributes)
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("Feature", value)?;
+        Ok(Feature {
+            name: reader.string("name")?,
+            location: reader.message("location")?,
+            description: reader.string("description")?,
+            tags: reader.strings("tags")?,
+            cluster_size: reader.integer("cluster_size")?,
+            names_by_locale: reader.map("names_by_locale")?,
+            id: reader.string("id")?,
+            attributes: reader.attributes("attributes")?,
+        })
+    }
+}
+
+impl ProtoJson for Period {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .int("weekday", self.weekday.into())
+            .int("opensMinute", self.opens_minute.into())
+            .int("closesMinute", self.closes_minute.into())
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("OpeningHours.Period", value)?;
+        Ok(Period {
+            weekday: reader.integer("weekday")?,
+            opens_minute: reader.integer("opens_minute")?,
+            closes_minute: reader.integer("closes_minute")?,
+        })
+    }
+}
+
+impl ProtoJson for OpeningHours {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .repeated("periods", &self.periods, ProtoJson::to_json)
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("OpeningHours", value)?;
+        Ok(OpeningHours { periods: reader.messages("periods")? })
+    }
+}
+
+impl ProtoJson for Photo {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .string("url", &self.url)
+            .string("caption", &self.caption)
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("Photo", value)?;
+        Ok(Photo { url: reader.string("url")?, caption: reader.string("caption")? })
+    }
+}
+
+impl ProtoJson for Rating {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .float("stars", self.stars)
+            .int("count", self.count.into())
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("Rating", value)?;
+        Ok(Rating { stars: reader.float("stars")?, count: reader.integer("count")? })
+    }
+}
+
+impl ProtoJson for RouteNote {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .message("location", self.location.as_ref())
+            .string("message", &self.message)
+            .uint64("sequence", self.sequence)
+            .string("sender", &self.sender)
+            .uint64("postedAtMs", self.posted_at_ms)
+            .string("fromUser", &self.from_user)
+            .string("toUser", &self.to_user)
+            .boolean("heartbeat", self.heartbeat)
+            .message("rejection", self.rejection.as_ref())
+            .string("room", &self.room)
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("RouteNote", value)?;
+        Ok(RouteNote {
+            location: reader.message("location")?,
+            message: reader.string("message")?,
+            sequence: reader.integer("sequence")?,
+            sender: reader.string("sender")?,
+            posted_at_ms: reader.integer("posted_at_ms")?,
+            from_user: reader.string("from_user")?,
+            to_user: reader.string("to_user")?,
+            heartbeat: reader.boolean("heartbeat")?,
+            rejection: reader.message("rejection")?,
+            room: reader.string("room")?,
+        })
+    }
+}
+
+impl ProtoJson for RouteSummary {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .int("pointCount", self.point_count.into())
+            .int("featureCount", self.feature_count.into())
+            .int("distance", self.distance.into())
+            .int("elapsedTime", self.elapsed_time.into())
+            .string("polyline", &self.polyline)
+            .boolean("polylineTruncated", self.polyline_truncated)
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("RouteSummary", value)?;
+        Ok(RouteSummary {
+            point_count: reader.integer("point_count")?,
+            feature_count: reader.integer("feature_count")?,
+            distance: reader.integer("distance")?,
+            elapsed_time: reader.integer("elapsed_time")?,
+            polyline: reader.string("polyline")?,
+            polyline_truncated: reader.boolean("polyline_truncated")?,
+        })
+    }
+}
+
+impl ProtoJson for ImportFailure {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .uint64("index", self.index)
+            .string("message", &self.message)
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("ImportFailure", value)?;
+        Ok(ImportFailure { index: reader.integer("index")?, message: reader.string("message")? })
+    }
+}
+
+impl ProtoJson for ImportSummary {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .int("inserted", self.inserted.into())
+            .int("replaced", self.replaced.into())
+            .int("skipped", self.skipped.into())
+            .int("failed", self.failed.into())
+            .repeated("failures", &self.failures, ProtoJson::to_json)
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("ImportSummary", value)?;
+        Ok(ImportSummary {
+            inserted: reader.integer("inserted")?,
+            replaced: reader.integer("replaced")?,
+            skipped: reader.integer("skipped")?,
+            failed: reader.integer("failed")?,
+            failures: reader.messages("failures")?,
+        })
+    }
+}
+
+impl ProtoJson for PointWithTimestamp {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .message("point", self.point.as_ref())
+            .uint64("atMs", self.at_ms)
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("PointWithTimestamp", value)?;
+        Ok(PointWithTimestamp { point: reader.message("point")?, at_ms: reader.integer("at_ms")? })
+    }
+}
+
+impl ProtoJson for TimeRange {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .uint64("sinceMs", self.since_ms)
+            .uint64("untilMs", self.until_ms)
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("TimeRange", value)?;
+        Ok(TimeRange { since_ms: reader.integer("since_ms")?, until_ms: reader.integer("until_ms")? })
+    }
+}
+
+const CHANGE_ACTIONS: &[(i32, &str)] = &[
+    (Action::Unknown as i32, "UNKNOWN"),
+    (Action::Added as i32, "ADDED"),
+    (Action::Deleted as i32, "DELETED"),
+    (Action::Updated as i32, "UPDATED"),
+];
+
+impl ProtoJson for ChangeEvent {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .uint64("id", self.id)
+            .uint64("atMs", self.at_ms)
+            .string("actor", &self.actor)
+            .enumeration("action", self.action, CHANGE_ACTIONS)
+            .message("before", self.before.as_ref())
+            .message("after", self.after.as_ref())
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("ChangeEvent", value)?;
+        Ok(ChangeEvent {
+            id: reader.integer("id")?,
+            at_ms: reader.integer("at_ms")?,
+            actor: reader.string("actor")?,
+            action: reader.enumeration("action", CHANGE_ACTIONS)?,
+            before: reader.message("before")?,
+            after: reader.message("after")?,
+        })
+    }
+}
+
+impl ProtoJson for ExportRequest {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .int("batchSize", self.batch_size.into())
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("ExportRequest", value)?;
+        Ok(ExportRequest { batch_size: reader.integer("batch_size")? })
+    }
+}
+
+impl ProtoJson for ExportTrailer {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .uint64("featureCount", self.feature_count)
+            .string("checksum", &self.checksum)
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("ExportTrailer", value)?;
+        Ok(ExportTrailer { feature_count: reader.integer("feature_count")?, checksum: reader.string("checksum")? })
+    }
+}
+
+impl ProtoJson for FeatureChunk {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .repeated("features", &self.features, ProtoJson::to_json)
+            .message("trailer", self.trailer.as_ref())
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("FeatureChunk", value)?;
+        Ok(FeatureChunk { features: reader.messages("features")?, trailer: reader.message("trailer")? })
+    }
+}
+
+impl ProtoJson for ReplicateRequest {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .string("epoch", &self.epoch)
+            .uint64("after", self.after)
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("ReplicateRequest", value)?;
+        Ok(ReplicateRequest { epoch: reader.string("epoch")?, after: reader.integer("after")? })
+    }
+}
+
+const REPLICATION_KINDS: &[(i32, &str)] = &[
+    (Kind::Unknown as i32, "UNKNOWN"),
+    (Kind::Reset as i32, "RESET"),
+    (Kind::Added as i32, "ADDED"),
+    (Kind::Removed as i32, "REMOVED"),
+    (Kind::Synced as i32, "SYNCED"),
+];
+
+impl ProtoJson for ReplicationEvent {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .uint64("id", self.id)
+            .enumeration("kind", self.kind, REPLICATION_KINDS)
+            .message("feature", self.feature.as_ref())
+            .string("epoch", &self.epoch)
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("ReplicationEvent", value)?;
+        Ok(ReplicationEvent {
+            id: reader.integer("id")?,
+            kind: reader.enumeration("kind", REPLICATION_KINDS)?,
+            feature: reader.message("feature")?,
+            epoch: reader.string("epoch")?,
+        })
+    }
+}
+
+impl ProtoJson for Rejection {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .int("code", self.code.into())
+            .string("message", &self.message)
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("Rejection", value)?;
+        Ok(Rejection { code: reader.integer("code")?, message: reader.string("message")? })
+    }
+}
+
+impl ProtoJson for ClientConfig {
+    fn to_json(&self) -> Value {
+        Writer::default()
+            .uint64("version", self.version)
+            .int("defaultDeadlineMs", self.default_deadline_ms.into())
+            .uint32_map("methodDeadlinesMs", &self.method_deadlines_ms)
+            .int("maxRequestMessageBytes", self.max_request_message_bytes.into())
+            .int("maxResponseMessageBytes", self.max_response_message_bytes.into())
+            .double("maxCallsPerSecond", self.max_calls_per_second)
+            .int("burst", self.burst.into())
+            .done()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let reader = Reader::new("ClientConfig", value)?;
+        Ok(ClientConfig {
+            version: reader.integer("version")?,
+            default_deadline_ms: reader.integer("default_deadline_ms")?,
+            method_deadlines_ms: reader.uint32_map("method_deadlines_ms")?,
+            max_request_message_bytes: reader.integer("max_request_message_bytes")?,
+            max_response_message_bytes: reader.integer("max_response_message_bytes")?,
+            max_calls_per_second: reader.double("max_calls_per_second")?,
+            burst: reader.integer("burst")?,
+        })
+    }
+}
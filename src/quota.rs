@@ -0,0 +1,553 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::{Stream, StreamExt};
+use prost::Message;
+use tonic::{Code, Request, Response, Status, Streaming};
+
+use crate::admin::{Quota, QuotaUsage, QuotaUsages, QuotaWindow};
+use crate::google_rpc::{quota_failure::Violation, QuotaFailure};
+use crate::metrics;
+use crate::request_context::RequestContext;
+use crate::route_guide::route_guide_server::RouteGuide;
+use crate::route_guide::{
+    ChangeEvent, ClientConfig, ExportRequest, Feature, FeatureChunk, ImportSummary, Point, PointWithTimestamp, Rectangle,
+    ReplicateRequest, ReplicationEvent, Room, RoomMembers, RouteNote, RouteSummary, TimeRange,
+};
+
+
+/// How much each subject may use the RouteGuide service per UTC day and calendar month. `None`
+/// is no limit.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct QuotaLimits {
+    pub daily_rpcs: Option<u64>,
+    pub monthly_rpcs: Option<u64>,
+    /// Bytes are the encoded size of the messages the server sends.
+    pub daily_bytes: Option<u64>,
+    pub monthly_bytes: Option<u64>,
+}
+
+impl QuotaLimits {
+    pub fn is_unlimited(&self) -> bool {
+        *self == QuotaLimits::default()
+    }
+}
+
+
+/// The date of a day since the Unix epoch, as (year, month, day).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Howard Hinnant's algorithm, with years starting on 1 March.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// The days from the Unix epoch to the first of the month.
+fn days_from_civil(year: i64, month: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (if month > 2 { month - 3 } else { month + 9 }) as i64;
+    let doy = (153 * mp + 2) / 5;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// One UTC calendar day or month, named like `2026-10-14` or `2026-10`.
+#[derive(Debug, Clone, PartialEq)]
+struct Window {
+    period: &'static str,
+    name: String,
+    ends: SystemTime,
+}
+
+/// The day and the month `now` is in.
+fn windows(now: SystemTime) -> (Window, Window) {
+    let days = (now.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()) / DAY) as i64;
+    let (year, month, day) = civil_from_days(days);
+    let next_month = if month == 12 { days_from_civil(year + 1, 1) } else { days_from_civil(year, month + 1) };
+    let at = |days: i64| UNIX_EPOCH + Duration::from_secs(days.max(0) as u64 * DAY);
+    (
+        Window { period: "day", name: format!("{:04}-{:02}-{:02}", year, month, day), ends: at(days + 1) },
+        Window { period: "month", name: format!("{:04}-{:02}", year, month), ends: at(next_month) },
+    )
+}
+
+
+/// Where quota usage is kept between restarts.
+pub trait QuotaStore: Debug + Send + Sync {
+    fn load(&self) -> io::Result<Vec<QuotaUsage>>;
+
+    /// Replaces what's stored.
+    fn save(&self, usages: &[QuotaUsage]) -> io::Result<()>;
+}
+
+/// Keeps nothing; usage starts from zero after a restart.
+#[derive(Debug, Default)]
+pub struct MemoryQuotaStore;
+
+impl QuotaStore for MemoryQuotaStore {
+    fn load(&self) -> io::Result<Vec<QuotaUsage>> {
+        Ok(vec![])
+    }
+
+    fn save(&self, _usages: &[QuotaUsage]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The usage as one protobuf encoded `QuotaUsages`, written to a temporary file and renamed
+/// over the old one, so a crash leaves either the old or the new usage.
+#[derive(Debug)]
+pub struct FileQuotaStore {
+    path: PathBuf,
+}
+
+impl FileQuotaStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileQuotaStore { path: path.into() }
+    }
+}
+
+impl QuotaStore for FileQuotaStore {
+    fn load(&self) -> io::Result<Vec<QuotaUsage>> {
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let usages = QuotaUsages::decode(&bytes[..]).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(usages.usages)
+    }
+
+    fn save(&self, usages: &[QuotaUsage]) -> io::Result<()> {
+        let message = QuotaUsages { usages: usages.to_vec() };
+        let mut encoded = Vec::with_capacity(message.encoded_len());
+        message.encode(&mut encoded).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, &encoded)?;
+        fs::rename(&temporary, &self.path)
+    }
+}
+
+
+#[derive(Debug, Copy, Clone, Default)]
+struct Usage {
+    rpcs: u64,
+    bytes: u64,
+}
+
+/// Counts the RouteGuide calls of each subject, and the bytes sent to it, per day and month,
+/// and refuses calls past the limits. The counts are in memory and written to the store by
+/// `flush`.
+#[derive(Debug)]
+pub struct QuotaTracker {
+    limits: QuotaLimits,
+    store: Arc<dyn QuotaStore>,
+    // By subject and window name.
+    usage: Mutex<HashMap<(String, String), Usage>>,
+}
+
+impl QuotaTracker {
+    /// Starts from the usage in `store`.
+    pub fn open(limits: QuotaLimits, store: Arc<dyn QuotaStore>) -> io::Result<Self> {
+        let usage = store
+            .load()?
+            .into_iter()
+            .map(|usage| ((usage.subject, usage.window), Usage { rpcs: usage.rpcs, bytes: usage.bytes }))
+            .collect();
+        Ok(QuotaTracker { limits, store, usage: Mutex::new(usage) })
+    }
+
+    pub fn limits(&self) -> QuotaLimits {
+        self.limits
+    }
+
+    /// The limits used up in the windows `now` is in, as (kind, window, what was used, limit).
+    /// A limit is used up once usage reaches it.
+    fn exhausted(&self, usage: &HashMap<(String, String), Usage>, subject: &str, now: SystemTime) -> Vec<(&'static str, Window, u64, u64)> {
+        let (day, month) = windows(now);
+        let limits = [
+            ("rpcs", day.clone(), self.limits.daily_rpcs),
+            ("bytes", day, self.limits.daily_bytes),
+            ("rpcs", month.clone(), self.limits.monthly_rpcs),
+            ("bytes", month, self.limits.monthly_bytes),
+        ];
+        let mut exhausted = vec![];
+        for (kind, window, limit) in limits.iter().cloned() {
+            let limit = match limit {
+                Some(limit) => limit,
+                None => continue,
+            };
+            let used = usage.get(&(subject.to_string(), window.name.clone())).copied().unwrap_or_default();
+            let used = if kind == "rpcs" { used.rpcs } else { used.bytes };
+            if used >= limit {
+                exhausted.push((kind, window, used, limit));
+            }
+        }
+        exhausted
+    }
+
+    fn add(usage: &mut HashMap<(String, String), Usage>, subject: &str, rpcs: u64, bytes: u64, now: SystemTime) {
+        let (day, month) = windows(now);
+        for window in &[day, month] {
+            let used = usage.entry((subject.to_string(), window.name.clone())).or_default();
+            used.rpcs += rpcs;
+            used.bytes += bytes;
+        }
+    }
+
+    /// Counts a call by `subject`, or fails with RESOURCE_EXHAUSTED if one of its quotas is
+    /// used up.
+    pub fn start_call(&self, subject: &str) -> Result<(), Status> {
+        self.start_call_at(subject, SystemTime::now())
+    }
+
+    fn start_call_at(&self, subject: &str, now: SystemTime) -> Result<(), Status> {
+        // Checked and counted under one lock, so concurrent calls can't all pass the last slot.
+        let mut usage = self.usage.lock().unwrap();
+        let exhausted = self.exhausted(&usage, subject, now);
+        if !exhausted.is_empty() {
+            return Err(exhausted_status(subject, &exhausted));
+        }
+        Self::add(&mut usage, subject, 1, 0, now);
+        Ok(())
+    }
+
+    /// Counts `bytes` sent to `subject`. Fails once that uses up one of its byte quotas.
+    pub fn add_bytes(&self, subject: &str, bytes: usize) -> Result<(), Status> {
+        self.add_bytes_at(subject, bytes, SystemTime::now())
+    }
+
+    fn add_bytes_at(&self, subject: &str, bytes: usize, now: SystemTime) -> Result<(), Status> {
+        let exhausted: Vec<_> = {
+            let mut usage = self.usage.lock().unwrap();
+            Self::add(&mut usage, subject, 0, bytes as u64, now);
+            self.exhausted(&usage, subject, now).into_iter().filter(|(kind, ..)| *kind == "bytes").collect()
+        };
+        if exhausted.is_empty() {
+            return Ok(());
+        }
+        Err(exhausted_status(subject, &exhausted))
+    }
+
+    pub fn quota(&self, subject: &str) -> Quota {
+        let usage = self.usage.lock().unwrap();
+        let (day, month) = windows(SystemTime::now());
+        let message = |window: Window, rpc_limit: Option<u64>, byte_limit: Option<u64>| {
+            let used = usage.get(&(subject.to_string(), window.name.clone())).copied().unwrap_or_default();
+            QuotaWindow {
+                rpcs: used.rpcs,
+                rpc_limit: rpc_limit.unwrap_or(0),
+                bytes: used.bytes,
+                byte_limit: byte_limit.unwrap_or(0),
+                resets_at_ms: window.ends.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64),
+                window: window.name,
+            }
+        };
+        Quota {
+            subject: subject.to_string(),
+            day: Some(message(day, self.limits.daily_rpcs, self.limits.daily_bytes)),
+            month: Some(message(month, self.limits.monthly_rpcs, self.limits.monthly_bytes)),
+        }
+    }
+
+    /// Writes the usage to the store, forgetting windows that have ended.
+    pub fn flush(&self) -> io::Result<()> {
+        let usages: Vec<QuotaUsage> = {
+            let mut usage = self.usage.lock().unwrap();
+            let (day, month) = windows(SystemTime::now());
+            usage.retain(|(_, window), _| *window == day.name || *window == month.name);
+            usage
+                .iter()
+                .map(|((subject, window), used)| QuotaUsage { subject: subject.clone(), window: window.clone(), rpcs: used.rpcs, bytes: used.bytes })
+                .collect()
+        };
+        self.store.save(&usages)
+    }
+}
+
+/// RESOURCE_EXHAUSTED with a `google.rpc.QuotaFailure` in the details.
+fn exhausted_status(subject: &str, exhausted: &[(&'static str, Window, u64, u64)]) -> Status {
+    let violations: Vec<Violation> = exhausted
+        .iter()
+        .map(|(kind, window, used, limit)| {
+            metrics::registry()
+                .counter("quota_exhausted_calls_total", "RouteGuide calls refused or ended because a quota was used up.", &[("kind", kind), ("period", window.period)])
+                .inc();
+            let resets = window.ends.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+            Violation {
+                subject: subject.to_string(),
+                description: format!("{} of {} {} used in {}; resets at {} (Unix time)", used, limit, kind, window.name, resets),
+            }
+        })
+        .collect();
+
+    let message = violations.iter().map(|violation| violation.description.clone()).collect::<Vec<_>>().join("; ");
+    let details = QuotaFailure { violations };
+    let mut encoded = Vec::with_capacity(details.encoded_len());
+    if details.encode(&mut encoded).is_err() {
+        return Status::resource_exhausted(message);
+    }
+    Status::with_details(Code::ResourceExhausted, message, encoded.into())
+}
+
+
+type Metered<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + Sync + 'static>>;
+
+/// Counts the messages of `stream` against the subject's byte quotas, and ends it with
+/// RESOURCE_EXHAUSTED after the message that used one up.
+fn metered<T, S>(stream: S, tracker: Arc<QuotaTracker>, subject: String) -> Metered<T>
+    where
+        T: Message + Send + Sync + Unpin + 'static,
+        S: Stream<Item = Result<T, Status>> + Send + Sync + 'static,
+{
+    let output = async_stream::stream! {
+        let mut stream = Box::pin(stream);
+        while let Some(message) = stream.next().await {
+            let used_up = match &message {
+                Ok(message) => tracker.add_bytes(&subject, message.encoded_len()).err(),
+                Err(_) => None,
+            };
+            yield message;
+            if let Some(status) = used_up {
+                yield Err(status);
+                break;
+            }
+        }
+    };
+    Box::pin(output)
+}
+
+
+/// Enforces the quotas of each caller on the RouteGuide methods before `inner` sees the call,
+/// and counts what `inner` sends back. Meant to go inside `Authorized`, so denied calls don't
+/// count. GetQuota is answered here, from the caller's own usage, and never reaches `inner`.
+#[derive(Debug)]
+pub struct Quotas<S> {
+    pub inner: S,
+    pub tracker: Arc<QuotaTracker>,
+}
+
+impl<S> Quotas<S> {
+    /// The caller, once its call is counted.
+    fn start<T>(&self, request: &Request<T>) -> Result<String, Status> {
+        let subject = RequestContext::of(request)?.subject;
+        self.tracker.start_call(&subject)?;
+        Ok(subject)
+    }
+
+    /// A unary response is sent whatever it uses up; the next call is refused.
+    fn unary<T: Message>(&self, subject: &str, response: Result<Response<T>, Status>) -> Result<Response<T>, Status> {
+        let response = response?;
+        let _ = self.tracker.add_bytes(subject, response.get_ref().encoded_len());
+        Ok(response)
+    }
+
+    fn stream<T, St>(&self, subject: String, response: Result<Response<St>, Status>) -> Result<Response<Metered<T>>, Status>
+        where
+            T: Message + Send + Sync + Unpin + 'static,
+            St: Stream<Item = Result<T, Status>> + Send + Sync + 'static,
+    {
+        let response = response?;
+        let metadata = response.metadata().clone();
+        let mut metered = Response::new(metered(response.into_inner(), self.tracker.clone(), subject));
+        *metered.metadata_mut() = metadata;
+        Ok(metered)
+    }
+}
+
+#[tonic::async_trait]
+impl<S: RouteGuide> RouteGuide for Quotas<S> {
+    type ListFeaturesStream = Metered<Feature>;
+    type RouteChatStream = Metered<RouteNote>;
+    type GetNotesAtStream = Metered<RouteNote>;
+    type ListChangesStream = Metered<ChangeEvent>;
+    type ExportFeaturesStream = Metered<FeatureChunk>;
+    type ReplicateStream = Metered<ReplicationEvent>;
+    type WatchConfigStream = Metered<ClientConfig>;
+
+    async fn get_feature(&self, request: Request<Point>) -> Result<Response<Feature>, Status> {
+        let subject = self.start(&request)?;
+        self.unary(&subject, self.inner.get_feature(request).await)
+    }
+
+    async fn list_features(&self, request: Request<Rectangle>) -> Result<Response<Self::ListFeaturesStream>, Status> {
+        let subject = self.start(&request)?;
+        self.stream(subject, self.inner.list_features(request).await)
+    }
+
+    async fn record_route(&self, request: Request<Streaming<Point>>) -> Result<Response<RouteSummary>, Status> {
+        let subject = self.start(&request)?;
+        self.unary(&subject, self.inner.record_route(request).await)
+    }
+
+    async fn route_chat(&self, request: Request<Streaming<RouteNote>>) -> Result<Response<Self::RouteChatStream>, Status> {
+        let subject = self.start(&request)?;
+        self.stream(subject, self.inner.route_chat(request).await)
+    }
+
+    async fn add_feature(&self, request: Request<Feature>) -> Result<Response<Feature>, Status> {
+        let subject = self.start(&request)?;
+        self.unary(&subject, self.inner.add_feature(request).await)
+    }
+
+    async fn delete_feature(&self, request: Request<Point>) -> Result<Response<Feature>, Status> {
+        let subject = self.start(&request)?;
+        self.unary(&subject, self.inner.delete_feature(request).await)
+    }
+
+    async fn get_notes_at(&self, request: Request<Point>) -> Result<Response<Self::GetNotesAtStream>, Status> {
+        let subject = self.start(&request)?;
+        self.stream(subject, self.inner.get_notes_at(request).await)
+    }
+
+    async fn import_features(&self, request: Request<Streaming<Feature>>) -> Result<Response<ImportSummary>, Status> {
+        let subject = self.start(&request)?;
+        self.unary(&subject, self.inner.import_features(request).await)
+    }
+
+    async fn get_feature_as_of(&self, request: Request<PointWithTimestamp>) -> Result<Response<Feature>, Status> {
+        let subject = self.start(&request)?;
+        self.unary(&subject, self.inner.get_feature_as_of(request).await)
+    }
+
+    async fn list_changes(&self, request: Request<TimeRange>) -> Result<Response<Self::ListChangesStream>, Status> {
+        let subject = self.start(&request)?;
+        self.stream(subject, self.inner.list_changes(request).await)
+    }
+
+    async fn export_features(&self, request: Request<ExportRequest>) -> Result<Response<Self::ExportFeaturesStream>, Status> {
+        let subject = self.start(&request)?;
+        self.stream(subject, self.inner.export_features(request).await)
+    }
+
+    async fn replicate(&self, request: Request<ReplicateRequest>) -> Result<Response<Self::ReplicateStream>, Status> {
+        let subject = self.start(&request)?;
+        self.stream(subject, self.inner.replicate(request).await)
+    }
+
+    async fn watch_config(&self, request: Request<()>) -> Result<Response<Self::WatchConfigStream>, Status> {
+        let subject = self.start(&request)?;
+        self.stream(subject, self.inner.watch_config(request).await)
+    }
+
+    async fn list_room_members(&self, request: Request<Room>) -> Result<Response<RoomMembers>, Status> {
+        let subject = self.start(&request)?;
+        self.unary(&subject, self.inner.list_room_members(request).await)
+    }
+
+    async fn get_quota(&self, request: Request<()>) -> Result<Response<Quota>, Status> {
+        let subject = RequestContext::of(&request)?.subject;
+        Ok(Response::new(self.tracker.quota(&subject)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn tracker(limits: QuotaLimits) -> QuotaTracker {
+        QuotaTracker::open(limits, Arc::new(MemoryQuotaStore)).unwrap()
+    }
+
+    #[test]
+    fn days_and_dates_agree() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(days_from_civil(1970, 1), 0);
+        for days in -800_000..800_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month) + day as i64 - 1, days, "{}-{}-{}", year, month, day);
+        }
+    }
+
+    #[test]
+    fn leap_years() {
+        let february = |year| days_from_civil(year, 3) - days_from_civil(year, 2);
+        assert_eq!(february(2024), 29);
+        assert_eq!(february(2026), 28);
+        assert_eq!(february(2000), 29);
+        assert_eq!(february(2100), 28);
+        assert_eq!(civil_from_days(days_from_civil(2024, 2) + 28), (2024, 2, 29));
+    }
+
+    #[test]
+    fn windows_roll_over_into_the_next_month() {
+        // 2026-01-31 23:59:59 UTC.
+        let (day, month) = windows(at(1_769_817_600 + DAY - 1));
+        assert_eq!((day.name.as_str(), day.ends), ("2026-01-31", at(1_769_904_000)));
+        assert_eq!((month.name.as_str(), month.ends), ("2026-01", at(1_769_904_000)));
+
+        let (day, month) = windows(at(1_769_904_000));
+        assert_eq!((day.name.as_str(), day.ends), ("2026-02-01", at(1_769_904_000 + DAY)));
+        assert_eq!(month.name, "2026-02");
+    }
+
+    #[test]
+    fn windows_roll_over_into_the_next_year() {
+        // 2026-12-31 12:00 UTC.
+        let (day, month) = windows(at(1_798_675_200 + DAY / 2));
+        assert_eq!((day.name.as_str(), day.ends), ("2026-12-31", at(1_798_761_600)));
+        assert_eq!((month.name.as_str(), month.ends), ("2026-12", at(1_798_761_600)));
+
+        let (day, month) = windows(at(1_798_761_600));
+        assert_eq!((day.name.as_str(), month.name.as_str()), ("2027-01-01", "2027-01"));
+    }
+
+    #[test]
+    fn calls_stop_at_the_limit() {
+        let tracker = tracker(QuotaLimits { daily_rpcs: Some(2), ..QuotaLimits::default() });
+        let now = at(1_798_675_200);
+        assert!(tracker.start_call_at("token:a", now).is_ok());
+        assert!(tracker.start_call_at("token:a", now).is_ok());
+        assert_eq!(tracker.start_call_at("token:a", now).unwrap_err().code(), Code::ResourceExhausted);
+        assert!(tracker.start_call_at("token:b", now).is_ok());
+        // The next day, in the next month and year.
+        assert!(tracker.start_call_at("token:a", now + Duration::from_secs(DAY)).is_ok());
+    }
+
+    #[test]
+    fn reaching_a_byte_limit_uses_it_up() {
+        let tracker = tracker(QuotaLimits { monthly_bytes: Some(10), ..QuotaLimits::default() });
+        let now = at(1_798_675_200);
+        assert!(tracker.start_call_at("token:a", now).is_ok());
+        assert!(tracker.add_bytes_at("token:a", 9, now).is_ok());
+        assert_eq!(tracker.add_bytes_at("token:a", 1, now).unwrap_err().code(), Code::ResourceExhausted);
+        assert_eq!(tracker.start_call_at("token:a", now).unwrap_err().code(), Code::ResourceExhausted);
+    }
+
+    #[test]
+    fn concurrent_calls_share_the_last_slots() {
+        let tracker = Arc::new(tracker(QuotaLimits { daily_rpcs: Some(5), ..QuotaLimits::default() }));
+        let now = at(1_798_675_200);
+        let threads: Vec<_> = (0..16)
+            .map(|_| {
+                let tracker = tracker.clone();
+                std::thread::spawn(move || (0..10).filter(|_| tracker.start_call_at("token:a", now).is_ok()).count())
+            })
+            .collect();
+        let started: usize = threads.into_iter().map(|thread| thread.join().unwrap()).sum();
+        assert_eq!(started, 5);
+    }
+}
@@ -0,0 +1,458 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tonic::Status;
+
+use crate::geo;
+use crate::index::FeatureIndex;
+use crate::metrics;
+use crate::route_guide::{Point, RouteSummary};
+use crate::route_spool::PathSpool;
+use crate::validation::Check;
+
+
+/// What happens to a point that fails one of the plausibility checks: too fast, too frequent or
+/// repeated too often.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GuardPolicy {
+    /// The upload fails with INVALID_ARGUMENT, naming the point.
+    Reject,
+    /// The point is dropped and the upload goes on, as if it was never sent.
+    Filter,
+}
+
+impl FromStr for GuardPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(GuardPolicy::Reject),
+            "filter" => Ok(GuardPolicy::Filter),
+            other    => Err(format!("unknown policy '{}' (expected reject or filter)", other)),
+        }
+    }
+}
+
+
+/// Sanity checks applied while a route is recorded. `None` disables a check.
+#[derive(Debug, Copy, Clone)]
+pub struct RecorderLimits {
+    /// Maximum number of points in a single route.
+    pub max_points: Option<u32>,
+    /// Maximum plausible speed between two consecutive points, in meters per second.
+    pub max_speed: Option<f64>,
+    /// Maximum number of points counted in any one second.
+    pub max_rate: Option<u32>,
+    /// Maximum number of times in a row a point may be sent again. A few are keepalives from a
+    /// client that hasn't moved; many more are a client stuck in a loop.
+    pub max_repeats: Option<u32>,
+    /// What happens to points that are too fast, too frequent or repeated too often. Routes with
+    /// too many points are always refused.
+    pub policy: GuardPolicy,
+    /// How long an upload may go without a point (or keepalive) before it's considered stalled.
+    pub idle_timeout: Option<Duration>,
+    /// How many bytes of a route's path, kept for its polyline, stay in memory before the rest
+    /// goes to disk. `None` keeps it all in memory.
+    pub spool_after: Option<usize>,
+    /// How many bytes of polyline a summary has at most, so it stays well within a message.
+    /// Longer paths are cut at the last point that fits, and the summary says so.
+    pub max_polyline: usize,
+}
+
+impl Default for RecorderLimits {
+    fn default() -> Self {
+        RecorderLimits {
+            max_points: Some(100_000),
+            max_speed: None,
+            max_rate: None,
+            max_repeats: None,
+            policy: GuardPolicy::Reject,
+            idle_timeout: Some(Duration::from_secs(30)),
+            spool_after: Some(1 << 20),
+            max_polyline: 3 << 20,
+        }
+    }
+}
+
+
+/// Why a point was refused. `index` is the point's position in the upload, starting at 0 and
+/// counting every point received.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordError {
+    TooManyPoints { limit: u32 },
+    TooFast { index: u64, speed: f64, limit: f64 },
+    TooFrequent { index: u64, limit: u32 },
+    Repeated { index: u64, limit: u32 },
+    /// The path couldn't be spooled to disk, or read back.
+    Spool { error: String },
+}
+
+impl RecordError {
+    /// For the `route_points_filtered_total` metric.
+    fn reason(&self) -> &'static str {
+        match self {
+            RecordError::TooManyPoints { .. } => "too_many_points",
+            RecordError::TooFast { .. }       => "too_fast",
+            RecordError::TooFrequent { .. }   => "too_frequent",
+            RecordError::Repeated { .. }      => "repeated",
+            RecordError::Spool { .. }         => "spool",
+        }
+    }
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::TooManyPoints { limit } =>
+                write!(f, "route has more than {} points", limit),
+            RecordError::TooFast { speed, limit, .. } =>
+                write!(f, "route implies a speed of {:.1} m/s (limit is {:.1} m/s)", speed, limit),
+            RecordError::TooFrequent { limit, .. } =>
+                write!(f, "route has more than {} points in a second", limit),
+            RecordError::Repeated { limit, .. } =>
+                write!(f, "point was repeated more than {} times in a row", limit),
+            RecordError::Spool { error } =>
+                write!(f, "couldn't spool the route to disk: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+impl From<std::io::Error> for RecordError {
+    fn from(error: std::io::Error) -> Self {
+        RecordError::Spool { error: error.to_string() }
+    }
+}
+
+/// Refused points are INVALID_ARGUMENT with a `google.rpc.BadRequest` naming them, like
+/// `points[41]`.
+impl From<RecordError> for Status {
+    fn from(error: RecordError) -> Self {
+        let index = match error {
+            RecordError::TooManyPoints { .. } => return Status::resource_exhausted(error.to_string()),
+            RecordError::Spool { .. } => return Status::internal(error.to_string()),
+            RecordError::TooFast { index, .. } | RecordError::TooFrequent { index, .. } | RecordError::Repeated { index, .. } => index,
+        };
+        let mut check = Check::default();
+        check.fail(&format!("points[{}]", index), error.to_string());
+        match check.into_result() {
+            Err(status) => status,
+            Ok(()) => Status::invalid_argument(error.to_string()),
+        }
+    }
+}
+
+
+/// Accumulates the points of a route as they are streamed in and builds the `RouteSummary`.
+#[derive(Debug)]
+pub struct RouteRecorder {
+    index: Arc<FeatureIndex>,
+    limits: RecorderLimits,
+    started: Instant,
+    last: Option<(Point, Instant)>,
+    /// Times in a row the last point was sent again.
+    repeats: u32,
+    /// When the points of the last second were counted, for `max_rate`.
+    recent: VecDeque<Instant>,
+    /// Points received, counted or not.
+    received: u64,
+    filtered: u64,
+    point_count: u32,
+    /// Points at a known feature. Several features at one location count once, as the index
+    /// has one feature per location.
+    feature_count: u32,
+    distance: f64,
+    /// The points counted, with `keep_path`.
+    path: Option<PathSpool>,
+}
+
+impl RouteRecorder {
+    pub fn new(index: Arc<FeatureIndex>, limits: RecorderLimits) -> Self {
+        Self::starting_at(index, limits, Instant::now())
+    }
+
+    /// Like `new`, but with an explicit start time so synthetic routes can be replayed with
+    /// made-up timestamps.
+    pub fn starting_at(index: Arc<FeatureIndex>, limits: RecorderLimits, started: Instant) -> Self {
+        RouteRecorder {
+            index,
+            limits,
+            started,
+            last: None,
+            repeats: 0,
+            recent: VecDeque::new(),
+            received: 0,
+            filtered: 0,
+            point_count: 0,
+            feature_count: 0,
+            distance: 0.0,
+            path: None,
+        }
+    }
+
+    /// Adds a point received now.
+    pub fn push(&mut self, point: Point) -> Result<(), RecordError> {
+        self.push_at(point, Instant::now())
+    }
+
+    /// Adds a point received at `at`. A rejected point leaves the recorder unchanged, but for
+    /// the count of points received.
+    ///
+    /// A point equal to the previous one is a keepalive from a client that hasn't moved, and
+    /// isn't counted. With the `Filter` policy, points that fail the plausibility checks are
+    /// dropped instead of rejected.
+    pub fn push_at(&mut self, point: Point, at: Instant) -> Result<(), RecordError> {
+        let index = self.received;
+        self.received += 1;
+
+        match self.check(point, at, index) {
+            Ok(()) => Ok(()),
+            Err(error) => match error {
+                RecordError::TooManyPoints { .. } | RecordError::Spool { .. } => Err(error),
+                _ if self.limits.policy == GuardPolicy::Reject => Err(error),
+                _ => {
+                    self.filtered += 1;
+                    metrics::registry()
+                        .counter("route_points_filtered_total", "RecordRoute points dropped as implausible, by reason.", &[("reason", error.reason())])
+                        .inc();
+                    Ok(())
+                },
+            },
+        }
+    }
+
+    fn check(&mut self, point: Point, at: Instant, index: u64) -> Result<(), RecordError> {
+        if self.last.as_ref().is_some_and(|(last_point, _)| *last_point == point) {
+            if let Some(limit) = self.limits.max_repeats {
+                if self.repeats >= limit {
+                    return Err(RecordError::Repeated { index, limit });
+                }
+            }
+            self.repeats += 1;
+            return Ok(());
+        }
+
+        if let Some(limit) = self.limits.max_points {
+            if self.point_count >= limit {
+                return Err(RecordError::TooManyPoints { limit });
+            }
+        }
+
+        if let Some(limit) = self.limits.max_rate {
+            while self.recent.front().is_some_and(|&counted| at.saturating_duration_since(counted) >= Duration::from_secs(1)) {
+                self.recent.pop_front();
+            }
+            if self.recent.len() as u32 >= limit {
+                return Err(RecordError::TooFrequent { index, limit });
+            }
+        }
+
+        let step = match self.last.as_ref() {
+            Some((last_point, last_time)) => {
+                let step = geo::distance(last_point, &point);
+
+                if let Some(limit) = self.limits.max_speed {
+                    let seconds = at.saturating_duration_since(*last_time).as_secs_f64();
+                    let speed = if seconds > 0.0 { step / seconds } else if step > 0.0 { f64::INFINITY } else { 0.0 };
+                    if speed > limit {
+                        return Err(RecordError::TooFast { index, speed, limit });
+                    }
+                }
+
+                step
+            },
+            None => 0.0,
+        };
+
+        if let Some(path) = &mut self.path {
+            path.push(&point)?;
+        }
+        self.point_count += 1;
+        self.distance += step;
+        if self.index.contains(&point) {
+            self.feature_count += 1;
+        }
+        if self.limits.max_rate.is_some() {
+            self.recent.push_back(at);
+        }
+        self.last = Some((point, at));
+        self.repeats = 0;
+
+        Ok(())
+    }
+
+    /// Keeps the points, so the summary has the path as a polyline. Past `spool_after` bytes
+    /// of it, the path is kept on disk until the summary is built; past `max_polyline`, the
+    /// rest isn't kept.
+    pub fn keep_path(mut self) -> Self {
+        self.path = Some(PathSpool::new(self.limits.spool_after, self.limits.max_polyline));
+        self
+    }
+
+    pub fn point_count(&self) -> u32 {
+        self.point_count
+    }
+
+    /// Points dropped by the `Filter` policy.
+    pub fn filtered(&self) -> u64 {
+        self.filtered
+    }
+
+    /// The summary so far, with the elapsed time measured up to `now`. Fails only if a
+    /// spooled path can't be read back.
+    pub fn summary_at(&self, now: Instant) -> Result<RouteSummary, RecordError> {
+        Ok(RouteSummary {
+            point_count: self.point_count as i32,
+            feature_count: self.feature_count as i32,
+            distance: self.distance.round() as i32,
+            elapsed_time: now.saturating_duration_since(self.started).as_secs() as i32,
+            polyline: match &self.path {
+                Some(path) => path.polyline()?,
+                None => String::new(),
+            },
+            polyline_truncated: matches!(&self.path, Some(path) if path.is_truncated()),
+        })
+    }
+
+    pub fn finish(self) -> Result<RouteSummary, RecordError> {
+        self.summary_at(Instant::now())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::route_guide::Feature;
+
+    /// A point every 100 m or so going north, from the equator.
+    fn northbound(count: usize) -> Vec<Point> {
+        (0..count).map(|i| Point::from_degrees(i as f64 * 0.001, 0.0)).collect()
+    }
+
+    fn feature(name: &str, latitude: f64, longitude: f64) -> Feature {
+        Feature { name: name.to_string(), location: Some(Point::from_degrees(latitude, longitude)), ..Feature::default() }
+    }
+
+    /// Pushes the points a second apart, from `started`.
+    fn replay(recorder: &mut RouteRecorder, started: Instant, points: &[Point]) -> Result<(), RecordError> {
+        for (i, point) in points.iter().enumerate() {
+            recorder.push_at(point.clone(), started + Duration::from_secs(i as u64))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn distance_and_elapsed_time() {
+        let started = Instant::now();
+        let mut recorder = RouteRecorder::starting_at(Arc::default(), RecorderLimits::default(), started);
+        replay(&mut recorder, started, &northbound(11)).unwrap();
+
+        // A hundredth of a degree of latitude.
+        let summary = recorder.summary_at(started + Duration::from_secs(60)).unwrap();
+        assert_eq!(summary.point_count, 11);
+        assert!((summary.distance - 1112).abs() <= 1, "{} m", summary.distance);
+        assert_eq!(summary.elapsed_time, 60);
+    }
+
+    #[test]
+    fn features_hit() {
+        let index = FeatureIndex::new(vec![
+            feature("start", 0.0, 0.0),
+            feature("also at the start", 0.0, 0.0),
+            feature("on the way", 0.005, 0.0),
+            feature("elsewhere", 1.0, 1.0),
+        ]);
+        let started = Instant::now();
+        let mut recorder = RouteRecorder::starting_at(Arc::new(index), RecorderLimits::default(), started);
+        let mut points = northbound(11);
+        // Back to the start, which counts again.
+        points.push(Point::from_degrees(0.0, 0.0));
+        replay(&mut recorder, started, &points).unwrap();
+
+        assert_eq!(recorder.finish().unwrap().feature_count, 3);
+    }
+
+    #[test]
+    fn max_points() {
+        let limits = RecorderLimits { max_points: Some(5), ..RecorderLimits::default() };
+        let started = Instant::now();
+        let mut recorder = RouteRecorder::starting_at(Arc::default(), limits, started);
+
+        let error = replay(&mut recorder, started, &northbound(10)).unwrap_err();
+        assert_eq!(error, RecordError::TooManyPoints { limit: 5 });
+        assert_eq!(recorder.finish().unwrap().point_count, 5);
+    }
+
+    #[test]
+    fn max_speed() {
+        // About 111 m/s between points a second apart.
+        let limits = RecorderLimits { max_speed: Some(50.0), ..RecorderLimits::default() };
+        let started = Instant::now();
+        let mut recorder = RouteRecorder::starting_at(Arc::default(), limits, started);
+
+        match replay(&mut recorder, started, &northbound(3)) {
+            Err(RecordError::TooFast { index: 1, speed, limit }) => {
+                assert!((speed - 111.2).abs() < 0.1, "{} m/s", speed);
+                assert_eq!(limit, 50.0);
+            },
+            other => panic!("expected TooFast for the second point, got {:?}", other),
+        }
+        assert_eq!(recorder.finish().unwrap().point_count, 1);
+    }
+
+    #[test]
+    fn max_speed_filtered() {
+        let limits = RecorderLimits { max_speed: Some(50.0), policy: GuardPolicy::Filter, ..RecorderLimits::default() };
+        let started = Instant::now();
+        let mut recorder = RouteRecorder::starting_at(Arc::default(), limits, started);
+        // The second point is a jump of a degree; the third is back near the first, slowly.
+        let points = [Point::from_degrees(0.0, 0.0), Point::from_degrees(1.0, 0.0), Point::from_degrees(0.0001, 0.0)];
+        replay(&mut recorder, started, &points).unwrap();
+
+        assert_eq!(recorder.filtered(), 1);
+        let summary = recorder.finish().unwrap();
+        assert_eq!(summary.point_count, 2);
+        assert!((summary.distance - 11).abs() <= 1, "{} m", summary.distance);
+    }
+
+    #[test]
+    fn spooled_summary_stays_bounded() {
+        let limits = RecorderLimits { max_points: None, spool_after: Some(64), max_polyline: 1024, ..RecorderLimits::default() };
+        let mut recorder = RouteRecorder::new(Arc::default(), limits).keep_path();
+        let points = northbound(10_000);
+        for point in &points {
+            recorder.push(point.clone()).unwrap();
+        }
+        assert!(recorder.path.as_ref().unwrap().is_spooled());
+
+        let summary = recorder.finish().unwrap();
+        assert_eq!(summary.point_count, 10_000);
+        assert!(summary.polyline_truncated);
+        assert!(summary.polyline.len() <= 1024, "{} bytes of polyline", summary.polyline.len());
+
+        // Cut at a point, so what's there is the start of the route.
+        let decoded = geo::decode_polyline(&summary.polyline).unwrap();
+        assert!(!decoded.is_empty());
+        let kept = &points[..decoded.len()];
+        assert!(decoded.iter().zip(kept).all(|(a, b)| geo::distance(a, b) < 1.0));
+    }
+
+    #[test]
+    fn short_path_is_whole() {
+        let limits = RecorderLimits { spool_after: Some(64), ..RecorderLimits::default() };
+        let mut recorder = RouteRecorder::new(Arc::default(), limits).keep_path();
+        let points = northbound(100);
+        for point in &points {
+            recorder.push(point.clone()).unwrap();
+        }
+
+        let summary = recorder.finish().unwrap();
+        assert!(!summary.polyline_truncated);
+        assert_eq!(geo::decode_polyline(&summary.polyline).unwrap().len(), points.len());
+    }
+}
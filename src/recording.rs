@@ -0,0 +1,308 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::TryStreamExt as _;
+use http_body::Body as HttpBody;
+use hyper::{Body, HeaderMap, Request as HyperRequest, Response as HyperResponse};
+use serde::{Deserialize, Serialize};
+use tonic::body::BoxBody;
+use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder};
+use tonic::transport::NamedService;
+use tonic::Status;
+use tower::Service;
+
+
+/// One line of a recording file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A call was received. `metadata` has the ASCII request headers, with credentials redacted.
+    Start { call: u64, at_ms: u64, path: String, metadata: Vec<(String, String)> },
+    /// A message sent by the client, base64 encoded.
+    Request { call: u64, at_ms: u64, message: String },
+    /// A message sent by the server, base64 encoded.
+    Response { call: u64, at_ms: u64, message: String },
+    /// The call finished with this grpc-status.
+    End { call: u64, at_ms: u64, status: Option<i32>, message: Option<String> },
+}
+
+impl Event {
+    pub fn call(&self) -> u64 {
+        match self {
+            Event::Start { call, .. } | Event::Request { call, .. } | Event::Response { call, .. } | Event::End { call, .. } => *call,
+        }
+    }
+
+    pub fn at_ms(&self) -> u64 {
+        match self {
+            Event::Start { at_ms, .. } | Event::Request { at_ms, .. } | Event::Response { at_ms, .. } | Event::End { at_ms, .. } => *at_ms,
+        }
+    }
+}
+
+const REDACTED_HEADERS: &[&str] = &["authorization", "cookie"];
+
+
+/// Appends events to a newline-delimited JSON file.
+#[derive(Debug)]
+pub struct Recorder {
+    out: Mutex<BufWriter<File>>,
+    started: Instant,
+    next_call: AtomicU64,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> io::Result<Self> {
+        Ok(Recorder {
+            out: Mutex::new(BufWriter::new(File::create(path)?)),
+            started: Instant::now(),
+            next_call: AtomicU64::new(0),
+        })
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    fn record(&self, event: &Event) {
+        let mut out = self.out.lock().unwrap();
+        let written = serde_json::to_writer(&mut *out, event)
+            .map_err(io::Error::from)
+            .and_then(|_| out.write_all(b"\n"))
+            .and_then(|_| out.flush());
+
+        if let Err(e) = written {
+            tracing::error!("failed to write a recording: {}", e);
+        }
+    }
+
+    fn start(&self, path: &str, headers: &HeaderMap) -> u64 {
+        let call = self.next_call.fetch_add(1, Ordering::Relaxed);
+        let metadata = headers
+            .iter()
+            .filter_map(|(key, value)| {
+                let value = if REDACTED_HEADERS.contains(&key.as_str()) { "<redacted>" } else { value.to_str().ok()? };
+                Some((key.to_string(), value.to_string()))
+            })
+            .collect();
+
+        self.record(&Event::Start { call, at_ms: self.now_ms(), path: path.to_string(), metadata });
+        call
+    }
+
+    fn end(&self, call: u64, headers: &HeaderMap) {
+        let status = headers.get("grpc-status").and_then(|value| value.to_str().ok()?.parse().ok());
+        let message = headers.get("grpc-message").and_then(|value| value.to_str().ok()).map(str::to_string);
+
+        self.record(&Event::End { call, at_ms: self.now_ms(), status, message });
+    }
+}
+
+/// Reads a recording written by `Recorder`.
+pub fn read_recording(path: &str) -> io::Result<Vec<Event>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut events = vec![];
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        events.push(serde_json::from_str(&line).map_err(io::Error::from)?);
+    }
+
+    Ok(events)
+}
+
+
+/// Splits a gRPC body into messages. Chunks can end anywhere inside a message, so bytes are
+/// buffered until a whole length-prefixed message is available.
+#[derive(Debug, Default)]
+pub struct GrpcFrames {
+    buffer: BytesMut,
+}
+
+impl GrpcFrames {
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Bytes> {
+        self.buffer.extend_from_slice(chunk);
+        let mut messages = vec![];
+
+        while self.buffer.len() >= 5 {
+            let length = u32::from_be_bytes([self.buffer[1], self.buffer[2], self.buffer[3], self.buffer[4]]) as usize;
+            if self.buffer.len() < 5 + length {
+                break;
+            }
+            self.buffer.advance(5);
+            messages.push(self.buffer.split_to(length).freeze());
+        }
+
+        messages
+    }
+}
+
+
+/// Response body that records the messages and the final status as they pass through.
+struct RecordingBody {
+    inner: BoxBody,
+    recorder: Arc<Recorder>,
+    call: u64,
+    frames: GrpcFrames,
+}
+
+impl HttpBody for RecordingBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_data(cx);
+
+        if let Poll::Ready(Some(Ok(chunk))) = &result {
+            for message in this.frames.push(chunk) {
+                this.recorder.record(&Event::Response {
+                    call: this.call,
+                    at_ms: this.recorder.now_ms(),
+                    message: base64::encode(&message),
+                });
+            }
+        }
+
+        result
+    }
+
+    fn poll_trailers(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_trailers(cx);
+
+        if let Poll::Ready(Ok(Some(trailers))) = &result {
+            this.recorder.end(this.call, trailers);
+        }
+
+        result
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+
+/// Records every call passing through to `inner`. Without a recorder it only forwards.
+#[derive(Debug, Clone)]
+pub struct RecordingService<S> {
+    pub inner: S,
+    pub recorder: Option<Arc<Recorder>>,
+}
+
+impl<S> Service<HyperRequest<Body>> for RecordingService<S>
+    where
+        S: Service<HyperRequest<Body>, Response = HyperResponse<BoxBody>>
+        + NamedService
+        + Clone
+        + Send
+        + 'static,
+        S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: HyperRequest<Body>) -> Self::Future {
+        let mut svc = self.inner.clone();
+
+        let recorder = match self.recorder.clone() {
+            Some(recorder) => recorder,
+            None => return Box::pin(async move { svc.call(request).await }),
+        };
+
+        let call = recorder.start(request.uri().path(), request.headers());
+        let (parts, body) = request.into_parts();
+
+        let mut frames = GrpcFrames::default();
+        let request_recorder = recorder.clone();
+        let body = Body::wrap_stream(body.map_ok(move |chunk| {
+            for message in frames.push(&chunk) {
+                request_recorder.record(&Event::Request {
+                    call,
+                    at_ms: request_recorder.now_ms(),
+                    message: base64::encode(&message),
+                });
+            }
+            chunk
+        }));
+        let request = HyperRequest::from_parts(parts, body);
+
+        Box::pin(async move {
+            let response = svc.call(request).await?;
+
+            // Errors are often sent "trailers-only", with the status in the headers.
+            if response.headers().contains_key("grpc-status") {
+                recorder.end(call, response.headers());
+            }
+
+            let (parts, body) = response.into_parts();
+            let body = BoxBody::new(RecordingBody { inner: body, recorder, call, frames: GrpcFrames::default() });
+            Ok(HyperResponse::from_parts(parts, body))
+        })
+    }
+}
+
+impl<S: NamedService> NamedService for RecordingService<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+
+/// Passes already encoded messages through untouched, so calls can be replayed without knowing
+/// their types.
+#[derive(Debug, Default, Clone)]
+pub struct RawCodec;
+
+#[derive(Debug, Default, Clone)]
+pub struct RawEncoder;
+
+#[derive(Debug, Default, Clone)]
+pub struct RawDecoder;
+
+impl Codec for RawCodec {
+    type Encode = Bytes;
+    type Decode = Bytes;
+    type Encoder = RawEncoder;
+    type Decoder = RawDecoder;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        RawEncoder
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        RawDecoder
+    }
+}
+
+impl Encoder for RawEncoder {
+    type Item = Bytes;
+    type Error = Status;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut EncodeBuf<'_>) -> Result<(), Self::Error> {
+        dst.put_slice(&item);
+        Ok(())
+    }
+}
+
+impl Decoder for RawDecoder {
+    type Item = Bytes;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<Self::Item>, Self::Error> {
+        Ok(Some(src.to_bytes()))
+    }
+}
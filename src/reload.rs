@@ -0,0 +1,237 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::load_shed::ShedConfig;
+use crate::route_guide::ClientConfig;
+use crate::tenant::TenantId;
+
+
+/// The listeners used when the configuration doesn't name any.
+pub const DEFAULT_LISTEN: &[&str] = &["[::1]:50051", "[::1]:50052"];
+
+pub const DEFAULT_CERT: &str = "data/tls/server.pem";
+pub const DEFAULT_KEY: &str = "data/tls/server.key";
+
+
+/// The certificate files the gRPC listeners serve with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsFiles {
+    pub cert: String,
+    pub key: String,
+    /// With a client CA, clients must present a certificate signed by it.
+    pub client_ca: Option<String>,
+}
+
+impl Default for TlsFiles {
+    fn default() -> Self {
+        TlsFiles { cert: DEFAULT_CERT.to_string(), key: DEFAULT_KEY.to_string(), client_ca: None }
+    }
+}
+
+
+/// The server settings that `--config` can set and SIGHUP reloads. Anything the file leaves out
+/// keeps the value from the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfig {
+    /// `tracing` directives, as in RUST_LOG.
+    pub log_filter: Option<String>,
+    pub shedding: ShedConfig,
+    /// Tokens by tenant, along with those from the command line. A reload revokes the tokens
+    /// of tenants it no longer lists; tenants it never listed keep theirs.
+    pub tokens: Vec<(TenantId, String)>,
+    pub listen: Vec<SocketAddr>,
+    pub tls: TlsFiles,
+    /// What WatchConfig recommends to clients. Its version is left to `ClientConfigs`.
+    pub client: ClientConfig,
+}
+
+impl ServerConfig {
+    /// The settings the file doesn't mention come from `base`.
+    ///
+    /// One setting per line, the name and its value separated by whitespace, with `#` comments:
+    ///
+    /// ```text
+    /// log info,rust_server::chat_hub=debug
+    /// load-shedding on
+    /// max-concurrency 500
+    /// target-latency-ms 250
+    /// token default 1234
+    /// listen [::1]:50051
+    /// listen [::1]:50052
+    /// tls-cert data/tls/server.pem
+    /// tls-key data/tls/server.key
+    /// client-ca data/tls/client_ca.pem
+    /// client-deadline-ms 5000
+    /// client-deadline-ms ListFeatures 30000
+    /// client-max-request-bytes 1048576
+    /// client-max-response-bytes 4194304
+    /// client-max-calls-per-second 50
+    /// client-burst 100
+    /// ```
+    pub fn parse(text: &str, base: &ServerConfig) -> Result<ServerConfig, String> {
+        let mut config = base.clone();
+        let mut listen = vec![];
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let at = |e: String| format!("line {}: {}", i + 1, e);
+
+            let mut words = line.splitn(2, char::is_whitespace);
+            let (name, value) = (words.next().unwrap_or(""), words.next().unwrap_or("").trim());
+            if value.is_empty() {
+                return Err(at(format!("'{}' has no value", name)));
+            }
+            match name {
+                "log" => config.log_filter = Some(value.to_string()),
+                "load-shedding" => config.shedding.enabled = match value {
+                    "on"  => true,
+                    "off" => false,
+                    _ => return Err(at(format!("load-shedding is on or off, not '{}'", value))),
+                },
+                "max-concurrency" => config.shedding.max_limit = number(value).map_err(at)?,
+                "target-latency-ms" => config.shedding.target_latency = Duration::from_millis(number(value).map_err(at)?),
+                "token" => {
+                    let mut words = value.split_whitespace();
+                    let (tenant, token) = match (words.next(), words.next(), words.next()) {
+                        (Some(tenant), Some(token), None) => (tenant, token),
+                        _ => return Err(at("expected `token <tenant> <token>`".to_string())),
+                    };
+                    let tenant = TenantId::new(tenant).map_err(|status| at(status.message().to_string()))?;
+                    config.tokens.retain(|(id, _)| *id != tenant);
+                    config.tokens.push((tenant, token.to_string()));
+                },
+                "listen" => listen.push(value.parse().map_err(|_| at(format!("'{}' isn't an address like [::1]:50051", value)))?),
+                "tls-cert" => config.tls.cert = value.to_string(),
+                "tls-key" => config.tls.key = value.to_string(),
+                "client-ca" => config.tls.client_ca = Some(value.to_string()),
+                "client-deadline-ms" => {
+                    let mut words = value.split_whitespace();
+                    match (words.next(), words.next(), words.next()) {
+                        (Some(ms), None, None) => config.client.default_deadline_ms = number(ms).map_err(at)?,
+                        (Some(method), Some(ms), None) => {
+                            config.client.method_deadlines_ms.insert(method.to_string(), number(ms).map_err(at)?);
+                        },
+                        _ => return Err(at("expected `client-deadline-ms [method] <milliseconds>`".to_string())),
+                    }
+                },
+                "client-max-request-bytes" => config.client.max_request_message_bytes = number(value).map_err(at)?,
+                "client-max-response-bytes" => config.client.max_response_message_bytes = number(value).map_err(at)?,
+                "client-max-calls-per-second" => config.client.max_calls_per_second = number::<f64>(value)
+                    .ok()
+                    .filter(|rate| *rate >= 0.0)
+                    .ok_or_else(|| at(format!("client-max-calls-per-second is a number of calls, not '{}'", value)))?,
+                "client-burst" => config.client.burst = number(value).map_err(at)?,
+                other => return Err(at(format!("unknown setting '{}'", other))),
+            }
+        }
+
+        if !listen.is_empty() {
+            config.listen = listen;
+        }
+        if config.shedding.max_limit < config.shedding.min_limit {
+            return Err(format!("max-concurrency must be at least {}", config.shedding.min_limit));
+        }
+        Ok(config)
+    }
+
+    /// What changed from `self` to `new`.
+    pub fn changes(&self, new: &ServerConfig) -> Changes {
+        Changes {
+            log_filter: self.log_filter != new.log_filter,
+            shedding: self.shedding != new.shedding,
+            tokens: self.tokens != new.tokens,
+            listeners: self.listen != new.listen || self.tls != new.tls,
+            client_config: self.client != new.client,
+        }
+    }
+
+    /// The tokens `self` gave tenants that `new` no longer lists, to revoke going from one to
+    /// the other.
+    pub fn dropped_tokens<'a>(&'a self, new: &'a ServerConfig) -> impl Iterator<Item = &'a (TenantId, String)> {
+        self.tokens.iter().filter(move |(tenant, _)| !new.tokens.iter().any(|(kept, _)| kept == tenant))
+    }
+}
+
+fn number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("'{}' isn't a number", value))
+}
+
+
+/// Which parts of the configuration a reload has to apply. All but `listeners` are changed in
+/// place; new listeners or TLS settings need the listeners to be bound again.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Changes {
+    pub log_filter: bool,
+    pub shedding: bool,
+    pub tokens: bool,
+    pub listeners: bool,
+    pub client_config: bool,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        *self == Changes::default()
+    }
+
+    /// Like `log-filter, listeners`, for logging what a reload did.
+    pub fn names(&self) -> Vec<&'static str> {
+        let all = [
+            (self.log_filter, "log-filter"),
+            (self.shedding, "load-shedding"),
+            (self.tokens, "tokens"),
+            (self.listeners, "listeners"),
+            (self.client_config, "client-config"),
+        ];
+        all.iter().filter(|(changed, _)| *changed).map(|&(_, name)| name).collect()
+    }
+}
+
+
+/// The order to rebind listeners in when going from `old` to `new` addresses, one at a time so
+/// the others keep serving. Kept addresses come first, since the old listener has to go before
+/// the new one can bind; then new ones; then those that are gone, which are only stopped.
+pub fn rebind_order(old: &[SocketAddr], new: &[SocketAddr]) -> Vec<Rebind> {
+    let kept = new.iter().filter(|address| old.contains(address)).map(|&address| Rebind::Replace(address));
+    let added = new.iter().filter(|address| !old.contains(address)).map(|&address| Rebind::Start(address));
+    let removed = old.iter().filter(|address| !new.contains(address)).map(|&address| Rebind::Stop(address));
+    kept.chain(added).chain(removed).collect()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Rebind {
+    /// Drain the listener on the address, then bind it again with the new settings.
+    Replace(SocketAddr),
+    Start(SocketAddr),
+    /// Drain the listener and leave the address unbound.
+    Stop(SocketAddr),
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_no_longer_listed_are_dropped() {
+        let default = TenantId::new("default").unwrap();
+        let base = ServerConfig {
+            log_filter: None,
+            shedding: ShedConfig::default(),
+            tokens: vec![(default.clone(), "1234".to_string())],
+            listen: vec![],
+            tls: TlsFiles::default(),
+            client: ClientConfig::default(),
+        };
+        let old = ServerConfig::parse("token a secret\ntoken b other\ntoken default 5678", &base).unwrap();
+        let new = ServerConfig::parse("token b changed", &base).unwrap();
+
+        let dropped: Vec<_> = old.dropped_tokens(&new).collect();
+        assert_eq!(dropped, vec![&(TenantId::new("a").unwrap(), "secret".to_string())]);
+        // The built-in token is always listed, so the default tenant goes back to it.
+        assert!(new.tokens.contains(&(default, "1234".to_string())));
+        assert!(old.changes(&new).tokens);
+    }
+}
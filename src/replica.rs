@@ -0,0 +1,156 @@
+use std::sync::Arc;
+#[cfg(feature = "client")]
+use std::time::Duration;
+
+use futures::Stream;
+use tokio::sync::broadcast;
+use tonic::Status;
+#[cfg(feature = "client")]
+use tonic::{body::BoxBody, client::GrpcService, codegen::{Body, HttpBody, StdError}, Request};
+
+#[cfg(feature = "client")]
+use crate::client_metadata;
+use crate::feature_events::{ChangeKind, FeatureEvent};
+use crate::metrics;
+#[cfg(feature = "client")]
+use crate::route_guide::route_guide_client::RouteGuideClient;
+use crate::route_guide::replication_event::Kind;
+#[cfg(feature = "client")]
+use crate::route_guide::Feature;
+use crate::route_guide::{ReplicateRequest, ReplicationEvent};
+use crate::tenant::TenantData;
+
+
+fn change(event: FeatureEvent) -> ReplicationEvent {
+    let kind = match event.kind {
+        ChangeKind::Added => Kind::Added,
+        ChangeKind::Removed => Kind::Removed,
+    };
+    ReplicationEvent { id: event.id, kind: kind as i32, feature: Some(event.feature), epoch: String::new() }
+}
+
+fn marker(kind: Kind, id: u64, epoch: &str) -> ReplicationEvent {
+    ReplicationEvent { id, kind: kind as i32, feature: None, epoch: epoch.to_string() }
+}
+
+/// The events of a Replicate call. A replica that can resume gets the changes it missed;
+/// any other gets a copy of the features first. A replica too slow to keep up with the
+/// changes is sent a new copy.
+pub fn events(tenant: Arc<TenantData>, request: &ReplicateRequest) -> impl Stream<Item = Result<ReplicationEvent, Status>> + Send + Sync + 'static {
+    let epoch = tenant.feature_events().epoch().to_string();
+    let resume = Some(request.after).filter(|&after| after > 0 && request.epoch == epoch);
+
+    async_stream::stream! {
+        let mut resumed = None;
+        if let Some(after) = resume {
+            let subscription = tenant.feature_events().subscribe(Some(after));
+            if !subscription.gap {
+                for event in subscription.missed {
+                    yield Ok(change(event));
+                }
+                resumed = Some(subscription.live);
+            }
+        }
+
+        'copies: loop {
+            let mut live = match resumed.take() {
+                Some(live) => live,
+                None => {
+                    let (features, last_id, subscription) = tenant.replicate();
+                    yield Ok(marker(Kind::Reset, last_id, &epoch));
+                    for feature in features.iter() {
+                        yield Ok(ReplicationEvent { id: last_id, kind: Kind::Added as i32, feature: Some(feature.clone()), epoch: String::new() });
+                    }
+                    yield Ok(marker(Kind::Synced, last_id, &epoch));
+                    subscription.live
+                },
+            };
+
+            loop {
+                match live.recv().await {
+                    Ok(event) => yield Ok(change(event)),
+                    // Changes were dropped because this replica is too slow.
+                    Err(broadcast::RecvError::Lagged(_)) => {
+                        metrics::registry()
+                            .counter("replication_copies_total", "Copies of the features sent to replicas that fell behind.", &[])
+                            .inc();
+                        continue 'copies;
+                    },
+                    Err(broadcast::RecvError::Closed) => break 'copies,
+                }
+            }
+        }
+    }
+}
+
+
+/// Keeps `tenant` a read replica of the caller's features on the primary `client` talks to,
+/// authenticating with `token`. Never returns: when the stream breaks it's opened again after
+/// `retry`, resuming where it left off if the primary still has the changes since.
+#[cfg(feature = "client")]
+pub async fn follow<T>(mut client: RouteGuideClient<T>, tenant: Arc<TenantData>, token: String, retry: Duration)
+    where
+        T: GrpcService<BoxBody>,
+        T::ResponseBody: Body + HttpBody + Send + 'static,
+        T::Error: Into<StdError>,
+        <T::ResponseBody as HttpBody>::Error: Into<StdError> + Send,
+{
+    tenant.set_replica(true);
+    let mut position = ReplicateRequest::default();
+    loop {
+        match replicate(&mut client, &tenant, &token, &mut position).await {
+            Ok(()) => tracing::warn!("the primary ended replication"),
+            Err(e) => tracing::warn!(code = ?e.code(), "replication failed: {}", e.message()),
+        }
+        tokio::time::delay_for(retry).await;
+    }
+}
+
+/// One Replicate call, keeping `position` at the last change applied.
+#[cfg(feature = "client")]
+async fn replicate<T>(client: &mut RouteGuideClient<T>, tenant: &TenantData, token: &str, position: &mut ReplicateRequest) -> Result<(), Status>
+    where
+        T: GrpcService<BoxBody>,
+        T::ResponseBody: Body + HttpBody + Send + 'static,
+        T::Error: Into<StdError>,
+        <T::ResponseBody as HttpBody>::Error: Into<StdError> + Send,
+{
+    let request = client_metadata::with_token(Request::new(position.clone()), token)
+        .map_err(|e| Status::invalid_argument(e.to_string()))?;
+    let mut events = client.replicate(request).await?.into_inner();
+    let applied = metrics::registry().gauge("replica_last_change_id", "The id of the last change a replica applied.", &[]);
+
+    // The features of a copy, until it's complete.
+    let mut copy: Option<Vec<Feature>> = None;
+    while let Some(event) = events.message().await? {
+        let kind = match Kind::from_i32(event.kind) {
+            Some(Kind::Reset) => {
+                copy = Some(vec![]);
+                continue;
+            },
+            Some(Kind::Synced) => {
+                if let Some(features) = copy.take() {
+                    tracing::info!(features = features.len(), "copied the primary's features");
+                    tenant.replace_features(features);
+                }
+                position.epoch = event.epoch;
+                position.after = event.id;
+                applied.set(event.id as i64);
+                continue;
+            },
+            Some(Kind::Added) => ChangeKind::Added,
+            Some(Kind::Removed) => ChangeKind::Removed,
+            Some(Kind::Unknown) | None => continue,
+        };
+        let feature = event.feature.unwrap_or_default();
+        match &mut copy {
+            Some(features) => features.push(feature),
+            None => {
+                tenant.apply_change(kind, feature);
+                position.after = event.id;
+                applied.set(event.id as i64);
+            },
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::{Request, Status};
+
+use crate::http::middleware::{next_request_id, REQUEST_ID_HEADER};
+use crate::tenant::{TenantId, SUBJECT_HEADER, TENANT_HEADER};
+
+
+/// Metadata `stamp` records the client's deadline under, in milliseconds since the Unix epoch,
+/// so handlers don't have to work it out from `grpc-timeout` and the time the call came in.
+pub const DEADLINE_HEADER: &str = "x-deadline-ms";
+
+const MAX_REQUEST_ID_LENGTH: usize = 64;
+
+
+/// What the layers in front of the RouteGuide handlers learned about a call.
+///
+/// Tonic's interceptors can only pass metadata on, so the layers record what they found there
+/// (the tenant and subject by `Tenants::interceptor`, the request id and deadline by `stamp`)
+/// and `of` reads it back, already checked, in one go.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestContext {
+    pub tenant: TenantId,
+    /// Who made the call, like `token:default` or `certificate:alice`.
+    pub subject: String,
+    /// The client's `x-request-id`, or one made up by `stamp`, so slow-RPC logs can be matched
+    /// to the client's.
+    pub request_id: String,
+    pub deadline: Option<SystemTime>,
+    pub peer: Option<SocketAddr>,
+}
+
+impl RequestContext {
+    /// The context of an authenticated call. Fails for calls that didn't go through the
+    /// interceptors.
+    pub fn of<T>(request: &Request<T>) -> Result<RequestContext, Status> {
+        let metadata = request.metadata();
+        let tenant = text(metadata, TENANT_HEADER).ok_or_else(|| Status::unauthenticated("request has no tenant"))?;
+        let subject = text(metadata, SUBJECT_HEADER).ok_or_else(|| Status::unauthenticated("request has no subject"))?;
+        let deadline = text(metadata, DEADLINE_HEADER)
+            .and_then(|ms| ms.parse().ok())
+            .map(|ms| UNIX_EPOCH + Duration::from_millis(ms));
+
+        Ok(RequestContext {
+            tenant: TenantId::new(tenant)?,
+            subject: subject.to_string(),
+            request_id: text(metadata, REQUEST_ID_HEADER).unwrap_or("").to_string(),
+            deadline,
+            peer: request.remote_addr(),
+        })
+    }
+
+    /// The time left until the deadline; zero once it's passed, and `None` without one.
+    pub fn remaining(&self) -> Option<Duration> {
+        let deadline = self.deadline?;
+        Some(deadline.duration_since(SystemTime::now()).unwrap_or_default())
+    }
+}
+
+fn text<'a>(metadata: &'a MetadataMap, key: &str) -> Option<&'a str> {
+    metadata.get(key).and_then(|value| value.to_str().ok())
+}
+
+
+/// A `grpc-timeout` value: up to 8 digits and a unit, H, M, S, m, u or n.
+fn parse_timeout(value: &str) -> Option<Duration> {
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+    let (digits, unit) = value.split_at(value.len() - 1);
+    let amount: u64 = digits.parse().ok()?;
+    Some(match unit {
+        "H" => Duration::from_secs(amount * 3600),
+        "M" => Duration::from_secs(amount * 60),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    })
+}
+
+/// Records the request id and deadline of a call for `RequestContext`, in the interceptor. A
+/// client's own request id is kept if it's 1 to 64 visible ASCII characters; a deadline header
+/// it sent itself is replaced.
+pub fn stamp(request: &mut Request<()>) {
+    let metadata = request.metadata_mut();
+
+    let request_id = text(metadata, REQUEST_ID_HEADER)
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH)
+        .map(str::to_string)
+        .unwrap_or_else(next_request_id);
+    if let Ok(value) = MetadataValue::from_str(&request_id) {
+        metadata.insert(REQUEST_ID_HEADER, value);
+    }
+
+    metadata.remove(DEADLINE_HEADER);
+    let deadline = text(metadata, "grpc-timeout")
+        .and_then(parse_timeout)
+        .and_then(|timeout| (SystemTime::now() + timeout).duration_since(UNIX_EPOCH).ok());
+    if let Some(deadline) = deadline {
+        metadata.insert(DEADLINE_HEADER, MetadataValue::from(deadline.as_millis() as u64));
+    }
+}
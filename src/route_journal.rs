@@ -0,0 +1,236 @@
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tonic::body::BoxBody;
+use tonic::client::GrpcService;
+use tonic::codegen::{Body, HttpBody, StdError};
+use tonic::metadata::MetadataValue;
+use tonic::{Code, Request, Status};
+
+use crate::route_guide::route_guide_client::RouteGuideClient;
+use crate::route_guide::{Point, RouteSummary};
+
+
+/// The metadata key the server recognizes retried calls by, like `idempotency::IDEMPOTENCY_HEADER`.
+pub const IDEMPOTENCY_HEADER: &str = "idempotency-key";
+
+/// How often `replay_until_empty` tries again while the server can't be reached.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+const ROUTE: u8 = 1;
+const ACKNOWLEDGED: u8 = 2;
+
+
+/// A route that couldn't be uploaded. `key` is sent as the idempotency key of every attempt, so
+/// the server records it once however often it's replayed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournaledRoute {
+    pub key: String,
+    pub points: Vec<Point>,
+}
+
+
+/// Routes recorded while offline, in an append-only file.
+///
+/// Each record is a type (u8), the length of the key (u8) and the key. Routes follow with the
+/// number of points (u32, little endian) and each point's latitude and longitude (i32, little
+/// endian); acknowledgements of uploaded routes have nothing more. A truncated record at the end
+/// (from a crash during an append) is ignored.
+#[derive(Debug)]
+pub struct RouteJournal {
+    path: PathBuf,
+    // Serializes writers, like FileNoteStore.
+    lock: Mutex<()>,
+}
+
+impl RouteJournal {
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(RouteJournal { path, lock: Mutex::new(()) })
+    }
+
+    fn append(&self, record: &[u8]) -> io::Result<()> {
+        let _lock = self.lock.lock().unwrap();
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        // One write per record, so a record is never split between two appends.
+        file.write_all(record)?;
+        file.sync_data()
+    }
+
+    /// Keeps the route until it's acknowledged.
+    pub fn record(&self, route: &JournaledRoute) -> io::Result<()> {
+        self.append(&encode_route(route)?)
+    }
+
+    /// Marks the route as uploaded.
+    pub fn acknowledge(&self, key: &str) -> io::Result<()> {
+        self.append(&header(ACKNOWLEDGED, key)?)
+    }
+
+    /// The routes not acknowledged yet, oldest first.
+    pub fn pending(&self) -> io::Result<Vec<JournaledRoute>> {
+        let _lock = self.lock.lock().unwrap();
+        read_pending(&self.path)
+    }
+
+    /// Rewrites the file with only the pending routes, so it doesn't grow forever.
+    pub fn compact(&self) -> io::Result<()> {
+        let _lock = self.lock.lock().unwrap();
+        let pending = read_pending(&self.path)?;
+
+        let temporary = self.path.with_extension("tmp");
+        {
+            let mut out = BufWriter::new(File::create(&temporary)?);
+            for route in &pending {
+                out.write_all(&encode_route(route)?)?;
+            }
+            out.into_inner()?.sync_all()?;
+        }
+        fs::rename(&temporary, &self.path)
+    }
+}
+
+fn header(kind: u8, key: &str) -> io::Result<Vec<u8>> {
+    if key.is_empty() || key.len() > u8::MAX as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "journal keys are 1 to 255 bytes"));
+    }
+    let mut record = vec![kind, key.len() as u8];
+    record.extend_from_slice(key.as_bytes());
+    Ok(record)
+}
+
+fn encode_route(route: &JournaledRoute) -> io::Result<Vec<u8>> {
+    let mut record = header(ROUTE, &route.key)?;
+    record.extend_from_slice(&(route.points.len() as u32).to_le_bytes());
+    for point in &route.points {
+        record.extend_from_slice(&point.latitude.to_le_bytes());
+        record.extend_from_slice(&point.longitude.to_le_bytes());
+    }
+    Ok(record)
+}
+
+/// Fills `buffer`, or returns false at the end of the file.
+fn read_exact_or_end(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buffer) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn read_pending(path: &PathBuf) -> io::Result<Vec<JournaledRoute>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut routes: Vec<JournaledRoute> = vec![];
+
+    loop {
+        let mut header = [0u8; 2];
+        if !read_exact_or_end(&mut reader, &mut header)? {
+            break;
+        }
+        let mut key = vec![0u8; header[1] as usize];
+        if !read_exact_or_end(&mut reader, &mut key)? {
+            break;
+        }
+        let key = String::from_utf8(key).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        match header[0] {
+            ROUTE => {
+                let mut count = [0u8; 4];
+                if !read_exact_or_end(&mut reader, &mut count)? {
+                    break;
+                }
+                let mut points = vec![0u8; u32::from_le_bytes(count) as usize * 8];
+                if !read_exact_or_end(&mut reader, &mut points)? {
+                    break;
+                }
+                let points = points
+                    .chunks(8)
+                    .map(|point| Point {
+                        latitude: i32::from_le_bytes(point[..4].try_into().unwrap()),
+                        longitude: i32::from_le_bytes(point[4..].try_into().unwrap()),
+                        read_mask: None,
+                    })
+                    .collect();
+                routes.push(JournaledRoute { key, points });
+            },
+            ACKNOWLEDGED => routes.retain(|route| route.key != key),
+            kind => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown journal record type {}", kind))),
+        }
+    }
+
+    Ok(routes)
+}
+
+
+/// A new random key for a route.
+pub fn new_key() -> String {
+    format!("route-{:016x}", rand::random::<u64>())
+}
+
+/// Whether a failed upload should be kept for later: the server couldn't be reached, rather than
+/// refusing the route.
+pub fn is_offline(status: &Status) -> bool {
+    matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded | Code::Unknown)
+}
+
+/// Uploads the pending routes, oldest first, each as a RecordRoute call of its own, and returns
+/// how many were uploaded. Stops at the first route that fails because the server is still
+/// offline; routes the server refuses are reported and dropped, since replaying them wouldn't help.
+pub async fn replay<T>(journal: &RouteJournal, client: &mut RouteGuideClient<T>) -> io::Result<usize>
+    where
+        T: GrpcService<BoxBody>,
+        T::ResponseBody: Body + HttpBody + Send + 'static,
+        T::Error: Into<StdError>,
+        <T::ResponseBody as HttpBody>::Error: Into<StdError> + Send,
+{
+    let mut uploaded = 0;
+    for route in journal.pending()? {
+        match upload(client, &route.key, route.points).await {
+            Ok(_) => uploaded += 1,
+            Err(status) if is_offline(&status) => break,
+            Err(status) => tracing::warn!(key = %route.key, "dropping a journaled route: {}", status.message()),
+        }
+        journal.acknowledge(&route.key)?;
+    }
+
+    if uploaded > 0 {
+        journal.compact()?;
+    }
+    Ok(uploaded)
+}
+
+/// Replays every `RETRY_INTERVAL` until nothing is pending.
+pub async fn replay_until_empty<T>(journal: &RouteJournal, client: &mut RouteGuideClient<T>) -> io::Result<()>
+    where
+        T: GrpcService<BoxBody>,
+        T::ResponseBody: Body + HttpBody + Send + 'static,
+        T::Error: Into<StdError>,
+        <T::ResponseBody as HttpBody>::Error: Into<StdError> + Send,
+{
+    loop {
+        replay(journal, client).await?;
+        if journal.pending()?.is_empty() {
+            return Ok(());
+        }
+        tokio::time::delay_for(RETRY_INTERVAL).await;
+    }
+}
+
+async fn upload<T>(client: &mut RouteGuideClient<T>, key: &str, points: Vec<Point>) -> Result<RouteSummary, Status>
+    where
+        T: GrpcService<BoxBody>,
+        T::ResponseBody: Body + HttpBody + Send + 'static,
+        T::Error: Into<StdError>,
+        <T::ResponseBody as HttpBody>::Error: Into<StdError> + Send,
+{
+    let mut request = Request::new(futures::stream::iter(points));
+    let key = MetadataValue::from_str(key).map_err(|_| Status::invalid_argument("journal key isn't valid metadata"))?;
+    request.metadata_mut().insert(IDEMPOTENCY_HEADER, key);
+
+    Ok(client.record_route(request).await?.into_inner())
+}
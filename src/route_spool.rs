@@ -0,0 +1,142 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+
+use once_cell::sync::Lazy;
+
+use crate::geo::PolylineEncoder;
+use crate::metrics::{self, GaugeGuard};
+use crate::route_guide::Point;
+
+
+static DIRECTORY: Lazy<RwLock<Option<PathBuf>>> = Lazy::new(|| RwLock::new(None));
+static NEXT: AtomicU64 = AtomicU64::new(0);
+
+/// Where paths are spooled from now on, instead of the system's temporary directory.
+pub fn configure(directory: PathBuf) {
+    *DIRECTORY.write().unwrap() = Some(directory);
+}
+
+fn directory() -> PathBuf {
+    DIRECTORY.read().unwrap().clone().unwrap_or_else(std::env::temp_dir)
+}
+
+
+/// The file of a spooled path, removed when dropped.
+#[derive(Debug)]
+struct Spooled {
+    path: PathBuf,
+    file: File,
+    _open: GaugeGuard,
+}
+
+impl Spooled {
+    fn create() -> io::Result<Self> {
+        let name = format!("route-spool-{}-{}.polyline", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+        let path = directory().join(name);
+        let file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+        let open = metrics::registry()
+            .gauge("route_spools_open", "RecordRoute paths being spooled to disk.", &[])
+            .track();
+        metrics::registry()
+            .counter("route_spooled_uploads_total", "RecordRoute uploads whose path outgrew memory and went to disk.", &[])
+            .inc();
+        tracing::debug!(path = %path.display(), "spooling a route's path to disk");
+        Ok(Spooled { path, file, _open: open })
+    }
+}
+
+impl Drop for Spooled {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            tracing::warn!(path = %self.path.display(), "couldn't remove a route spool: {}", e);
+        }
+    }
+}
+
+
+/// The path of a route being recorded, encoded as a polyline while the points arrive. It stays
+/// in memory up to `threshold` bytes; past that, it goes to a temporary file a threshold at a
+/// time, so an upload of millions of points takes disk rather than memory until its summary is
+/// built. `None` keeps it all in memory. The path is cut at the last point that fits in `limit`
+/// bytes, which bounds both the file and the polyline read back from it.
+#[derive(Debug)]
+pub struct PathSpool {
+    encoder: PolylineEncoder,
+    /// What hasn't been written to the file.
+    buffer: String,
+    /// The bytes in the file.
+    written: usize,
+    threshold: Option<usize>,
+    limit: usize,
+    truncated: bool,
+    spooled: Option<Spooled>,
+}
+
+impl PathSpool {
+    pub fn new(threshold: Option<usize>, limit: usize) -> Self {
+        PathSpool {
+            encoder: PolylineEncoder::default(),
+            buffer: String::new(),
+            written: 0,
+            threshold,
+            limit,
+            truncated: false,
+            spooled: None,
+        }
+    }
+
+    pub fn push(&mut self, point: &Point) -> io::Result<()> {
+        if self.truncated {
+            return Ok(());
+        }
+        let before = self.buffer.len();
+        self.encoder.push(point, &mut self.buffer);
+        if self.written + self.buffer.len() > self.limit {
+            self.buffer.truncate(before);
+            self.truncated = true;
+            return Ok(());
+        }
+        match self.threshold {
+            Some(threshold) if self.buffer.len() >= threshold => self.spill(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Moves the buffer to the file. Written unbuffered, so the file can be read back at any
+    /// time.
+    fn spill(&mut self) -> io::Result<()> {
+        if self.spooled.is_none() {
+            self.spooled = Some(Spooled::create()?);
+        }
+        let spooled = self.spooled.as_mut().unwrap();
+        spooled.file.write_all(self.buffer.as_bytes())?;
+        metrics::registry()
+            .counter("route_spooled_bytes_total", "Bytes of RecordRoute paths written to disk.", &[])
+            .add(self.buffer.len() as u64);
+        self.written += self.buffer.len();
+        self.buffer.clear();
+        Ok(())
+    }
+
+    pub fn is_spooled(&self) -> bool {
+        self.spooled.is_some()
+    }
+
+    /// Whether points were left out for the polyline to fit in its limit.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// The path so far, reading back what was spooled. It's at most `limit` bytes.
+    pub fn polyline(&self) -> io::Result<String> {
+        let mut polyline = String::with_capacity(self.written + self.buffer.len());
+        if let Some(spooled) = &self.spooled {
+            File::open(&spooled.path)?.take(self.written as u64).read_to_string(&mut polyline)?;
+        }
+        polyline.push_str(&self.buffer);
+        Ok(polyline)
+    }
+}
@@ -0,0 +1,169 @@
+// tokio-metrics and console-subscriber both need tokio 1.x, so the same signals are collected
+// here by hand: per-task poll counts and durations, slow polls (a task blocking its worker
+// thread), the delay between a task being woken and polled, and how late the timer fires, which
+// grows when every worker is busy. Disable the `runtime-metrics` feature to turn it all off.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use futures::task::{waker_ref, ArcWake};
+
+use crate::metrics::{self, Counter, Gauge, Histogram, DURATION_BUCKETS};
+
+
+/// A poll longer than this is considered to block the worker thread.
+pub const SLOW_POLL: Duration = Duration::from_millis(10);
+
+pub const POLL_BUCKETS: &[f64] = &[0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+
+
+pub fn enabled() -> bool {
+    cfg!(feature = "runtime-metrics")
+}
+
+
+struct TaskMetrics {
+    polls: Arc<Counter>,
+    slow_polls: Arc<Counter>,
+    poll_duration: Arc<Histogram>,
+    scheduled_delay: Arc<Histogram>,
+    alive: Arc<Gauge>,
+}
+
+impl TaskMetrics {
+    fn new(task: &str) -> Self {
+        let registry = metrics::registry();
+        let labels = [("task", task)];
+
+        TaskMetrics {
+            polls: registry.counter("tokio_task_polls_total", "Number of times tasks were polled.", &labels),
+            slow_polls: registry.counter("tokio_task_slow_polls_total", "Polls that took longer than 10ms.", &labels),
+            poll_duration: registry.histogram("tokio_task_poll_duration_seconds", "Time spent in a single poll.", &labels, POLL_BUCKETS),
+            scheduled_delay: registry.histogram("tokio_task_scheduled_delay_seconds", "Time between a task being woken and polled.", &labels, POLL_BUCKETS),
+            alive: registry.gauge("tokio_tasks_alive", "Instrumented tasks that haven't completed.", &labels),
+        }
+    }
+}
+
+
+/// Remembers when the task was woken, then forwards the wake-up.
+struct WakeTracker {
+    waker: Mutex<Option<Waker>>,
+    woken_at: Mutex<Option<Instant>>,
+}
+
+impl ArcWake for WakeTracker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.woken_at.lock().unwrap().get_or_insert_with(Instant::now);
+        if let Some(waker) = arc_self.waker.lock().unwrap().as_ref() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+
+/// A future whose polls are recorded under a task name. See `instrument`.
+pub struct Instrumented<F> {
+    inner: Pin<Box<F>>,
+    state: Option<(TaskMetrics, Arc<WakeTracker>)>,
+}
+
+/// Wraps a future (typically one passed to `tokio::spawn`) so its polls show up in the runtime
+/// metrics, labelled with `task`.
+pub fn instrument<F: Future>(task: &str, future: F) -> Instrumented<F> {
+    let state = if enabled() {
+        let metrics = TaskMetrics::new(task);
+        metrics.alive.inc();
+        let tracker = Arc::new(WakeTracker { waker: Mutex::new(None), woken_at: Mutex::new(None) });
+        Some((metrics, tracker))
+    } else {
+        None
+    };
+
+    Instrumented { inner: Box::pin(future), state }
+}
+
+impl<F: Future> Future for Instrumented<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        let (metrics, tracker) = match this.state.as_ref() {
+            Some(state) => state,
+            None => return this.inner.as_mut().poll(cx),
+        };
+
+        if let Some(woken_at) = tracker.woken_at.lock().unwrap().take() {
+            metrics.scheduled_delay.observe(woken_at.elapsed().as_secs_f64());
+        }
+        *tracker.waker.lock().unwrap() = Some(cx.waker().clone());
+
+        let waker = waker_ref(tracker);
+        let mut context = Context::from_waker(&waker);
+
+        let start = Instant::now();
+        let result = this.inner.as_mut().poll(&mut context);
+        let elapsed = start.elapsed();
+
+        metrics.polls.inc();
+        metrics.poll_duration.observe(elapsed.as_secs_f64());
+        if elapsed >= SLOW_POLL {
+            metrics.slow_polls.inc();
+        }
+
+        result
+    }
+}
+
+impl<F> Drop for Instrumented<F> {
+    fn drop(&mut self) {
+        if let Some((metrics, _)) = self.state.as_ref() {
+            metrics.alive.dec();
+        }
+    }
+}
+
+
+/// Runs blocking work (like the synchronous database load) on the blocking pool, so it can't
+/// stall the async workers, and records how long it took.
+pub async fn blocking<F, T>(task: &str, f: F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+{
+    let start = Instant::now();
+    let result = tokio::task::spawn_blocking(f).await.expect("blocking task panicked");
+
+    if enabled() {
+        metrics::registry()
+            .histogram("tokio_blocking_duration_seconds", "Duration of work run on the blocking pool.", &[("task", task)], DURATION_BUCKETS)
+            .observe(start.elapsed().as_secs_f64());
+    }
+
+    result
+}
+
+
+/// Spawns a task that sleeps for `interval` over and over and records how late it wakes up. When
+/// workers are blocked, timers fire late and this grows.
+pub fn spawn_scheduler_probe(interval: Duration) {
+    if !enabled() {
+        return;
+    }
+
+    let lateness = metrics::registry().histogram(
+        "tokio_scheduler_delay_seconds", "How late a timer task was polled after its deadline.", &[], POLL_BUCKETS
+    );
+
+    tokio::spawn(async move {
+        loop {
+            let deadline = Instant::now() + interval;
+            tokio::time::delay_for(interval).await;
+            lateness.observe(Instant::now().saturating_duration_since(deadline).as_secs_f64());
+        }
+    });
+}
@@ -0,0 +1,145 @@
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::{Code, Request, Status};
+
+use crate::geo;
+use crate::route_guide::{Feature, Point, Rectangle};
+
+
+/// Request metadata that asks ListFeatures for partial results: `true` lets the stream complete
+/// with what could be read, reporting the rest in the trailers, rather than fail.
+pub const PARTIAL_RESULTS_HEADER: &str = "x-partial-results";
+
+/// Trailer with the parts of the rectangle that weren't read, `;`-separated, each as
+/// `lo latitude,lo longitude,hi latitude,hi longitude` in E7 degrees. Each is a Rectangle that
+/// can be asked for again.
+pub const SKIPPED_HEADER: &str = "x-skipped-ranges";
+
+/// Trailer with the number of errors the scan ran into.
+pub const ERRORS_HEADER: &str = "x-scan-errors";
+
+/// The most latitude bands ListFeatures reads the rectangle in. More bands loses less to a
+/// failure but reads the features once per band.
+pub const MAX_BANDS: u32 = 16;
+
+
+/// Where a scan reads its features, a range at a time. A source that can fail, like storage on
+/// disk, fails only the range it couldn't read, which a partial scan skips.
+pub trait FeatureSource {
+    /// The features inside `range`, in the source's own order, the same one every time.
+    fn read(&self, range: &Rectangle) -> Result<Vec<Feature>, Status>;
+}
+
+
+/// Whether the call asked for partial results.
+pub fn allows_partial<T>(request: &Request<T>) -> bool {
+    request.metadata().get(PARTIAL_RESULTS_HEADER).and_then(|value| value.to_str().ok()) == Some("true")
+}
+
+/// The rectangle cut into at most `count` latitude bands of equal height, south first. Bands
+/// don't overlap and cover the rectangle, keeping its longitudes, so they too can cross the
+/// antimeridian.
+pub fn bands(rect: &Rectangle, count: u32) -> Vec<Rectangle> {
+    let bounds = match geo::Bounds::of(rect) {
+        Some(bounds) => bounds,
+        None => return vec![],
+    };
+    let (south, north) = (bounds.south as i64, bounds.north as i64);
+    let height = ((north - south + 1) + count.max(1) as i64 - 1) / count.max(1) as i64;
+
+    (0..)
+        .map(|i| south + i * height)
+        .take_while(|&band_south| band_south <= north)
+        .map(|band_south| band(band_south as i32, (band_south + height - 1).min(north) as i32, &bounds))
+        .collect()
+}
+
+fn band(south: i32, north: i32, bounds: &geo::Bounds) -> Rectangle {
+    Rectangle {
+        lo: Some(Point { latitude: south, longitude: bounds.west, read_mask: None }),
+        hi: Some(Point { latitude: north, longitude: bounds.east, read_mask: None }),
+        cluster: None,
+        read_mask: None,
+        resume_after: String::new(),
+    }
+}
+
+
+/// What a partial scan couldn't read.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanReport {
+    pub skipped: Vec<Rectangle>,
+    pub errors: u32,
+}
+
+impl ScanReport {
+    pub fn is_complete(&self) -> bool {
+        self.skipped.is_empty() && self.errors == 0
+    }
+
+    /// Records that reading `range` failed.
+    pub fn skip(&mut self, range: Rectangle) {
+        self.skipped.push(range);
+        self.errors += 1;
+    }
+
+    pub fn trailers(&self) -> MetadataMap {
+        let mut trailers = MetadataMap::new();
+        trailers.insert(ERRORS_HEADER, MetadataValue::from(self.errors));
+        if !self.skipped.is_empty() {
+            let ranges: Vec<String> = self.skipped.iter().map(format_range).collect();
+            if let Ok(value) = MetadataValue::from_str(&ranges.join(";")) {
+                trailers.insert(SKIPPED_HEADER, value);
+            }
+        }
+        trailers
+    }
+
+    /// The end of a successful stream, carrying the report in its trailers.
+    pub fn into_status(self) -> Status {
+        Status::with_metadata(Code::Ok, "", self.trailers())
+    }
+
+    /// The report in a call's trailers; `None` if they have none, as from servers that don't
+    /// report.
+    pub fn from_trailers(trailers: &MetadataMap) -> Result<Option<ScanReport>, String> {
+        let errors = match trailers.get(ERRORS_HEADER) {
+            Some(value) => value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| format!("{} isn't a number", ERRORS_HEADER))?,
+            None => return Ok(None),
+        };
+        let skipped = match trailers.get(SKIPPED_HEADER) {
+            Some(value) => {
+                let value = value.to_str().map_err(|_| format!("{} isn't text", SKIPPED_HEADER))?;
+                value.split(';').map(parse_range).collect::<Result<_, _>>()?
+            },
+            None => vec![],
+        };
+        Ok(Some(ScanReport { skipped, errors }))
+    }
+}
+
+fn format_range(range: &Rectangle) -> String {
+    let (lo, hi) = (range.lo.clone().unwrap_or_default(), range.hi.clone().unwrap_or_default());
+    format!("{},{},{},{}", lo.latitude, lo.longitude, hi.latitude, hi.longitude)
+}
+
+fn parse_range(text: &str) -> Result<Rectangle, String> {
+    let numbers = text
+        .split(',')
+        .map(|number| number.trim().parse::<i32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("'{}' isn't a range of four E7 coordinates", text))?;
+    match numbers[..] {
+        [lo_latitude, lo_longitude, hi_latitude, hi_longitude] => Ok(Rectangle {
+            lo: Some(Point { latitude: lo_latitude, longitude: lo_longitude, read_mask: None }),
+            hi: Some(Point { latitude: hi_latitude, longitude: hi_longitude, read_mask: None }),
+            cluster: None,
+            read_mask: None,
+            resume_after: String::new(),
+        }),
+        _ => Err(format!("'{}' isn't a range of four E7 coordinates", text)),
+    }
+}
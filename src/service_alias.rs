@@ -0,0 +1,123 @@
+use std::marker::PhantomData;
+use std::task::{Context, Poll};
+
+use hyper::{Body, Request as HyperRequest};
+use tonic::codegen::http::uri::{PathAndQuery, Uri};
+use tonic::transport::NamedService;
+use tower::Service;
+
+
+/// A gRPC service name to serve under, in addition to the name the service was generated with.
+pub trait AliasName {
+    const NAME: &'static str;
+    /// Request metadata dropped from calls under this name, for features of the service that
+    /// its older wire format doesn't have.
+    const DROPPED_METADATA: &'static [&'static str] = &[];
+}
+
+/// Serves `S` under `N::NAME` by rewriting request paths to `S::NAME`. Only works when the
+/// messages of both services are wire compatible.
+pub struct ServiceAlias<S, N> {
+    inner: S,
+    name: PhantomData<fn() -> N>,
+}
+
+impl<S, N> ServiceAlias<S, N> {
+    pub fn new(inner: S) -> Self {
+        ServiceAlias { inner, name: PhantomData }
+    }
+}
+
+impl<S: Clone, N> Clone for ServiceAlias<S, N> {
+    fn clone(&self) -> Self {
+        ServiceAlias::new(self.inner.clone())
+    }
+}
+
+impl<S, N> Service<HyperRequest<Body>> for ServiceAlias<S, N>
+    where
+        S: Service<HyperRequest<Body>> + NamedService,
+        N: AliasName,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: HyperRequest<Body>) -> Self::Future {
+        let method = request.uri().path().strip_prefix(&format!("/{}/", N::NAME)).map(str::to_string);
+
+        // The router only sends requests for `N::NAME` here, so the prefix is always there.
+        if let Some(method) = method {
+            let mut parts = request.uri().clone().into_parts();
+            parts.path_and_query = format!("/{}/{}", S::NAME, method).parse::<PathAndQuery>().ok();
+            if let Ok(uri) = Uri::from_parts(parts) {
+                *request.uri_mut() = uri;
+            }
+        }
+
+        for name in N::DROPPED_METADATA {
+            request.headers_mut().remove(*name);
+        }
+
+        self.inner.call(request)
+    }
+}
+
+impl<S, N: AliasName> NamedService for ServiceAlias<S, N> {
+    const NAME: &'static str = N::NAME;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::future::{self, Ready};
+
+    /// Answers with the path and headers it was called with.
+    #[derive(Clone)]
+    struct Echo;
+
+    impl NamedService for Echo {
+        const NAME: &'static str = "routeguide.v2.RouteGuide";
+    }
+
+    impl Service<HyperRequest<Body>> for Echo {
+        type Response = HyperRequest<Body>;
+        type Error = std::convert::Infallible;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: HyperRequest<Body>) -> Self::Future {
+            future::ready(Ok(request))
+        }
+    }
+
+    struct Frozen;
+
+    impl AliasName for Frozen {
+        const NAME: &'static str = "routeguide.v1.RouteGuide";
+        const DROPPED_METADATA: &'static [&'static str] = &["x-chat-heartbeats"];
+    }
+
+    #[tokio::test]
+    async fn renames_and_drops_metadata() {
+        let request = HyperRequest::builder()
+            .uri("http://localhost/routeguide.v1.RouteGuide/RouteChat")
+            .header("x-chat-heartbeats", "true")
+            .header("x-chat-client-id", "a")
+            .body(Body::empty())
+            .unwrap();
+        let seen = ServiceAlias::<_, Frozen>::new(Echo).call(request).await.unwrap();
+        assert_eq!(seen.uri().path(), "/routeguide.v2.RouteGuide/RouteChat");
+        assert!(seen.headers().get("x-chat-heartbeats").is_none());
+        assert_eq!(seen.headers()["x-chat-client-id"], "a");
+    }
+}
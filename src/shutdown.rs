@@ -0,0 +1,63 @@
+use std::error::Error;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+
+
+pub type HookError = Box<dyn Error + Send + Sync>;
+
+struct Hook {
+    name: &'static str,
+    timeout: Duration,
+    run: Box<dyn FnOnce() -> BoxFuture<'static, Result<(), HookError>> + Send>,
+}
+
+/// Work that has to happen before the process exits, like flushing state to disk. Subsystems
+/// register a hook when they start; `run` calls them in registration order once the servers
+/// have stopped.
+#[derive(Default)]
+pub struct ShutdownHooks {
+    hooks: Mutex<Vec<Hook>>,
+}
+
+impl ShutdownHooks {
+    /// Registers a hook that may take up to `timeout`. A slow or failing hook is logged and
+    /// doesn't keep the rest from running.
+    pub fn register<F, Fut, E>(&self, name: &'static str, timeout: Duration, hook: F)
+        where
+            F: FnOnce() -> Fut + Send + 'static,
+            Fut: Future<Output = Result<(), E>> + Send + 'static,
+            E: Into<HookError>,
+    {
+        let run = Box::new(move || -> BoxFuture<'static, Result<(), HookError>> {
+            let future = hook();
+            Box::pin(async move { future.await.map_err(Into::into) })
+        });
+        self.hooks.lock().unwrap().push(Hook { name, timeout, run });
+    }
+
+    /// Runs the registered hooks one after another. Returns how many failed or timed out. Hooks
+    /// only ever run once; calling this again runs the ones registered since.
+    pub async fn run(&self) -> usize {
+        let hooks = std::mem::take(&mut *self.hooks.lock().unwrap());
+        let mut failed = 0;
+
+        for hook in hooks {
+            match tokio::time::timeout(hook.timeout, (hook.run)()).await {
+                Ok(Ok(())) => {},
+                Ok(Err(e)) => {
+                    tracing::error!(hook = %hook.name, "shutdown hook failed: {}", e);
+                    failed += 1;
+                },
+                Err(_) => {
+                    tracing::error!(hook = %hook.name, timeout = ?hook.timeout, "shutdown hook timed out");
+                    failed += 1;
+                },
+            }
+        }
+
+        failed
+    }
+}
@@ -0,0 +1,333 @@
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use http_body::Body as HttpBody;
+use hyper::{Body, HeaderMap, Request as HyperRequest, Response as HyperResponse};
+use tonic::body::BoxBody;
+use tonic::transport::NamedService;
+use tonic::{Code, Status};
+use tower::Service;
+
+use crate::admin::{ErrorBudget, ErrorBudgets};
+use crate::metrics;
+
+
+/// Windows are kept as this many slots, so calls leave the window a slot at a time.
+const SLOTS: u32 = 60;
+
+
+/// The share of a method's calls that should succeed, like `GetFeature=99.9` for 99.9%. From
+/// `--slo`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slo {
+    pub method: String,
+    /// Between 0 and 1, like 0.999.
+    pub target: f64,
+}
+
+impl FromStr for Slo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let usage = || format!("'{}' isn't an objective like GetFeature=99.9 (in percent)", s);
+        let i = s.find('=').ok_or_else(usage)?;
+        let (method, percent) = (s[..i].trim(), s[i + 1..].trim().trim_end_matches('%'));
+        if method.is_empty() {
+            return Err(usage());
+        }
+        let percent = percent.parse::<f64>().map_err(|_| usage())?;
+        if !(percent > 0.0 && percent < 100.0) {
+            return Err(format!("the objective of {} must be above 0% and below 100%", method));
+        }
+        Ok(Slo { method: method.to_string(), target: percent / 100.0 })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SloConfig {
+    pub slos: Vec<Slo>,
+    /// How far back success rates look.
+    pub window: Duration,
+    /// A budget isn't exhausted on fewer calls than this in the window, so a few early
+    /// failures don't trip degradation.
+    pub min_calls: u64,
+    /// Shed the methods without an objective while a budget is exhausted.
+    pub degrade: bool,
+}
+
+impl Default for SloConfig {
+    fn default() -> Self {
+        SloConfig { slos: vec![], window: Duration::from_secs(3600), min_calls: 100, degrade: false }
+    }
+}
+
+
+/// Whether a call that ended with `code` spends error budget. Only the server's failures do:
+/// a call the client got wrong, wasn't allowed to make or ran out of quota for was served as
+/// it should be.
+pub fn counts_as_failure(code: Code) -> bool {
+    matches!(code, Code::Unknown | Code::DeadlineExceeded | Code::Internal | Code::Unavailable | Code::DataLoss)
+}
+
+
+#[derive(Debug, Default, Copy, Clone)]
+struct Slot {
+    index: u64,
+    calls: u64,
+    failures: u64,
+}
+
+/// The calls of the last `window`, by slot, oldest first.
+#[derive(Debug)]
+struct Window {
+    target: f64,
+    slots: VecDeque<Slot>,
+}
+
+impl Window {
+    fn record(&mut self, now: u64, failed: bool) {
+        self.expire(now);
+        if self.slots.back().is_none_or(|slot| slot.index != now) {
+            self.slots.push_back(Slot { index: now, ..Slot::default() });
+        }
+        let slot = self.slots.back_mut().unwrap();
+        slot.calls += 1;
+        slot.failures += failed as u64;
+    }
+
+    fn expire(&mut self, now: u64) {
+        while self.slots.front().is_some_and(|slot| slot.index + SLOTS as u64 <= now) {
+            self.slots.pop_front();
+        }
+    }
+
+    fn totals(&self) -> (u64, u64) {
+        self.slots.iter().fold((0, 0), |(calls, failures), slot| (calls + slot.calls, failures + slot.failures))
+    }
+
+    /// 1 with nothing spent, 0 with all of it, and below 0 once overspent.
+    fn remaining(&self) -> f64 {
+        let (calls, failures) = self.totals();
+        if calls == 0 {
+            return 1.0;
+        }
+        1.0 - failures as f64 / ((1.0 - self.target) * calls as f64)
+    }
+}
+
+
+/// Success rates of the methods with an objective, over a rolling window, and the error budget
+/// they leave: the failures the objective allows in the window, less those there were. An
+/// exhausted budget can put the server in degradation, shedding the methods without an
+/// objective so the capacity goes to those with one.
+///
+/// Budgets are exported as `slo_error_budget_remaining_permille` as calls end, and the calls
+/// counted in `slo_calls_total`.
+#[derive(Debug)]
+pub struct SloTracker {
+    config: SloConfig,
+    started: Instant,
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+impl SloTracker {
+    pub fn new(config: SloConfig) -> Self {
+        let windows = config.slos
+            .iter()
+            .map(|slo| (slo.method.clone(), Window { target: slo.target, slots: VecDeque::new() }))
+            .collect();
+        SloTracker { config, started: Instant::now(), windows: Mutex::new(windows) }
+    }
+
+    pub fn config(&self) -> &SloConfig {
+        &self.config
+    }
+
+    fn slot(&self) -> u64 {
+        let slot = (self.config.window / SLOTS).as_millis().max(1);
+        (self.started.elapsed().as_millis() / slot) as u64
+    }
+
+    /// Counts a call to `method`, by name like `GetFeature`, that ended with `code`. Calls to
+    /// methods without an objective are left out.
+    pub fn record(&self, method: &str, code: Code) {
+        let now = self.slot();
+        let mut windows = self.windows.lock().unwrap();
+        let window = match windows.get_mut(method) {
+            Some(window) => window,
+            None => return,
+        };
+        let failed = counts_as_failure(code);
+        window.record(now, failed);
+        let remaining = window.remaining();
+        drop(windows);
+
+        let registry = metrics::registry();
+        let outcome = if failed { "failure" } else { "success" };
+        registry
+            .counter("slo_calls_total", "Calls to methods with an objective, by whether they spent error budget.", &[("method", method), ("outcome", outcome)])
+            .inc();
+        registry
+            .gauge("slo_error_budget_remaining_permille", "Error budget left in the window, in thousandths; negative once overspent.", &[("method", method)])
+            .set((remaining * 1000.0).round() as i64);
+    }
+
+    /// Whether a budget is exhausted, on enough calls to tell.
+    pub fn exhausted(&self) -> bool {
+        let now = self.slot();
+        let mut windows = self.windows.lock().unwrap();
+        windows.values_mut().any(|window| {
+            window.expire(now);
+            window.totals().0 >= self.config.min_calls && window.remaining() <= 0.0
+        })
+    }
+
+    /// Whether calls to `method` are shed: with degradation on, calls to methods without an
+    /// objective while a budget is exhausted.
+    pub fn sheds(&self, method: &str) -> bool {
+        if !self.config.degrade || self.windows.lock().unwrap().contains_key(method) {
+            return false;
+        }
+        let degraded = self.exhausted();
+        metrics::registry().gauge("slo_degraded", "1 while methods without an objective are shed.", &[]).set(degraded as i64);
+        degraded
+    }
+
+    /// For the admin service.
+    pub fn error_budgets(&self) -> ErrorBudgets {
+        let now = self.slot();
+        let mut windows = self.windows.lock().unwrap();
+        let mut budgets: Vec<_> = windows
+            .iter_mut()
+            .map(|(method, window)| {
+                window.expire(now);
+                let (calls, failures) = window.totals();
+                ErrorBudget {
+                    method: method.clone(),
+                    target: window.target,
+                    calls,
+                    failures,
+                    success_rate: if calls == 0 { 1.0 } else { 1.0 - failures as f64 / calls as f64 },
+                    budget_remaining: window.remaining(),
+                }
+            })
+            .collect();
+        drop(windows);
+        budgets.sort_by(|a, b| a.method.cmp(&b.method));
+
+        ErrorBudgets {
+            budgets,
+            window_secs: self.config.window.as_secs(),
+            degraded: self.config.degrade && self.exhausted(),
+        }
+    }
+}
+
+
+/// The trailers-only answer for a shed call.
+fn shed() -> HyperResponse<BoxBody> {
+    HyperResponse::builder()
+        .status(200)
+        .header("content-type", "application/grpc")
+        .header("grpc-status", "14")
+        .header("grpc-message", "shedding this method while the server's error budget is exhausted, retry later")
+        .body(BoxBody::empty())
+        .unwrap()
+}
+
+fn status(headers: &HeaderMap) -> Option<Code> {
+    let code = headers.get("grpc-status")?.to_str().ok()?.parse::<i32>().ok()?;
+    Some(Code::from_i32(code))
+}
+
+/// Response body that counts the call once its trailers arrive. Calls the client cancels
+/// aren't counted.
+struct CountedBody {
+    inner: BoxBody,
+    tracker: Arc<SloTracker>,
+    method: String,
+}
+
+impl HttpBody for CountedBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_trailers(cx);
+        match &result {
+            Poll::Ready(Ok(Some(trailers))) => this.tracker.record(&this.method, status(trailers).unwrap_or(Code::Unknown)),
+            Poll::Ready(Err(_)) => this.tracker.record(&this.method, Code::Internal),
+            _ => {},
+        }
+        result
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+
+/// Counts the outcome of every call to `inner` in `tracker`, and rejects with UNAVAILABLE the
+/// calls it sheds. Shed calls aren't counted, so shedding doesn't spend the budgets it's there
+/// to save.
+#[derive(Debug, Clone)]
+pub struct SloService<S> {
+    pub inner: S,
+    pub tracker: Arc<SloTracker>,
+}
+
+impl<S> Service<HyperRequest<Body>> for SloService<S>
+    where
+        S: Service<HyperRequest<Body>, Response = HyperResponse<BoxBody>>
+        + NamedService
+        + Clone
+        + Send
+        + 'static,
+        S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: HyperRequest<Body>) -> Self::Future {
+        let path = request.uri().path();
+        let method = path.rsplit('/').next().unwrap_or(path).to_string();
+        if self.tracker.sheds(&method) {
+            metrics::registry()
+                .counter("slo_shed_calls_total", "Calls shed while an error budget was exhausted.", &[])
+                .inc();
+            return Box::pin(async { Ok(shed()) });
+        }
+
+        let (mut svc, tracker) = (self.inner.clone(), self.tracker.clone());
+        Box::pin(async move {
+            let response = svc.call(request).await?;
+
+            // Errors are often sent "trailers-only", with the status in the headers.
+            if let Some(code) = status(response.headers()) {
+                tracker.record(&method, code);
+                return Ok(response);
+            }
+            Ok(response.map(|body| BoxBody::new(CountedBody { inner: body, tracker, method })))
+        })
+    }
+}
+
+impl<S: NamedService> NamedService for SloService<S> {
+    const NAME: &'static str = S::NAME;
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+
+/// The delays between attempts to reach the server at startup: `initial` first, then twice the
+/// last one up to `max`, each with up to a quarter taken off at random so clients started
+/// together don't retry together. Written `200..5000`, in milliseconds.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RetrySchedule {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for RetrySchedule {
+    fn default() -> Self {
+        RetrySchedule { initial: Duration::from_millis(200), max: Duration::from_secs(5) }
+    }
+}
+
+impl FromStr for RetrySchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let usage = || format!("'{}' isn't a schedule like 200..5000 (in milliseconds)", s);
+        let i = s.find("..").ok_or_else(usage)?;
+        let initial = s[..i].trim().parse::<u64>().map_err(|_| usage())?;
+        let max = s[i + 2..].trim().parse::<u64>().map_err(|_| usage())?;
+        if initial == 0 || max < initial {
+            return Err(format!("'{}' must start above 0 and not end below its start", s));
+        }
+        Ok(RetrySchedule { initial: Duration::from_millis(initial), max: Duration::from_millis(max) })
+    }
+}
+
+impl fmt::Display for RetrySchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.initial.as_millis(), self.max.as_millis())
+    }
+}
+
+impl RetrySchedule {
+    /// The delay before attempt `attempt + 1`, without the jitter; attempts count from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let doublings = attempt.saturating_sub(1).min(31);
+        self.initial.checked_mul(1 << doublings).unwrap_or(self.max).min(self.max)
+    }
+
+    fn jittered(&self, attempt: u32) -> Duration {
+        self.delay(attempt).mul_f64(1.0 - rand::thread_rng().gen_range(0.0, 0.25))
+    }
+}
+
+
+/// How waiting for the server went.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Waited {
+    Reachable { attempts: u32, elapsed: Duration },
+    /// `timeout` passed first; `error` is why the last attempt failed.
+    TimedOut { attempts: u32, error: String },
+}
+
+/// Calls `attempt` until it succeeds or `timeout` has passed, waiting as `schedule` says in
+/// between. `progress` hears about each failed attempt, with its number, the time so far and the
+/// error.
+pub async fn wait_for<F, Fut>(schedule: RetrySchedule, timeout: Duration, mut attempt: F, mut progress: impl FnMut(u32, Duration, &str)) -> Waited
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<(), String>>,
+{
+    let started = Instant::now();
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let remaining = timeout.checked_sub(started.elapsed()).unwrap_or_default();
+        let error = match tokio::time::timeout(remaining, attempt()).await {
+            Ok(Ok(())) => return Waited::Reachable { attempts, elapsed: started.elapsed() },
+            Ok(Err(error)) => error,
+            Err(_) => format!("no answer within {:?}", timeout),
+        };
+        progress(attempts, started.elapsed(), &error);
+
+        let delay = schedule.jittered(attempts);
+        if started.elapsed() + delay >= timeout {
+            return Waited::TimedOut { attempts, error };
+        }
+        tokio::time::delay_for(delay).await;
+    }
+}
@@ -0,0 +1,82 @@
+use std::io;
+use std::time::Instant;
+
+use crate::admin::{AuditEntry, QuotaUsage};
+use crate::audit::{AuditFilter, AuditLog};
+use crate::metrics::{self, DURATION_BUCKETS};
+use crate::note_store::NoteStore;
+use crate::quota::QuotaStore;
+use crate::route_guide::{Point, RouteNote};
+use crate::tenant::TenantId;
+
+
+/// A store with its calls timed, by backend and operation, apart from the RPC metrics: a slow
+/// GetNotesAt is a slow query when `storage_operation_duration_seconds` says so too, and a slow
+/// network when it doesn't. Failed calls are timed as well, and counted in
+/// `storage_errors_total`.
+///
+/// Wraps a note store, an audit log or a quota store, whichever `S` is.
+#[derive(Debug)]
+pub struct Instrumented<S> {
+    inner: S,
+    backend: &'static str,
+}
+
+impl<S> Instrumented<S> {
+    /// `backend` names the store in the metrics, like `sqlite` or `file`.
+    pub fn new(inner: S, backend: &'static str) -> Self {
+        Instrumented { inner, backend }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn time<T>(&self, operation: &'static str, call: impl FnOnce(&S) -> io::Result<T>) -> io::Result<T> {
+        let start = Instant::now();
+        let result = call(&self.inner);
+        let labels = [("backend", self.backend), ("operation", operation)];
+        let registry = metrics::registry();
+        registry
+            .histogram("storage_operation_duration_seconds", "Time a storage backend took to answer.", &labels, DURATION_BUCKETS)
+            .observe(start.elapsed().as_secs_f64());
+        if result.is_err() {
+            registry.counter("storage_errors_total", "Storage backend calls that failed.", &labels).inc();
+        }
+        result
+    }
+}
+
+impl<S: NoteStore> NoteStore for Instrumented<S> {
+    fn append(&self, tenant: &TenantId, note: &RouteNote) -> io::Result<()> {
+        self.time("append_note", |inner| inner.append(tenant, note))
+    }
+
+    fn notes_at(&self, tenant: &TenantId, location: &Point) -> io::Result<Vec<RouteNote>> {
+        self.time("notes_at", |inner| inner.notes_at(tenant, location))
+    }
+
+    fn compact(&self) -> io::Result<()> {
+        self.time("compact_notes", |inner| inner.compact())
+    }
+}
+
+impl<S: AuditLog> AuditLog for Instrumented<S> {
+    fn append(&self, entry: AuditEntry) -> io::Result<AuditEntry> {
+        self.time("append_audit_entry", |inner| inner.append(entry))
+    }
+
+    fn query(&self, filter: &AuditFilter) -> io::Result<Vec<AuditEntry>> {
+        self.time("query_audit_log", |inner| inner.query(filter))
+    }
+}
+
+impl<S: QuotaStore> QuotaStore for Instrumented<S> {
+    fn load(&self) -> io::Result<Vec<QuotaUsage>> {
+        self.time("load_quotas", |inner| inner.load())
+    }
+
+    fn save(&self, usages: &[QuotaUsage]) -> io::Result<()> {
+        self.time("save_quotas", |inner| inner.save(usages))
+    }
+}
@@ -0,0 +1,94 @@
+use std::future::Future;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use futures::stream::{self, BoxStream};
+use futures::{FutureExt, Stream, StreamExt, TryStreamExt};
+use hyper::Body;
+use serde_json::json;
+use tonic::Status;
+
+use crate::proto_json::ProtoJson;
+
+
+/// Helpers for streams of messages, like the `tonic::Streaming` of a call's responses or
+/// requests. Each takes the stream, so it can be handed on to another task.
+pub trait StreamingExt<T>: Stream<Item = Result<T, Status>> + Sized + Send + 'static
+    where T: Send + 'static
+{
+    /// The messages, or RESOURCE_EXHAUSTED once there are more than `limit`.
+    fn collect_limited(self, limit: usize) -> BoxFuture<'static, Result<Vec<T>, Status>> {
+        async move {
+            let mut messages = vec![];
+            let stream = self;
+            futures::pin_mut!(stream);
+            while let Some(message) = stream.next().await {
+                if messages.len() == limit {
+                    return Err(Status::resource_exhausted(format!("more than {} messages", limit)));
+                }
+                messages.push(message?);
+            }
+            Ok(messages)
+        }.boxed()
+    }
+
+    /// Runs `f` on each message, up to `limit` at a time, as they arrive. Ends with the first
+    /// error, from the stream or from `f`, leaving the rest of the stream unread and dropping
+    /// the calls of `f` still running.
+    fn try_for_each_concurrent<F, Fut>(self, limit: usize, f: F) -> BoxFuture<'static, Result<(), Status>>
+        where
+            F: FnMut(T) -> Fut + Send + 'static,
+            Fut: Future<Output = Result<(), Status>> + Send + 'static,
+    {
+        TryStreamExt::try_for_each_concurrent(self, limit.max(1), f).boxed()
+    }
+
+    /// Fails with DEADLINE_EXCEEDED, and ends, when a message takes longer than `timeout`,
+    /// counting from the previous one.
+    fn timeout_per_message(self, timeout: Duration) -> BoxStream<'static, Result<T, Status>> {
+        stream::unfold(Some(Box::pin(self)), move |state| async move {
+            let mut stream = state?;
+            match tokio::time::timeout(timeout, stream.next()).await {
+                Ok(Some(message)) => Some((message, Some(stream))),
+                Ok(None) => None,
+                Err(_) => Some((Err(Status::deadline_exceeded(format!("no message for {:?}", timeout))), None)),
+            }
+        }).boxed()
+    }
+
+    /// Rewrites the errors, e.g. to add what the stream was for to their message.
+    fn map_status<F>(self, f: F) -> BoxStream<'static, Result<T, Status>>
+        where F: Fn(Status) -> Status + Send + 'static
+    {
+        self.map(move |message| message.map_err(&f)).boxed()
+    }
+
+    /// The messages as newline-delimited JSON, one per line, for answering an HTTP request
+    /// while the messages arrive; they're read on a task of their own. An error ends the body
+    /// with a last line of `{"error": message}`, since the status line has been sent by then.
+    fn into_ndjson_body(self) -> Body where T: ProtoJson {
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            let stream = self;
+            futures::pin_mut!(stream);
+            while let Some(message) = stream.next().await {
+                let (line, last) = match message {
+                    Ok(message) => (message.to_json(), false),
+                    Err(status) => (json!({ "error": status.message() }), true),
+                };
+                // Fails once the client has gone away.
+                if sender.send_data(Bytes::from(format!("{}\n", line))).await.is_err() || last {
+                    return;
+                }
+            }
+        });
+        body
+    }
+}
+
+impl<S, T> StreamingExt<T> for S
+    where
+        S: Stream<Item = Result<T, Status>> + Send + 'static,
+        T: Send + 'static,
+{}
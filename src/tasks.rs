@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::{AbortHandle, Abortable};
+use tokio::sync::Notify;
+use tonic::Status;
+
+use crate::metrics;
+use crate::panics;
+use crate::runtime_metrics;
+
+
+#[derive(Debug, Default)]
+struct Inner {
+    next_id: AtomicU64,
+    tasks: Mutex<HashMap<u64, (&'static str, AbortHandle)>>,
+    closed: AtomicBool,
+    // Holds a permit if a task finished while nobody waited, so `shutdown` rechecks.
+    finished: Notify,
+}
+
+/// The background work handlers spawn, like the producers of ListFeatures streams, so shutdown
+/// can wait for it and abort what's left rather than drop it wherever it happens to be.
+///
+/// Live tasks are counted by name in the `background_tasks` gauge. A task that panics is logged
+/// and counted in `background_task_panics_total`; what it was producing just ends.
+#[derive(Debug, Clone, Default)]
+pub struct TaskTracker {
+    inner: Arc<Inner>,
+}
+
+impl TaskTracker {
+    /// Spawns `future` as a task called `task`, which also names it in the runtime metrics.
+    /// Fails with `unavailable` once shutdown has begun.
+    pub fn spawn<F>(&self, task: &'static str, future: F) -> Result<(), Status>
+        where F: Future<Output = ()> + Send + 'static,
+    {
+        if self.inner.closed.load(Ordering::SeqCst) {
+            return Err(Status::unavailable("the server is shutting down"));
+        }
+
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let (handle, registration) = AbortHandle::new_pair();
+        self.inner.tasks.lock().unwrap().insert(id, (task, handle));
+        let alive = metrics::registry().gauge("background_tasks", "Background tasks spawned by handlers that haven't finished.", &[("task", task)]);
+        alive.inc();
+
+        let inner = self.inner.clone();
+        tokio::spawn(runtime_metrics::instrument(task, async move {
+            let _ = Abortable::new(panics::catch_task(task, future), registration).await;
+            alive.dec();
+            inner.tasks.lock().unwrap().remove(&id);
+            inner.finished.notify();
+        }));
+        Ok(())
+    }
+
+    /// The number of tasks that haven't finished.
+    pub fn len(&self) -> usize {
+        self.inner.tasks.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Refuses new tasks, waits up to `grace` for the running ones to finish, then aborts the
+    /// rest at their next await. Returns how many were aborted.
+    pub async fn shutdown(&self, grace: Duration) -> usize {
+        self.inner.closed.store(true, Ordering::SeqCst);
+
+        let idle = async {
+            while !self.is_empty() {
+                self.inner.finished.notified().await;
+            }
+        };
+        if tokio::time::timeout(grace, idle).await.is_ok() {
+            return 0;
+        }
+
+        let tasks = self.inner.tasks.lock().unwrap();
+        for (task, handle) in tasks.values() {
+            tracing::warn!(task = *task, "aborted a background task still running at shutdown");
+            handle.abort();
+        }
+        tasks.len()
+    }
+}
@@ -0,0 +1,620 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use tonic::{Request, Status, metadata::MetadataValue};
+
+use crate::admin::{audit_entry::Action, AuditEntry};
+use crate::audit::{self, AuditFilter, AuditLog, MemoryAuditLog};
+use crate::chat::ChatSequences;
+use crate::chat_hub::{ChatHub, HubConfig};
+use crate::event_bus::{Event, EventBus, FeatureAdded, FeatureDeleted, NotePosted, RouteRecorded};
+use crate::feature_events::{ChangeKind, FeatureEvents, Subscription};
+use crate::ids::{IdGenerator, Ulids};
+use crate::import::{DuplicatePolicy, Imported};
+use crate::index::FeatureIndex;
+use crate::note_store::{MemoryNoteStore, NoteStore};
+#[cfg(feature = "tls")]
+use crate::peer_identity::PeerIdentityExt;
+use crate::request_context::RequestContext;
+use crate::route_guide::{Feature, Point, RouteNote, RouteSummary};
+use crate::wal::{FeatureWal, WalRecord};
+
+
+/// Metadata key the auth layer stores the resolved tenant under. Any value sent by the client is
+/// overwritten, so handlers can trust it.
+pub const TENANT_HEADER: &str = "x-tenant-id";
+
+/// Metadata key the auth layer stores who made the call under, like `TENANT_HEADER`: the
+/// tenant of the token as `token:<tenant>`, or the certificate name as `certificate:<name>`.
+pub const SUBJECT_HEADER: &str = "x-auth-subject";
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TenantId(String);
+
+impl TenantId {
+    /// Tenant ids end up in metadata, so they're restricted to ASCII letters, digits, '-' and '_'.
+    pub fn new(id: &str) -> Result<Self, Status> {
+        let valid = !id.is_empty()
+            && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+        if valid {
+            Ok(TenantId(id.to_string()))
+        } else {
+            Err(Status::invalid_argument(format!("invalid tenant id '{}'", id)))
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for TenantId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+
+/// Everything a single tenant can see. Its features and routes are held here, in memory; only
+/// the chat history and the audit log go to shared stores, keyed by tenant.
+#[derive(Debug)]
+pub struct TenantData {
+    pub id: TenantId,
+    features: RwLock<Arc<FeatureIndex>>,
+    routes: Mutex<Vec<RouteSummary>>,
+    notes: Arc<dyn NoteStore>,
+    audit: Arc<dyn AuditLog>,
+    chat_sequences: ChatSequences,
+    chat_hub: ChatHub,
+    feature_events: Arc<FeatureEvents>,
+    events: EventBus,
+    ids: Arc<dyn IdGenerator>,
+    wal: Option<Arc<FeatureWal>>,
+    replica: AtomicBool,
+}
+
+impl TenantData {
+    /// Features without an id get one from `ids`, as do the ones added later. The tenant's events
+    /// go to the subscribers of `events`, and to its own feed of feature changes.
+    pub fn new(id: TenantId, mut features: Vec<Feature>, notes: Arc<dyn NoteStore>, audit: Arc<dyn AuditLog>, chat: HubConfig, ids: Arc<dyn IdGenerator>, events: EventBus) -> Self {
+        for feature in features.iter_mut().filter(|feature| feature.id.is_empty()) {
+            feature.id = ids.next_id();
+        }
+        let feature_events = Arc::new(FeatureEvents::default());
+        TenantData {
+            id,
+            features: RwLock::new(Arc::new(FeatureIndex::new(features))),
+            routes: Mutex::default(),
+            notes,
+            audit,
+            chat_sequences: ChatSequences::default(),
+            chat_hub: ChatHub::new(chat),
+            events: events.with(feature_events.clone()),
+            feature_events,
+            ids,
+            wal: None,
+            replica: AtomicBool::new(false),
+        }
+    }
+
+    /// Logs every change to the features to `wal` before making it.
+    pub fn with_wal(mut self, wal: Arc<FeatureWal>) -> Self {
+        self.wal = Some(wal);
+        self
+    }
+
+    /// A snapshot of the features. Later writes don't affect it, so it can be held across awaits.
+    pub fn features(&self) -> Arc<FeatureIndex> {
+        self.features.read().unwrap().clone()
+    }
+
+    /// Adds the feature on behalf of `actor` and returns it with the id it was given; an id it
+    /// came with is replaced. Fails with ALREADY_EXISTS if its location is taken.
+    pub fn add_feature(&self, mut feature: Feature, actor: &str) -> Result<Feature, Status> {
+        self.writable()?;
+        let mut features = self.features.write().unwrap();
+        let taken = feature.location.as_ref().is_none_or(|location| features.contains(location));
+        if taken {
+            return Err(Status::already_exists("a feature already exists at this location"));
+        }
+        feature.id = self.ids.next_id();
+
+        // Recorded first and under the lock, so the log has every change in order.
+        self.record(audit::entry(&self.id, actor, Action::AddFeature, None, Some(&feature)))?;
+        self.log(WalRecord::Put(feature.clone()))?;
+
+        // Copies the index only if a snapshot of it is still in use.
+        Arc::make_mut(&mut *features).insert(feature.clone());
+        self.added(actor, feature.clone());
+        Ok(feature)
+    }
+
+    /// Adds the features on behalf of `actor`, in one write, and says what became of each:
+    /// features whose location is taken are handled by `policy`. They must have a location. New
+    /// features get a new id, and replaced ones keep theirs.
+    pub fn import_features(&self, batch: Vec<Feature>, policy: DuplicatePolicy, actor: &str) -> Result<Vec<Imported>, Status> {
+        self.writable()?;
+        let mut index = self.features.write().unwrap();
+        let features = Arc::make_mut(&mut *index);
+        let mut outcomes = Vec::with_capacity(batch.len());
+
+        for mut feature in batch {
+            let existing = feature.location.as_ref().and_then(|location| features.get(location)).cloned();
+            let imported = match (existing, policy) {
+                (None, _) => {
+                    feature.id = self.ids.next_id();
+                    self.record(audit::entry(&self.id, actor, Action::AddFeature, None, Some(&feature)))?;
+                    self.log(WalRecord::Put(feature.clone()))?;
+                    features.insert(feature.clone());
+                    self.added(actor, feature);
+                    Imported::Inserted
+                },
+                (Some(existing), DuplicatePolicy::Replace) => {
+                    feature.id = existing.id.clone();
+                    self.record(audit::entry(&self.id, actor, Action::UpdateFeature, Some(&existing), Some(&feature)))?;
+                    self.log(WalRecord::Put(feature.clone()))?;
+                    features.replace(feature.clone());
+                    self.deleted(actor, existing);
+                    self.added(actor, feature);
+                    Imported::Replaced
+                },
+                (Some(_), DuplicatePolicy::Skip) => Imported::Skipped,
+                (Some(_), DuplicatePolicy::Fail) => Imported::Duplicate,
+            };
+            outcomes.push(imported);
+        }
+        Ok(outcomes)
+    }
+
+    /// Deletes the feature at the location on behalf of `actor` and returns it. Fails with
+    /// NOT_FOUND if there is none.
+    pub fn delete_feature(&self, location: &Point, actor: &str) -> Result<Feature, Status> {
+        self.writable()?;
+        let mut features = self.features.write().unwrap();
+        let feature = features
+            .get(location)
+            .cloned()
+            .ok_or_else(|| Status::not_found("no feature at this location"))?;
+
+        self.record(audit::entry(&self.id, actor, Action::DeleteFeature, Some(&feature), None))?;
+        self.log(WalRecord::Delete(location.clone()))?;
+
+        Arc::make_mut(&mut *features).remove(location);
+        self.deleted(actor, feature.clone());
+        Ok(feature)
+    }
+
+    /// The tenant's audit log entries at or after `since` and before `until`, oldest first. Reads
+    /// the log, which may be a file, so call it off the runtime.
+    pub fn audit_entries(&self, since: Option<SystemTime>, until: Option<SystemTime>) -> Result<Vec<AuditEntry>, Status> {
+        let filter = AuditFilter { tenant: Some(self.id.clone()), since, until };
+        self.audit.query(&filter).map_err(|e| Status::internal(format!("failed to read the audit log: {}", e)))
+    }
+
+    fn record(&self, entry: AuditEntry) -> Result<(), Status> {
+        self.audit.append(entry)
+            .map(|_| ())
+            .map_err(|e| Status::internal(format!("failed to record the change: {}", e)))
+    }
+
+    fn log(&self, record: WalRecord) -> Result<(), Status> {
+        match &self.wal {
+            Some(wal) => wal.append(&record).map_err(|e| Status::internal(format!("failed to log the change: {}", e))),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the features into the database of the write-ahead log, if there is one, and
+    /// returns the number of changes that were logged. Writes wait for it, so call it off the
+    /// runtime.
+    pub fn checkpoint(&self) -> io::Result<usize> {
+        let wal = match &self.wal {
+            Some(wal) => wal,
+            None => return Ok(0),
+        };
+        // Held for reading until the log is emptied, so no change is logged in between.
+        let features = self.features.read().unwrap();
+        let changes = wal.len();
+        wal.checkpoint(&features.iter().cloned().collect::<Vec<_>>())?;
+        Ok(changes)
+    }
+
+    /// Makes the tenant a read replica, or a primary again. A replica's features change only
+    /// through `replace_features` and `apply_change`, and calls that would change them fail
+    /// with FAILED_PRECONDITION.
+    pub fn set_replica(&self, replica: bool) {
+        self.replica.store(replica, Ordering::SeqCst);
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.replica.load(Ordering::SeqCst)
+    }
+
+    fn writable(&self) -> Result<(), Status> {
+        if self.is_replica() {
+            return Err(Status::failed_precondition("this server is a read replica; make changes on the primary"));
+        }
+        Ok(())
+    }
+
+    /// A snapshot of the features, the id of the last change it includes and a subscription to
+    /// the changes after it, taken together so that no change is in both or neither.
+    pub fn replicate(&self) -> (Arc<FeatureIndex>, u64, Subscription) {
+        // Changes are published under the write lock.
+        let features = self.features.read().unwrap();
+        (features.clone(), self.feature_events.last_id(), self.feature_events.subscribe(None))
+    }
+
+    /// Replaces every feature with a copy of the primary's, publishing the differences. Not
+    /// audited or logged, since the primary did that.
+    pub fn replace_features(&self, copy: Vec<Feature>) {
+        let mut features = self.features.write().unwrap();
+        let copy = FeatureIndex::new(copy);
+        let removed: Vec<Feature> = features
+            .iter()
+            .filter(|feature| feature.location.as_ref().and_then(|location| copy.get(location)) != Some(*feature))
+            .cloned()
+            .collect();
+        let added: Vec<Feature> = copy
+            .iter()
+            .filter(|feature| feature.location.as_ref().and_then(|location| features.get(location)) != Some(*feature))
+            .cloned()
+            .collect();
+
+        *features = Arc::new(copy);
+        for feature in removed {
+            self.deleted("", feature);
+        }
+        for feature in added {
+            self.added("", feature);
+        }
+    }
+
+    /// Makes a change the primary made, and publishes it. An added feature takes the place of
+    /// the one at its location, if any.
+    pub fn apply_change(&self, kind: ChangeKind, feature: Feature) {
+        let location = match &feature.location {
+            Some(location) => location.clone(),
+            None => return,
+        };
+        let mut features = self.features.write().unwrap();
+        let index = Arc::make_mut(&mut *features);
+        let replaced = index.remove(&location);
+        if kind == ChangeKind::Added {
+            index.remove_by_id(&feature.id);
+            index.insert(feature.clone());
+        }
+
+        match (kind, replaced) {
+            (ChangeKind::Added, replaced) => {
+                if let Some(replaced) = replaced {
+                    self.deleted("", replaced);
+                }
+                self.added("", feature);
+            },
+            (ChangeKind::Removed, Some(removed)) => self.deleted("", removed),
+            (ChangeKind::Removed, None) => {},
+        }
+    }
+
+    /// Changes to the features, for watchers.
+    pub fn feature_events(&self) -> &FeatureEvents {
+        &self.feature_events
+    }
+
+    /// Where the tenant's events are published.
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    // Called under the write lock, like the feed of changes always was.
+    fn added(&self, actor: &str, feature: Feature) {
+        self.events.publish(Event::FeatureAdded(FeatureAdded { tenant: self.id.clone(), actor: actor.to_string(), feature }));
+    }
+
+    fn deleted(&self, actor: &str, feature: Feature) {
+        self.events.publish(Event::FeatureDeleted(FeatureDeleted { tenant: self.id.clone(), actor: actor.to_string(), feature }));
+    }
+
+    pub fn add_route(&self, summary: RouteSummary) {
+        self.routes.lock().unwrap().push(summary.clone());
+        self.events.publish(Event::RouteRecorded(RouteRecorded { tenant: self.id.clone(), summary }));
+    }
+
+    /// Announces a note that's been let through, before it's passed on.
+    pub fn note_posted(&self, note: &RouteNote) {
+        self.events.publish(Event::NotePosted(NotePosted { tenant: self.id.clone(), note: note.clone() }));
+    }
+
+    /// The routes recorded so far, oldest first.
+    pub fn routes(&self) -> Vec<RouteSummary> {
+        self.routes.lock().unwrap().clone()
+    }
+
+    pub fn route_count(&self) -> usize {
+        self.routes.lock().unwrap().len()
+    }
+
+    /// Stores the note and returns all notes at its location, including the new one.
+    pub fn add_note(&self, location: Point, note: RouteNote) -> Result<Vec<RouteNote>, Status> {
+        self.notes.append(&self.id, &note)
+            .map_err(|e| Status::internal(format!("failed to store note: {}", e)))?;
+        self.notes_at(&location)
+    }
+
+    /// Sequence numbers of the tenant's chat clients. Kept in memory only, so a restart forgets
+    /// them and clients start over.
+    pub fn chat_sequences(&self) -> &ChatSequences {
+        &self.chat_sequences
+    }
+
+    /// The tenant's RouteChat participants.
+    pub fn chat_hub(&self) -> &ChatHub {
+        &self.chat_hub
+    }
+
+    pub fn notes_at(&self, location: &Point) -> Result<Vec<RouteNote>, Status> {
+        self.notes.notes_at(&self.id, location)
+            .map_err(|e| Status::internal(format!("failed to read notes: {}", e)))
+    }
+}
+
+
+/// Vouches for bearer tokens the registry doesn't know, like ones an identity provider issued.
+/// Asked from the interceptor, so it can't wait: what it knows has to be at hand already.
+pub trait TokenAuthority: fmt::Debug + Send + Sync {
+    /// The tenant and subject the token stands for, `None` if the authority doesn't know the
+    /// token, or an error to answer the call with.
+    fn authenticate(&self, token: &str) -> Result<Option<(TenantId, String)>, Status>;
+}
+
+
+/// The tenant registry: maps bearer tokens to tenants and tenants to their data.
+#[derive(Debug)]
+pub struct Tenants {
+    tokens: RwLock<HashMap<String, TenantId>>,
+    tenants: RwLock<HashMap<TenantId, Arc<TenantData>>>,
+    notes: Arc<dyn NoteStore>,
+    audit: Arc<dyn AuditLog>,
+    chat: HubConfig,
+    ids: Arc<dyn IdGenerator>,
+    events: EventBus,
+    authority: Option<Arc<dyn TokenAuthority>>,
+}
+
+impl Default for Tenants {
+    fn default() -> Self {
+        Self::new(Arc::new(MemoryNoteStore::default()), Arc::new(MemoryAuditLog::default()), HubConfig::default())
+    }
+}
+
+impl Tenants {
+    /// A registry whose tenants keep their chat history in `notes`, record changes to their
+    /// features in `audit` and fan chat notes out as configured by `chat`.
+    pub fn new(notes: Arc<dyn NoteStore>, audit: Arc<dyn AuditLog>, chat: HubConfig) -> Self {
+        Tenants { tokens: RwLock::default(), tenants: RwLock::default(), notes, audit, chat, ids: Arc::new(Ulids::default()), events: EventBus::default(), authority: None }
+    }
+
+    /// Features get their ids from `ids` instead of being given ULIDs.
+    pub fn with_ids(mut self, ids: Arc<dyn IdGenerator>) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Every tenant's events go to the subscribers of `events`, as do events that aren't a
+    /// tenant's, like configuration reloads.
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Tokens that aren't any tenant's are checked with `authority`.
+    pub fn with_authority(mut self, authority: Arc<dyn TokenAuthority>) -> Self {
+        self.authority = Some(authority);
+        self
+    }
+
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Creates the tenant with the given features, or, if it already exists, keeps its data and
+    /// replaces its token. A token that's another tenant's fails with ALREADY_EXISTS.
+    pub fn provision(&self, id: TenantId, token: &str, features: Vec<Feature>) -> Result<Arc<TenantData>, Status> {
+        self.provision_with(id, token, features, None)
+    }
+
+    /// Like `provision`, for a tenant whose features are kept in a database file: a new
+    /// tenant logs its changes to `wal` (see `TenantData::with_wal`).
+    pub fn provision_durable(&self, id: TenantId, token: &str, features: Vec<Feature>, wal: Arc<FeatureWal>) -> Result<Arc<TenantData>, Status> {
+        self.provision_with(id, token, features, Some(wal))
+    }
+
+    fn provision_with(&self, id: TenantId, token: &str, features: Vec<Feature>, wal: Option<Arc<FeatureWal>>) -> Result<Arc<TenantData>, Status> {
+        let mut tokens = self.tokens.write().unwrap();
+        if tokens.get(token).is_some_and(|owner| *owner != id) {
+            return Err(Status::already_exists(format!("the token of tenant {} is another tenant's", id)));
+        }
+        tokens.retain(|_, tenant| *tenant != id);
+        tokens.insert(token.to_string(), id.clone());
+
+        let (notes, audit, chat, ids, events) = (self.notes.clone(), self.audit.clone(), self.chat, self.ids.clone(), self.events.clone());
+        let mut tenants = self.tenants.write().unwrap();
+        let data = tenants
+            .entry(id.clone())
+            .or_insert_with(move || {
+                let data = TenantData::new(id, features, notes, audit, chat, ids, events);
+                Arc::new(match wal {
+                    Some(wal) => data.with_wal(wal),
+                    None => data,
+                })
+            })
+            .clone();
+        Ok(data)
+    }
+
+    pub fn authenticate(&self, token: &str) -> Option<TenantId> {
+        self.tokens.read().unwrap().get(token).cloned()
+    }
+
+    /// Stops `token` authenticating, if it's still `id`'s. The tenant and its data stay.
+    pub fn revoke(&self, id: &TenantId, token: &str) -> bool {
+        let mut tokens = self.tokens.write().unwrap();
+        if tokens.get(token) != Some(id) {
+            return false;
+        }
+        tokens.remove(token);
+        true
+    }
+
+    pub fn get(&self, id: &TenantId) -> Option<Arc<TenantData>> {
+        self.tenants.read().unwrap().get(id).cloned()
+    }
+
+    /// All tenants, sorted by id.
+    pub fn list(&self) -> Vec<(TenantId, Arc<TenantData>)> {
+        let mut tenants: Vec<_> = self.tenants
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(id, data)| (id.clone(), data.clone()))
+            .collect();
+        tenants.sort_by(|a, b| a.0.cmp(&b.0));
+        tenants
+    }
+
+    /// The tenant named by the client certificate: its common name or one of its alternative
+    /// names is the id of an existing tenant.
+    #[cfg(feature = "tls")]
+    pub fn authenticate_peer<T>(&self, request: &Request<T>) -> Option<TenantId> {
+        let identity = request.peer_identity()?;
+        let tenants = self.tenants.read().unwrap();
+        let tenant = identity
+            .names()
+            .filter_map(|name| TenantId::new(name).ok())
+            .find(|id| tenants.contains_key(id));
+        tenant
+    }
+
+    /// Without TLS there are no client certificates.
+    #[cfg(not(feature = "tls"))]
+    pub fn authenticate_peer<T>(&self, _request: &Request<T>) -> Option<TenantId> {
+        None
+    }
+
+    /// Interceptor that resolves the tenant from the `authorization: Bearer <token>` header, by
+    /// the registry's tokens and then its authority, or with mutual TLS from the client
+    /// certificate, and records it under `TENANT_HEADER` and the subject under `SUBJECT_HEADER`.
+    pub fn interceptor(self: Arc<Self>) -> impl Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static {
+        move |mut request: Request<()>| {
+            let token = request
+                .metadata()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .map(str::to_string);
+            let from_token = match &token {
+                Some(token) => match self.authenticate(token) {
+                    Some(tenant) => Some((tenant.clone(), format!("token:{}", tenant))),
+                    None => match &self.authority {
+                        Some(authority) => authority.authenticate(token)?,
+                        None => None,
+                    },
+                },
+                None => None,
+            };
+            let (tenant, subject) = match from_token {
+                Some(found) => found,
+                None => {
+                    let tenant = self.authenticate_peer(&request)
+                        .ok_or_else(|| Status::unauthenticated("No valid auth token"))?;
+                    let subject = format!("certificate:{}", tenant);
+                    (tenant, subject)
+                },
+            };
+
+            let value = MetadataValue::from_str(tenant.as_str())
+                .map_err(|_| Status::internal("tenant id is not valid metadata"))?;
+            request.metadata_mut().insert(TENANT_HEADER, value);
+            let value = MetadataValue::from_str(&subject)
+                .map_err(|_| Status::internal("subject is not valid metadata"))?;
+            request.metadata_mut().insert(SUBJECT_HEADER, value);
+
+            Ok(request)
+        }
+    }
+
+    /// Who made the request, as recorded by the interceptor.
+    pub fn subject<T>(request: &Request<T>) -> Result<String, Status> {
+        request
+            .metadata()
+            .get(SUBJECT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+            .ok_or_else(|| Status::unauthenticated("request has no subject"))
+    }
+
+    /// The data of the tenant a call's context names.
+    pub fn for_context(&self, context: &RequestContext) -> Result<Arc<TenantData>, Status> {
+        self.get(&context.tenant)
+            .ok_or_else(|| Status::permission_denied(format!("unknown tenant '{}'", context.tenant)))
+    }
+
+    /// The data of the tenant the request was authenticated as.
+    pub fn scope<T>(&self, request: &Request<T>) -> Result<Arc<TenantData>, Status> {
+        let id = request
+            .metadata()
+            .get(TENANT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| Status::unauthenticated("request has no tenant"))?;
+
+        self.get(&TenantId::new(id)?)
+            .ok_or_else(|| Status::permission_denied(format!("unknown tenant '{}'", id)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    #[test]
+    fn tokens_stay_with_their_tenant() {
+        let tenants = Tenants::default();
+        let (a, b) = (TenantId::new("a").unwrap(), TenantId::new("b").unwrap());
+        tenants.provision(a.clone(), "secret", vec![]).unwrap();
+
+        let error = tenants.provision(b.clone(), "secret", vec![]).unwrap_err();
+        assert_eq!(error.code(), Code::AlreadyExists);
+        assert_eq!(tenants.authenticate("secret"), Some(a.clone()));
+        assert!(tenants.get(&b).is_none());
+
+        // A tenant can be provisioned again with its own token, or change it.
+        tenants.provision(a.clone(), "secret", vec![]).unwrap();
+        tenants.provision(a.clone(), "another", vec![]).unwrap();
+        assert_eq!(tenants.authenticate("secret"), None);
+        assert_eq!(tenants.authenticate("another"), Some(a.clone()));
+    }
+
+    #[test]
+    fn revoking_keeps_the_tenant() {
+        let tenants = Tenants::default();
+        let (a, b) = (TenantId::new("a").unwrap(), TenantId::new("b").unwrap());
+        tenants.provision(a.clone(), "secret", vec![]).unwrap();
+        tenants.provision(b.clone(), "other", vec![]).unwrap();
+
+        // Only the tenant's own token.
+        assert!(!tenants.revoke(&a, "other"));
+        assert!(tenants.revoke(&a, "secret"));
+        assert_eq!(tenants.authenticate("secret"), None);
+        assert_eq!(tenants.authenticate("other"), Some(b));
+        assert!(tenants.get(&a).is_some());
+    }
+}
@@ -0,0 +1,98 @@
+use std::time::{Duration, Instant};
+
+use futures::{Stream, StreamExt};
+use prost::Message;
+use tokio::sync::watch;
+
+use crate::route_guide::Point;
+
+
+/// How far a RecordRoute upload has got.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct UploadProgress {
+    pub points_sent: u64,
+    /// How many points the route has, if known up front.
+    pub total_points: Option<u64>,
+    /// Encoded size of everything sent, including the gRPC framing.
+    pub bytes_sent: u64,
+    pub keepalives_sent: u64,
+    pub elapsed: Duration,
+}
+
+impl UploadProgress {
+    pub fn fraction(&self) -> Option<f64> {
+        let total = self.total_points?;
+        Some(if total == 0 { 1.0 } else { self.points_sent as f64 / total as f64 })
+    }
+
+    /// The time left if the remaining points go at the rate of the ones sent so far.
+    pub fn eta(&self) -> Option<Duration> {
+        let total = self.total_points?;
+        if self.points_sent == 0 {
+            return None;
+        }
+        let per_point = self.elapsed.as_secs_f64() / self.points_sent as f64;
+        Some(Duration::from_secs_f64(per_point * total.saturating_sub(self.points_sent) as f64))
+    }
+
+    /// A one-line progress bar, like `[#####     ] 50/100 points, 1.2 KiB, 3s left`.
+    pub fn bar(&self, width: usize) -> String {
+        let filled = self.fraction().map_or(0, |fraction| (fraction * width as f64).round() as usize).min(width);
+        let points = match self.total_points {
+            Some(total) => format!("{}/{}", self.points_sent, total),
+            None => self.points_sent.to_string(),
+        };
+        let eta = self.eta().map_or(String::new(), |eta| format!(", {}s left", eta.as_secs()));
+
+        format!(
+            "[{}{}] {} points, {:.1} KiB{}",
+            "#".repeat(filled), " ".repeat(width - filled), points, self.bytes_sent as f64 / 1024.0, eta
+        )
+    }
+}
+
+
+/// Passes `points` through, counting what goes out. When `points` has nothing for `keepalive`,
+/// the last point is sent again, which the server treats as a keepalive rather than a new point,
+/// so it can tell a slow upload from a stalled one.
+pub fn track<S>(points: S, total_points: Option<u64>, keepalive: Duration) -> (impl Stream<Item = Point>, watch::Receiver<UploadProgress>)
+    where
+        S: Stream<Item = Point> + Send + 'static,
+{
+    let initial = UploadProgress { total_points, ..UploadProgress::default() };
+    let (sender, receiver) = watch::channel(initial);
+
+    let stream = async_stream::stream! {
+        let started = Instant::now();
+        let mut progress = initial;
+        let mut last: Option<Point> = None;
+        futures::pin_mut!(points);
+
+        loop {
+            let (point, is_keepalive) = match tokio::time::timeout(keepalive, points.next()).await {
+                Ok(Some(point)) => (point, false),
+                Ok(None) => break,
+                Err(_) => match &last {
+                    Some(point) => (point.clone(), true),
+                    None => continue,
+                },
+            };
+
+            if is_keepalive {
+                progress.keepalives_sent += 1;
+            } else {
+                progress.points_sent += 1;
+            }
+            // Every message has a 5 byte header: the compression flag and the length.
+            progress.bytes_sent += 5 + point.encoded_len() as u64;
+            progress.elapsed = started.elapsed();
+            // Fails only once the receiver is gone, and then nobody cares.
+            let _ = sender.broadcast(progress);
+
+            last = Some(point.clone());
+            yield point;
+        }
+    };
+
+    (stream, receiver)
+}
@@ -0,0 +1,318 @@
+use std::ops::RangeInclusive;
+
+use prost::Message;
+use prost_types::FieldMask;
+use tonic::{Code, Request, Response, Status, Streaming};
+
+use crate::admin::Quota;
+use crate::attributes;
+use crate::export;
+use crate::field_mask::FeatureMask;
+use crate::geo;
+use crate::google_rpc::{bad_request::FieldViolation, BadRequest};
+use crate::route_guide::route_guide_server::RouteGuide;
+use crate::route_guide::{Clustering, ExportRequest, Feature, ImportSummary, Point, PointWithTimestamp, Rectangle, ReplicateRequest, Room, RoomMembers, RouteNote, RouteSummary, TimeRange};
+
+
+pub const LATITUDE: RangeInclusive<i32> = -900_000_000..=900_000_000;
+pub const LONGITUDE: RangeInclusive<i32> = -1_800_000_000..=1_800_000_000;
+
+/// Finer grids than this have cells smaller than a unit of E7 longitude.
+pub const MAX_ZOOM: u32 = 31;
+
+/// The longest RouteNote message, in bytes.
+pub const MAX_NOTE_LENGTH: usize = 1000;
+
+/// The longest RouteChat room name, in bytes.
+pub const MAX_ROOM_LENGTH: usize = 64;
+
+
+/// The rules of a message.
+pub trait Validate {
+    /// Records every rule the message breaks in `check`. What has an obvious fix, like the
+    /// corners of a Rectangle in the wrong order, is fixed instead.
+    fn validate(&mut self, check: &mut Check);
+}
+
+/// Collects the broken rules of a message, with the path of each field.
+#[derive(Debug, Default)]
+pub struct Check {
+    path: Vec<&'static str>,
+    violations: Vec<FieldViolation>,
+}
+
+impl Check {
+    fn path(&self, field: &str) -> String {
+        let mut path = self.path.join(".");
+        if !path.is_empty() {
+            path.push('.');
+        }
+        path.push_str(field);
+        path
+    }
+
+    pub fn fail(&mut self, field: &str, description: impl Into<String>) {
+        let field = self.path(field);
+        self.violations.push(FieldViolation { field, description: description.into() });
+    }
+
+    pub fn range<T: PartialOrd + std::fmt::Display>(&mut self, field: &str, value: T, range: RangeInclusive<T>) {
+        if !range.contains(&value) {
+            self.fail(field, format!("{} is outside {} to {}", value, range.start(), range.end()));
+        }
+    }
+
+    pub fn not_blank(&mut self, field: &str, value: &str) {
+        if value.trim().is_empty() {
+            self.fail(field, "must not be empty");
+        }
+    }
+
+    pub fn max_length(&mut self, field: &str, value: &str, max: usize) {
+        if value.len() > max {
+            self.fail(field, format!("is {} bytes, more than {}", value.len(), max));
+        }
+    }
+
+    /// A Feature read mask may only name Feature fields.
+    pub fn feature_mask(&mut self, field: &str, mask: Option<&FieldMask>) {
+        if let Err(e) = FeatureMask::parse(mask) {
+            self.fail(field, e);
+        }
+    }
+
+    /// Checks the nested message, which must be set.
+    pub fn required<T: Validate>(&mut self, field: &'static str, value: &mut Option<T>) {
+        match value {
+            Some(value) => self.nested(field, value),
+            None => self.fail(field, "is required"),
+        }
+    }
+
+    /// Checks the nested message if it's set.
+    pub fn optional<T: Validate>(&mut self, field: &'static str, value: &mut Option<T>) {
+        if let Some(value) = value {
+            self.nested(field, value);
+        }
+    }
+
+    fn nested<T: Validate>(&mut self, field: &'static str, value: &mut T) {
+        self.path.push(field);
+        value.validate(self);
+        self.path.pop();
+    }
+
+    /// INVALID_ARGUMENT with a `google.rpc.BadRequest` in the details, if any rule was broken.
+    pub fn into_result(self) -> Result<(), Status> {
+        if self.violations.is_empty() {
+            return Ok(());
+        }
+
+        let message = self.violations
+            .iter()
+            .map(|violation| format!("{} {}", violation.field, violation.description))
+            .collect::<Vec<_>>()
+            .join("; ");
+        let details = BadRequest { field_violations: self.violations };
+        let mut encoded = Vec::with_capacity(details.encoded_len());
+        details.encode(&mut encoded).map_err(|_| Status::internal("failed to encode the violations"))?;
+
+        Err(Status::with_details(Code::InvalidArgument, message, encoded.into()))
+    }
+}
+
+/// Applies the rules of the message.
+pub fn validate<T: Validate>(message: &mut T) -> Result<(), Status> {
+    let mut check = Check::default();
+    message.validate(&mut check);
+    check.into_result()
+}
+
+
+impl Validate for Point {
+    fn validate(&mut self, check: &mut Check) {
+        check.range("latitude", self.latitude, LATITUDE);
+        check.range("longitude", self.longitude, LONGITUDE);
+        check.feature_mask("read_mask", self.read_mask.as_ref());
+    }
+}
+
+impl Validate for Rectangle {
+    fn validate(&mut self, check: &mut Check) {
+        check.required("lo", &mut self.lo);
+        check.required("hi", &mut self.hi);
+        check.optional("cluster", &mut self.cluster);
+        check.feature_mask("read_mask", self.read_mask.as_ref());
+        if self.cluster.is_some() && !self.resume_after.is_empty() {
+            check.fail("resume_after", "can't resume a clustered listing");
+        }
+
+        // The latitudes can come in any order; lo becomes the south-west corner. Longitudes keep
+        // theirs, since lo east of hi means the rectangle crosses the antimeridian.
+        if let Some(bounds) = geo::Bounds::of(self) {
+            let (lo, hi) = bounds.corners();
+            self.lo = Some(lo);
+            self.hi = Some(hi);
+        }
+    }
+}
+
+impl Validate for Clustering {
+    fn validate(&mut self, check: &mut Check) {
+        check.range("zoom", self.zoom, 0..=MAX_ZOOM);
+    }
+}
+
+impl Validate for Feature {
+    fn validate(&mut self, check: &mut Check) {
+        check.not_blank("name", &self.name);
+        check.required("location", &mut self.location);
+        for locale in self.names_by_locale.keys() {
+            if locale.is_empty() || !locale.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                check.fail("names_by_locale", format!("'{}' isn't a locale like fr or pt-BR", locale));
+            }
+        }
+        for (key, attribute) in &self.attributes {
+            if key.is_empty() {
+                check.fail("attributes", "keys must not be empty");
+            }
+            if let Err(e) = attributes::registry().check(attribute) {
+                check.fail("attributes", format!("'{}': {}", key, e));
+            }
+        }
+    }
+}
+
+impl Validate for RouteNote {
+    fn validate(&mut self, check: &mut Check) {
+        if self.room.is_empty() {
+            check.required("location", &mut self.location);
+        } else {
+            check.optional("location", &mut self.location);
+            check.max_length("room", &self.room, MAX_ROOM_LENGTH);
+            if !self.to_user.is_empty() {
+                check.fail("to_user", "can't be set for a room note");
+            }
+        }
+        check.max_length("message", &self.message, MAX_NOTE_LENGTH);
+    }
+}
+
+impl Validate for Room {
+    fn validate(&mut self, check: &mut Check) {
+        check.not_blank("name", &self.name);
+        check.max_length("name", &self.name, MAX_ROOM_LENGTH);
+    }
+}
+
+impl Validate for ExportRequest {
+    fn validate(&mut self, check: &mut Check) {
+        check.range("batch_size", self.batch_size as usize, 0..=export::MAX_BATCH_SIZE);
+    }
+}
+
+impl Validate for ReplicateRequest {
+    fn validate(&mut self, check: &mut Check) {
+        if self.after != 0 && self.epoch.is_empty() {
+            check.fail("epoch", "is needed to resume after a change");
+        }
+    }
+}
+
+impl Validate for PointWithTimestamp {
+    fn validate(&mut self, check: &mut Check) {
+        check.required("point", &mut self.point);
+    }
+}
+
+impl Validate for TimeRange {
+    fn validate(&mut self, check: &mut Check) {
+        if self.since_ms != 0 && self.until_ms != 0 && self.until_ms < self.since_ms {
+            check.fail("until_ms", format!("{} is before since_ms {}", self.until_ms, self.since_ms));
+        }
+    }
+}
+
+
+fn validated<T: Validate>(mut request: Request<T>) -> Result<Request<T>, Status> {
+    validate(request.get_mut())?;
+    Ok(request)
+}
+
+/// Applies the rules of each request message before the RouteGuide handlers see it.
+///
+/// The messages of client streams arrive after the handler is called, so RecordRoute, RouteChat
+/// and ImportFeatures handlers have to call `validate` on each one themselves.
+#[derive(Debug)]
+pub struct Validated<S>(pub S);
+
+#[tonic::async_trait]
+impl<S: RouteGuide> RouteGuide for Validated<S> {
+    type ListFeaturesStream = S::ListFeaturesStream;
+    type RouteChatStream = S::RouteChatStream;
+    type GetNotesAtStream = S::GetNotesAtStream;
+    type ListChangesStream = S::ListChangesStream;
+    type ExportFeaturesStream = S::ExportFeaturesStream;
+    type ReplicateStream = S::ReplicateStream;
+    type WatchConfigStream = S::WatchConfigStream;
+
+    async fn get_feature(&self, request: Request<Point>) -> Result<Response<Feature>, Status> {
+        self.0.get_feature(validated(request)?).await
+    }
+
+    async fn list_features(&self, request: Request<Rectangle>) -> Result<Response<Self::ListFeaturesStream>, Status> {
+        self.0.list_features(validated(request)?).await
+    }
+
+    async fn record_route(&self, request: Request<Streaming<Point>>) -> Result<Response<RouteSummary>, Status> {
+        self.0.record_route(request).await
+    }
+
+    async fn route_chat(&self, request: Request<Streaming<RouteNote>>) -> Result<Response<Self::RouteChatStream>, Status> {
+        self.0.route_chat(request).await
+    }
+
+    async fn add_feature(&self, request: Request<Feature>) -> Result<Response<Feature>, Status> {
+        self.0.add_feature(validated(request)?).await
+    }
+
+    async fn delete_feature(&self, request: Request<Point>) -> Result<Response<Feature>, Status> {
+        self.0.delete_feature(validated(request)?).await
+    }
+
+    async fn get_notes_at(&self, request: Request<Point>) -> Result<Response<Self::GetNotesAtStream>, Status> {
+        self.0.get_notes_at(validated(request)?).await
+    }
+
+    async fn import_features(&self, request: Request<Streaming<Feature>>) -> Result<Response<ImportSummary>, Status> {
+        self.0.import_features(request).await
+    }
+
+    async fn get_feature_as_of(&self, request: Request<PointWithTimestamp>) -> Result<Response<Feature>, Status> {
+        self.0.get_feature_as_of(validated(request)?).await
+    }
+
+    async fn list_changes(&self, request: Request<TimeRange>) -> Result<Response<Self::ListChangesStream>, Status> {
+        self.0.list_changes(validated(request)?).await
+    }
+
+    async fn export_features(&self, request: Request<ExportRequest>) -> Result<Response<Self::ExportFeaturesStream>, Status> {
+        self.0.export_features(validated(request)?).await
+    }
+
+    async fn replicate(&self, request: Request<ReplicateRequest>) -> Result<Response<Self::ReplicateStream>, Status> {
+        self.0.replicate(validated(request)?).await
+    }
+
+    async fn watch_config(&self, request: Request<()>) -> Result<Response<Self::WatchConfigStream>, Status> {
+        self.0.watch_config(request).await
+    }
+
+    async fn list_room_members(&self, request: Request<Room>) -> Result<Response<RoomMembers>, Status> {
+        self.0.list_room_members(validated(request)?).await
+    }
+
+    async fn get_quota(&self, request: Request<()>) -> Result<Response<Quota>, Status> {
+        self.0.get_quota(request).await
+    }
+}
@@ -0,0 +1,200 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use prost::Message;
+
+use crate::binary_db;
+use crate::proto_json::ProtoJson;
+use crate::route_guide::{Feature, Point};
+
+
+const PUT: u8 = 1;
+const DELETE: u8 = 2;
+
+/// A change to the features, as logged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WalRecord {
+    /// The feature was added, or replaced the one at its location.
+    Put(Feature),
+    /// The feature at the location was deleted.
+    Delete(Point),
+}
+
+impl WalRecord {
+    fn encode(&self) -> Vec<u8> {
+        let (kind, message) = match self {
+            WalRecord::Put(feature) => (PUT, encoded(feature)),
+            WalRecord::Delete(location) => (DELETE, encoded(location)),
+        };
+        let mut body = Vec::with_capacity(1 + message.len());
+        body.push(kind);
+        body.extend_from_slice(&message);
+        body
+    }
+
+    fn decode(body: &[u8]) -> io::Result<Self> {
+        let invalid = |e: prost::DecodeError| io::Error::new(io::ErrorKind::InvalidData, e);
+        match body.split_first() {
+            Some((&PUT, message)) => Ok(WalRecord::Put(Feature::decode(message).map_err(invalid)?)),
+            Some((&DELETE, message)) => Ok(WalRecord::Delete(Point::decode(message).map_err(invalid)?)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "unknown write-ahead log record")),
+        }
+    }
+}
+
+fn encoded<M: Message>(message: &M) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(message.encoded_len());
+    message.encode(&mut bytes).unwrap();
+    bytes
+}
+
+/// FNV-1a, so a record torn by a crash is told apart from one that was written whole.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5u32, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193))
+}
+
+
+/// A write-ahead log of the changes to the features of a database file, next to it as
+/// `<database>.wal`. A change is synced to the log before it's made, and the database is
+/// rewritten from the features now and then, which empties the log; on startup the log is
+/// replayed over the database, so no acknowledged change is lost if the server is killed.
+///
+/// Records are the length of the body (u32, little endian), its checksum (u32, little endian)
+/// and the body: 1 and a protobuf `Feature`, or 2 and the protobuf `Point` that was deleted.
+#[derive(Debug)]
+pub struct FeatureWal {
+    database: PathBuf,
+    path: PathBuf,
+    // The log, opened for appending, and the number of records in it.
+    state: Mutex<(File, usize)>,
+}
+
+impl FeatureWal {
+    /// Opens the log of the database at `database`, creating it if needed. Returns it with the
+    /// records to replay.
+    pub fn open(database: impl Into<PathBuf>) -> io::Result<(Self, Vec<WalRecord>)> {
+        let database = database.into();
+        let mut path = database.clone().into_os_string();
+        path.push(".wal");
+        let path = PathBuf::from(path);
+
+        let records = read_log(&path)?;
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        // Cuts off a torn record, so the next append doesn't follow it.
+        file.set_len(records.iter().map(|(_, length)| length).sum::<usize>() as u64)?;
+        let records: Vec<WalRecord> = records.into_iter().map(|(record, _)| record).collect();
+
+        let count = records.len();
+        Ok((FeatureWal { database, path, state: Mutex::new((file, count)) }, records))
+    }
+
+    /// The changes logged since the last checkpoint.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Logs the change and syncs it. Until this returns the change mustn't be made.
+    pub fn append(&self, record: &WalRecord) -> io::Result<()> {
+        let body = record.encode();
+        let mut bytes = Vec::with_capacity(8 + body.len());
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&checksum(&body).to_le_bytes());
+        bytes.extend_from_slice(&body);
+
+        let mut state = self.state.lock().unwrap();
+        let (file, count) = &mut *state;
+        file.write_all(&bytes)?;
+        file.sync_data()?;
+        *count += 1;
+        Ok(())
+    }
+
+    /// Rewrites the database with `features`, which must include every logged change, and
+    /// empties the log. The database is written to a temporary file that replaces it, in its
+    /// own format, so a crash leaves either the old database and the log or the new one.
+    pub fn checkpoint(&self, features: &[Feature]) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let mut temporary = self.database.clone().into_os_string();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+
+        if binary_db::is_binary(&self.database) {
+            binary_db::write(&temporary, features)?;
+        } else {
+            let records: Vec<_> = features.iter().map(ProtoJson::to_json).collect();
+            let json = serde_json::to_vec_pretty(&records).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let mut file = File::create(&temporary)?;
+            file.write_all(&json)?;
+            file.sync_all()?;
+        }
+        fs::rename(&temporary, &self.database)?;
+        if let Some(directory) = self.database.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+            // So the rename itself survives a crash.
+            File::open(directory)?.sync_all()?;
+        }
+
+        let (file, count) = &mut *state;
+        file.set_len(0)?;
+        file.sync_all()?;
+        *count = 0;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Reads the records with the length of each. Reading stops at a record that's cut short or
+/// whose checksum doesn't match, which is where a crash during an append leaves the log.
+fn read_log(path: &Path) -> io::Result<Vec<(WalRecord, usize)>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let mut reader = BufReader::new(file);
+    let mut records = vec![];
+
+    loop {
+        let mut header = [0u8; 8];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {},
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let expected = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+
+        // Read up to the length rather than allocated for it, since a torn length can be anything.
+        let mut body = Vec::new();
+        (&mut reader).take(length as u64).read_to_end(&mut body)?;
+        if body.len() < length || checksum(&body) != expected {
+            break;
+        }
+
+        records.push((WalRecord::decode(&body)?, 8 + length));
+    }
+
+    Ok(records)
+}
+
+/// The features of the database with the logged changes made, in order.
+pub fn replay(mut features: Vec<Feature>, records: Vec<WalRecord>) -> Vec<Feature> {
+    for record in records {
+        match record {
+            WalRecord::Put(feature) => match features.iter_mut().find(|existing| existing.location == feature.location) {
+                Some(existing) => *existing = feature,
+                None => features.push(feature),
+            },
+            WalRecord::Delete(location) => features.retain(|feature| feature.location.as_ref() != Some(&location)),
+        }
+    }
+    features
+}
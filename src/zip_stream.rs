@@ -0,0 +1,172 @@
+use std::io::{self, Write};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use flate2::write::DeflateEncoder;
+use flate2::Crc;
+
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR: u32 = 0x0807_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+
+/// Zip 2.0, for deflate and data descriptors.
+const VERSION: u16 = 20;
+/// The sizes and CRC follow the data, and the name is UTF-8.
+const FLAGS: u16 = 0x0008 | 0x0800;
+const DEFLATE: u16 = 8;
+/// 1980-01-01 00:00, the earliest a zip can say, so the same entries give the same archive.
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+
+#[derive(Debug)]
+struct Entry {
+    name: String,
+    crc: u32,
+    compressed: u64,
+    size: u64,
+    offset: u64,
+}
+
+struct Open {
+    entry: Entry,
+    crc: Crc,
+    encoder: DeflateEncoder<Vec<u8>>,
+}
+
+/// Writes a zip archive a piece at a time, for streaming it out: each call returns the bytes
+/// that come next, and nothing but the entries' names and sizes is kept. Entries are deflated,
+/// with their sizes and CRC in a data descriptor after the data, since they aren't known when
+/// the header goes out.
+///
+/// There's no zip64, so archives stop at 4 GiB and 65535 entries; going past fails.
+#[derive(Default)]
+pub struct ZipWriter {
+    offset: u64,
+    entries: Vec<Entry>,
+    open: Option<Open>,
+}
+
+impl ZipWriter {
+    pub fn new() -> Self {
+        ZipWriter::default()
+    }
+
+    /// Starts an entry, ending the one before.
+    pub fn start(&mut self, name: &str) -> io::Result<Bytes> {
+        let mut out = BytesMut::new();
+        out.extend_from_slice(&self.end_entry()?);
+
+        let entry = Entry { name: name.to_string(), crc: 0, compressed: 0, size: 0, offset: self.offset };
+        out.put_u32_le(LOCAL_HEADER);
+        out.put_u16_le(VERSION);
+        out.put_u16_le(FLAGS);
+        out.put_u16_le(DEFLATE);
+        out.put_u16_le(DOS_TIME);
+        out.put_u16_le(DOS_DATE);
+        out.put_u32_le(0);
+        out.put_u32_le(0);
+        out.put_u32_le(0);
+        out.put_u16_le(name_length(name)?);
+        out.put_u16_le(0);
+        out.put_slice(name.as_bytes());
+        self.offset += (30 + name.len()) as u64;
+
+        self.open = Some(Open { entry, crc: Crc::new(), encoder: DeflateEncoder::new(Vec::new(), flate2::Compression::default()) });
+        Ok(out.freeze())
+    }
+
+    /// Adds `data` to the entry, returning what's been compressed so far; often nothing, until
+    /// the compressor has a block.
+    pub fn write(&mut self, data: &[u8]) -> io::Result<Bytes> {
+        let open = self.open.as_mut().ok_or_else(|| io::Error::other("no zip entry started"))?;
+        open.crc.update(data);
+        open.entry.size += data.len() as u64;
+        open.encoder.write_all(data)?;
+
+        let compressed = std::mem::take(open.encoder.get_mut());
+        open.entry.compressed += compressed.len() as u64;
+        self.offset += compressed.len() as u64;
+        Ok(Bytes::from(compressed))
+    }
+
+    /// The rest of the last entry, and the central directory.
+    pub fn finish(mut self) -> io::Result<Bytes> {
+        let mut out = BytesMut::new();
+        out.extend_from_slice(&self.end_entry()?);
+
+        if self.entries.len() > u16::MAX as usize {
+            return Err(io::Error::other("too many zip entries without zip64"));
+        }
+        let before = out.len();
+        for entry in &self.entries {
+            out.put_u32_le(CENTRAL_HEADER);
+            out.put_u16_le(VERSION);
+            out.put_u16_le(VERSION);
+            out.put_u16_le(FLAGS);
+            out.put_u16_le(DEFLATE);
+            out.put_u16_le(DOS_TIME);
+            out.put_u16_le(DOS_DATE);
+            out.put_u32_le(entry.crc);
+            out.put_u32_le(entry.compressed as u32);
+            out.put_u32_le(entry.size as u32);
+            out.put_u16_le(entry.name.len() as u16);
+            // Extra field, comment, disk, internal and external attributes.
+            out.put_u16_le(0);
+            out.put_u16_le(0);
+            out.put_u16_le(0);
+            out.put_u16_le(0);
+            out.put_u32_le(0);
+            out.put_u32_le(entry.offset as u32);
+            out.put_slice(entry.name.as_bytes());
+        }
+        let size = (out.len() - before) as u64;
+        check_size(self.offset + size)?;
+
+        out.put_u32_le(END_OF_CENTRAL_DIRECTORY);
+        out.put_u16_le(0);
+        out.put_u16_le(0);
+        out.put_u16_le(self.entries.len() as u16);
+        out.put_u16_le(self.entries.len() as u16);
+        out.put_u32_le(size as u32);
+        out.put_u32_le(self.offset as u32);
+        out.put_u16_le(0);
+        Ok(out.freeze())
+    }
+
+    /// The end of the compressed data and the data descriptor, if an entry is open.
+    fn end_entry(&mut self) -> io::Result<Bytes> {
+        let Open { mut entry, crc, encoder } = match self.open.take() {
+            Some(open) => open,
+            None => return Ok(Bytes::new()),
+        };
+        let mut out = BytesMut::from(&encoder.finish()?[..]);
+        entry.compressed += out.len() as u64;
+        entry.crc = crc.sum();
+        check_size(self.offset + out.len() as u64)?;
+        check_size(entry.size)?;
+
+        out.put_u32_le(DATA_DESCRIPTOR);
+        out.put_u32_le(entry.crc);
+        out.put_u32_le(entry.compressed as u32);
+        out.put_u32_le(entry.size as u32);
+        self.offset += out.len() as u64;
+        self.entries.push(entry);
+        Ok(out.freeze())
+    }
+}
+
+fn name_length(name: &str) -> io::Result<u16> {
+    if name.len() > u16::MAX as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "zip entry name too long"));
+    }
+    Ok(name.len() as u16)
+}
+
+fn check_size(size: u64) -> io::Result<()> {
+    if size > u32::MAX as u64 {
+        return Err(io::Error::other("zip archive over 4 GiB without zip64"));
+    }
+    Ok(())
+}
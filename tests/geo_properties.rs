@@ -0,0 +1,167 @@
+//! Randomized checks of the geo and index invariants, with a good share of edge cases: the poles,
+//! the antimeridian, zero-width and zero-height rectangles, rectangles across the antimeridian
+//! and ones missing a corner.
+
+use std::collections::HashSet;
+use std::f64::consts::PI;
+
+use proptest::prelude::*;
+use proptest::sample::Index;
+
+use rust_server::geo::{self, Bounds, CORD_FACTOR, EARTH_RADIUS, MAX_LONGITUDE};
+use rust_server::index::FeatureIndex;
+use rust_server::route_guide::{Feature, Point, Rectangle};
+
+
+const MAX_LATITUDE: i32 = 900_000_000;
+
+/// Coordinates where bugs tend to be.
+const EDGE_LATITUDES: &[i32] = &[-MAX_LATITUDE, -1, 0, 1, MAX_LATITUDE];
+const EDGE_LONGITUDES: &[i32] = &[-MAX_LONGITUDE, -MAX_LONGITUDE + 1, -1, 0, 1, MAX_LONGITUDE - 1, MAX_LONGITUDE];
+
+fn latitude() -> impl Strategy<Value = i32> {
+    prop_oneof![4 => -MAX_LATITUDE..=MAX_LATITUDE, 1 => prop::sample::select(EDGE_LATITUDES)]
+}
+
+fn longitude() -> impl Strategy<Value = i32> {
+    prop_oneof![4 => -MAX_LONGITUDE..=MAX_LONGITUDE, 1 => prop::sample::select(EDGE_LONGITUDES)]
+}
+
+fn point() -> impl Strategy<Value = Point> {
+    (latitude(), longitude()).prop_map(|(latitude, longitude)| Point { latitude, longitude, read_mask: None })
+}
+
+/// Near `center`, so rectangles around it catch some of the points.
+fn point_near(center: Point, spread: i32) -> impl Strategy<Value = Point> {
+    (-spread..=spread, -spread..=spread).prop_map(move |(north, east)| {
+        let latitude = (center.latitude as i64 + north as i64).max(-MAX_LATITUDE as i64).min(MAX_LATITUDE as i64);
+        // Wrapped, so points near the antimeridian land on both sides of it.
+        let mut longitude = center.longitude as i64 + east as i64;
+        if longitude > MAX_LONGITUDE as i64 {
+            longitude -= 2 * MAX_LONGITUDE as i64;
+        } else if longitude < -MAX_LONGITUDE as i64 {
+            longitude += 2 * MAX_LONGITUDE as i64;
+        }
+        Point { latitude: latitude as i32, longitude: longitude as i32, read_mask: None }
+    })
+}
+
+/// Mostly ordinary rectangles, and some that are a point, a line or missing a corner.
+fn rectangle(center: Point) -> impl Strategy<Value = Rectangle> {
+    (0..50_000_000).prop_flat_map(move |spread| {
+        (point_near(center.clone(), spread), point_near(center.clone(), spread), 0..10).prop_map(|(lo, mut hi, shape)| {
+            match shape {
+                0 => hi = lo.clone(),
+                1 => hi.latitude = lo.latitude,
+                2 => hi.longitude = lo.longitude,
+                3 => return Rectangle { lo: Some(lo), hi: None, ..Rectangle::default() },
+                _ => {},
+            }
+            Rectangle { lo: Some(lo), hi: Some(hi), ..Rectangle::default() }
+        })
+    })
+}
+
+
+/// A few hundred features clustered around a point, a rectangle there and three loose points.
+/// Some features share a location, so the first-one-wins rule is exercised too.
+#[derive(Debug, Clone)]
+struct Case {
+    features: Vec<Feature>,
+    rect: Rectangle,
+    points: [Point; 3],
+}
+
+fn case() -> impl Strategy<Value = Case> {
+    point().prop_flat_map(|center| {
+        let features = prop::collection::vec(point_near(center.clone(), 60_000_000), 0..300);
+        let duplicates = prop::collection::vec(any::<Index>(), 0..15);
+        let points = (point_near(center.clone(), 10_000_000), point(), point_near(center.clone(), 1_000));
+        (features, duplicates, rectangle(center), points).prop_map(|(locations, duplicates, rect, (a, b, c))| {
+            let mut features: Vec<Feature> = locations
+                .into_iter()
+                .enumerate()
+                .map(|(i, location)| Feature { id: format!("f{}", i), name: format!("feature {}", i), location: Some(location), ..Feature::default() })
+                .collect();
+            if !features.is_empty() {
+                for (i, duplicate) in duplicates.iter().enumerate() {
+                    let location = features[duplicate.index(features.len())].location.clone();
+                    features.push(Feature { id: format!("dup{}", i), location, ..Feature::default() });
+                }
+            }
+            Case { features, rect, points: [a, b, c] }
+        })
+    })
+}
+
+impl Case {
+    fn locations(&self) -> HashSet<&Point> {
+        self.features.iter().filter_map(|feature| feature.location.as_ref()).collect()
+    }
+}
+
+
+proptest! {
+    #[test]
+    fn in_rectangle_finds_what_brute_force_finds(case in case()) {
+        let index = FeatureIndex::new(case.features.clone());
+        let indexed: Vec<&str> = index.in_rectangle(&case.rect).map(|feature| feature.id.as_str()).collect();
+        let brute: Vec<&str> = case.features
+            .iter()
+            .filter(|feature| feature.location.as_ref().is_some_and(|location| geo::in_range(location, &case.rect)))
+            .map(|feature| feature.id.as_str())
+            .collect();
+        prop_assert_eq!(indexed, brute);
+    }
+
+    #[test]
+    fn lookups_agree_with_the_features(case in case()) {
+        let index = FeatureIndex::new(case.features.clone());
+        let locations = case.locations();
+        for point in case.points.iter().chain(locations.iter().copied()) {
+            prop_assert_eq!(index.contains(point), locations.contains(point), "contains({:?})", point);
+            let first = case.features.iter().find(|feature| feature.location.as_ref() == Some(point));
+            prop_assert_eq!(index.get(point), first, "get({:?}) isn't the first feature there", point);
+        }
+    }
+
+    #[test]
+    fn split_covers_the_rectangle(case in case()) {
+        if let Some(bounds) = Bounds::of(&case.rect) {
+            let parts = bounds.split();
+            prop_assert!(!parts.iter().any(Bounds::crosses_antimeridian), "{:?} splits into parts that cross the antimeridian", bounds);
+            for point in case.points.iter().chain(case.locations()) {
+                prop_assert_eq!(bounds.contains(point), parts.iter().any(|part| part.contains(point)), "{:?} and its parts disagree about {:?}", bounds, point);
+            }
+            prop_assert!(bounds.area() >= 0.0, "{:?} has a negative area", bounds);
+        }
+    }
+
+    /// In metres, allowing for floating point.
+    #[test]
+    fn distance_is_a_metric(a in point(), b in point(), c in point()) {
+        let (ab, ba, bc, ac) = (geo::distance(&a, &b), geo::distance(&b, &a), geo::distance(&b, &c), geo::distance(&a, &c));
+        prop_assert!((ab - ba).abs() <= 1e-6, "{} one way and {} the other", ab, ba);
+        prop_assert_eq!(geo::distance(&a, &a), 0.0);
+        prop_assert!((0.0..=PI * EARTH_RADIUS + 1e-6).contains(&ab), "{}, more than half the earth around", ab);
+        prop_assert!(ac <= ab + bc + 1e-6, "{}, more than the {} by way of {:?}", ac, ab + bc, b);
+    }
+
+    #[test]
+    fn e7_survives_degrees(point in point()) {
+        let latitude = (point.latitude as f64 / CORD_FACTOR * CORD_FACTOR).round() as i32;
+        let longitude = (point.longitude as f64 / CORD_FACTOR * CORD_FACTOR).round() as i32;
+        prop_assert_eq!((latitude, longitude), (point.latitude, point.longitude));
+    }
+
+    #[test]
+    fn polylines_survive_encoding(points in prop::collection::vec(point(), 0..50)) {
+        let decoded = geo::decode_polyline(&geo::encode_polyline(&points)).unwrap();
+        prop_assert_eq!(decoded.len(), points.len());
+        for (point, decoded) in points.iter().zip(&decoded) {
+            // Rounding to 5 places moves a coordinate by up to half of 100 E7 units.
+            let close = (point.latitude - decoded.latitude).abs() <= 50 && (point.longitude - decoded.longitude).abs() <= 50;
+            prop_assert!(close, "{:?} comes back from a polyline as {:?}", point, decoded);
+        }
+    }
+}